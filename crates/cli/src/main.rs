@@ -0,0 +1,2996 @@
+//! SSR Sandbox CLI
+//!
+//! Single-shot mode:
+//!   ssr-sandbox <chunks-dir> <entry-point> [props-json]
+//!
+//! Server mode (persistent process, reads from stdin):
+//!   ssr-sandbox --server <chunks-dir>
+//!
+//! Supervisor mode (server mode spread over renderer processes that are
+//! restarted when they crash):
+//!   ssr-sandbox --server --supervise 4 <chunks-dir>
+//!
+//! Diff mode (compare output of two bundles):
+//!   ssr-sandbox diff <chunks-old> <chunks-new> <entry> [props-json]
+//!
+//! Hydration check mode (flag nondeterministic output):
+//!   ssr-sandbox hydration-check <chunks-dir> <entry> [props-json]
+//!
+//! Doctor mode (check the runtime before serving, e.g. as a container init check):
+//!   ssr-sandbox doctor <chunks-dir> [entry]
+//!
+//! Proto-test mode (answer requests with edge-case responses to test a host's client):
+//!   ssr-sandbox proto-test [scenario...]
+//!
+//! Analyze mode (size, compile and eval time of every module an entry imports):
+//!   ssr-sandbox analyze <chunks-dir> <entry> [--write-profile <file>]
+//!
+//! Protocol (server mode):
+//!   Request (stdin):
+//!     entry.js
+//!     {"page":"home","user":"Alice"}
+//!
+//!   Response (stdout):
+//!     Status:Ok
+//!     Length:1234
+//!
+//!     <!DOCTYPE html>...
+//!
+//!   Structured renders (`{html, head, statusCode, headers, redirect}`) add
+//!   optional lines before the blank line:
+//!     Http-Status:404
+//!     Redirect:/login
+//!     Header:Cache-Control: no-store
+//!     Head:"<title>Home</title>"
+//!     Warning:slow_fetch fetch(https://api.example.com/data) took 1500ms
+//!
+//!   Error response:
+//!     Status:Error
+//!     Length:42
+//!
+//!     Render function threw: undefined is not...
+//!
+//!   A CSP nonce for the response can be sent between the entry and props lines
+//!   (passed to the render function as `context.nonce`):
+//!     entry.js
+//!     Csp-Nonce:r4nd0mB4se64
+//!     {"page":"home"}
+//!
+//!   So can a request id, which tags the request's stderr lines and violations,
+//!   is passed to the render function as `context.requestId` and is echoed back
+//!   as a `Request-Id:` response line:
+//!     entry.js
+//!     Request-Id:req-42
+//!     {"page":"home"}
+//!
+//!   And the URL, headers and locale of the HTTP request being rendered
+//!   (exposed as `globalThis.__SSR_REQUEST__` and `location`):
+//!     entry.js
+//!     Url:https://example.com/products?page=2
+//!     Header:Accept-Language: de-DE,de;q=0.9
+//!     Locale:de-DE
+//!     {}
+//!
+//!   An `Export:` line renders that named export of the entry as a fragment
+//!   instead of its default export:
+//!     components.js
+//!     Export:CartSummary
+//!     {"userId":42}
+//!
+//!   `Timeout:<ms>` (up to --max-request-timeout) and `Max-Heap:<MB>` (a soft
+//!   heap cap) lines override the limits for one request:
+//!     reports.js
+//!     Timeout:10000
+//!     Max-Heap:48
+//!     {"range":"year"}
+//!
+//!   `@fragments` followed by a JSON array renders several fragments for one
+//!   request; the body holds their framed responses (count in `Parts:`):
+//!     @fragments
+//!     [{"entry":"header.js"},{"entry":"page.js","props":{"id":1}}]
+//!
+//!   With `--protocol json`, requests and responses are NDJSON objects instead:
+//!     {"id":1,"entry":"entry.js","props":{"page":"home"},"timeout_ms":2000,"max_heap_mb":32,"request_id":"req-42"}
+//!     {"id":1,"ok":true,"body":"<!DOCTYPE html>...","requestId":"req-42"}
+//!
+//!   Admin commands are a single line starting with `@` (no props line):
+//!     @config    Effective runtime configuration as JSON
+//!     @deny <entry> [props=<sha256> | route=<pattern>]
+//!                Refuse matching renders until the rule is removed
+//!     @allow <entry> [props=<sha256> | route=<pattern>]
+//!                Remove a deny rule
+//!     @denylist  Current deny rules as JSON
+//!     @stats     Sandbox violation, fragment cache and cache memory counters as JSON
+//!     @health    Liveness and readiness as JSON
+//!     @unload <path>
+//!                Drop the runtimes that loaded modules below <path> (relative to
+//!                the chunks dir), reclaiming their memory
+//!     @island <id>
+//!                Render an island deferred by an earlier render (`Island:` lines)
+//!   Other `@` entries are reserved and answered with `Error-Code:unsupported_command`.
+
+mod watch;
+
+use anyhow::{anyhow, Result};
+use futures_util::StreamExt;
+#[cfg(feature = "crash-dumps")]
+use ssr_sandbox_core::install_crash_dumps;
+use ssr_sandbox_core::{
+    analyze_entry, count_violation, create_runtime, diff_html, execute_ssr, execute_ssr_stream,
+    find_hydration_mismatches, format_diff, install_crash_hook, is_valid_request_id,
+    preload_modules, props_hash, request_tag, sanitize_props, serve_health, verify_snapshot,
+    warm_up_origins, write_crash_report, AdaptiveTimeout, AuditLog, AuditOutcome, AuditRecord,
+    ConsoleLimits, ConsoleOutput, DataDir, DenyRule, Denylist, DeterminismConfig, ExecuteOptions,
+    Health, HtmlRewrites, Island, IsolationMode, RefreshRoute, RuntimeProfile, SandboxConfig,
+    SandboxViolation, SanitizePolicy, SecretHeaders, SsrError, SsrResult, SsrRuntime, StorageMode,
+    TenantLimits, TenantQuota, TenantRouter, ThreadAffinity, TimerLimits, TlsPolicy, TlsVersion,
+    ViolationKind, ViolationMonitor, Warning, WarningCode, WasmLimits,
+};
+use ssr_sandbox_protocol::{
+    read_request, read_request_async, write_response, Incoming, Protocol, Request, Response, ADMIN_COMMANDS,
+    MAX_FRAGMENTS,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use watch::{ChunksWatcher, DEFAULT_WATCH_DEBOUNCE};
+
+fn print_usage() {
+    eprintln!("SSR Sandbox - Secure server-side rendering runtime");
+    eprintln!();
+    eprintln!("Single-shot mode:");
+    eprintln!("  ssr-sandbox [options] <chunks-dir> <entry-point> [props-json]");
+    eprintln!();
+    eprintln!("Server mode (persistent process):");
+    eprintln!("  ssr-sandbox --server [options] <chunks-dir>");
+    eprintln!();
+    eprintln!("Diff mode (render with two bundles and compare the HTML structure):");
+    eprintln!("  ssr-sandbox diff [options] <chunks-old> <chunks-new> <entry> [props-json]");
+    eprintln!("                        <entry> is relative to each chunks dir");
+    eprintln!("                        Exits with status 1 if the output differs");
+    eprintln!();
+    eprintln!("Hydration check mode (render twice and flag nondeterministic output):");
+    eprintln!("  ssr-sandbox hydration-check [options] <chunks-dir> <entry> [props-json]");
+    eprintln!("                        <entry> is relative to chunks-dir");
+    eprintln!("                        Exits with status 1 if mismatches are found");
+    eprintln!();
+    eprintln!("Doctor mode (check the snapshot, V8, chunks dir, fetch origins and clock):");
+    eprintln!("  ssr-sandbox doctor [options] <chunks-dir> [entry]");
+    eprintln!("                        Also loads <entry> (relative to chunks-dir) if given");
+    eprintln!("                        Exits with status 1 if a check fails");
+    eprintln!();
+    eprintln!("Proto-test mode (test a host's client against every kind of server response):");
+    eprintln!("  ssr-sandbox proto-test [--protocol json] [scenario...]");
+    eprintln!("                        Reads requests like --server, but answers them with the");
+    eprintln!("                        scenarios below in turn (or only those given):");
+    for (name, description) in PROTO_TEST_SCENARIOS {
+        eprintln!("                          {:<18} {}", name, description);
+    }
+    eprintln!();
+    eprintln!("Analyze mode (what each module of an entry's import graph costs a cold start):");
+    eprintln!("  ssr-sandbox analyze [options] <chunks-dir> <entry>");
+    eprintln!("                        Lists size, compile and eval time per module, most");
+    eprintln!("                        expensive first (--json prints them as a JSON array)");
+    eprintln!("  --write-profile <file>");
+    eprintln!("                        Also write a --runtime-profile omitting the optional");
+    eprintln!("                        globals (crypto, timers, fetch, ...) no module refers to");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --max-heap-size <MB>  Maximum V8 heap size in megabytes (default: 64)");
+    eprintln!("                        Use 0 for unlimited (not recommended)");
+    eprintln!("  --heap-watermark <percent>");
+    eprintln!("                        Clear caches and collect garbage before a render when the");
+    eprintln!("                        heap is above <percent> of --max-heap-size (default: off)");
+    eprintln!("  --memory-budget <MB>  Heap all isolates may reserve together: refuse to start if");
+    eprintln!("                        the heap limits of the pools add up to more, and split it");
+    eprintln!("                        over the isolates when --max-heap-size is not given");
+    eprintln!("  --timeout <ms>        Maximum render time in milliseconds (default: 5000)");
+    eprintln!("                        Use 0 for unlimited (not recommended)");
+    eprintln!("  --max-request-timeout <ms>");
+    eprintln!("                        Server mode: longest timeout a request may ask for");
+    eprintln!("                        (Timeout: line, timeout_ms). Default: requests can only");
+    eprintln!("                        shorten --timeout");
+    eprintln!("  --adaptive-timeout <max-ms>");
+    eprintln!("                        Server mode: time out each entry's renders at twice the");
+    eprintln!("                        p99 of its recent renders (at least 100ms), never later");
+    eprintln!("                        than max-ms or a tenant's or explicit --timeout. See @stats");
+    eprintln!("  --module-timeout <ms> Maximum time to import the entry module, including");
+    eprintln!("                        top-level await (default: only --timeout applies)");
+    eprintln!("  --max-event-loop-turns <n>");
+    eprintln!("                        Fail a render after n event loop turns (default: unlimited)");
+    eprintln!("  --max-html-size <MB>  Fail a render that returns or streams more HTML (default:");
+    eprintln!("                        unlimited)");
+    eprintln!("  --allow-origin <url>  Allow fetch() to this origin (can be specified multiple times)");
+    eprintln!("                        Example: --allow-origin https://api.example.com");
+    eprintln!("                        Append a path prefix ending in /* to allow only those paths,");
+    eprintln!("                        e.g. --allow-origin 'https://api.example.com/v1/*'");
+    eprintln!("  --fetch-timeout <ms>  Reject a fetch() that takes longer with an AbortError");
+    eprintln!("  --fetch-budget <ms>   Total fetch() time per render, added up (default: unlimited)");
+    eprintln!("  --max-fetches <n>     Maximum fetch() calls per render (default: unlimited)");
+    eprintln!("  --max-concurrent-fetches <n>");
+    eprintln!("                        Maximum fetch() calls of a render in flight at once (default:");
+    eprintln!("                        unlimited); calls beyond either limit reject");
+    eprintln!("  --block-private-networks");
+    eprintln!("                        Refuse fetch() to loopback/private/link-local/metadata");
+    eprintln!("                        addresses (checked after DNS resolution)");
+    eprintln!("  --tls13 <origin>      Require TLS 1.3 for fetch() to this origin");
+    eprintln!("  --pin-spki <origin>=<sha256-base64>");
+    eprintln!("                        Only accept this server public key hash for the origin");
+    eprintln!("                        (can be specified multiple times, e.g. for key rotation)");
+    eprintln!("  --self-signed-cert <origin>=<pem-file>");
+    eprintln!("                        Accept this self-signed certificate (localhost origins only)");
+    eprintln!("  --fetch-secret-header <origin>=<header>:<env-var>");
+    eprintln!("                        Add a header with the value of <env-var> to every fetch()");
+    eprintln!("                        to the origin (e.g. Authorization); JS never sees it");
+    eprintln!("  --integrity <file>    JSON manifest of chunk path -> sha256-<base64> hash;");
+    eprintln!("                        modules that don't match or aren't listed are refused");
+    eprintln!("  --import-map <file>   Deno-style import map resolving bare specifiers (react,");
+    eprintln!("                        #app/utils) to files in chunks-dir, relative to the map");
+    eprintln!("  --code-cache-dir <dir>");
+    eprintln!("                        Store V8 code cache of loaded chunks in <dir> and reuse it,");
+    eprintln!("                        skipping compilation in new runtimes (dir must be trusted)");
+    eprintln!("  --data-dir <dir>      Read-only directory of static data files for __ssr_read_data__()");
+    eprintln!("  --max-data-file-size <bytes>");
+    eprintln!("                        Largest file __ssr_read_data__() will read (default: 1048576)");
+    eprintln!("  --protocol <name>     Server mode: line (default) or json (one JSON object per line,");
+    eprintln!("                        requests {{id, entry, props, timeout_ms, max_heap_mb, csp_nonce, request_id, export, request}}, id echoed back)");
+    eprintln!("  --line-timeout <ms>   Server mode: answer a request whose next line takes longer");
+    eprintln!("                        to arrive with an incomplete_request error (default: 10000,");
+    eprintln!("                        0 waits forever)");
+    eprintln!("  --watch               Server mode: reload changed files in chunks-dir without");
+    eprintln!("                        restarting (for development)");
+    eprintln!("  --allow-entry <pattern>");
+    eprintln!("                        Server mode: only render entries matching the pattern");
+    eprintln!("                        (relative to chunks-dir, * is a wildcard, can be specified");
+    eprintln!("                        multiple times)");
+    eprintln!("  --warmup <entry>      Server mode: load and compile this entry (relative to chunks-dir)");
+    eprintln!("                        in every runtime before it serves requests (can be specified");
+    eprintln!("                        multiple times)");
+    eprintln!("  --refresh <seconds>:<entry>[:<props-json>]");
+    eprintln!("                        Server mode: render this route every <seconds> in the");
+    eprintln!("                        background to keep __ssr_cache__ and the Cache API warm");
+    eprintln!("                        (can be specified multiple times)");
+    eprintln!("  --inject-nonce        Server mode: add the request's CSP nonce to <script>/<style>");
+    eprintln!("                        tags in the output that don't have a nonce attribute");
+    eprintln!("  --violation-alert <n> Server mode: print an [ALERT] line to stderr when more than");
+    eprintln!("                        n sandbox violations happen within a minute");
+    eprintln!("  --health-addr <addr>  Server mode: serve GET /livez and GET /readyz over HTTP");
+    eprintln!("                        on <addr>; /readyz is 503 until runtimes are warmed up");
+    eprintln!("  --supervise <n>       Server mode: run <n> renderer processes, send each request");
+    eprintln!("                        to an idle one and restart renderers that crash or run");
+    eprintln!("                        out of memory (only the request in flight fails)");
+    eprintln!("  --audit-log <path>    Server mode: append a JSON line per render (entry, duration,");
+    eprintln!("                        outcome, violations) to <path>, rotated at --audit-log-size");
+    eprintln!("                        <MB> (default: 100) keeping --audit-log-keep <n> files (default: 5)");
+    eprintln!("  --crash-report-dir <dir>");
+    eprintln!("                        On a panic or V8 out-of-memory error, write a JSON crash report");
+    eprintln!("                        (renders in progress, recent events) to <dir> before exiting");
+    eprintln!("  --crash-dump-dir <dir>");
+    eprintln!("                        On a segfault or other fatal signal, write a dump");
+    eprintln!("                        (version, config hash, registers, memory map) to <dir>");
+    eprintln!("                        (needs the crash-dumps feature)");
+    eprintln!("  --redact <pattern>    Redact props in logs: a path (user.email) or a key pattern");
+    eprintln!("                        at any depth (*token*), can be specified multiple times");
+    eprintln!("  --max-console-entries <n>");
+    eprintln!("                        Maximum captured console entries per render (default: 1000)");
+    eprintln!("                        Consecutive repeats are collapsed into one entry");
+    eprintln!("  --max-timers <n>      Maximum pending setTimeout/setInterval timers (default: 100)");
+    eprintln!("  --max-timer-delay <ms>");
+    eprintln!("                        Timers with a longer delay never fire (default: 5000)");
+    eprintln!("  --max-wasm-size <MB>  Largest WebAssembly module renders may compile or import");
+    eprintln!("                        (default: 16)");
+    eprintln!("  --max-wasm-memory <MB>");
+    eprintln!("                        Maximum size of each WebAssembly memory (default: 128)");
+    eprintln!("  --runtime-profile <file>");
+    eprintln!("                        JSON profile of optional globals to delete, e.g.");
+    eprintln!("                        {{\"omit\":[\"crypto\",\"wasm\"]}} (see analyze --write-profile)");
+    eprintln!("  --asset-origin <url>  Serve assets under --asset-prefix from this origin, rewriting");
+    eprintln!("                        src/href attributes of the output (streamed output included)");
+    eprintln!("  --asset-prefix <path> Root-relative URL prefix of assets moved to --asset-origin,");
+    eprintln!("                        e.g. /static/ (can be specified multiple times)");
+    eprintln!("  --stream              Single-shot only: write HTML chunks as they are rendered");
+    eprintln!("                        (render may return a ReadableStream)");
+    eprintln!("  --json                Single-shot only: print {{html, console, durationMs, error}}");
+    eprintln!("                        as one JSON document instead of HTML and [LOG] lines");
+    eprintln!("                        (analyze mode: print the module costs as JSON)");
+    eprintln!("  --storage <mode>      localStorage/sessionStorage: disabled (default, undefined),");
+    eprintln!("                        memory (wiped before every render) or strict (throws)");
+    eprintln!("  --isolation <mode>    Server mode: isolate (default, one runtime reused for all");
+    eprintln!("                        renders) or per-request (fresh isolate for every render)");
+    eprintln!("  --pool-size <n>       Pre-warmed isolates for --isolation per-request (default: 2)");
+    eprintln!("  --max-requests-per-isolate <n>");
+    eprintln!("                        Server mode: replace a runtime after n renders (default: never)");
+    eprintln!("  --max-resident-heap <MB>");
+    eprintln!("                        Server mode: replace a runtime whose V8 heap stays above <MB>");
+    eprintln!("                        after a render and a full GC (default: never)");
+    eprintln!("  --tenant-heap-size <tenant>=<MB>");
+    eprintln!("  --tenant-timeout <tenant>=<ms>");
+    eprintln!("                        Server mode: limits for entries under <chunks-dir>/<tenant>/,");
+    eprintln!("                        rendered in separate isolates on their own thread");
+    eprintln!("  --tenant-quota <tenant>=<resource>:<n>,...");
+    eprintln!("                        Refuse the tenant's renders once it used n renders, cpu_ms or");
+    eprintln!("                        fetch_bytes in the current period (period:<seconds>, default 3600)");
+    eprintln!("  --thread-affinity <none|numa|cpus:<list>>");
+    eprintln!("                        Server mode, Linux: pin tenant threads to the CPUs of one NUMA");
+    eprintln!("                        node each, or to one CPU each of <list> (e.g. cpus:0-3,8)");
+    eprintln!("  --context-per-request Reset globalThis before and after every render, so renders");
+    eprintln!("                        can't share data through globals");
+    eprintln!("  --cache-api           Expose the Cache API (caches.open/match), backed by an");
+    eprintln!("                        in-memory store shared by all renders of the process");
+    eprintln!("  --fragment-cache <MB> Enable __ssr_cache__ for memoizing HTML fragments across");
+    eprintln!("                        renders on the same isolate, up to <MB> per isolate");
+    eprintln!("  --cache-memory <MB>   Memory budget shared by the Cache API and __ssr_cache__,");
+    eprintln!("                        evicting the least recently used entries across both");
+    eprintln!("  --deterministic       Fixed clock and seeded Math.random/crypto random values,");
+    eprintln!("                        reset for every render (default in diff mode)");
+    eprintln!("  --seed <n>            PRNG seed for --deterministic (default: 0)");
+    eprintln!("  --fixed-time <ms>     Date.now() for --deterministic (default: 946684800000)");
+    eprintln!();
+    eprintln!("Examples:");
+    eprintln!("  ssr-sandbox ./dist/chunks ./dist/chunks/entry.js '{{\"page\":\"home\"}}'");
+    eprintln!("  ssr-sandbox --server ./dist/chunks");
+    eprintln!("  ssr-sandbox --timeout 5000 --server ./dist/chunks");
+    eprintln!("  ssr-sandbox --supervise 4 --server ./dist/chunks");
+    eprintln!("  ssr-sandbox --allow-origin https://api.example.com --server ./dist/chunks");
+    eprintln!("  ssr-sandbox diff ./dist-old ./dist-new entry.js '{{\"page\":\"home\"}}'");
+    eprintln!("  ssr-sandbox hydration-check ./dist entry.js '{{\"page\":\"home\"}}'");
+    eprintln!("  ssr-sandbox doctor --allow-origin https://api.example.com ./dist entry.js");
+    eprintln!("  ssr-sandbox analyze ./dist entry.js");
+    eprintln!("  ssr-sandbox proto-test --protocol json huge-body slow-write");
+    eprintln!("  ssr-sandbox analyze --write-profile profile.json ./dist entry.js");
+}
+
+/// Values of an `<origin>=<value>` option, e.g. `--pin-spki https://api.example.com=<hash>`
+fn parse_origin_values(args: &[String], name: &str) -> Result<Vec<(String, String)>> {
+    let mut values = vec![];
+    for arg in parse_values(args, name)? {
+        let (origin, value) = arg
+            .split_once('=')
+            .ok_or_else(|| anyhow!("{} expects <origin>=<value>, got '{}'", name, arg))?;
+        values.push((origin.to_string(), value.to_string()));
+    }
+    Ok(values)
+}
+
+/// Per-origin TLS overrides from --tls13, --pin-spki and --self-signed-cert
+fn parse_tls_policies(args: &[String]) -> Result<HashMap<String, TlsPolicy>> {
+    let mut policies: HashMap<String, TlsPolicy> = HashMap::new();
+    for origin in parse_values(args, "--tls13")? {
+        policies.entry(origin).or_default().min_version = Some(TlsVersion::Tls13);
+    }
+    for (origin, pin) in parse_origin_values(args, "--pin-spki")? {
+        policies.entry(origin).or_default().spki_sha256.push(pin);
+    }
+    for (origin, path) in parse_origin_values(args, "--self-signed-cert")? {
+        policies.entry(origin).or_default().self_signed_cert = Some(path);
+    }
+    Ok(policies)
+}
+
+/// Per-origin secret fetch headers from --fetch-secret-header
+/// (`<origin>=<header>:<env-var>`), read from the environment so the secrets
+/// don't show up in the process list
+fn parse_secret_headers(args: &[String]) -> Result<HashMap<String, SecretHeaders>> {
+    let mut headers: HashMap<String, SecretHeaders> = HashMap::new();
+    for (origin, value) in parse_origin_values(args, "--fetch-secret-header")? {
+        let (name, var) = value.split_once(':').ok_or_else(|| {
+            anyhow!(
+                "--fetch-secret-header expects <origin>=<header>:<env-var>, got '{}'",
+                value
+            )
+        })?;
+        let secret = std::env::var(var).map_err(|_| {
+            anyhow!("--fetch-secret-header: environment variable '{}' is not set", var)
+        })?;
+        headers.entry(origin).or_default().insert(name, secret);
+    }
+    Ok(headers)
+}
+
+/// Per-tenant limits from --tenant-heap-size, --tenant-timeout (`<tenant>=<value>`)
+/// and --tenant-quota
+fn parse_tenant_limits(args: &[String]) -> Result<BTreeMap<String, TenantLimits>> {
+    let mut tenants: BTreeMap<String, TenantLimits> = BTreeMap::new();
+    for i in 0..args.len() {
+        let name = args[i].as_str();
+        if !matches!(
+            name,
+            "--tenant-heap-size" | "--tenant-timeout" | "--tenant-quota"
+        ) {
+            continue;
+        }
+        let arg = args
+            .get(i + 1)
+            .ok_or_else(|| anyhow!("Missing value for {}", name))?;
+        let (tenant, value) = arg
+            .split_once('=')
+            .ok_or_else(|| anyhow!("{} expects <tenant>=<value>, got '{}'", name, arg))?;
+        let limits = tenants.entry(tenant.to_string()).or_default();
+        if name == "--tenant-quota" {
+            limits.quota = parse_tenant_quota(value)?;
+            continue;
+        }
+        let value: u64 = value
+            .parse()
+            .map_err(|_| anyhow!("{} expects a number, got '{}'", name, value))?;
+        if name == "--tenant-heap-size" {
+            let bytes = usize::try_from(value).ok().and_then(megabytes);
+            limits.max_heap_size =
+                Some(bytes.ok_or_else(|| anyhow!("{} of {} MB is too large", name, value))?);
+        } else {
+            limits.timeout_ms = Some(value);
+        }
+    }
+    Ok(tenants)
+}
+
+/// Quota of --tenant-quota `<tenant>=<resource>:<n>,...`, e.g. `renders:1000,cpu_ms:60000`
+fn parse_tenant_quota(spec: &str) -> Result<TenantQuota> {
+    let mut quota = TenantQuota::default();
+    for part in spec.split(',') {
+        let (resource, value) = part
+            .split_once(':')
+            .ok_or_else(|| anyhow!("--tenant-quota expects <resource>:<n>, got '{}'", part))?;
+        let value: u64 = value
+            .parse()
+            .map_err(|_| anyhow!("--tenant-quota expects a number, got '{}'", value))?;
+        match resource {
+            "renders" => quota.max_renders = Some(value),
+            "cpu_ms" => quota.max_cpu_ms = Some(value),
+            "fetch_bytes" => quota.max_fetch_bytes = Some(value),
+            "period" => quota.period_secs = value,
+            _ => {
+                return Err(anyhow!(
+                    "Unknown --tenant-quota resource '{}' (expected renders, cpu_ms, fetch_bytes or period)",
+                    resource
+                ))
+            }
+        }
+    }
+    Ok(quota)
+}
+
+/// Integrity manifest from --integrity <file> (JSON object of chunk path to sha256-<base64>)
+fn parse_integrity(args: &[String]) -> Result<Option<BTreeMap<String, String>>> {
+    let Some(path) = parse_option::<String>(args, "--integrity")? else {
+        return Ok(None);
+    };
+    let json = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("Failed to read integrity manifest '{}': {}", path, e))?;
+    let manifest = serde_json::from_str(&json)
+        .map_err(|e| anyhow!("Invalid integrity manifest '{}': {}", path, e))?;
+    Ok(Some(manifest))
+}
+
+/// Routes from --refresh `<seconds>:<entry>[:<props-json>]` (can be specified multiple times)
+fn parse_refresh_routes(args: &[String]) -> Result<Vec<RefreshRoute>> {
+    let mut routes = vec![];
+    for arg in parse_values(args, "--refresh")? {
+        let mut parts = arg.splitn(3, ':');
+        let (Some(interval), Some(entry)) = (parts.next(), parts.next()) else {
+            return Err(anyhow!(
+                "--refresh expects <seconds>:<entry>[:<props-json>], got '{}'",
+                arg
+            ));
+        };
+        let interval_secs = interval
+            .parse()
+            .map_err(|_| anyhow!("--refresh expects a number of seconds, got '{}'", interval))?;
+        routes.push(RefreshRoute {
+            entry: entry.to_string(),
+            props: parse_props(parts.next())?,
+            interval_secs,
+        });
+    }
+    Ok(routes)
+}
+
+/// Patterns from --allow-entry (can be specified multiple times), None if there are none
+fn parse_allowed_entries(args: &[String]) -> Result<Option<Vec<String>>> {
+    let patterns = parse_values(args, "--allow-entry")?;
+    Ok((!patterns.is_empty()).then_some(patterns))
+}
+
+/// --asset-origin with the --asset-prefix paths (can be specified multiple times)
+fn parse_html_rewrites(args: &[String]) -> Result<HtmlRewrites> {
+    Ok(HtmlRewrites {
+        asset_origin: parse_option(args, "--asset-origin")?,
+        asset_prefixes: parse_values(args, "--asset-prefix")?,
+    })
+}
+
+/// --audit-log <path>, rotated at --audit-log-size <MB> keeping --audit-log-keep <n> old files
+fn parse_audit_log(args: &[String]) -> Result<Option<AuditLog>> {
+    let Some(path) = parse_option::<String>(args, "--audit-log")? else {
+        return Ok(None);
+    };
+    let max_bytes = parse_megabytes(args, "--audit-log-size")?
+        .map_or(AuditLog::DEFAULT_MAX_BYTES, |bytes| bytes as u64);
+    let keep = parse_option(args, "--audit-log-keep")?.unwrap_or(AuditLog::DEFAULT_KEEP);
+    Ok(Some(AuditLog::open(path, max_bytes, keep)?))
+}
+
+/// Value of option `name` (None if it isn't given); an unparsable or missing
+/// value is an error rather than falling back to the default
+fn parse_option<T>(args: &[String], name: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let Some(i) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    let value = args
+        .get(i + 1)
+        .ok_or_else(|| anyhow!("Missing value for {}", name))?;
+    value
+        .parse()
+        .map(Some)
+        .map_err(|e| anyhow!("Invalid value '{}' for {}: {}", value, name, e))
+}
+
+/// Values of option `name`, which can be specified multiple times; a missing
+/// value is an error like in [`parse_option`]
+fn parse_values(args: &[String], name: &str) -> Result<Vec<String>> {
+    let mut values = vec![];
+    for i in 0..args.len() {
+        if args[i] == name {
+            let value = args
+                .get(i + 1)
+                .ok_or_else(|| anyhow!("Missing value for {}", name))?;
+            values.push(value.clone());
+        }
+    }
+    Ok(values)
+}
+
+/// Value of a size option given in MB, in bytes
+fn parse_megabytes(args: &[String], name: &str) -> Result<Option<usize>> {
+    let Some(mb) = parse_option::<usize>(args, name)? else {
+        return Ok(None);
+    };
+    let bytes = megabytes(mb)
+        .ok_or_else(|| anyhow!("Invalid value '{}' for {}: too large", mb, name))?;
+    Ok(Some(bytes))
+}
+
+/// `mb` MB in bytes, None if that doesn't fit in a usize
+fn megabytes(mb: usize) -> Option<usize> {
+    mb.checked_mul(1024 * 1024)
+}
+
+/// Options that take a value (skipped when collecting positional args)
+const VALUE_OPTIONS: &[&str] = &[
+    "--max-heap-size",
+    "--heap-watermark",
+    "--memory-budget",
+    "--timeout",
+    "--allow-origin",
+    "--tls13",
+    "--pin-spki",
+    "--self-signed-cert",
+    "--fetch-secret-header",
+    "--max-console-entries",
+    "--storage",
+    "--max-event-loop-turns",
+    "--max-html-size",
+    "--module-timeout",
+    "--max-request-timeout",
+    "--adaptive-timeout",
+    "--isolation",
+    "--pool-size",
+    "--tenant-heap-size",
+    "--tenant-timeout",
+    "--tenant-quota",
+    "--thread-affinity",
+    "--integrity",
+    "--import-map",
+    "--violation-alert",
+    "--protocol",
+    "--line-timeout",
+    "--redact",
+    "--health-addr",
+    "--supervise",
+    "--audit-log",
+    "--audit-log-size",
+    "--audit-log-keep",
+    "--crash-report-dir",
+    "--crash-dump-dir",
+    "--code-cache-dir",
+    "--max-timers",
+    "--max-timer-delay",
+    "--max-wasm-size",
+    "--max-wasm-memory",
+    "--runtime-profile",
+    "--write-profile",
+    "--data-dir",
+    "--max-data-file-size",
+    "--seed",
+    "--fixed-time",
+    "--fragment-cache",
+    "--cache-memory",
+    "--warmup",
+    "--refresh",
+    "--max-requests-per-isolate",
+    "--max-resident-heap",
+    "--allow-entry",
+    "--fetch-timeout",
+    "--fetch-budget",
+    "--max-fetches",
+    "--max-concurrent-fetches",
+    "--asset-origin",
+    "--asset-prefix",
+];
+
+/// Options that don't take a value
+const FLAG_OPTIONS: &[&str] = &[
+    "--cache-api",
+    "--stream",
+    "--json",
+    "--context-per-request",
+    "--block-private-networks",
+    "--inject-nonce",
+    "--deterministic",
+    "--watch",
+];
+
+fn filter_options(args: &[String]) -> Vec<String> {
+    let mut result = vec![args[0].clone()];
+    let mut skip_next = false;
+    for arg in args.iter().skip(1) {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+        if VALUE_OPTIONS.contains(&arg.as_str()) {
+            skip_next = true;
+            continue;
+        }
+        if FLAG_OPTIONS.contains(&arg.as_str()) {
+            continue;
+        }
+        result.push(arg.clone());
+    }
+    result
+}
+
+/// Arguments of renderer `n` (from 1) of `count` in supervisor mode: the same
+/// options and server mode args, with the json protocol, no health endpoints,
+/// an audit log of its own and its share of the memory budget
+fn renderer_args(options: &[String], n: usize, count: usize) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut options = options.iter().skip(1);
+    while let Some(option) = options.next() {
+        let value = match option.as_str() {
+            "--supervise" | "--protocol" | "--health-addr" => {
+                options.next();
+                continue;
+            }
+            "--audit-log" => options.next().map(|path| format!("{}.renderer{}", path, n)),
+            "--memory-budget" => options.next().map(|mb| match mb.parse::<usize>() {
+                Ok(mb) => (mb / count).to_string(),
+                Err(_) => mb.clone(),
+            }),
+            _ => {
+                args.push(option.clone());
+                continue;
+            }
+        };
+        args.push(option.clone());
+        args.extend(value);
+    }
+    args.extend(["--protocol".to_string(), "json".to_string()]);
+    args
+}
+
+/// Build the sandbox config from command line options (chunks_dir is set per mode)
+fn build_config(args: &[String]) -> Result<SandboxConfig> {
+    let max_heap_size = parse_megabytes(args, "--max-heap-size")?;
+    // Convert 0 to None (unlimited)
+    let max_heap_size = max_heap_size.and_then(|s| if s == 0 { None } else { Some(s) });
+
+    let timeout_ms = parse_option::<u64>(args, "--timeout")?;
+    // Convert 0 to None (unlimited)
+    let timeout_ms = timeout_ms.and_then(|t| if t == 0 { None } else { Some(t) });
+    let adaptive_timeout = parse_option(args, "--adaptive-timeout")?
+        .filter(|&ms| ms > 0)
+        .map(|max_ms| AdaptiveTimeout {
+            max_ms,
+            ..Default::default()
+        });
+
+    let mut console_limits = ConsoleLimits::default();
+    if let Some(max_entries) = parse_option(args, "--max-console-entries")? {
+        console_limits.max_entries = max_entries;
+    }
+
+    let mut timer_limits = TimerLimits::default();
+    if let Some(max_pending) = parse_option(args, "--max-timers")? {
+        timer_limits.max_pending = max_pending;
+    }
+    if let Some(max_delay_ms) = parse_option(args, "--max-timer-delay")? {
+        timer_limits.max_delay_ms = max_delay_ms;
+    }
+
+    let mut wasm_limits = WasmLimits::default();
+    if let Some(bytes) = parse_megabytes(args, "--max-wasm-size")? {
+        wasm_limits.max_module_bytes = bytes;
+    }
+    if let Some(bytes) = parse_megabytes(args, "--max-wasm-memory")? {
+        wasm_limits.max_memory_bytes = bytes as u64;
+    }
+
+    // With a memory budget, isolates without --max-heap-size get a share of it
+    let memory_budget = parse_megabytes(args, "--memory-budget")?;
+    let default_heap_size = memory_budget.is_none().then_some(64 * 1024 * 1024);
+
+    let config = SandboxConfig {
+        max_heap_size: max_heap_size.or(default_heap_size),
+        memory_budget,
+        heap_watermark: parse_option::<f64>(args, "--heap-watermark")?.map(|pct| pct / 100.0),
+        // Adaptive timeouts are capped by `timeout_ms`, so without `--timeout`
+        // their max replaces the default
+        timeout_ms: timeout_ms
+            .or(adaptive_timeout.as_ref().map(|adaptive| adaptive.max_ms))
+            .or(Some(5_000)),
+        max_request_timeout_ms: parse_option(args, "--max-request-timeout")?.filter(|&ms| ms > 0),
+        adaptive_timeout,
+        allowed_origins: parse_values(args, "--allow-origin")?,
+        tls_policies: parse_tls_policies(args)?,
+        fetch_secret_headers: parse_secret_headers(args)?,
+        block_private_networks: args.iter().any(|arg| arg == "--block-private-networks"),
+        fetch_request_timeout_ms: parse_option(args, "--fetch-timeout")?.filter(|&ms| ms > 0),
+        total_fetch_budget_ms: parse_option(args, "--fetch-budget")?.filter(|&ms| ms > 0),
+        max_fetch_requests: parse_option(args, "--max-fetches")?,
+        max_concurrent_fetches: parse_option(args, "--max-concurrent-fetches")?,
+        console_limits,
+        timer_limits,
+        wasm_limits,
+        runtime_profile: parse_option::<String>(args, "--runtime-profile")?
+            .map(RuntimeProfile::load)
+            .transpose()?,
+        cache_api: args.iter().any(|arg| arg == "--cache-api"),
+        storage: match parse_option::<String>(args, "--storage")? {
+            Some(storage) => storage.parse()?,
+            None => StorageMode::default(),
+        },
+        max_event_loop_turns: parse_option(args, "--max-event-loop-turns")?,
+        max_html_bytes: parse_megabytes(args, "--max-html-size")?,
+        module_timeout_ms: parse_option(args, "--module-timeout")?.filter(|&ms| ms > 0),
+        isolation: match parse_option::<String>(args, "--isolation")? {
+            Some(isolation) => isolation.parse()?,
+            None => IsolationMode::default(),
+        },
+        pool_size: parse_option(args, "--pool-size")?.unwrap_or(2),
+        context_per_request: args.iter().any(|arg| arg == "--context-per-request"),
+        tenants: parse_tenant_limits(args)?,
+        thread_affinity: match parse_option::<String>(args, "--thread-affinity")? {
+            Some(affinity) => affinity.parse()?,
+            None => ThreadAffinity::default(),
+        },
+        integrity: parse_integrity(args)?,
+        import_map: parse_option(args, "--import-map")?,
+        sanitize: SanitizePolicy {
+            redact: parse_values(args, "--redact")?,
+        },
+        code_cache_dir: parse_option(args, "--code-cache-dir")?,
+        data_dir: parse_option(args, "--data-dir")?,
+        max_data_file_size: parse_option(args, "--max-data-file-size")?
+            .unwrap_or(DataDir::DEFAULT_MAX_FILE_SIZE),
+        deterministic: parse_determinism(args)?,
+        fragment_cache_size: parse_megabytes(args, "--fragment-cache")?,
+        cache_memory_budget: parse_megabytes(args, "--cache-memory")?,
+        warmup: parse_values(args, "--warmup")?,
+        refresh_routes: parse_refresh_routes(args)?,
+        max_requests_per_isolate: parse_option(args, "--max-requests-per-isolate")?
+            .filter(|&n| n > 0),
+        max_resident_heap_bytes: parse_megabytes(args, "--max-resident-heap")?,
+        allowed_entries: parse_allowed_entries(args)?,
+        html_rewrites: parse_html_rewrites(args)?,
+        ..Default::default()
+    };
+    config.reserve_memory()
+}
+
+/// `--deterministic`, optionally with `--seed` / `--fixed-time` (either implies it)
+fn parse_determinism(args: &[String]) -> Result<Option<DeterminismConfig>> {
+    let seed = parse_option(args, "--seed")?;
+    let epoch_ms = parse_option(args, "--fixed-time")?;
+    if !args.iter().any(|arg| arg == "--deterministic") && seed.is_none() && epoch_ms.is_none() {
+        return Ok(None);
+    }
+    let defaults = DeterminismConfig::default();
+    Ok(Some(DeterminismConfig {
+        epoch_ms: epoch_ms.unwrap_or(defaults.epoch_ms),
+        seed: seed.unwrap_or(defaults.seed),
+    }))
+}
+
+fn parse_props(props_json: Option<&str>) -> Result<serde_json::Value> {
+    let props: serde_json::Value = match props_json {
+        Some(json) => serde_json::from_str(json).map_err(|e| anyhow!("Invalid props JSON: {}", e))?,
+        None => serde_json::json!({}),
+    };
+
+    // Sanitize props to prevent prototype pollution
+    sanitize_props(props)
+}
+
+/// Run in single-shot mode (original behavior)
+async fn run_single_shot(
+    config: SandboxConfig,
+    entry_point: &str,
+    props_json: Option<&str>,
+    stream: bool,
+    json: bool,
+) -> Result<()> {
+    if stream && json {
+        return Err(anyhow!("--json can't be combined with --stream"));
+    }
+    let props = parse_props(props_json)?;
+
+    let mut runtime = create_runtime(&config)?;
+
+    if stream {
+        // Write HTML chunks to stdout as soon as the render produces them
+        let mut html = execute_ssr_stream(&mut runtime, Path::new(entry_point), props, config.timeout_ms).await?;
+        let mut stdout = std::io::stdout();
+        while let Some(chunk) = html.next().await {
+            stdout.write_all(&chunk?)?;
+            stdout.flush()?;
+        }
+        writeln!(stdout)?;
+
+        print_console(&html.console(), None);
+        print_violations(html.sandbox_violations(), None);
+        print_warnings(html.warnings());
+        return Ok(());
+    }
+
+    if json {
+        let started = Instant::now();
+        let result = execute_ssr(&mut runtime, Path::new(entry_point), props, config.timeout_ms).await;
+        return print_json_result(result.map_err(Into::into), started.elapsed());
+    }
+
+    let result = match execute_ssr(&mut runtime, Path::new(entry_point), props, config.timeout_ms).await {
+        Ok(result) => result,
+        Err(e) => {
+            print_failed_render(&e);
+            return Err(e.into());
+        }
+    };
+
+    // Print captured console output to stderr
+    print_console(&result.console, None);
+    print_violations(&result.sandbox_violations, None);
+    print_warnings(&result.warnings);
+    for line in Response::from(&result).header_lines() {
+        eprintln!("[response] {}", line);
+    }
+
+    // Print HTML to stdout
+    println!("{}", result.html);
+
+    Ok(())
+}
+
+/// Print the outcome of a single-shot render as one JSON document (--json).
+///
+/// Console output goes into the document instead of stderr. Exits with status
+/// 1 if the render failed, like without --json.
+fn print_json_result(result: Result<SsrResult>, elapsed: Duration) -> Result<()> {
+    let (html, console, error) = match &result {
+        Ok(result) => {
+            print_violations(&result.sandbox_violations, None);
+            print_warnings(&result.warnings);
+            (Some(result.html.as_str()), Some(&result.console), None)
+        }
+        Err(e) => {
+            let console = e.downcast_ref::<SsrError>().map(|e| &e.console);
+            (None, console, Some(e.to_string()))
+        }
+    };
+    let console = console.cloned().unwrap_or_default();
+    let output = serde_json::json!({
+        "html": html,
+        "console": {
+            "logs": console.logs(),
+            "warns": console.warns(),
+            "errors": console.errors(),
+            "entries": console.entries,
+        },
+        "durationMs": elapsed.as_secs_f64() * 1000.0,
+        "error": error,
+    });
+    println!("{}", output);
+    if result.is_err() {
+        std::io::stdout().flush()?;
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Run in diff mode: render the same entry with two bundles and print a structural HTML diff.
+///
+/// Renders are deterministic (unless configured otherwise), so timestamps and
+/// random ids don't show up as differences.
+async fn run_diff(config: SandboxConfig, old_dir: &str, new_dir: &str, entry: &str, props_json: Option<&str>) -> Result<()> {
+    let props = parse_props(props_json)?;
+    let config = SandboxConfig {
+        deterministic: config.deterministic.or(Some(DeterminismConfig::default())),
+        ..config
+    };
+
+    let mut outputs = Vec::with_capacity(2);
+    for chunks_dir in [old_dir, new_dir] {
+        let config = SandboxConfig {
+            chunks_dir: chunks_dir.to_string(),
+            ..config.clone()
+        };
+
+        let mut runtime = create_runtime(&config)?;
+        let entry_path = Path::new(&config.chunks_dir).join(entry);
+        let result = execute_ssr(&mut runtime, &entry_path, props.clone(), config.timeout_ms)
+            .await
+            .map_err(|e| anyhow!("Render with '{}' failed: {}", chunks_dir, e))?;
+        outputs.push(result.html);
+    }
+
+    let diff = format_diff(&diff_html(&outputs[0], &outputs[1]), 3);
+    if diff.is_empty() {
+        eprintln!("[ssr-sandbox] No differences");
+        return Ok(());
+    }
+
+    println!("--- {}", old_dir);
+    println!("+++ {}", new_dir);
+    print!("{}", diff);
+    std::io::stdout().flush()?;
+    std::process::exit(1);
+}
+
+/// Run in hydration check mode: render the same entry twice (in fresh runtimes,
+/// a second apart) and report any output that differs between the two renders.
+async fn run_hydration_check(config: SandboxConfig, entry: &str, props_json: Option<&str>) -> Result<()> {
+    let props = parse_props(props_json)?;
+    // Deterministic mode would hide exactly what this looks for
+    let config = SandboxConfig {
+        deterministic: None,
+        ..config
+    };
+    let entry_path = Path::new(&config.chunks_dir).join(entry);
+
+    let mut outputs = Vec::with_capacity(2);
+    for pass in 0..2 {
+        if pass > 0 {
+            // Let the clock move on so second-resolution timestamps differ too
+            tokio::time::sleep(std::time::Duration::from_millis(1_100)).await;
+        }
+        let mut runtime = create_runtime(&config)?;
+        let result = execute_ssr(&mut runtime, &entry_path, props.clone(), config.timeout_ms).await?;
+        outputs.push(result.html);
+    }
+
+    let mismatches = find_hydration_mismatches(&outputs[0], &outputs[1]);
+    if mismatches.is_empty() {
+        eprintln!("[ssr-sandbox] No nondeterministic output detected");
+        return Ok(());
+    }
+
+    println!("Found {} nondeterministic fragment(s) that will likely cause hydration mismatches:", mismatches.len());
+    for (i, mismatch) in mismatches.iter().enumerate() {
+        println!();
+        println!("#{} suspected cause: {}", i + 1, mismatch.suspected_cause);
+        println!("  render 1: {}", mismatch.first);
+        println!("  render 2: {}", mismatch.second);
+    }
+    std::io::stdout().flush()?;
+    std::process::exit(1);
+}
+
+/// Earliest believable wall clock time (2024-01-01T00:00:00Z), in seconds
+const DOCTOR_MIN_UNIX_TIME: u64 = 1_704_067_200;
+/// How far the JS clock may be from the system clock
+const DOCTOR_MAX_CLOCK_SKEW_MS: u64 = 1_000;
+
+/// Run in analyze mode: measure what each module of `entry`'s import graph
+/// costs a cold start, and list them most expensive first. With
+/// `write_profile`, also write a [`RuntimeProfile`] omitting the optional
+/// globals none of the modules refer to.
+async fn run_analyze(
+    config: SandboxConfig,
+    entry: &str,
+    json: bool,
+    write_profile: Option<&str>,
+) -> Result<()> {
+    let entry_path = Path::new(&config.chunks_dir).join(entry);
+    let mut costs = analyze_entry(&config, &entry_path).await?;
+    if let Some(path) = write_profile {
+        let mut sources = Vec::new();
+        // Imported .wasm modules reach WebAssembly through the runtime, not the global
+        for cost in costs.iter().filter(|cost| !cost.path.ends_with(".wasm")) {
+            let file = Path::new(&config.chunks_dir).join(&cost.path);
+            sources.push(
+                std::fs::read_to_string(&file)
+                    .map_err(|e| anyhow!("Failed to read '{}': {}", file.display(), e))?,
+            );
+        }
+        let profile = RuntimeProfile::detect(sources.iter().map(String::as_str));
+        profile.save(path)?;
+        eprintln!(
+            "[ssr-sandbox] Wrote runtime profile to {} (omits: {})",
+            path,
+            match profile.omit.is_empty() {
+                true => "nothing".to_string(),
+                false => profile.omit.iter().cloned().collect::<Vec<_>>().join(", "),
+            }
+        );
+    }
+    costs.sort_by(|a, b| (b.compile_ms + b.eval_ms).total_cmp(&(a.compile_ms + a.eval_ms)));
+
+    if json {
+        println!("{}", serde_json::to_string(&costs)?);
+        return Ok(());
+    }
+    println!("{:>10}  {:>10}  {:>10}  MODULE", "SIZE", "COMPILE", "EVAL");
+    for cost in &costs {
+        println!(
+            "{:>10}  {:>8.1}ms  {:>8.1}ms  {}",
+            cost.size, cost.compile_ms, cost.eval_ms, cost.path
+        );
+    }
+    println!(
+        "{:>10}  {:>8.1}ms  {:>8.1}ms  total ({} modules)",
+        costs.iter().map(|cost| cost.size).sum::<u64>(),
+        costs.iter().map(|cost| cost.compile_ms).sum::<f64>(),
+        costs.iter().map(|cost| cost.eval_ms).sum::<f64>(),
+        costs.len()
+    );
+    Ok(())
+}
+
+/// Responses of proto-test mode, sent in turn (or only those named on the
+/// command line). Each one exercises a part of a host's client where framing
+/// bugs tend to hide.
+const PROTO_TEST_SCENARIOS: &[(&str, &str)] = &[
+    ("plain", "a small HTML body"),
+    (
+        "framing-lookalike",
+        "a body with Status:/Length: lines and blank lines in it",
+    ),
+    (
+        "unicode",
+        "multi-byte characters, CRLF and U+2028 (Length counts bytes)",
+    ),
+    ("empty", "an empty body"),
+    ("error", "an error response"),
+    (
+        "all-fields",
+        "every optional field: status, redirect, headers, head, warnings, islands",
+    ),
+    ("huge-body", "an 8MB body"),
+    ("slow-write", "a response written a few bytes at a time"),
+    (
+        "stderr-flood",
+        "256KB on stderr first (blocks unless the host drains stderr)",
+    ),
+];
+
+/// Run in proto-test mode: stand in for `--server`, reading requests like it
+/// does and answering them with [`PROTO_TEST_SCENARIOS`] instead of renders,
+/// so a host's client can be tested against every kind of response.
+fn run_proto_test(protocol: Protocol, only: &[String]) -> Result<()> {
+    let scenarios: Vec<&str> = match only {
+        [] => PROTO_TEST_SCENARIOS.iter().map(|(name, _)| *name).collect(),
+        names => names
+            .iter()
+            .map(|name| {
+                PROTO_TEST_SCENARIOS
+                    .iter()
+                    .find(|(scenario, _)| scenario == name)
+                    .map(|(scenario, _)| *scenario)
+                    .ok_or_else(|| anyhow!("Unknown proto-test scenario '{}'", name))
+            })
+            .collect::<Result<_>>()?,
+    };
+    eprintln!("[ssr-sandbox] State:alive");
+    eprintln!("[ssr-sandbox] State:ready");
+    eprintln!("[ssr-sandbox] Proto-test mode, reading from stdin...");
+
+    let mut reader = std::io::stdin().lock();
+    let mut stdout = std::io::stdout();
+    let (mut answered, mut invalid) = (0, 0);
+    while let Some(incoming) = read_request(protocol, &mut reader)? {
+        let (id, request_id, entry) = match incoming {
+            Incoming::Request(request) => {
+                // The server drops invalid ids rather than failing the render
+                let request_id = request.request_id.filter(|id| {
+                    let valid = is_valid_request_id(id);
+                    if !valid {
+                        eprintln!(
+                            "[ssr-sandbox] proto-test #{}: invalid Request-Id '{}'",
+                            answered + 1,
+                            id
+                        );
+                    }
+                    valid
+                });
+                (request.id, request_id, request.entry)
+            }
+            // Answered like the server does, so hosts see how their malformed requests fail
+            Incoming::Invalid { id, error } => {
+                invalid += 1;
+                answered += 1;
+                eprintln!(
+                    "[ssr-sandbox] proto-test #{}: invalid request: {}",
+                    answered, error
+                );
+                write_response(protocol, &mut stdout, &id, &Response::invalid_request(&error))?;
+                continue;
+            }
+        };
+        // Invalid requests don't take a turn, so every scenario gets one
+        let scenario = scenarios[(answered - invalid) % scenarios.len()];
+        answered += 1;
+        eprintln!(
+            "[ssr-sandbox] proto-test #{}: {} ({})",
+            answered, scenario, entry
+        );
+
+        let body = format!("<p>proto-test #{} {}</p>", answered, scenario);
+        let mut response = match scenario {
+            "framing-lookalike" => Response::ok(format!(
+                "Status:Ok\nLength:0\n\n{}\n\nStatus:Error\nLength:3\n\n",
+                body
+            )),
+            "unicode" => Response::ok(format!(
+                "{}\r\n<p>héllo wörld — 日本語 🎉\u{2028}</p>",
+                body
+            )),
+            "empty" => Response::ok(""),
+            "error" => Response::error(format!("proto-test #{}: simulated render error", answered)),
+            "all-fields" => Response {
+                status_code: Some(302),
+                redirect: Some("/login?next=%2F".to_string()),
+                headers: BTreeMap::from([
+                    ("cache-control".to_string(), "no-store".to_string()),
+                    ("x-proto-test".to_string(), answered.to_string()),
+                ]),
+                head: Some(
+                    "<title>proto-test</title>\n<meta name=\"robots\" content=\"noindex\">"
+                        .to_string(),
+                ),
+                warnings: vec![Warning {
+                    code: WarningCode::SlowFetch,
+                    message: "simulated warning\nspanning lines".to_string(),
+                }],
+                islands: vec![Island::new(
+                    "island.js",
+                    serde_json::json!({ "n": answered }),
+                )],
+                ..Response::ok(body)
+            },
+            "huge-body" => Response::ok(body.repeat(8 * 1024 * 1024 / body.len() + 1)),
+            _ => Response::ok(body),
+        };
+        response.request_id = request_id;
+
+        match scenario {
+            "slow-write" => {
+                let mut framed = Vec::new();
+                write_response(protocol, &mut framed, &id, &response)?;
+                // Odd-sized writes, so chunks split header lines and the body
+                for chunk in framed.chunks(7) {
+                    stdout.write_all(chunk)?;
+                    stdout.flush()?;
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            }
+            "stderr-flood" => {
+                let line = format!(
+                    "[ssr-sandbox] proto-test #{}: stderr flood {}\n",
+                    answered,
+                    "-".repeat(64)
+                );
+                std::io::stderr().write_all(line.repeat(256 * 1024 / line.len() + 1).as_bytes())?;
+                write_response(protocol, &mut stdout, &id, &response)?;
+            }
+            _ => write_response(protocol, &mut stdout, &id, &response)?,
+        }
+    }
+
+    eprintln!(
+        "[ssr-sandbox] proto-test: answered {} request(s), {} invalid",
+        answered, invalid
+    );
+    if answered - invalid < scenarios.len() {
+        eprintln!(
+            "[ssr-sandbox] proto-test: send at least {} valid requests to get every scenario",
+            scenarios.len()
+        );
+    }
+    Ok(())
+}
+
+/// Run in doctor mode: check everything a render depends on and print a
+/// PASS/FAIL line per check.
+///
+/// Checks the embedded snapshot, V8 initialization, that the chunks dir is
+/// readable, that `entry` (if given) resolves and evaluates, that every
+/// allowed fetch origin answers, and that the clocks are sane.
+async fn run_doctor(config: SandboxConfig, entry: Option<&str>) -> Result<()> {
+    // A fixed clock would fail the clock check, and deleted globals the
+    // globals check
+    let config = SandboxConfig {
+        deterministic: None,
+        runtime_profile: None,
+        ..config
+    };
+    let mut checks: Vec<(String, Result<String>)> = Vec::new();
+
+    checks.push((
+        "snapshot".to_string(),
+        verify_snapshot().map(|size| format!("{} bytes, hash matches the build", size)),
+    ));
+    checks.push((
+        "chunks-dir".to_string(),
+        check_chunks_dir(&config.chunks_dir),
+    ));
+
+    let runtime = create_runtime(&config)
+        .map_err(|e| anyhow!("{}", e))
+        .and_then(|mut runtime| {
+            runtime.execute_script(
+                "<ssr-doctor>",
+                "if (typeof fetch !== 'function' || typeof TextEncoder !== 'function') \
+                 throw new Error('runtime globals are missing');",
+            )?;
+            Ok(runtime)
+        });
+    let mut runtime = match runtime {
+        Ok(runtime) => {
+            checks.push((
+                "v8".to_string(),
+                Ok("runtime created from the snapshot".to_string()),
+            ));
+            Some(runtime)
+        }
+        Err(e) => {
+            checks.push(("v8".to_string(), Err(e)));
+            None
+        }
+    };
+
+    if let Some(entry) = entry {
+        let result = match runtime.as_mut() {
+            Some(runtime) => {
+                let entry_path = Path::new(&config.chunks_dir).join(entry);
+                preload_modules(runtime, &[entry_path])
+                    .await
+                    .map(|()| "loaded and evaluated".to_string())
+                    .map_err(Into::into)
+            }
+            None => Err(anyhow!("skipped, V8 failed to initialize")),
+        };
+        checks.push((format!("module {}", entry), result));
+    }
+
+    if config.allowed_origins.is_empty() {
+        checks.push(("fetch".to_string(), Ok("no allowed origins".to_string())));
+    }
+    for (origin, result) in warm_up_origins(&config).await {
+        let result = result.map(|elapsed| format!("connected in {}ms", elapsed.as_millis()));
+        checks.push((format!("fetch {}", origin), result));
+    }
+
+    checks.push(("clock".to_string(), check_clock(runtime.as_mut()).await));
+
+    let mut failed = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(detail) => println!("PASS  {:<24} {}", name, detail),
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {:<24} {}", name, e);
+            }
+        }
+    }
+    std::io::stdout().flush()?;
+    if failed > 0 {
+        eprintln!("[ssr-sandbox] {} of {} checks failed", failed, checks.len());
+        std::process::exit(1);
+    }
+    eprintln!("[ssr-sandbox] All {} checks passed", checks.len());
+    Ok(())
+}
+
+/// The chunks dir exists and its entries can be listed
+fn check_chunks_dir(chunks_dir: &str) -> Result<String> {
+    let entries = std::fs::read_dir(chunks_dir)
+        .map_err(|e| anyhow!("Cannot read '{}': {}", chunks_dir, e))?
+        .count();
+    Ok(format!("{} ({} entries)", chunks_dir, entries))
+}
+
+/// The wall clock is past a sane floor, the monotonic clock advances and the
+/// JS `Date.now()` agrees with the system clock
+async fn check_clock(runtime: Option<&mut SsrRuntime>) -> Result<String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|_| anyhow!("System time is before the Unix epoch"))?;
+    if now.as_secs() < DOCTOR_MIN_UNIX_TIME {
+        return Err(anyhow!(
+            "System time {}s is before 2024, the clock is not set",
+            now.as_secs()
+        ));
+    }
+
+    let started = Instant::now();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    if started.elapsed() < Duration::from_millis(10) {
+        return Err(anyhow!(
+            "Monotonic clock did not advance during a 10ms sleep"
+        ));
+    }
+
+    if let Some(runtime) = runtime {
+        let now_ms = now.as_millis() + started.elapsed().as_millis();
+        runtime.execute_script(
+            "<ssr-doctor>",
+            format!(
+                "if (Math.abs(Date.now() - {}) > {}) \
+                 throw new Error(`Date.now() is ${{Date.now() - {}}}ms off the system clock`);",
+                now_ms, DOCTOR_MAX_CLOCK_SKEW_MS, now_ms
+            ),
+        )?;
+    }
+    Ok(format!("{}s since the epoch", now.as_secs()))
+}
+
+/// Server mode state shared by all requests
+struct Server {
+    config: SandboxConfig,
+    router: TenantRouter,
+    /// Emergency lever for known-crashing pages, updated with @deny / @allow
+    denylist: Denylist,
+    violations: ViolationMonitor,
+    /// Add the request's CSP nonce to inline scripts/styles of the output
+    inject_nonce: bool,
+    islands: IslandStore,
+    /// JSONL record of every render, from --audit-log
+    audit_log: Option<AuditLog>,
+    /// Ready once the runtimes are warmed up, reported by @health and --health-addr
+    health: Health,
+}
+
+/// A render that passed the props checks and the denylist
+struct PreparedRender {
+    entry_path: PathBuf,
+    props: serde_json::Value,
+    options: ExecuteOptions,
+    label: RenderLabel,
+}
+
+/// What the failure log line and the audit record of a render show
+struct RenderLabel {
+    entry: String,
+    /// Props hash, for `@deny <entry> props=<hash>`
+    hash: String,
+    /// Props with `--redact` patterns applied
+    logged_props: serde_json::Value,
+    request_id: Option<String>,
+    /// When the render was prepared, for the audit log duration
+    started: Instant,
+}
+
+/// Most deferred islands server mode remembers for `@island <id>`
+const MAX_STORED_ISLANDS: usize = 10_000;
+
+/// Islands deferred by recent renders, so `@island <id>` can render them
+#[derive(Default)]
+struct IslandStore {
+    islands: HashMap<String, Island>,
+    /// Ids, oldest first
+    order: VecDeque<String>,
+}
+
+impl IslandStore {
+    fn remember(&mut self, islands: &[Island]) {
+        for island in islands {
+            if self.islands.contains_key(&island.id) {
+                continue;
+            }
+            if self.order.len() >= MAX_STORED_ISLANDS {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.islands.remove(&oldest);
+                }
+            }
+            self.order.push_back(island.id.clone());
+            self.islands.insert(island.id.clone(), island.clone());
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<&Island> {
+        self.islands.get(id)
+    }
+}
+
+impl Server {
+    async fn handle(&mut self, request: Request) -> Result<Response> {
+        // A deferred island renders its entry with the props it was deferred with
+        let request = match request.entry.strip_prefix("@island ").map(str::trim) {
+            Some(id) => match self.islands.get(id) {
+                Some(island) => Request {
+                    entry: island.entry.clone(),
+                    props: island.props.clone(),
+                    ..request
+                },
+                None => return Ok(Response::error(format!("Unknown island '{}'", id))),
+            },
+            None => request,
+        };
+        // Admin commands
+        if request.entry == "@fragments" {
+            return Ok(self.render_fragments(request).await);
+        }
+        if request.entry.starts_with('@') {
+            return self.admin_command(&request.entry).await;
+        }
+
+        let options = self.options(&request, request.export.clone());
+        let render = match self.prepare(request.entry, request.props, options) {
+            Ok(render) => render,
+            Err(message) => return Ok(Response::error(message)),
+        };
+        // Execute SSR (reuses the same runtime unless isolation is per-request,
+        // render functions are cached in JS)
+        let result = self
+            .router
+            .render(&render.entry_path, render.props, render.options)
+            .await;
+        Ok(self.finish(&render.label, result))
+    }
+
+    /// Render the fragments of a `@fragments` request, concurrently where they
+    /// belong to different tenants
+    async fn render_fragments(&mut self, request: Request) -> Response {
+        if request.fragments.is_empty() || request.fragments.len() > MAX_FRAGMENTS {
+            return Response::error(format!(
+                "A @fragments request needs 1-{} fragments",
+                MAX_FRAGMENTS
+            ));
+        }
+
+        let mut parts: Vec<Option<Response>> = vec![];
+        let mut renders = vec![];
+        let mut labels = vec![];
+        for fragment in &request.fragments {
+            let options = self.options(&request, fragment.export.clone());
+            match self.prepare(fragment.entry.clone(), fragment.props.clone(), options) {
+                Ok(render) => {
+                    renders.push((render.entry_path, render.props, render.options));
+                    labels.push(render.label);
+                    parts.push(None);
+                }
+                Err(message) => parts.push(Some(Response::error(message))),
+            }
+        }
+
+        let mut results = self.router.render_many(renders).await.into_iter().zip(labels);
+        let parts = parts
+            .into_iter()
+            .map(|part| match part {
+                Some(response) => response,
+                None => match results.next() {
+                    Some((result, label)) => self.finish(&label, result),
+                    None => Response::error("Fragment was not rendered"),
+                },
+            })
+            .collect();
+        Response {
+            parts,
+            ..Response::ok("")
+        }
+    }
+
+    /// Per-render options of a request
+    fn options(&self, request: &Request, export: Option<String>) -> ExecuteOptions {
+        ExecuteOptions {
+            timeout_ms: request.timeout_ms,
+            csp_nonce: request.csp_nonce.clone(),
+            inject_nonce: self.inject_nonce,
+            request_id: request.request_id.clone(),
+            host_feed: None,
+            export,
+            max_heap_size: request.max_heap_mb.map(|mb| mb.saturating_mul(1024 * 1024)),
+            request: request.request.clone(),
+        }
+    }
+
+    /// Transform and sanitize the props of a render and check it against the denylist.
+    ///
+    /// Returns the error message for the response if the render is refused.
+    fn prepare(
+        &self,
+        entry: String,
+        props: serde_json::Value,
+        options: ExecuteOptions,
+    ) -> std::result::Result<PreparedRender, String> {
+        let props = self
+            .config
+            .props_transformers
+            .apply(props)
+            .map_err(|e| e.to_string())?;
+
+        // Sanitize props to prevent prototype pollution
+        let props = match self.config.sanitize.sanitize(props) {
+            Ok(p) => p,
+            Err(e) => {
+                let error_msg = e.to_string();
+                if error_msg.starts_with("Prototype pollution attempt") {
+                    count_violation(ViolationKind::ForbiddenPropKey);
+                }
+                return Err(error_msg);
+            }
+        };
+
+        let hash = props_hash(&props);
+        if let Some(rule) = self.denylist.check(&entry, &props, &hash) {
+            return Err(format!("Render denied by denylist rule '{}'", rule));
+        }
+
+        // Build full entry path
+        let entry_path = Path::new(&self.config.chunks_dir).join(&entry);
+
+        // Props are moved into the render, keep a log-safe copy for errors
+        let label = RenderLabel {
+            logged_props: self.config.sanitize.redact(&props),
+            request_id: options.request_id.clone().filter(|id| is_valid_request_id(id)),
+            entry,
+            hash,
+            started: Instant::now(),
+        };
+        Ok(PreparedRender {
+            entry_path,
+            props,
+            options,
+            label,
+        })
+    }
+
+    /// Log the outcome of a render and turn it into its response
+    fn finish(&mut self, label: &RenderLabel, result: Result<SsrResult, SsrError>) -> Response {
+        self.audit(label, &result);
+        match result {
+            Ok(result) => {
+                // Log console output to stderr
+                print_console(&result.console, result.request_id.as_deref());
+                print_violations(&result.sandbox_violations, result.request_id.as_deref());
+                self.islands.remember(&result.islands);
+                Response::from(&result)
+            }
+            Err(e) => {
+                // The hash is what `@deny <entry> props=<hash>` needs to block this render
+                eprintln!(
+                    "[ssr-sandbox]{} Render of {} failed (props={}): {}",
+                    request_tag(label.request_id.as_deref()),
+                    label.entry,
+                    label.hash,
+                    label.logged_props
+                );
+                print_failed_render(&e);
+                Response::error(e.to_string())
+            }
+        }
+    }
+
+    /// Append the outcome of a render to the --audit-log
+    fn audit(&mut self, label: &RenderLabel, result: &Result<SsrResult, SsrError>) {
+        let Some(audit_log) = &mut self.audit_log else {
+            return;
+        };
+        let mut record = AuditRecord::new(&label.entry, &label.hash, label.started.elapsed());
+        record.request_id = label.request_id.clone();
+        match result {
+            Ok(result) => record.violations = result.sandbox_violations.clone(),
+            Err(e) => {
+                record.outcome = AuditOutcome::Error;
+                record.error = Some(e.to_string());
+            }
+        }
+        if let Err(e) = audit_log.record(&record) {
+            eprintln!("[ssr-sandbox] {}", e);
+        }
+    }
+
+    async fn admin_command(&mut self, command: &str) -> Result<Response> {
+        if command == "@config" {
+            return Ok(Response::ok(serde_json::to_string_pretty(&self.config.describe())?));
+        }
+        if command == "@stats" {
+            self.violations.observe();
+            return Ok(Response::ok(serde_json::to_string_pretty(&self.violations.describe(&self.config.entry_timeouts))?));
+        }
+        if command == "@health" {
+            return Ok(Response::ok(serde_json::to_string_pretty(&self.health.describe())?));
+        }
+        if command == "@denylist" {
+            return Ok(Response::ok(serde_json::to_string_pretty(self.denylist.rules())?));
+        }
+        if let Some(spec) = command.strip_prefix("@deny ") {
+            let rule = match DenyRule::parse(spec) {
+                Ok(rule) => rule,
+                Err(e) => return Ok(Response::error(e.to_string())),
+            };
+            if !self.denylist.deny(rule.clone()) {
+                return Ok(Response::ok(format!("Already denied: {}", rule)));
+            }
+            eprintln!("[ssr-sandbox] Denylist: added {}", rule);
+            return Ok(Response::ok(format!("Denied: {}", rule)));
+        }
+        if let Some(prefix) = command.strip_prefix("@unload ").map(str::trim) {
+            // Replaced by the refill after this response, like after a render
+            let dropped = match self.router.unload(prefix).await {
+                Ok(dropped) => dropped,
+                Err(e) => return Ok(Response::error(e.to_string())),
+            };
+            eprintln!(
+                "[ssr-sandbox] Unloaded modules below '{}': dropped {} runtime(s)",
+                prefix, dropped
+            );
+            return Ok(Response::ok(format!("Dropped {} runtime(s)", dropped)));
+        }
+        if let Some(spec) = command.strip_prefix("@allow ") {
+            let rule = match DenyRule::parse(spec) {
+                Ok(rule) => rule,
+                Err(e) => return Ok(Response::error(e.to_string())),
+            };
+            if !self.denylist.allow(&rule) {
+                return Ok(Response::error(format!("Not on the denylist: {}", rule)));
+            }
+            eprintln!("[ssr-sandbox] Denylist: removed {}", rule);
+            return Ok(Response::ok(format!("Allowed: {}", rule)));
+        }
+        // A known command in the wrong form, e.g. `@deny` without a rule
+        let name = command.split_whitespace().next().unwrap_or(command);
+        if ADMIN_COMMANDS.contains(&name) {
+            return Ok(Response::error(format!("Missing argument for {}", name)));
+        }
+        Ok(Response::unsupported_command(command))
+    }
+}
+
+/// How long the next line of a request may take to arrive (--line-timeout)
+const DEFAULT_LINE_TIMEOUT_MS: u64 = 10_000;
+
+/// Read requests from stdin on a task of their own, giving up on those whose
+/// next line takes longer than `line_timeout`
+fn read_requests(
+    protocol: Protocol,
+    line_timeout: Option<Duration>,
+) -> tokio::sync::mpsc::Receiver<Result<Option<Incoming>>> {
+    // A blocking thread feeds the reader; tokio's stdin would hold up the
+    // exit while it waits for input
+    let (chunks_tx, chunks) = tokio::sync::mpsc::channel(1);
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let chunk = match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => Ok(buf[..read].to_vec()),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let done = chunk.is_err();
+            if chunks_tx.blocking_send(chunk).is_err() || done {
+                break;
+            }
+        }
+    });
+
+    let (requests_tx, requests) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(async move {
+        let mut reader = tokio::io::BufReader::new(StdinChunks {
+            chunks,
+            chunk: Vec::new(),
+            pos: 0,
+        });
+        loop {
+            let incoming = read_request_async(protocol, &mut reader, line_timeout).await;
+            let done = !matches!(incoming, Ok(Some(_)));
+            if requests_tx.send(incoming).await.is_err() || done {
+                break;
+            }
+        }
+    });
+    requests
+}
+
+/// Stdin as read by the thread of [`read_requests`]
+struct StdinChunks {
+    chunks: tokio::sync::mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl tokio::io::AsyncRead for StdinChunks {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        if self.pos == self.chunk.len() {
+            match std::task::ready!(self.chunks.poll_recv(cx)) {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Some(Err(e)) => return std::task::Poll::Ready(Err(e)),
+                // End of input
+                None => return std::task::Poll::Ready(Ok(())),
+            }
+        }
+        let read = buf.remaining().min(self.chunk.len() - self.pos);
+        buf.put_slice(&self.chunk[self.pos..self.pos + read]);
+        self.pos += read;
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+/// Options of server mode that aren't part of the [`SandboxConfig`]
+struct ServerOptions {
+    protocol: Protocol,
+    line_timeout: Option<Duration>,
+    /// Reload changed files in the chunks dir (--watch)
+    watch: bool,
+    violation_alert: Option<u64>,
+    inject_nonce: bool,
+    audit_log: Option<AuditLog>,
+    health_addr: Option<String>,
+}
+
+/// Run in server mode (persistent process, reads requests from stdin)
+async fn run_server(config: SandboxConfig, options: ServerOptions) -> Result<()> {
+    let ServerOptions {
+        protocol,
+        line_timeout,
+        watch,
+        violation_alert,
+        inject_nonce,
+        audit_log,
+        health_addr,
+    } = options;
+    // Alive from here on, ready once the runtimes are warmed up
+    let health = Health::new();
+    eprintln!("[ssr-sandbox] State:alive");
+    if let Some(addr) = health_addr {
+        let addr = serve_health(&addr, health.clone()).await?;
+        eprintln!("[ssr-sandbox] Health endpoints listening on {}", addr);
+    }
+    let mut shutdown = shutdown_signal()?;
+
+    // Create runtime(s) ONCE at startup (V8 cold start happens here)
+    let mut server = Server {
+        router: TenantRouter::new(config.clone())?,
+        config,
+        denylist: Denylist::default(),
+        violations: ViolationMonitor::new(violation_alert),
+        inject_nonce,
+        islands: IslandStore::default(),
+        audit_log,
+        health,
+    };
+    if let Some(budget) = server.config.memory_budget {
+        for pool in server.config.memory_reservations() {
+            let name = match pool.tenant.as_str() {
+                "" => "the default pool",
+                tenant => tenant,
+            };
+            eprintln!(
+                "[ssr-sandbox] Memory budget {}MB: {} isolate(s) of {}MB for {}",
+                budget / (1024 * 1024),
+                pool.isolates,
+                pool.max_heap_size.unwrap_or_default() / (1024 * 1024),
+                name
+            );
+        }
+    }
+    // Load --warmup entries before signaling ready, so no request pays for compiling them
+    server.router.warm_up().await?;
+    // Connect to the fetch origins too, so the first fetches skip DNS, TCP and TLS setup
+    for (origin, result) in warm_up_origins(&server.config).await {
+        match result {
+            Ok(elapsed) => eprintln!("[ssr-sandbox] Connected to {} in {}ms", origin, elapsed.as_millis()),
+            Err(e) => eprintln!("[ssr-sandbox] Could not connect to {}: {}", origin, e),
+        }
+    }
+
+    // Fill the caches of the --refresh routes before the first request too
+    refresh_routes(&mut server).await?;
+
+    // Watched from here on, so changes made during startup don't reload it again
+    let mut watcher = None;
+    if watch {
+        let dir = &server.config.chunks_dir;
+        watcher = Some(ChunksWatcher::new(dir, DEFAULT_WATCH_DEBOUNCE)?);
+        eprintln!("[ssr-sandbox] Watching {} for changes", dir);
+    }
+
+    // Requests are read on their own task, so refreshes can run while stdin is idle
+    let mut requests = read_requests(protocol, line_timeout);
+    let mut stdout = std::io::stdout();
+
+    // Signal ready
+    server.health.set_ready(true);
+    eprintln!("[ssr-sandbox] State:ready");
+    eprintln!("[ssr-sandbox] Server ready, reading from stdin...");
+
+    loop {
+        let due = server.router.next_refresh();
+        // A signal is only noticed between requests, so the render in progress
+        // finishes (or times out) and its response is written in full
+        let incoming = tokio::select! {
+            biased;
+            signal = &mut shutdown => {
+                eprintln!("[ssr-sandbox] Got {}, shutting down", signal.unwrap_or("a signal"));
+                // Requests already read are refused rather than left unanswered
+                server.health.set_ready(false);
+                while let Ok(Ok(Some(incoming))) = requests.try_recv() {
+                    let id = match incoming {
+                        Incoming::Request(request) => request.id,
+                        Incoming::Invalid { id, .. } => id,
+                    };
+                    write_response(protocol, &mut stdout, &id, &Response::shutting_down())?;
+                }
+                break;
+            }
+            // Before requests, so a request sent after a rebuild gets the new code
+            changed = next_change(watcher.as_mut()) => {
+                reload_changed(&mut server, changed).await?;
+                continue;
+            }
+            incoming = requests.recv() => incoming,
+            _ = sleep_until(due) => {
+                refresh_routes(&mut server).await?;
+                continue;
+            }
+        };
+        // None at EOF - stdin closed, exit gracefully
+        let Some(incoming) = incoming.transpose()?.flatten() else {
+            break;
+        };
+        let (id, response) = match incoming {
+            Incoming::Request(request) => {
+                // Echoed on every response to the request, including errors
+                let id = request.id.clone();
+                let request_id = request.request_id.clone().filter(|id| is_valid_request_id(id));
+                let response = server.handle(request).await?;
+                (id, Response { request_id, ..response })
+            }
+            Incoming::Invalid { id, error } => (id, Response::invalid_request(&error)),
+        };
+        write_response(protocol, &mut stdout, &id, &response)?;
+
+        report_violation_spike(&mut server.violations);
+
+        // Replace used or broken runtimes now that the response is out
+        server.router.refill()?;
+        if let Err(e) = server.router.warm_up().await {
+            eprintln!("[ssr-sandbox] Warmup failed: {}", e);
+        }
+    }
+
+    server.health.set_ready(false);
+    eprintln!("[ssr-sandbox] Server shutting down");
+    Ok(())
+}
+
+/// Render the --refresh routes that are due, then replace the runtimes they used
+async fn refresh_routes(server: &mut Server) -> Result<()> {
+    for (entry, result) in server.router.refresh_due().await {
+        if let Err(e) = result {
+            eprintln!("[ssr-sandbox] Refresh of {} failed: {}", entry, e);
+        }
+    }
+    server.router.refill()?;
+    if let Err(e) = server.router.warm_up().await {
+        eprintln!("[ssr-sandbox] Warmup failed: {}", e);
+    }
+    Ok(())
+}
+
+/// Next batch of changed files in the chunks dir, never without --watch
+async fn next_change(watcher: Option<&mut ChunksWatcher>) -> BTreeSet<PathBuf> {
+    match watcher {
+        Some(watcher) => watcher.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Unload the files --watch saw change, so the next render loads them from
+/// disk again, and refill the pools (and refresh caches) right away
+async fn reload_changed(server: &mut Server, changed: BTreeSet<PathBuf>) -> Result<()> {
+    // Runtimes read the import map when they are created, so a new map needs
+    // all of them replaced
+    let chunks_dir = Path::new(&server.config.chunks_dir).canonicalize()?;
+    let import_map = server
+        .config
+        .import_map
+        .as_ref()
+        .and_then(|path| Path::new(path).canonicalize().ok());
+    let prefixes = match import_map {
+        Some(map) if changed.iter().any(|path| chunks_dir.join(path) == map) => {
+            vec![PathBuf::new()]
+        }
+        _ => changed.iter().cloned().collect(),
+    };
+    let mut dropped = 0;
+    for prefix in &prefixes {
+        dropped += server.router.unload(prefix).await?;
+    }
+    let files = changed
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>();
+    eprintln!(
+        "[ssr-sandbox] Changed: {} (dropped {} runtime(s))",
+        files.join(", "),
+        dropped
+    );
+    refresh_routes(server).await
+}
+
+/// Renderers that exit sooner than this after starting are restarted after a delay
+const MIN_RENDERER_UPTIME: Duration = Duration::from_secs(10);
+/// Longest delay before restarting a renderer that keeps crashing
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+/// How long renderers get to exit once stdin is closed
+const SUPERVISOR_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a renderer process of supervisor mode reports
+enum RendererEvent {
+    /// A response line on its stdout
+    Response {
+        index: usize,
+        generation: u64,
+        line: String,
+    },
+    /// It printed `State:ready`
+    Ready { index: usize, generation: u64 },
+    /// Its stdout closed and it exited
+    Exited {
+        index: usize,
+        generation: u64,
+        status: String,
+    },
+}
+
+/// A request for one renderer, in the json protocol
+struct RendererJob {
+    /// Client request it answers (None for replayed admin commands)
+    seq: Option<u64>,
+    request: serde_json::Value,
+}
+
+/// One renderer process of supervisor mode
+#[derive(Default)]
+struct Renderer {
+    /// Bumped on every restart, so events of the previous process are ignored
+    generation: u64,
+    /// None while the process is down
+    stdin: Option<tokio::process::ChildStdin>,
+    ready: bool,
+    in_flight: Option<RendererJob>,
+    /// Admin commands for this renderer, run before any queued render
+    pinned: VecDeque<RendererJob>,
+    started: Option<Instant>,
+    /// Restarts in a row after a short uptime
+    crashes: u32,
+    restart_at: Option<Instant>,
+}
+
+/// A client request waiting for its response
+struct PendingReply {
+    /// Echoed in the response (json protocol)
+    id: serde_json::Value,
+    /// Renderers that still have to answer
+    replies: usize,
+    response: Option<Response>,
+    /// Why a renderer couldn't answer, sent if none did
+    error: Option<String>,
+}
+
+/// Supervisor mode: renderer child processes (`--server --protocol json`)
+/// behind one stdin/stdout, so a crash only fails the request it happened in
+struct Supervisor {
+    program: PathBuf,
+    /// Arguments of each renderer
+    args: Vec<Vec<String>>,
+    protocol: Protocol,
+    health: Health,
+    renderers: Vec<Renderer>,
+    /// Renders waiting for an idle renderer
+    queue: VecDeque<RendererJob>,
+    /// By request order; the line protocol answers in that order
+    pending: BTreeMap<u64, PendingReply>,
+    next_seq: u64,
+    /// `@deny`/`@allow` commands so far, replayed to restarted renderers
+    replay: Vec<serde_json::Value>,
+    /// Islands deferred by any renderer, so `@island <id>` can go to any renderer
+    islands: IslandStore,
+    /// Set once a renderer got ready; before that, a renderer exiting is a startup failure
+    serving: bool,
+    events_tx: tokio::sync::mpsc::UnboundedSender<RendererEvent>,
+}
+
+impl Supervisor {
+    /// Start renderer `index`, with the `@deny`/`@allow` history queued first
+    fn spawn(&mut self, index: usize) -> Result<()> {
+        let mut command = std::process::Command::new(&self.program);
+        command
+            .args(&self.args[index])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        // A Ctrl-C in the terminal reaches the supervisor only, which drains them
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = tokio::process::Command::from(command)
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("Failed to start renderer {}: {}", index + 1, e))?;
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            return Err(anyhow!("Renderer {} has no stdio pipes", index + 1));
+        };
+        eprintln!(
+            "[ssr-sandbox] Started renderer {} (pid {})",
+            index + 1,
+            child.id().unwrap_or_default()
+        );
+
+        let renderer = &mut self.renderers[index];
+        renderer.generation += 1;
+        renderer.stdin = Some(stdin);
+        renderer.ready = false;
+        renderer.started = Some(Instant::now());
+        renderer.restart_at = None;
+        renderer.pinned = self
+            .replay
+            .iter()
+            .map(|request| RendererJob {
+                seq: None,
+                request: request.clone(),
+            })
+            .collect();
+        let generation = renderer.generation;
+
+        // Responses, then the exit status once stdout closes
+        let events = self.events_tx.clone();
+        tokio::spawn(async move {
+            use tokio::io::AsyncBufReadExt;
+            let mut lines = tokio::io::BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let event = RendererEvent::Response {
+                    index,
+                    generation,
+                    line,
+                };
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+            let status = match child.wait().await {
+                Ok(status) => status.to_string(),
+                Err(e) => e.to_string(),
+            };
+            let _ = events.send(RendererEvent::Exited {
+                index,
+                generation,
+                status,
+            });
+        });
+        // Logs are passed through, tagged with the renderer
+        let events = self.events_tx.clone();
+        tokio::spawn(async move {
+            use tokio::io::AsyncBufReadExt;
+            let mut lines = tokio::io::BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                eprintln!("[renderer {}] {}", index + 1, line);
+                if line == "[ssr-sandbox] State:ready" {
+                    let _ = events.send(RendererEvent::Ready { index, generation });
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Queue a client request, or answer it right away if it can't be sent
+    fn submit(&mut self, incoming: Incoming) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let (id, request) = match incoming {
+            Incoming::Request(request) => (request.id.clone(), request),
+            Incoming::Invalid { id, error } => {
+                return self.answer(seq, id, Response::invalid_request(&error))
+            }
+        };
+        // Renderers don't share islands, so the island is sent as a plain render
+        let request = match request.entry.strip_prefix("@island ").map(str::trim) {
+            Some(island_id) => match self.islands.get(island_id) {
+                Some(island) => Request {
+                    entry: island.entry.clone(),
+                    props: island.props.clone(),
+                    ..request
+                },
+                None => {
+                    let message = format!("Unknown island '{}'", island_id);
+                    return self.answer(seq, id, Response::error(message));
+                }
+            },
+            None => request,
+        };
+        let mut value = match serde_json::to_value(&request) {
+            Ok(value) => value,
+            Err(e) => return self.answer(seq, id, Response::error(e.to_string())),
+        };
+        value["id"] = serde_json::json!(seq);
+
+        if request.entry == "@fragments" || !request.entry.starts_with('@') {
+            self.queue.push_back(RendererJob {
+                seq: Some(seq),
+                request: value,
+            });
+            self.pending.insert(seq, PendingReply::new(id, 1));
+            return;
+        }
+
+        // Admin commands go to every running renderer and the first reply answers;
+        // renderers that are down get the `@deny`/`@allow` history when they restart
+        if request.entry.starts_with("@deny ") || request.entry.starts_with("@allow ") {
+            self.replay.push(value.clone());
+        }
+        let mut replies = 0;
+        for renderer in &mut self.renderers {
+            if renderer.stdin.is_some() {
+                replies += 1;
+                renderer.pinned.push_back(RendererJob {
+                    seq: Some(seq),
+                    request: value.clone(),
+                });
+            }
+        }
+        if replies == 0 {
+            let message = "No renderer is running";
+            return self.answer(seq, id, Response::error(message));
+        }
+        self.pending.insert(seq, PendingReply::new(id, replies));
+    }
+
+    /// Answer a request read after shutdown began, in order
+    fn refuse(&mut self, incoming: Incoming) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let id = match incoming {
+            Incoming::Request(request) => request.id,
+            Incoming::Invalid { id, .. } => id,
+        };
+        self.answer(seq, id, Response::shutting_down());
+    }
+
+    /// A request answered without a renderer
+    fn answer(&mut self, seq: u64, id: serde_json::Value, response: Response) {
+        let mut pending = PendingReply::new(id, 0);
+        pending.response = Some(response);
+        self.pending.insert(seq, pending);
+    }
+
+    /// Send the next job to every idle ready renderer
+    async fn dispatch(&mut self) {
+        use tokio::io::AsyncWriteExt;
+        for index in 0..self.renderers.len() {
+            let renderer = &mut self.renderers[index];
+            if !renderer.ready || renderer.in_flight.is_some() {
+                continue;
+            }
+            let Some(job) = renderer
+                .pinned
+                .pop_front()
+                .or_else(|| self.queue.pop_front())
+            else {
+                continue;
+            };
+            let Some(stdin) = renderer.stdin.as_mut() else {
+                continue;
+            };
+            let line = format!("{}\n", job.request);
+            renderer.in_flight = Some(job);
+            // A renderer that died is noticed when its stdout closes
+            if let Err(e) = stdin.write_all(line.as_bytes()).await {
+                eprintln!(
+                    "[ssr-sandbox] Failed to send a request to renderer {}: {}",
+                    index + 1,
+                    e
+                );
+                renderer.ready = false;
+            }
+        }
+    }
+
+    fn on_event(&mut self, event: RendererEvent) -> Result<()> {
+        match event {
+            RendererEvent::Response {
+                index,
+                generation,
+                line,
+            } => {
+                let renderer = &mut self.renderers[index];
+                if renderer.generation != generation {
+                    return Ok(());
+                }
+                let Some(job) = renderer.in_flight.take() else {
+                    return Ok(());
+                };
+                let response = serde_json::from_str::<Response>(&line).unwrap_or_else(|e| {
+                    Response::error(format!(
+                        "Invalid response from renderer {}: {}",
+                        index + 1,
+                        e
+                    ))
+                });
+                if let Some(seq) = job.seq {
+                    self.islands.remember(&response.islands);
+                    self.reply(seq, Ok(response));
+                }
+            }
+            RendererEvent::Ready { index, generation } => {
+                let renderer = &mut self.renderers[index];
+                if renderer.generation != generation {
+                    return Ok(());
+                }
+                renderer.ready = true;
+                self.serving = true;
+                eprintln!("[ssr-sandbox] Renderer {} ready", index + 1);
+                if !self.health.is_ready() {
+                    self.health.set_ready(true);
+                    eprintln!("[ssr-sandbox] State:ready");
+                }
+            }
+            RendererEvent::Exited {
+                index,
+                generation,
+                status,
+            } => {
+                let renderer = &mut self.renderers[index];
+                if renderer.generation != generation {
+                    return Ok(());
+                }
+                if !self.serving {
+                    return Err(anyhow!(
+                        "Renderer {} exited during startup ({})",
+                        index + 1,
+                        status
+                    ));
+                }
+                eprintln!("[ssr-sandbox] Renderer {} exited ({})", index + 1, status);
+                renderer.stdin = None;
+                renderer.ready = false;
+                let lost: Vec<u64> = renderer
+                    .in_flight
+                    .take()
+                    .into_iter()
+                    .chain(renderer.pinned.drain(..))
+                    .filter_map(|job| job.seq)
+                    .collect();
+
+                // Restart right away, unless it keeps dying soon after starting
+                let uptime = renderer
+                    .started
+                    .map_or(Duration::ZERO, |started| started.elapsed());
+                let delay = if uptime < MIN_RENDERER_UPTIME {
+                    renderer.crashes += 1;
+                    Duration::from_secs(1u64 << (renderer.crashes - 1).min(5))
+                        .min(MAX_RESTART_DELAY)
+                } else {
+                    renderer.crashes = 0;
+                    Duration::ZERO
+                };
+                renderer.restart_at = Some(Instant::now() + delay);
+                if delay > Duration::ZERO {
+                    eprintln!(
+                        "[ssr-sandbox] Restarting renderer {} in {}s",
+                        index + 1,
+                        delay.as_secs()
+                    );
+                }
+
+                let message = format!(
+                    "Renderer {} exited during the request ({})",
+                    index + 1,
+                    status
+                );
+                for seq in lost {
+                    self.reply(seq, Err(message.clone()));
+                }
+                let ready = self.renderers.iter().any(|renderer| renderer.ready);
+                self.health.set_ready(ready);
+            }
+        }
+        Ok(())
+    }
+
+    /// One renderer answered (or failed to answer) request `seq`
+    fn reply(&mut self, seq: u64, result: Result<Response, String>) {
+        let Some(pending) = self.pending.get_mut(&seq) else {
+            return;
+        };
+        pending.replies = pending.replies.saturating_sub(1);
+        match result {
+            Ok(response) => {
+                pending.response.get_or_insert(response);
+            }
+            Err(message) => {
+                pending.error.get_or_insert(message);
+            }
+        }
+    }
+
+    /// Write the responses that are complete (in request order for the line protocol)
+    fn flush(&mut self, out: &mut impl Write) -> Result<()> {
+        let done: Vec<u64> = match self.protocol {
+            Protocol::Line => self
+                .pending
+                .iter()
+                .take_while(|(_, pending)| pending.replies == 0)
+                .map(|(seq, _)| *seq)
+                .collect(),
+            Protocol::Json => self
+                .pending
+                .iter()
+                .filter(|(_, pending)| pending.replies == 0)
+                .map(|(seq, _)| *seq)
+                .collect(),
+        };
+        for seq in done {
+            let Some(pending) = self.pending.remove(&seq) else {
+                continue;
+            };
+            let response = pending.response.unwrap_or_else(|| {
+                Response::error(pending.error.unwrap_or_else(|| "No response".to_string()))
+            });
+            write_response(self.protocol, out, &pending.id, &response)?;
+        }
+        Ok(())
+    }
+
+    /// When the next renderer is due to be restarted
+    fn next_restart(&self) -> Option<Instant> {
+        self.renderers
+            .iter()
+            .filter_map(|renderer| renderer.restart_at)
+            .min()
+    }
+
+    fn restart_due(&mut self) {
+        let now = Instant::now();
+        for index in 0..self.renderers.len() {
+            if self.renderers[index].restart_at.is_some_and(|at| at <= now) {
+                if let Err(e) = self.spawn(index) {
+                    eprintln!("[ssr-sandbox] {}", e);
+                    self.renderers[index].restart_at = Some(now + MAX_RESTART_DELAY);
+                }
+            }
+        }
+    }
+}
+
+impl PendingReply {
+    fn new(id: serde_json::Value, replies: usize) -> Self {
+        Self {
+            id,
+            replies,
+            response: None,
+            error: None,
+        }
+    }
+}
+
+/// Run in supervisor mode: start one renderer process per `args` entry, send
+/// each request to an idle one and restart renderers that crash
+async fn run_supervisor(
+    program: PathBuf,
+    args: Vec<Vec<String>>,
+    protocol: Protocol,
+    line_timeout: Option<Duration>,
+    health_addr: Option<String>,
+) -> Result<()> {
+    let health = Health::new();
+    eprintln!("[ssr-sandbox] State:alive");
+    if let Some(addr) = health_addr {
+        let addr = serve_health(&addr, health.clone()).await?;
+        eprintln!("[ssr-sandbox] Health endpoints listening on {}", addr);
+    }
+
+    let mut shutdown = shutdown_signal()?;
+
+    let (events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+    let mut supervisor = Supervisor {
+        program,
+        renderers: args.iter().map(|_| Renderer::default()).collect(),
+        args,
+        protocol,
+        health,
+        queue: VecDeque::new(),
+        pending: BTreeMap::new(),
+        next_seq: 0,
+        replay: Vec::new(),
+        islands: IslandStore::default(),
+        serving: false,
+        events_tx,
+    };
+    for index in 0..supervisor.renderers.len() {
+        supervisor.spawn(index)?;
+    }
+
+    let mut requests = read_requests(protocol, line_timeout);
+    let mut stdout = std::io::stdout();
+
+    let mut input_open = true;
+    while input_open || !supervisor.pending.is_empty() {
+        let restart = supervisor.next_restart();
+        tokio::select! {
+            incoming = requests.recv(), if input_open => {
+                // None at EOF - stdin closed, finish the pending requests and exit
+                match incoming.transpose()?.flatten() {
+                    Some(incoming) => supervisor.submit(incoming),
+                    None => input_open = false,
+                }
+            }
+            Some(event) = events.recv() => supervisor.on_event(event)?,
+            _ = sleep_until(restart), if restart.is_some() => supervisor.restart_due(),
+            // Like the end of input: the accepted requests are finished first
+            signal = &mut shutdown, if input_open => {
+                eprintln!("[ssr-sandbox] Got {}, shutting down", signal.unwrap_or("a signal"));
+                input_open = false;
+                while let Ok(Ok(Some(incoming))) = requests.try_recv() {
+                    supervisor.refuse(incoming);
+                }
+            }
+        }
+        supervisor.dispatch().await;
+        supervisor.flush(&mut stdout)?;
+    }
+
+    // Renderers exit once their stdin closes
+    eprintln!("[ssr-sandbox] Server shutting down");
+    supervisor.health.set_ready(false);
+    let mut running = 0;
+    for renderer in &mut supervisor.renderers {
+        running += usize::from(renderer.stdin.take().is_some());
+    }
+    let deadline = tokio::time::Instant::now() + SUPERVISOR_SHUTDOWN_TIMEOUT;
+    while running > 0 {
+        match tokio::time::timeout_at(deadline, events.recv()).await {
+            Ok(Some(RendererEvent::Exited { .. })) => running -= 1,
+            Ok(Some(_)) => {}
+            // Renderers still running are killed when dropped
+            Ok(None) | Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+/// Resolves with the name of the first SIGTERM or SIGINT (Ctrl-C elsewhere)
+///
+/// Listening replaces their default action, so the process isn't killed in
+/// the middle of writing a response; a second signal exits right away.
+fn shutdown_signal() -> Result<tokio::sync::oneshot::Receiver<&'static str>> {
+    let (signalled, signal) = tokio::sync::oneshot::channel();
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        tokio::spawn(async move {
+            let name = tokio::select! {
+                _ = terminate.recv() => "SIGTERM",
+                _ = interrupt.recv() => "SIGINT",
+            };
+            let _ = signalled.send(name);
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
+            eprintln!("[ssr-sandbox] Got a second signal, exiting now");
+            std::process::exit(1);
+        });
+    }
+    #[cfg(not(unix))]
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            // Keep the sender, so the receiver never resolves
+            return std::future::pending::<()>().await;
+        }
+        let _ = signalled.send("Ctrl-C");
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("[ssr-sandbox] Got a second signal, exiting now");
+            std::process::exit(1);
+        }
+    });
+    Ok(signal)
+}
+
+/// Sleep until `at`, or forever without it
+async fn sleep_until(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Print an alert to stderr when sandbox violations spike
+fn report_violation_spike(violations: &mut ViolationMonitor) {
+    if let Some(alert) = violations.observe() {
+        eprintln!("[ALERT] {}", alert);
+    }
+}
+
+/// Print captured console output to stderr
+fn print_console(console: &ConsoleOutput, request_id: Option<&str>) {
+    let tag = request_tag(request_id);
+    for entry in &console.entries {
+        eprintln!("{}{} {}", entry.level.tag(), tag, entry.message);
+    }
+    if console.dropped > 0 {
+        eprintln!(
+            "[ssr-sandbox]{} {} console entries dropped (per-render cap reached)",
+            tag, console.dropped
+        );
+    }
+}
+
+/// Print the console output and JS stack trace of a failed render to stderr
+fn print_failed_render(e: &SsrError) {
+    print_console(&e.console, e.request_id.as_deref());
+    if let Some(stack) = &e.stack {
+        eprintln!("[STACK]{} {}", request_tag(e.request_id.as_deref()), stack);
+    }
+}
+
+/// Print runtime warnings of a single-shot render to stderr (server mode sends
+/// them in the response instead)
+fn print_warnings(warnings: &[Warning]) {
+    for warning in warnings {
+        eprintln!("[WARNING] {}", warning);
+    }
+}
+
+/// Print sandbox violations recorded during a render to stderr
+fn print_violations(violations: &[SandboxViolation], request_id: Option<&str>) {
+    let tag = request_tag(request_id);
+    for violation in violations {
+        eprintln!("[VIOLATION]{} {}", tag, violation);
+    }
+}
+
+#[cfg(not(feature = "crash-dumps"))]
+fn install_crash_dumps(_dir: String, _config: &SandboxConfig) -> Result<()> {
+    Err(anyhow!(
+        "--crash-dump-dir: ssr-sandbox was built without the crash-dumps feature"
+    ))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+
+    // Before any runtime, so the V8 setup isn't part of the first render
+    ssr_sandbox_core::init();
+
+    // Installed first, so runtimes created from here on report fatal OOMs
+    if let Some(dir) = parse_option::<String>(&args, "--crash-report-dir")? {
+        install_crash_hook(write_crash_report(dir))?;
+    }
+
+    // Parse options before filtering
+    let config = build_config(&args)?;
+    if let Some(dir) = parse_option::<String>(&args, "--crash-dump-dir")? {
+        install_crash_dumps(dir, &config)?;
+    }
+    let stream = args.iter().any(|arg| arg == "--stream");
+    let json = args.iter().any(|arg| arg == "--json");
+    let violation_alert = parse_option(&args, "--violation-alert")?;
+    let inject_nonce = args.iter().any(|arg| arg == "--inject-nonce");
+    let write_profile = parse_option::<String>(&args, "--write-profile")?;
+    let protocol = match parse_option::<String>(&args, "--protocol")? {
+        Some(protocol) => protocol.parse()?,
+        None => Protocol::default(),
+    };
+    // 0 turns it off
+    let line_timeout = parse_option(&args, "--line-timeout")?
+        .or(Some(DEFAULT_LINE_TIMEOUT_MS))
+        .filter(|&ms| ms > 0)
+        .map(Duration::from_millis);
+
+    // Filter out options to get positional args
+    let options = args;
+    let args = filter_options(&options);
+
+    if args.len() < 2 {
+        print_usage();
+        return Err(anyhow!("Missing required arguments"));
+    }
+
+    // Check for server mode
+    if args[1] == "--server" {
+        if args.len() < 3 {
+            print_usage();
+            return Err(anyhow!("Server mode requires chunks-dir argument"));
+        }
+        let config = SandboxConfig {
+            chunks_dir: args[2].clone(),
+            ..config
+        };
+        let health_addr = parse_option(&options, "--health-addr")?;
+        if let Some(count) = parse_option::<usize>(&options, "--supervise")? {
+            if count == 0 {
+                return Err(anyhow!("--supervise needs at least one renderer"));
+            }
+            let args = (1..=count)
+                .map(|n| renderer_args(&options, n, count))
+                .collect();
+            let program = std::env::current_exe()?;
+            return run_supervisor(program, args, protocol, line_timeout, health_addr).await;
+        }
+        let audit_log = parse_audit_log(&options)?;
+        let options = ServerOptions {
+            protocol,
+            line_timeout,
+            watch: options.iter().any(|arg| arg == "--watch"),
+            violation_alert,
+            inject_nonce,
+            audit_log,
+            health_addr,
+        };
+        return run_server(config, options).await;
+    }
+
+    // Diff mode
+    if args[1] == "diff" {
+        if args.len() < 5 {
+            print_usage();
+            return Err(anyhow!("Diff mode requires <chunks-old> <chunks-new> <entry>"));
+        }
+        let props_json = args.get(5).map(|s| s.as_str());
+        return run_diff(config, &args[2], &args[3], &args[4], props_json).await;
+    }
+
+    // Hydration check mode
+    if args[1] == "hydration-check" {
+        if args.len() < 4 {
+            print_usage();
+            return Err(anyhow!("Hydration check mode requires <chunks-dir> <entry>"));
+        }
+        let props_json = args.get(4).map(|s| s.as_str());
+        let config = SandboxConfig {
+            chunks_dir: args[2].clone(),
+            ..config
+        };
+        return run_hydration_check(config, &args[3], props_json).await;
+    }
+
+    // Proto-test mode
+    if args[1] == "proto-test" {
+        return run_proto_test(protocol, &args[2..]);
+    }
+
+    // Doctor mode
+    if args[1] == "doctor" {
+        if args.len() < 3 {
+            print_usage();
+            return Err(anyhow!("Doctor mode requires <chunks-dir>"));
+        }
+        let config = SandboxConfig {
+            chunks_dir: args[2].clone(),
+            ..config
+        };
+        return run_doctor(config, args.get(3).map(|s| s.as_str())).await;
+    }
+
+    // Analyze mode
+    if args[1] == "analyze" {
+        if args.len() < 4 {
+            print_usage();
+            return Err(anyhow!("Analyze mode requires <chunks-dir> <entry>"));
+        }
+        let config = SandboxConfig {
+            chunks_dir: args[2].clone(),
+            ..config
+        };
+        return run_analyze(config, &args[3], json, write_profile.as_deref()).await;
+    }
+
+    // Single-shot mode
+    if args.len() < 3 {
+        print_usage();
+        return Err(anyhow!("Missing required arguments"));
+    }
+
+    let chunks_dir = &args[1];
+    let entry_point = &args[2];
+    let props_json = args.get(3).map(|s| s.as_str());
+
+    let config = SandboxConfig {
+        chunks_dir: chunks_dir.clone(),
+        ..config
+    };
+    run_single_shot(config, entry_point, props_json, stream, json).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_tls_policies() {
+        let policies = parse_tls_policies(&args(&[
+            "ssr-sandbox",
+            "--tls13",
+            "https://api.example.com",
+            "--pin-spki",
+            "https://api.example.com=hash1",
+            "--pin-spki",
+            "https://api.example.com=hash2",
+            "--self-signed-cert",
+            "https://localhost:8443=./dev-cert.pem",
+        ]))
+        .unwrap();
+        assert_eq!(policies.len(), 2);
+        let api = &policies["https://api.example.com"];
+        assert_eq!(api.min_version, Some(TlsVersion::Tls13));
+        assert_eq!(api.spki_sha256, ["hash1", "hash2"]);
+        assert_eq!(api.self_signed_cert, None);
+        let local = &policies["https://localhost:8443"];
+        assert_eq!(local.min_version, None);
+        assert_eq!(local.self_signed_cert.as_deref(), Some("./dev-cert.pem"));
+
+        let err = parse_tls_policies(&args(&["ssr-sandbox", "--pin-spki", "hash1"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--pin-spki expects <origin>=<value>, got 'hash1'"
+        );
+    }
+
+    #[test]
+    fn test_parse_tenant_limits() {
+        let tenants = parse_tenant_limits(&args(&[
+            "ssr-sandbox",
+            "--tenant-heap-size",
+            "acme=64",
+            "--tenant-timeout",
+            "acme=500",
+            "--tenant-timeout",
+            "globex=2000",
+        ]))
+        .unwrap();
+        assert_eq!(tenants.len(), 2);
+        assert_eq!(tenants["acme"].max_heap_size, Some(64 * 1024 * 1024));
+        assert_eq!(tenants["acme"].timeout_ms, Some(500));
+        assert_eq!(tenants["globex"].max_heap_size, None);
+        assert_eq!(tenants["globex"].timeout_ms, Some(2000));
+
+        let err =
+            parse_tenant_limits(&args(&["ssr-sandbox", "--tenant-timeout", "500"])).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expects <tenant>=<value>, got '500'"));
+        let err = parse_tenant_limits(&args(&["ssr-sandbox", "--tenant-heap-size", "acme=big"]))
+            .unwrap_err();
+        assert!(err.to_string().contains("expects a number, got 'big'"));
+    }
+
+    #[test]
+    fn test_parse_refresh_routes() {
+        let routes = parse_refresh_routes(&args(&[
+            "ssr-sandbox",
+            "--refresh",
+            "60:pages/home.js",
+            "--refresh",
+            r#"300:pages/product.js:{"id":"a:b"}"#,
+        ]))
+        .unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].entry, "pages/home.js");
+        assert_eq!(routes[0].interval_secs, 60);
+        assert_eq!(routes[0].props, serde_json::json!({}));
+        // Only the first two colons separate fields, the props may contain more
+        assert_eq!(routes[1].entry, "pages/product.js");
+        assert_eq!(routes[1].interval_secs, 300);
+        assert_eq!(routes[1].props, serde_json::json!({"id": "a:b"}));
+
+        let refresh =
+            |route: &str| parse_refresh_routes(&args(&["ssr-sandbox", "--refresh", route]));
+        let err = refresh("pages/home.js").unwrap_err().to_string();
+        assert!(err.contains("expects <seconds>:<entry>[:<props-json>]"));
+        let err = refresh("soon:pages/home.js").unwrap_err().to_string();
+        assert!(err.contains("expects a number of seconds, got 'soon'"));
+        let err = refresh("60:pages/home.js:{").unwrap_err().to_string();
+        assert!(err.contains("Invalid props JSON"));
+    }
+
+    #[test]
+    fn test_parse_tenant_quota() {
+        let quota = parse_tenant_quota("renders:1000,cpu_ms:60000,period:60").unwrap();
+        assert_eq!(quota.max_renders, Some(1000));
+        assert_eq!(quota.max_cpu_ms, Some(60000));
+        assert_eq!(quota.max_fetch_bytes, None);
+        assert_eq!(quota.period_secs, 60);
+
+        let tenants = parse_tenant_limits(&args(&[
+            "ssr-sandbox",
+            "--tenant-quota",
+            "acme=fetch_bytes:1024",
+        ]))
+        .unwrap();
+        assert_eq!(tenants["acme"].quota.max_fetch_bytes, Some(1024));
+        assert_eq!(tenants["acme"].quota.period_secs, 3600);
+
+        for (spec, message) in [
+            ("renders", "expects <resource>:<n>, got 'renders'"),
+            ("renders:many", "expects a number, got 'many'"),
+            ("requests:10", "Unknown --tenant-quota resource 'requests'"),
+        ] {
+            let err = parse_tenant_quota(spec).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", spec, err);
+        }
+    }
+
+    #[test]
+    fn test_renderer_args() {
+        let options = args(&[
+            "ssr-sandbox",
+            "--supervise",
+            "4",
+            "--protocol",
+            "lines",
+            "--health-addr",
+            "127.0.0.1:9000",
+            "--audit-log",
+            "/var/log/ssr.log",
+            "--memory-budget",
+            "1024",
+            "--server",
+            "./dist",
+        ]);
+        assert_eq!(
+            renderer_args(&options, 2, 4),
+            args(&[
+                "--audit-log",
+                "/var/log/ssr.log.renderer2",
+                "--memory-budget",
+                "256",
+                "--server",
+                "./dist",
+                "--protocol",
+                "json",
+            ])
+        );
+
+        // A budget that isn't a number is passed on unchanged
+        let options = args(&[
+            "ssr-sandbox",
+            "--memory-budget",
+            "lots",
+            "--server",
+            "./dist",
+        ]);
+        assert_eq!(
+            renderer_args(&options, 1, 2),
+            args(&[
+                "--memory-budget",
+                "lots",
+                "--server",
+                "./dist",
+                "--protocol",
+                "json"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_secret_headers() {
+        std::env::set_var("SSR_SANDBOX_TEST_API_TOKEN", "Bearer s3cret");
+        let headers = parse_secret_headers(&args(&[
+            "ssr-sandbox",
+            "--fetch-secret-header",
+            "https://api.example.com=Authorization:SSR_SANDBOX_TEST_API_TOKEN",
+        ]))
+        .unwrap();
+        let api = &headers["https://api.example.com"];
+        assert!(api.contains("authorization"));
+        // The value never shows up in debug output
+        assert!(!format!("{:?}", api).contains("s3cret"));
+
+        let err = parse_secret_headers(&args(&[
+            "ssr-sandbox",
+            "--fetch-secret-header",
+            "https://api.example.com=Authorization",
+        ]))
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expects <origin>=<header>:<env-var>"));
+        let err = parse_secret_headers(&args(&[
+            "ssr-sandbox",
+            "--fetch-secret-header",
+            "https://api.example.com=Authorization:SSR_SANDBOX_TEST_UNSET",
+        ]))
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--fetch-secret-header: environment variable 'SSR_SANDBOX_TEST_UNSET' is not set"
+        );
+    }
+
+    #[test]
+    fn test_parse_option() {
+        let args = args(&[
+            "ssr-sandbox",
+            "--timeout",
+            "500",
+            "--pool-size",
+            "two",
+            "--seed",
+        ]);
+        assert_eq!(parse_option::<u64>(&args, "--timeout").unwrap(), Some(500));
+        assert_eq!(parse_option::<u64>(&args, "--fetch-timeout").unwrap(), None);
+
+        let err = parse_option::<usize>(&args, "--pool-size").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Invalid value 'two' for --pool-size"));
+        let err = parse_option::<u64>(&args, "--seed").unwrap_err();
+        assert_eq!(err.to_string(), "Missing value for --seed");
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let origins = args(&["ssr-sandbox", "--allow-origin", "https://a", "--allow-origin"]);
+        let err = parse_values(&origins, "--allow-origin").unwrap_err();
+        assert_eq!(err.to_string(), "Missing value for --allow-origin");
+        assert_eq!(
+            parse_values(&origins[..3], "--allow-origin").unwrap(),
+            ["https://a"]
+        );
+        assert!(parse_values(&origins, "--warmup").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_megabytes() {
+        let heap = args(&["ssr-sandbox", "--max-heap-size", "32"]);
+        let bytes = parse_megabytes(&heap, "--max-heap-size").unwrap();
+        assert_eq!(bytes, Some(32 * 1024 * 1024));
+
+        let too_large = usize::MAX.to_string();
+        let heap = args(&["ssr-sandbox", "--max-heap-size", &too_large]);
+        assert!(parse_megabytes(&heap, "--max-heap-size").is_err());
+    }
+
+    #[test]
+    fn test_build_config_rejects_bad_values() {
+        let timeout = args(&["ssr-sandbox", "--timeout", "abc"]);
+        assert!(build_config(&timeout).is_err());
+        let heap = args(&["ssr-sandbox", "--max-heap-size", "x"]);
+        assert!(build_config(&heap).is_err());
+        let redact = args(&["ssr-sandbox", "--warmup", "entry.js", "--redact"]);
+        assert!(build_config(&redact).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Watching the chunks dir for changes, for `--watch` in server mode.
+//!
+//! A rebuild usually writes several files in quick succession, so
+//! [`ChunksWatcher::changed`] waits for the first change and then until the
+//! directory has been quiet for a moment, and reports every file that changed
+//! in between at once. Server mode unloads those files (see
+//! [`TenantRouter::unload`](ssr_sandbox_core::TenantRouter::unload)), so the
+//! next render loads them from disk again while the process, and the V8
+//! platform, stay up.
+
+use anyhow::Error;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How long the chunks dir has to be quiet before changes are reported
+pub const DEFAULT_WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Watches a directory recursively and reports changed files
+pub struct ChunksWatcher {
+    root: PathBuf,
+    debounce: Duration,
+    events: mpsc::UnboundedReceiver<PathBuf>,
+    /// Changed since the last report, kept across cancelled calls
+    pending: BTreeSet<PathBuf>,
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl ChunksWatcher {
+    /// Start watching `dir` and everything below it
+    pub fn new(dir: impl AsRef<Path>, debounce: Duration) -> Result<Self, Error> {
+        let root = dir.as_ref().canonicalize()?;
+        let (sender, events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            match event {
+                // Reads don't change what a render would load
+                Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+                Ok(event) => {
+                    for path in event.paths {
+                        let _ = sender.send(path);
+                    }
+                }
+                Err(e) => eprintln!("[ssr-sandbox] Watch error: {}", e),
+            }
+        })?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+        Ok(Self {
+            root,
+            debounce,
+            events,
+            pending: BTreeSet::new(),
+            _watcher: watcher,
+        })
+    }
+
+    /// Wait for the next change, then until nothing changed for the debounce
+    /// period. Returns the changed paths, relative to the watched directory.
+    ///
+    /// Cancel safe: changes seen by a cancelled call are reported by the next.
+    pub async fn changed(&mut self) -> BTreeSet<PathBuf> {
+        loop {
+            let next = if self.pending.is_empty() {
+                Ok(self.events.recv().await)
+            } else {
+                tokio::time::timeout(self.debounce, self.events.recv()).await
+            };
+            match next {
+                Ok(Some(path)) => {
+                    // Events of the directory itself come with those of its files
+                    match path.strip_prefix(&self.root) {
+                        Ok(relative) if !relative.as_os_str().is_empty() => {
+                            self.pending.insert(relative.to_path_buf());
+                        }
+                        _ => {}
+                    }
+                }
+                // The watcher is gone, so nothing will change anymore
+                Ok(None) if self.pending.is_empty() => return std::future::pending().await,
+                Ok(None) | Err(_) => return std::mem::take(&mut self.pending),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reports_changed_files_once_quiet() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("pages")).unwrap();
+        let mut watcher = ChunksWatcher::new(dir.path(), Duration::from_millis(200)).unwrap();
+
+        std::fs::write(dir.path().join("entry.js"), "export default () => 'a'").unwrap();
+        std::fs::write(dir.path().join("pages/home.js"), "export default 1").unwrap();
+        let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+            .await
+            .unwrap();
+        assert!(changed.contains(Path::new("entry.js")));
+        assert!(changed.contains(Path::new("pages/home.js")));
+
+        // Nothing is reported until the next change
+        let quiet = tokio::time::timeout(Duration::from_millis(300), watcher.changed()).await;
+        assert!(quiet.is_err());
+    }
+}
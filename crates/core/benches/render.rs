@@ -0,0 +1,72 @@
+//! Per-render overhead of a warm runtime.
+//!
+//! `small_sync` takes the fast path (a cached render function returning a
+//! short string synchronously), the others show what the promise and event
+//! loop add on top of it.
+//!
+//! ```bash
+//! cargo bench -p ssr-sandbox-core --bench render
+//! ```
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use ssr_sandbox_core::{create_runtime, SandboxConfig};
+use std::path::Path;
+
+const ENTRIES: &[(&str, &str)] = &[
+    (
+        "small_sync",
+        "export default (props) => `<p>Hello ${props.name}</p>`;",
+    ),
+    (
+        "small_async",
+        "export default async (props) => `<p>Hello ${props.name}</p>`;",
+    ),
+    (
+        "large_sync",
+        "export default (props) => `<p>Hello ${props.name}</p>`.repeat(500);",
+    ),
+];
+
+fn bench_renders(c: &mut Criterion) {
+    let chunks = tempfile::tempdir().unwrap();
+    for (name, source) in ENTRIES {
+        std::fs::write(chunks.path().join(format!("{}.js", name)), source).unwrap();
+    }
+    let config = SandboxConfig {
+        chunks_dir: chunks.path().to_str().unwrap().to_string(),
+        ..Default::default()
+    };
+    let tokio = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let _guard = tokio.enter();
+    let mut runtime = create_runtime(&config).unwrap();
+    let props = serde_json::json!({"name": "world"});
+
+    let mut group = c.benchmark_group("render");
+    for (name, _) in ENTRIES {
+        let entry = chunks.path().join(format!("{}.js", name));
+        // The first render imports the entry and caches its render function
+        render(&tokio, &mut runtime, &entry, &props);
+        group.bench_function(*name, |b| {
+            b.iter(|| render(&tokio, &mut runtime, &entry, &props))
+        });
+    }
+    group.finish();
+}
+
+fn render(
+    tokio: &tokio::runtime::Runtime,
+    runtime: &mut ssr_sandbox_core::SsrRuntime,
+    entry: &Path,
+    props: &serde_json::Value,
+) -> usize {
+    let result = tokio
+        .block_on(runtime.render(entry, props.clone(), None))
+        .unwrap();
+    result.html.len()
+}
+
+criterion_group!(benches, bench_renders);
+criterion_main!(benches);
@@ -3,12 +3,16 @@
 //! This pre-compiles and evaluates all extension JS modules at build time,
 //! so runtime only needs to deserialize the snapshot instead of parsing/compiling JS.
 
+use sha2::{Digest, Sha256};
 use std::env;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 // Include the shared ops module using #[path] attribute
 // This ensures ops are IDENTICAL between build.rs and runtime
+// The snapshot only needs the op declarations; the host-side helpers, stats
+// and config types that live next to them in ops.rs are used by the library only
+#[allow(dead_code)]
 #[path = "src/ops.rs"]
 mod ops;
 
@@ -49,6 +53,13 @@ fn main() {
     )
     .expect("Failed to create snapshot");
 
+    // Embedded in the binary, so `ssr-sandbox doctor` can check the snapshot it carries
+    let sha256: String = Sha256::digest(&snapshot.output)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    println!("cargo:rustc-env=SSR_SNAPSHOT_SHA256={}", sha256);
+
     std::fs::write(&snapshot_path, snapshot.output).expect("Failed to write snapshot");
 
     println!(
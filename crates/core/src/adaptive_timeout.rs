@@ -0,0 +1,200 @@
+//! Render timeouts derived from how long an entry usually takes.
+//!
+//! A single `timeout_ms` has to fit the heaviest page, which leaves a page
+//! that suddenly hangs running for just as long. With
+//! [`SandboxConfig::adaptive_timeout`](crate::SandboxConfig::adaptive_timeout),
+//! pools time each entry's renders and give the next render of the entry a
+//! multiple of the p99 of its recent renders, bounded by a floor and a hard
+//! max. The render times are kept in the config's
+//! [`EntryTimeouts`](crate::SandboxConfig::entry_timeouts), shared by the
+//! pools of the config and its clones, and the timeout chosen for each entry
+//! is reported by `@stats`. Unloading modules forgets the render times of the
+//! entries below them, since the new code may take a different time.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How the timeouts of entries adapt, see [`SandboxConfig::adaptive_timeout`](crate::SandboxConfig::adaptive_timeout)
+#[derive(Debug, Clone, Serialize)]
+pub struct AdaptiveTimeout {
+    /// The timeout is the p99 of the entry's recent render times times this
+    /// (default: 2.0)
+    pub multiplier: f64,
+    /// Shortest timeout, so fast entries don't fail on a hiccup (default: 100ms)
+    pub min_ms: u64,
+    /// Hard max: no render gets longer, and renders of entries without enough
+    /// history get this (default: 30000ms)
+    pub max_ms: u64,
+    /// Render times kept per entry (default: 200)
+    pub window: usize,
+    /// Render times an entry needs before its p99 is used (default: 20)
+    pub min_samples: usize,
+}
+
+impl Default for AdaptiveTimeout {
+    fn default() -> Self {
+        Self {
+            multiplier: 2.0,
+            min_ms: 100,
+            max_ms: 30_000,
+            window: 200,
+            min_samples: 20,
+        }
+    }
+}
+
+/// Recent render times of an entry and the timeout they give
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryTimeout {
+    /// Timeout the entry's next render gets
+    pub timeout_ms: u64,
+    /// p99 of the recent render times (None until there are enough)
+    pub p99_ms: Option<u64>,
+    /// Render times kept
+    pub samples: usize,
+    #[serde(skip)]
+    times_ms: VecDeque<u64>,
+}
+
+/// Render times by entry, shared by every clone (and so by every pool of a
+/// config), see [`SandboxConfig::entry_timeouts`](crate::SandboxConfig::entry_timeouts)
+#[derive(Debug, Clone, Default)]
+pub struct EntryTimeouts(Arc<Mutex<BTreeMap<String, EntryTimeout>>>);
+
+impl EntryTimeouts {
+    /// Timeouts of all entries rendered with an adaptive timeout, by entry
+    pub fn by_entry(&self) -> BTreeMap<String, EntryTimeout> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Forget the render times of the entries below `prefix` (relative to the
+    /// chunks dir, empty for all of them)
+    pub(crate) fn forget(&self, prefix: &Path) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|entry, _| !Path::new(entry).starts_with(prefix));
+    }
+
+    /// Timeout in milliseconds for the next render of `entry`
+    pub(crate) fn timeout_ms(&self, adaptive: &AdaptiveTimeout, entry: &str) -> u64 {
+        let timeouts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        timeouts
+            .get(entry)
+            .map_or(adaptive.max_ms, |timeout| timeout.timeout_ms)
+    }
+
+    /// Record that a render of `entry` took `elapsed`. Renders that timed out
+    /// are recorded with their timeout, so an entry that got heavier for good
+    /// gets more time once its slow renders push up the p99.
+    pub(crate) fn record(&self, adaptive: &AdaptiveTimeout, entry: &str, elapsed: Duration) {
+        let mut timeouts = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let timeout = timeouts
+            .entry(entry.to_string())
+            .or_insert_with(|| EntryTimeout {
+                timeout_ms: adaptive.max_ms,
+                p99_ms: None,
+                samples: 0,
+                times_ms: VecDeque::new(),
+            });
+        timeout.times_ms.push_back(elapsed.as_millis() as u64);
+        while timeout.times_ms.len() > adaptive.window.max(1) {
+            timeout.times_ms.pop_front();
+        }
+        timeout.samples = timeout.times_ms.len();
+        timeout.p99_ms =
+            (timeout.samples >= adaptive.min_samples.max(1)).then(|| p99(&timeout.times_ms));
+        timeout.timeout_ms = match timeout.p99_ms {
+            Some(p99) => ((p99 as f64 * adaptive.multiplier).ceil() as u64)
+                .clamp(adaptive.min_ms, adaptive.max_ms),
+            None => adaptive.max_ms,
+        };
+    }
+}
+
+/// Nearest-rank 99th percentile of `times` (not empty)
+fn p99(times: &VecDeque<u64>) -> u64 {
+    let mut sorted: Vec<u64> = times.iter().copied().collect();
+    sorted.sort_unstable();
+    let rank = (sorted.len() * 99).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout_follows_p99() {
+        let adaptive = AdaptiveTimeout {
+            min_samples: 10,
+            window: 100,
+            ..Default::default()
+        };
+        let timeouts = EntryTimeouts::default();
+        let entry = "follows-p99.js";
+        assert_eq!(timeouts.timeout_ms(&adaptive, entry), 30_000);
+
+        for _ in 0..9 {
+            timeouts.record(&adaptive, entry, Duration::from_millis(200));
+        }
+        // Not enough history yet: the hard max applies
+        assert_eq!(timeouts.timeout_ms(&adaptive, entry), 30_000);
+        timeouts.record(&adaptive, entry, Duration::from_millis(400));
+        assert_eq!(timeouts.by_entry()[entry].p99_ms, Some(400));
+        assert_eq!(timeouts.timeout_ms(&adaptive, entry), 800);
+
+        // Old render times fall out of the window
+        for _ in 0..100 {
+            timeouts.record(&adaptive, entry, Duration::from_millis(10));
+        }
+        assert_eq!(timeouts.by_entry()[entry].samples, 100);
+        assert_eq!(timeouts.timeout_ms(&adaptive, entry), 100);
+
+        // Never above the hard max
+        for _ in 0..100 {
+            timeouts.record(&adaptive, entry, Duration::from_secs(60));
+        }
+        assert_eq!(timeouts.timeout_ms(&adaptive, entry), 30_000);
+    }
+
+    #[test]
+    fn test_shared_by_clones_only() {
+        let adaptive = AdaptiveTimeout::default();
+        let timeouts = EntryTimeouts::default();
+        timeouts
+            .clone()
+            .record(&adaptive, "a.js", Duration::from_millis(10));
+        assert!(timeouts.by_entry().contains_key("a.js"));
+        assert!(EntryTimeouts::default().by_entry().is_empty());
+    }
+
+    #[test]
+    fn test_forgets_unloaded_entries() {
+        let adaptive = AdaptiveTimeout::default();
+        let timeouts = EntryTimeouts::default();
+        for entry in ["forget/a.js", "forget/pages/b.js", "forget-not/c.js"] {
+            timeouts.record(&adaptive, entry, Duration::from_millis(10));
+        }
+
+        timeouts.forget(Path::new("forget/pages"));
+        let by_entry = timeouts.by_entry();
+        assert!(by_entry.contains_key("forget/a.js"));
+        assert!(!by_entry.contains_key("forget/pages/b.js"));
+
+        timeouts.forget(Path::new("forget"));
+        let by_entry = timeouts.by_entry();
+        assert!(!by_entry.contains_key("forget/a.js"));
+        assert!(by_entry.contains_key("forget-not/c.js"));
+    }
+
+    #[test]
+    fn test_p99() {
+        let times: VecDeque<u64> = (1..=200).collect();
+        assert_eq!(p99(&times), 198);
+        assert_eq!(p99(&VecDeque::from([5])), 5);
+    }
+}
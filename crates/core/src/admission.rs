@@ -0,0 +1,232 @@
+//! Render admission control.
+//!
+//! A denylist operators can update at runtime (see the `@deny` admin command)
+//! to stop rendering a known-crashing page without redeploying the host or the
+//! bundle. A rule matches an entry (or `*` for any entry) and optionally the
+//! exact props (by hash) or the route in `props.url`.
+
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// What a deny rule matches besides the entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DenyMatch {
+    /// Every render of the entry
+    Any,
+    /// Renders whose props hash to this value (see [`props_hash`])
+    PropsHash(String),
+    /// Renders whose `props.url` matches this pattern (`*` matches any run of characters)
+    Route(String),
+}
+
+/// A single denylist entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DenyRule {
+    /// Entry path as sent in the request, or `*` for all entries
+    pub entry: String,
+    #[serde(rename = "match")]
+    pub matcher: DenyMatch,
+}
+
+impl DenyRule {
+    /// Parse `<entry> [props=<sha256-hex> | route=<pattern>]`
+    pub fn parse(spec: &str) -> Result<Self, Error> {
+        let mut parts = spec.split_whitespace();
+        let entry = parts
+            .next()
+            .ok_or_else(|| anyhow!("Expected <entry> [props=<hash> | route=<pattern>]"))?;
+        let matcher = match parts.next() {
+            None => DenyMatch::Any,
+            Some(arg) => match arg.split_once('=') {
+                Some(("props", hash)) if is_sha256_hex(hash) => {
+                    DenyMatch::PropsHash(hash.to_ascii_lowercase())
+                }
+                Some(("props", hash)) => {
+                    return Err(anyhow!("props= expects a SHA-256 hex hash, got '{}'", hash))
+                }
+                Some(("route", pattern)) if !pattern.is_empty() => {
+                    DenyMatch::Route(pattern.to_string())
+                }
+                _ => return Err(anyhow!("Unknown deny matcher '{}'", arg)),
+            },
+        };
+        if parts.next().is_some() {
+            return Err(anyhow!("Expected <entry> [props=<hash> | route=<pattern>]"));
+        }
+        Ok(Self {
+            entry: entry.to_string(),
+            matcher,
+        })
+    }
+
+    fn matches(&self, entry: &str, props: &serde_json::Value, hash: &str) -> bool {
+        if self.entry != "*" && self.entry != entry {
+            return false;
+        }
+        match &self.matcher {
+            DenyMatch::Any => true,
+            DenyMatch::PropsHash(expected) => expected == hash,
+            DenyMatch::Route(pattern) => props
+                .get("url")
+                .and_then(|url| url.as_str())
+                .is_some_and(|url| glob_match(pattern, url)),
+        }
+    }
+}
+
+impl std::fmt::Display for DenyRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.matcher {
+            DenyMatch::Any => write!(f, "{}", self.entry),
+            DenyMatch::PropsHash(hash) => write!(f, "{} props={}", self.entry, hash),
+            DenyMatch::Route(pattern) => write!(f, "{} route={}", self.entry, pattern),
+        }
+    }
+}
+
+/// Renders refused by the server
+#[derive(Debug, Default)]
+pub struct Denylist {
+    rules: Vec<DenyRule>,
+}
+
+impl Denylist {
+    /// Add a rule, returns false if it was already present
+    pub fn deny(&mut self, rule: DenyRule) -> bool {
+        if self.rules.contains(&rule) {
+            return false;
+        }
+        self.rules.push(rule);
+        true
+    }
+
+    /// Remove a rule, returns false if it wasn't present
+    pub fn allow(&mut self, rule: &DenyRule) -> bool {
+        let before = self.rules.len();
+        self.rules.retain(|r| r != rule);
+        self.rules.len() != before
+    }
+
+    pub fn rules(&self) -> &[DenyRule] {
+        &self.rules
+    }
+
+    /// The first rule refusing this render, if any (`hash` is the [`props_hash`] of `props`)
+    pub fn check(&self, entry: &str, props: &serde_json::Value, hash: &str) -> Option<&DenyRule> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(entry, props, hash))
+    }
+}
+
+/// SHA-256 (hex) of the props as compact JSON with sorted keys
+pub fn props_hash(props: &serde_json::Value) -> String {
+    // serde_json::Value keeps object keys sorted, so equal props hash equally
+    Sha256::digest(props.to_string().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn is_sha256_hex(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Match `text` against a pattern where `*` matches any run of characters
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`: exact match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(DenyRule::parse("entry.js").unwrap().matcher, DenyMatch::Any);
+        assert_eq!(
+            DenyRule::parse("* route=/products/*").unwrap(),
+            DenyRule {
+                entry: "*".to_string(),
+                matcher: DenyMatch::Route("/products/*".to_string()),
+            }
+        );
+        let hash = "AB".repeat(32);
+        assert_eq!(
+            DenyRule::parse(&format!("entry.js props={}", hash))
+                .unwrap()
+                .matcher,
+            DenyMatch::PropsHash("ab".repeat(32))
+        );
+        assert!(DenyRule::parse("entry.js props=abc").is_err());
+        assert!(DenyRule::parse("entry.js host=x").is_err());
+        assert!(DenyRule::parse("").is_err());
+    }
+
+    #[test]
+    fn test_props_hash_ignores_key_order() {
+        let a: serde_json::Value = serde_json::from_str(r#"{"b":1,"a":2}"#).unwrap();
+        let b: serde_json::Value = serde_json::from_str(r#"{"a":2,"b":1}"#).unwrap();
+        assert_eq!(props_hash(&a), props_hash(&b));
+        assert_ne!(props_hash(&a), props_hash(&json!({"a": 3})));
+    }
+
+    fn check<'a>(
+        denylist: &'a Denylist,
+        entry: &str,
+        props: &serde_json::Value,
+    ) -> Option<&'a DenyRule> {
+        denylist.check(entry, props, &props_hash(props))
+    }
+
+    #[test]
+    fn test_denylist_check() {
+        let mut denylist = Denylist::default();
+        let crashing = json!({"url": "/products/42", "user": "x"});
+        assert!(check(&denylist, "entry.js", &crashing).is_none());
+
+        denylist
+            .deny(DenyRule::parse(&format!("entry.js props={}", props_hash(&crashing))).unwrap());
+        assert!(check(&denylist, "entry.js", &crashing).is_some());
+        assert!(check(&denylist, "other.js", &crashing).is_none());
+        assert!(check(&denylist, "entry.js", &json!({"url": "/products/42"})).is_none());
+
+        denylist.deny(DenyRule::parse("* route=/admin/*").unwrap());
+        assert!(check(&denylist, "other.js", &json!({"url": "/admin/users"})).is_some());
+        assert!(check(&denylist, "other.js", &json!({"url": "/users"})).is_none());
+        assert!(check(&denylist, "other.js", &json!({})).is_none());
+
+        assert!(!denylist.deny(DenyRule::parse("* route=/admin/*").unwrap()));
+        assert!(denylist.allow(&DenyRule::parse("* route=/admin/*").unwrap()));
+        assert!(check(&denylist, "other.js", &json!({"url": "/admin/users"})).is_none());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("/a/*/c", "/a/b/c"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("/a*", "/a"));
+        assert!(!glob_match("/a", "/ab"));
+        assert!(!glob_match("/a/*/c", "/a/b/d"));
+        assert!(glob_match("*x*", "axb"));
+    }
+}
@@ -0,0 +1,285 @@
+//! Pinning of tenant threads to CPUs and NUMA nodes.
+//!
+//! Every tenant of a [`TenantRouter`](crate::TenantRouter) renders on a
+//! thread of its own. On large machines, [`ThreadAffinity`] pins those
+//! threads to CPUs, or to the CPUs of one NUMA node each, so an isolate's
+//! heap stays in the memory of the node its thread runs on (Linux allocates
+//! pages on the node of the thread that first touches them). Where each
+//! thread ended up is reported by `@stats` and [`thread_placements`].
+
+use anyhow::{anyhow, bail, Error};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Where the NUMA topology is read from
+const NODES_DIR: &str = "/sys/devices/system/node";
+
+/// Highest CPU number a thread can be pinned to (`CPU_SETSIZE`)
+const MAX_CPUS: usize = 1024;
+
+/// Where the threads of tenant pools run, see [`SandboxConfig::thread_affinity`](crate::SandboxConfig::thread_affinity)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadAffinity {
+    /// Let the OS schedule the threads (default)
+    #[default]
+    Unpinned,
+    /// Pin each tenant thread to one of these CPUs, round robin in tenant
+    /// name order
+    Cpus(Vec<usize>),
+    /// Pin each tenant thread to all CPUs of one NUMA node, round robin over
+    /// the nodes in tenant name order
+    NumaNodes,
+}
+
+impl std::str::FromStr for ThreadAffinity {
+    type Err = Error;
+
+    /// `none`, `numa` or `cpus:<list>` with a Linux CPU list, e.g. `cpus:0-3,8`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ThreadAffinity::Unpinned),
+            "numa" => Ok(ThreadAffinity::NumaNodes),
+            _ => match s.strip_prefix("cpus:") {
+                Some(list) => Ok(ThreadAffinity::Cpus(parse_cpu_list(list)?)),
+                None => Err(anyhow!(
+                    "Unknown thread affinity '{}' (expected none, numa or cpus:<list>)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// CPUs a tenant thread is pinned to, and their NUMA node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CpuSet {
+    pub cpus: Vec<usize>,
+    pub numa_node: Option<usize>,
+}
+
+impl ThreadAffinity {
+    /// The CPUs of each of `threads` threads, None for all of them if unpinned
+    pub(crate) fn plan(&self, threads: usize) -> Result<Option<Vec<CpuSet>>, Error> {
+        let sets: Vec<CpuSet> = match self {
+            ThreadAffinity::Unpinned => return Ok(None),
+            ThreadAffinity::Cpus(cpus) => {
+                if cpus.is_empty() {
+                    bail!("Thread affinity needs at least one CPU");
+                }
+                let nodes = numa_nodes().unwrap_or_default();
+                cpus.iter()
+                    .map(|&cpu| CpuSet {
+                        cpus: vec![cpu],
+                        numa_node: node_of(&nodes, cpu),
+                    })
+                    .collect()
+            }
+            ThreadAffinity::NumaNodes => {
+                let nodes = numa_nodes()?;
+                if nodes.is_empty() {
+                    bail!("No NUMA nodes found in {}", NODES_DIR);
+                }
+                nodes
+                    .into_iter()
+                    .map(|(node, cpus)| CpuSet {
+                        cpus,
+                        numa_node: Some(node),
+                    })
+                    .collect()
+            }
+        };
+        Ok(Some(sets.iter().cycle().take(threads).cloned().collect()))
+    }
+}
+
+/// Where a tenant thread runs, see [`thread_placements`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ThreadPlacement {
+    pub tenant: String,
+    /// CPUs the thread may run on, as read back from the OS after pinning
+    /// (empty where they can't be read)
+    pub cpus: Vec<usize>,
+    /// NUMA node the thread was pinned to (None if unpinned or unknown)
+    pub numa_node: Option<usize>,
+    /// Whether the thread was pinned by [`ThreadAffinity`]
+    pub pinned: bool,
+}
+
+static PLACEMENTS: Mutex<BTreeMap<String, ThreadPlacement>> = Mutex::new(BTreeMap::new());
+
+/// Where the threads of all tenants run, by tenant name
+pub fn thread_placements() -> Vec<ThreadPlacement> {
+    let placements = PLACEMENTS.lock().unwrap_or_else(|e| e.into_inner());
+    placements.values().cloned().collect()
+}
+
+/// Pin the calling thread (of `tenant`) to `set` if given, and record where it runs
+pub(crate) fn place_current_thread(tenant: &str, set: Option<&CpuSet>) -> Result<(), Error> {
+    if let Some(set) = set {
+        pin_current_thread(&set.cpus)?;
+    }
+    let placement = ThreadPlacement {
+        tenant: tenant.to_string(),
+        cpus: current_thread_cpus(),
+        numa_node: set.and_then(|set| set.numa_node),
+        pinned: set.is_some(),
+    };
+    let mut placements = PLACEMENTS.lock().unwrap_or_else(|e| e.into_inner());
+    placements.insert(tenant.to_string(), placement);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn pin_current_thread(cpus: &[usize]) -> Result<(), Error> {
+    // SAFETY: cpu_set_t is a plain bit set, all zeroes is the empty set
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= MAX_CPUS {
+            bail!("CPU {} is out of range (at most {})", cpu, MAX_CPUS - 1);
+        }
+        // SAFETY: `cpu` is below CPU_SETSIZE
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    // SAFETY: pid 0 is the calling thread and `set` is a valid cpu_set_t
+    let result = unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) };
+    if result != 0 {
+        bail!(
+            "Failed to pin thread to CPUs {:?}: {}",
+            cpus,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_current_thread(_cpus: &[usize]) -> Result<(), Error> {
+    bail!("Thread affinity is only supported on Linux")
+}
+
+#[cfg(target_os = "linux")]
+fn current_thread_cpus() -> Vec<usize> {
+    // SAFETY: cpu_set_t is a plain bit set, all zeroes is the empty set
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    // SAFETY: pid 0 is the calling thread and `set` is a valid cpu_set_t
+    if unsafe { libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) } != 0 {
+        return Vec::new();
+    }
+    // SAFETY: every index is below CPU_SETSIZE
+    (0..MAX_CPUS)
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_thread_cpus() -> Vec<usize> {
+    Vec::new()
+}
+
+/// NUMA nodes and their CPUs, from sysfs
+fn numa_nodes() -> Result<Vec<(usize, Vec<usize>)>, Error> {
+    let mut nodes = Vec::new();
+    let entries = std::fs::read_dir(NODES_DIR)
+        .map_err(|e| anyhow!("Cannot read NUMA nodes from {}: {}", NODES_DIR, e))?;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(node) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|node| node.parse().ok())
+        else {
+            continue;
+        };
+        let cpus = std::fs::read_to_string(Path::new(NODES_DIR).join(&name).join("cpulist"))?;
+        let cpus = parse_cpu_list(cpus.trim())?;
+        // Memory-only nodes have no CPUs to run on
+        if !cpus.is_empty() {
+            nodes.push((node, cpus));
+        }
+    }
+    nodes.sort();
+    Ok(nodes)
+}
+
+fn node_of(nodes: &[(usize, Vec<usize>)], cpu: usize) -> Option<usize> {
+    nodes
+        .iter()
+        .find(|(_, cpus)| cpus.contains(&cpu))
+        .map(|(node, _)| *node)
+}
+
+/// Parse a Linux CPU list like `0-3,8,10-11`
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>, Error> {
+    let invalid = || anyhow!("Invalid CPU list '{}' (expected e.g. 0-3,8)", list);
+    let mut cpus = Vec::new();
+    for part in list.split(',').filter(|part| !part.is_empty()) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first, last),
+            None => (part, part),
+        };
+        let first: usize = first.trim().parse().map_err(|_| invalid())?;
+        let last: usize = last.trim().parse().map_err(|_| invalid())?;
+        if first > last || last >= MAX_CPUS {
+            return Err(invalid());
+        }
+        cpus.extend(first..=last);
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_thread_affinity() {
+        assert_eq!("none".parse::<ThreadAffinity>().unwrap(), ThreadAffinity::Unpinned);
+        assert_eq!("numa".parse::<ThreadAffinity>().unwrap(), ThreadAffinity::NumaNodes);
+        assert_eq!(
+            "cpus:0-2,8,2".parse::<ThreadAffinity>().unwrap(),
+            ThreadAffinity::Cpus(vec![0, 1, 2, 8])
+        );
+        assert!("cpus:3-1".parse::<ThreadAffinity>().is_err());
+        assert!("cpus:a".parse::<ThreadAffinity>().is_err());
+        assert!("cores".parse::<ThreadAffinity>().is_err());
+    }
+
+    #[test]
+    fn test_plan_round_robin() {
+        assert_eq!(ThreadAffinity::Unpinned.plan(3).unwrap(), None);
+        assert!(ThreadAffinity::Cpus(vec![]).plan(1).is_err());
+
+        let plan = ThreadAffinity::Cpus(vec![2, 5]).plan(3).unwrap().unwrap();
+        let cpus: Vec<_> = plan.iter().map(|set| set.cpus.clone()).collect();
+        assert_eq!(cpus, vec![vec![2], vec![5], vec![2]]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_places_current_thread() {
+        std::thread::spawn(|| {
+            let cpu = current_thread_cpus()[0];
+            let set = CpuSet {
+                cpus: vec![cpu],
+                numa_node: None,
+            };
+            place_current_thread("test-tenant", Some(&set)).unwrap();
+            assert_eq!(current_thread_cpus(), vec![cpu]);
+        })
+        .join()
+        .unwrap();
+
+        let placement = thread_placements()
+            .into_iter()
+            .find(|placement| placement.tenant == "test-tenant")
+            .unwrap();
+        assert!(placement.pinned);
+        assert_eq!(placement.cpus.len(), 1);
+    }
+}
@@ -0,0 +1,192 @@
+//! Append-only audit log of renders, for compliance retention.
+//!
+//! [`AuditLog`] writes one JSON object per line ([`AuditRecord`]) to its own
+//! file, independent of the stderr logs. Once the file would grow past its
+//! size limit it is rotated: `audit.jsonl` becomes `audit.jsonl.1`, the
+//! previous `.1` becomes `.2` and so on, and the oldest file beyond the number
+//! of kept files is deleted.
+
+use crate::ops::SandboxViolation;
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Outcome of an audited render
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Ok,
+    Error,
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    /// When the render finished, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub entry: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Hash of the props (the props themselves are never written)
+    pub props_hash: String,
+    pub duration_ms: f64,
+    pub outcome: AuditOutcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub violations: Vec<SandboxViolation>,
+}
+
+impl AuditRecord {
+    /// Record of a render that took `duration`, timestamped now
+    pub fn new(
+        entry: impl Into<String>,
+        props_hash: impl Into<String>,
+        duration: Duration,
+    ) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_millis() as u64);
+        Self {
+            timestamp_ms,
+            entry: entry.into(),
+            request_id: None,
+            props_hash: props_hash.into(),
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            outcome: AuditOutcome::Ok,
+            error: None,
+            violations: Vec::new(),
+        }
+    }
+}
+
+/// JSONL audit log file with size-based rotation
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl AuditLog {
+    /// Default size at which the log is rotated (100MB)
+    pub const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+    /// Default number of rotated files kept besides the current one
+    pub const DEFAULT_KEEP: usize = 5;
+
+    /// Open (or create) the log at `path`, appending to an existing file
+    pub fn open(path: impl AsRef<Path>, max_bytes: u64, keep: usize) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_append(&path)?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    /// Append `record`, rotating the file first if it would grow past its limit
+    pub fn record(&mut self, record: &AuditRecord) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file
+            .write_all(&line)
+            .and_then(|()| self.file.flush())
+            .map_err(|e| anyhow!("Failed to write audit log '{}': {}", self.path.display(), e))?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        let rename = |from: &Path, to: &Path| match std::fs::rename(from, to) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(anyhow!(
+                "Failed to rotate audit log '{}': {}",
+                from.display(),
+                e
+            )),
+            _ => Ok(()),
+        };
+        if self.keep == 0 {
+            let _ = std::fs::remove_file(&self.path);
+        } else {
+            let _ = std::fs::remove_file(rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                rename(&rotated(n), &rotated(n + 1))?;
+            }
+            rename(&self.path, &rotated(1))?;
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, Error> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow!("Failed to open audit log '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(path: &Path) -> Vec<serde_json::Value> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_rotates_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let record = |entry: &str| AuditRecord {
+            outcome: AuditOutcome::Error,
+            error: Some("boom".to_string()),
+            ..AuditRecord::new(entry, "abc123", Duration::from_millis(3))
+        };
+        let line_len = serde_json::to_vec(&record("a.js")).unwrap().len() as u64 + 1;
+
+        // Room for two lines per file, two rotated files kept
+        let mut log = AuditLog::open(&path, line_len * 2, 2).unwrap();
+        for entry in ["a.js", "b.js", "c.js", "d.js", "e.js", "f.js", "g.js"] {
+            log.record(&record(entry)).unwrap();
+        }
+
+        let entries = |path: &Path| -> Vec<String> {
+            lines(path)
+                .iter()
+                .map(|line| line["entry"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(entries(&path), ["g.js"]);
+        assert_eq!(entries(&dir.path().join("audit.jsonl.1")), ["e.js", "f.js"]);
+        assert_eq!(entries(&dir.path().join("audit.jsonl.2")), ["c.js", "d.js"]);
+        assert!(!dir.path().join("audit.jsonl.3").exists());
+
+        let first = &lines(&path)[0];
+        assert_eq!(first["outcome"], "error");
+        assert_eq!(first["props_hash"], "abc123");
+        assert_eq!(first["violations"], serde_json::json!([]));
+
+        // Reopening appends
+        let mut log = AuditLog::open(&path, line_len * 2, 2).unwrap();
+        log.record(&record("h.js")).unwrap();
+        assert_eq!(entries(&path), ["g.js", "h.js"]);
+    }
+}
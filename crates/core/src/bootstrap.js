@@ -0,0 +1,1520 @@
+// Bootstrap script - sets up additional APIs for SSR
+// Deno extensions provide: console, URL, URLSearchParams, TextEncoder, TextDecoder,
+// atob, btoa, crypto (randomUUID, getRandomValues, subtle)
+//
+// This script adds: fetch, console capture, SSR render function
+
+// Import deno extension modules and set up globals
+// Order matters: dependencies first
+import "ext:deno_webidl/00_webidl.js";
+import "ext:deno_console/01_console.js";
+import { URL, URLSearchParams } from "ext:deno_url/00_url.js";
+import "ext:deno_url/01_urlpattern.js";
+import "ext:deno_web/00_infra.js";
+import { DOMException } from "ext:deno_web/01_dom_exception.js";
+import "ext:deno_web/01_mimesniff.js";
+import {
+  Event,
+  EventTarget,
+  CustomEvent,
+  ErrorEvent,
+  CloseEvent,
+  MessageEvent,
+  ProgressEvent,
+  PromiseRejectionEvent,
+  saveGlobalThisReference,
+  setEventTargetData,
+} from "ext:deno_web/02_event.js";
+import { structuredClone } from "ext:deno_web/02_structured_clone.js";
+import "ext:deno_web/02_timers.js";
+import { AbortController, AbortSignal } from "ext:deno_web/03_abort_signal.js";
+import "ext:deno_web/04_global_interfaces.js";
+import { atob, btoa } from "ext:deno_web/05_base64.js";
+import {
+  ReadableStream,
+  TransformStream,
+  WritableStream,
+  ByteLengthQueuingStrategy,
+  CountQueuingStrategy,
+} from "ext:deno_web/06_streams.js";
+import {
+  TextEncoder,
+  TextDecoder,
+  TextEncoderStream,
+  TextDecoderStream,
+} from "ext:deno_web/08_text_encoding.js";
+import { Blob, File } from "ext:deno_web/09_file.js";
+import { FileReader } from "ext:deno_web/10_filereader.js";
+import {
+  locationConstructorDescriptor,
+  locationDescriptor,
+  setLocationHref,
+} from "ext:deno_web/12_location.js";
+import { MessageChannel, MessagePort } from "ext:deno_web/13_message_port.js";
+import { CompressionStream, DecompressionStream } from "ext:deno_web/14_compression.js";
+import { Performance, performance } from "ext:deno_web/15_performance.js";
+import { ImageData } from "ext:deno_web/16_image_data.js";
+import { crypto, Crypto, CryptoKey, SubtleCrypto } from "ext:deno_crypto/00_crypto.js";
+
+// Assign to globalThis
+globalThis.URL = URL;
+globalThis.URLSearchParams = URLSearchParams;
+globalThis.DOMException = DOMException;
+globalThis.Event = Event;
+globalThis.EventTarget = EventTarget;
+globalThis.CustomEvent = CustomEvent;
+globalThis.ErrorEvent = ErrorEvent;
+globalThis.CloseEvent = CloseEvent;
+globalThis.MessageEvent = MessageEvent;
+globalThis.ProgressEvent = ProgressEvent;
+globalThis.PromiseRejectionEvent = PromiseRejectionEvent;
+globalThis.structuredClone = structuredClone;
+globalThis.AbortController = AbortController;
+globalThis.AbortSignal = AbortSignal;
+globalThis.atob = atob;
+globalThis.btoa = btoa;
+globalThis.ReadableStream = ReadableStream;
+globalThis.TransformStream = TransformStream;
+globalThis.WritableStream = WritableStream;
+globalThis.ByteLengthQueuingStrategy = ByteLengthQueuingStrategy;
+globalThis.CountQueuingStrategy = CountQueuingStrategy;
+globalThis.TextEncoder = TextEncoder;
+globalThis.TextDecoder = TextDecoder;
+globalThis.TextEncoderStream = TextEncoderStream;
+globalThis.TextDecoderStream = TextDecoderStream;
+globalThis.Blob = Blob;
+globalThis.File = File;
+globalThis.FileReader = FileReader;
+globalThis.MessageChannel = MessageChannel;
+globalThis.MessagePort = MessagePort;
+globalThis.CompressionStream = CompressionStream;
+globalThis.DecompressionStream = DecompressionStream;
+globalThis.Performance = Performance;
+globalThis.performance = performance;
+globalThis.ImageData = ImageData;
+globalThis.crypto = crypto;
+globalThis.Crypto = Crypto;
+globalThis.CryptoKey = CryptoKey;
+globalThis.SubtleCrypto = SubtleCrypto;
+
+// Make the global scope an EventTarget (like `window`). deno_web reports errors
+// thrown by event listeners by dispatching an ErrorEvent on the saved global,
+// so without this any throwing listener crashes dispatchEvent() itself.
+Object.setPrototypeOf(globalThis, EventTarget.prototype);
+setEventTargetData(globalThis);
+saveGlobalThisReference(globalThis);
+
+const {
+  op_console,
+  op_fetch,
+  op_fetch_abort,
+  op_render_chunk,
+  op_render_phase,
+  op_cache_enabled,
+  op_cache_match,
+  op_cache_put,
+  op_cache_delete,
+  op_cache_keys,
+  op_caches_open,
+  op_caches_has,
+  op_caches_delete,
+  op_caches_match,
+  op_caches_keys,
+  op_storage_mode,
+  op_context_per_request,
+  op_warn_deprecated,
+  op_timer_limits,
+  op_read_data,
+  op_determinism,
+  op_fragment_cache_enabled,
+  op_fragment_cache_get,
+  op_fragment_cache_set,
+  op_host_call,
+  op_host_feed_next,
+  op_island_defer,
+  op_wasm_limits,
+  op_wasm_limit,
+  op_wasm_module_bytes,
+} = Deno.core.ops;
+
+// ============================================================================
+// Console Capture - wrap the deno_console to capture output
+// ============================================================================
+
+function formatArgs(args) {
+  return args
+    .map((arg) => {
+      if (typeof arg === "string") return arg;
+      if (arg === null) return "null";
+      if (arg === undefined) return "undefined";
+      try {
+        return JSON.stringify(arg, null, 2);
+      } catch {
+        return String(arg);
+      }
+    })
+    .join(" ");
+}
+
+// The arguments as JSON (ConsoleEntry::args_json), "" if they can't be serialized
+function argsJson(args) {
+  try {
+    return JSON.stringify(args) ?? "";
+  } catch {
+    return "";
+  }
+}
+
+function capture(method, args) {
+  op_console(method, formatArgs(args), argsJson(args));
+}
+
+// Save original console methods from deno_console
+const originalConsole = globalThis.console;
+
+// Override console to capture output while still using deno_console formatting
+globalThis.console = {
+  log: (...args) => capture("log", args),
+  info: (...args) => capture("info", args),
+  debug: (...args) => capture("debug", args),
+  warn: (...args) => capture("warn", args),
+  error: (...args) => capture("error", args),
+  trace: (...args) => {
+    // Like browsers: the message, then the stack of the caller
+    const stack = (new Error().stack ?? "").split("\n").slice(2).join("\n");
+    const message = formatArgs(["Trace:", ...args]);
+    op_console("trace", stack ? `${message}\n${stack}` : message, argsJson(args));
+  },
+  dir: originalConsole?.dir?.bind(originalConsole) || (() => {}),
+  table: originalConsole?.table?.bind(originalConsole) || (() => {}),
+  time: originalConsole?.time?.bind(originalConsole) || (() => {}),
+  timeEnd: originalConsole?.timeEnd?.bind(originalConsole) || (() => {}),
+  timeLog: originalConsole?.timeLog?.bind(originalConsole) || (() => {}),
+  group: originalConsole?.group?.bind(originalConsole) || (() => {}),
+  groupCollapsed: originalConsole?.groupCollapsed?.bind(originalConsole) || (() => {}),
+  groupEnd: originalConsole?.groupEnd?.bind(originalConsole) || (() => {}),
+  clear: originalConsole?.clear?.bind(originalConsole) || (() => {}),
+  assert: originalConsole?.assert?.bind(originalConsole) || (() => {}),
+  count: originalConsole?.count?.bind(originalConsole) || (() => {}),
+  countReset: originalConsole?.countReset?.bind(originalConsole) || (() => {}),
+};
+
+// ============================================================================
+// queueMicrotask / reportError
+// ============================================================================
+
+// Report an exception the way a browser does: dispatch a cancelable ErrorEvent
+// on the global scope, then capture it as a console error unless a listener
+// called preventDefault(). The render itself keeps going.
+function reportException(error) {
+  const message = error instanceof Error ? error.message : String(error);
+  const event = new ErrorEvent("error", { cancelable: true, message, error });
+  if (globalThis.dispatchEvent(event)) {
+    const details = error instanceof Error ? (error.stack || String(error)) : formatArgs([error]);
+    op_console_error("Uncaught " + details);
+  }
+}
+
+globalThis.reportError = function reportError(error) {
+  if (arguments.length < 1) {
+    throw new TypeError(
+      "Failed to execute 'reportError': 1 argument required, but only 0 present.",
+    );
+  }
+  reportException(error);
+};
+
+// queueMicrotask comes from deno_core. Errors thrown inside a microtask go
+// through reportError instead of failing the whole render.
+Deno.core.setReportExceptionCallback(reportException);
+
+// ============================================================================
+// Fetch API (Headers, Request, Response, fetch)
+// ============================================================================
+
+globalThis.Headers = class Headers {
+  #headers = new Map();
+
+  constructor(init) {
+    if (init instanceof Headers) {
+      for (const [key, value] of init) {
+        this.append(key, value);
+      }
+    } else if (Array.isArray(init)) {
+      for (const [key, value] of init) {
+        this.append(key, value);
+      }
+    } else if (init && typeof init === "object") {
+      for (const [key, value] of Object.entries(init)) {
+        this.append(key, value);
+      }
+    }
+  }
+
+  append(name, value) {
+    const key = name.toLowerCase();
+    const existing = this.#headers.get(key);
+    if (existing) {
+      this.#headers.set(key, existing + ", " + value);
+    } else {
+      this.#headers.set(key, String(value));
+    }
+  }
+
+  delete(name) {
+    this.#headers.delete(name.toLowerCase());
+  }
+
+  get(name) {
+    return this.#headers.get(name.toLowerCase()) ?? null;
+  }
+
+  has(name) {
+    return this.#headers.has(name.toLowerCase());
+  }
+
+  set(name, value) {
+    this.#headers.set(name.toLowerCase(), String(value));
+  }
+
+  *entries() {
+    yield* this.#headers.entries();
+  }
+
+  *keys() {
+    yield* this.#headers.keys();
+  }
+
+  *values() {
+    yield* this.#headers.values();
+  }
+
+  [Symbol.iterator]() {
+    return this.entries();
+  }
+
+  forEach(callback, thisArg) {
+    for (const [key, value] of this.#headers) {
+      callback.call(thisArg, value, key, this);
+    }
+  }
+};
+
+globalThis.Request = class Request {
+  #url;
+  #method;
+  #headers;
+  #body;
+  #signal;
+
+  constructor(input, init = {}) {
+    if (input instanceof Request) {
+      this.#url = input.url;
+      this.#method = init.method || input.method;
+      this.#headers = new Headers(init.headers || input.headers);
+      this.#body = init.body ?? input.#body;
+      this.#signal = init.signal ?? input.#signal;
+    } else {
+      this.#url = String(input);
+      this.#method = (init.method || "GET").toUpperCase();
+      this.#headers = new Headers(init.headers);
+      this.#body = init.body ?? null;
+      this.#signal = init.signal ?? null;
+    }
+  }
+
+  get url() { return this.#url; }
+  get method() { return this.#method; }
+  get headers() { return this.#headers; }
+  get body() { return this.#body; }
+  get signal() { return this.#signal; }
+
+  clone() {
+    return new Request(this);
+  }
+
+  async text() {
+    if (this.#body instanceof Blob) return this.#body.text();
+    return this.#body ? String(this.#body) : "";
+  }
+
+  async json() {
+    return JSON.parse(await this.text());
+  }
+};
+
+globalThis.Response = class Response {
+  #body;
+  #init;
+  #headers;
+
+  constructor(body, init = {}) {
+    this.#body = body ?? null;
+    this.#init = init;
+    this.#headers = new Headers(init.headers);
+  }
+
+  get ok() {
+    const status = this.#init.status ?? 200;
+    return status >= 200 && status < 300;
+  }
+
+  get status() {
+    return this.#init.status ?? 200;
+  }
+
+  get statusText() {
+    return this.#init.statusText ?? "";
+  }
+
+  get headers() {
+    return this.#headers;
+  }
+
+  get url() {
+    return this.#init.url ?? "";
+  }
+
+  get bodyUsed() {
+    return false; // Simplified
+  }
+
+  clone() {
+    return new Response(this.#body, this.#init);
+  }
+
+  // fetch() bodies are bytes (Uint8Array); strings, Blobs and buffers are
+  // accepted like in the real constructor
+  #isBytes() {
+    return this.#body instanceof ArrayBuffer || ArrayBuffer.isView(this.#body);
+  }
+
+  async text() {
+    if (this.#body instanceof Blob) return this.#body.text();
+    // Invalid UTF-8 becomes U+FFFD, as in browsers
+    if (this.#isBytes()) return new TextDecoder().decode(this.#body);
+    return this.#body ? String(this.#body) : "";
+  }
+
+  async json() {
+    return JSON.parse(await this.text());
+  }
+
+  async arrayBuffer() {
+    const body = this.#body;
+    if (body instanceof Blob) return body.arrayBuffer();
+    if (body instanceof ArrayBuffer) return body.slice(0);
+    if (ArrayBuffer.isView(body)) {
+      return body.buffer.slice(body.byteOffset, body.byteOffset + body.byteLength);
+    }
+    return new TextEncoder().encode(await this.text()).buffer;
+  }
+
+  async blob() {
+    if (this.#body instanceof Blob) return this.#body;
+    const type = this.#headers.get("content-type") ?? "";
+    return new Blob([await this.arrayBuffer()], { type });
+  }
+
+  static json(data, init = {}) {
+    return new Response(JSON.stringify(data), {
+      ...init,
+      headers: {
+        "content-type": "application/json",
+        ...init.headers,
+      },
+    });
+  }
+
+  static error() {
+    return new Response(null, { status: 0, statusText: "" });
+  }
+
+  static redirect(url, status = 302) {
+    return new Response(null, {
+      status,
+      headers: { location: url },
+    });
+  }
+};
+
+// Ids of in-flight fetches for op_fetch_abort
+let nextFetchAbortId = 0;
+
+globalThis.fetch = async function fetch(input, init = {}) {
+  let url, method, headers, body;
+  const signal = init.signal ?? (input instanceof Request ? input.signal : null);
+  signal?.throwIfAborted();
+
+  if (input instanceof Request) {
+    url = input.url;
+    method = init.method || input.method;
+    headers = {};
+    for (const [key, value] of (init.headers ? new Headers(init.headers) : input.headers)) {
+      headers[key] = value;
+    }
+    body = init.body ?? (input.body ? await input.text() : null);
+  } else {
+    url = String(input);
+    method = init.method || "GET";
+    headers = {};
+    if (init.headers) {
+      const h = new Headers(init.headers);
+      for (const [key, value] of h) {
+        headers[key] = value;
+      }
+    }
+    body = init.body ?? null;
+  }
+
+  // Call the Rust op (op_fetch returns a promise). Aborting the signal aborts
+  // the HTTP request itself, not just the promise.
+  nextFetchAbortId = (nextFetchAbortId + 1) >>> 0;
+  const abortId = nextFetchAbortId;
+  const onAbort = () => op_fetch_abort(abortId);
+  signal?.addEventListener("abort", onAbort, { once: true });
+  let result;
+  try {
+    result = await op_fetch({
+      url,
+      method,
+      headers: Object.keys(headers).length > 0 ? headers : null,
+      body: body instanceof Blob ? await body.text() : body ? String(body) : null,
+      abort_id: abortId,
+    });
+  } catch (e) {
+    if (signal?.aborted) {
+      throw signal.reason;
+    }
+    // Per-request timeout or render fetch budget (FetchConfig), like an aborted fetch
+    const message = String(e?.message);
+    if (message.startsWith("Fetch timed out") || message.startsWith("Fetch aborted")) {
+      throw new DOMException(e.message, "AbortError");
+    }
+    throw e;
+  } finally {
+    signal?.removeEventListener("abort", onAbort);
+  }
+
+  // Convert to Response object
+  return new Response(result.body, {
+    status: result.status,
+    statusText: result.status_text,
+    headers: result.headers,
+    url: result.url,
+  });
+};
+
+// ============================================================================
+// Cache API (opt-in via SandboxConfig.cache_api, otherwise `caches` is undefined)
+// ============================================================================
+
+{
+  const cacheKey = (request) => {
+    const url = request instanceof Request ? request.url : String(request);
+    try {
+      return new URL(url).href;
+    } catch {
+      return url;
+    }
+  };
+
+  const toResponse = (cached) =>
+    cached
+      ? new Response(cached.body, {
+        status: cached.status,
+        statusText: cached.status_text,
+        headers: cached.headers,
+        url: cached.url,
+      })
+      : undefined;
+
+  class Cache {
+    #name;
+
+    constructor(name) {
+      this.#name = name;
+    }
+
+    async match(request) {
+      return toResponse(op_cache_match(this.#name, cacheKey(request)));
+    }
+
+    async matchAll(request) {
+      const urls = request === undefined ? op_cache_keys(this.#name) : [cacheKey(request)];
+      return urls
+        .map((url) => toResponse(op_cache_match(this.#name, url)))
+        .filter(Boolean);
+    }
+
+    async add(request) {
+      const response = await fetch(request);
+      if (!response.ok) {
+        throw new TypeError(`Cache.add: bad response status ${response.status}`);
+      }
+      await this.put(request, response);
+    }
+
+    async addAll(requests) {
+      for (const request of requests) {
+        await this.add(request);
+      }
+    }
+
+    async put(request, response) {
+      const url = cacheKey(request);
+      const headers = {};
+      for (const [key, value] of response.headers) {
+        headers[key] = value;
+      }
+      op_cache_put(this.#name, url, {
+        status: response.status,
+        status_text: response.statusText,
+        headers,
+        url: response.url || url,
+        body: await response.text(),
+      });
+    }
+
+    async delete(request) {
+      return op_cache_delete(this.#name, cacheKey(request));
+    }
+
+    async keys() {
+      return op_cache_keys(this.#name).map((url) => new Request(url));
+    }
+  }
+
+  const cacheStorage = {
+    async open(name) {
+      op_caches_open(String(name));
+      return new Cache(String(name));
+    },
+    async has(name) {
+      return op_caches_has(String(name));
+    },
+    async delete(name) {
+      return op_caches_delete(String(name));
+    },
+    async keys() {
+      return op_caches_keys();
+    },
+    async match(request, options = {}) {
+      const url = cacheKey(request);
+      if (options.cacheName !== undefined) {
+        return toResponse(op_cache_match(String(options.cacheName), url));
+      }
+      return toResponse(op_caches_match(url));
+    },
+  };
+
+  // Checked on access: the snapshot is shared, the setting is per runtime
+  Object.defineProperty(globalThis, "caches", {
+    get: () => (op_cache_enabled() ? cacheStorage : undefined),
+    configurable: false,
+    enumerable: false,
+  });
+}
+
+// ============================================================================
+// Fragment Cache (__ssr_cache__, enabled by SandboxConfig.fragment_cache_size)
+// ============================================================================
+// Memoizes expensive subtree HTML across requests on the same isolate:
+//
+//   let nav = __ssr_cache__.get("nav");
+//   if (nav === undefined) __ssr_cache__.set("nav", nav = renderNav(), 60_000);
+//
+// `ttl` is in milliseconds (omit to keep the entry until it is evicted).
+// Undefined unless enabled; the cache lives in Rust, bounded by size.
+
+{
+  const fragmentCache = Object.freeze({
+    get(key) {
+      return op_fragment_cache_get(String(key)) ?? undefined;
+    },
+    set(key, html, ttl) {
+      if (typeof html !== "string") {
+        throw new TypeError("__ssr_cache__.set: html must be a string");
+      }
+      if (ttl !== undefined && !(Number.isFinite(ttl) && ttl >= 0)) {
+        throw new TypeError("__ssr_cache__.set: ttl must be a non-negative number of milliseconds");
+      }
+      return op_fragment_cache_set(String(key), html, ttl === undefined ? null : Math.floor(ttl));
+    },
+  });
+
+  // Checked on access: the snapshot is shared, the setting is per runtime
+  Object.defineProperty(globalThis, "__ssr_cache__", {
+    get: () => (op_fragment_cache_enabled() ? fragmentCache : undefined),
+    configurable: false,
+    enumerable: false,
+  });
+}
+
+// ============================================================================
+// Web Storage (localStorage/sessionStorage, mode set by SandboxConfig.storage)
+// ============================================================================
+
+// Wipes the in-memory stores, called before every render
+let resetStorage;
+
+{
+  class Storage {
+    #items = new Map();
+
+    get length() {
+      return this.#items.size;
+    }
+
+    key(index) {
+      return [...this.#items.keys()][index] ?? null;
+    }
+
+    getItem(key) {
+      return this.#items.get(String(key)) ?? null;
+    }
+
+    setItem(key, value) {
+      this.#items.set(String(key), String(value));
+    }
+
+    removeItem(key) {
+      this.#items.delete(String(key));
+    }
+
+    clear() {
+      this.#items.clear();
+    }
+  }
+
+  const strictStorage = (name) => {
+    const fail = () => {
+      throw new Error(
+        `${name} is not available during SSR (storage mode is "strict"). ` +
+          "Guard browser-only code or read it after hydration.",
+      );
+    };
+    return {
+      get length() {
+        return fail();
+      },
+      key: fail,
+      getItem: fail,
+      setItem: fail,
+      removeItem: fail,
+      clear: fail,
+    };
+  };
+
+  const storages = {
+    localStorage: { memory: new Storage(), strict: strictStorage("localStorage") },
+    sessionStorage: { memory: new Storage(), strict: strictStorage("sessionStorage") },
+  };
+
+  resetStorage = () => {
+    storages.localStorage.memory.clear();
+    storages.sessionStorage.memory.clear();
+  };
+
+  // Checked on access: the snapshot is shared, the setting is per runtime
+  for (const [name, modes] of Object.entries(storages)) {
+    Object.defineProperty(globalThis, name, {
+      get: () => modes[op_storage_mode()],
+      configurable: false,
+      enumerable: false,
+    });
+  }
+}
+
+// ============================================================================
+// Timers (limits set by SandboxConfig.timer_limits)
+// ============================================================================
+
+// setTimeout/setInterval run on deno_core's timers. A pending timer keeps the
+// render alive, so at most `max_pending` may exist at once and timers with a
+// delay above `max_delay_ms` are never scheduled. Timers still pending when a
+// render finishes are cancelled.
+let cancelPendingTimers;
+
+{
+  const { queueUserTimer, cancelTimer, getTimerDepth } = Deno.core;
+  const pending = new Set();
+  // Ids of never-scheduled timers are negative, so they can't clash
+  let droppedId = 0;
+
+  const schedule = (repeat, callback, delay, args) => {
+    if (typeof callback !== "function") {
+      throw new TypeError("Timer callback must be a function (strings are not evaluated)");
+    }
+    const { max_pending, max_delay_ms } = op_timer_limits();
+    delay = Math.max(0, Number(delay) || 0);
+    if (delay > max_delay_ms) {
+      return --droppedId;
+    }
+    if (pending.size >= max_pending) {
+      throw new RangeError(`Too many pending timers (limit ${max_pending})`);
+    }
+    const id = queueUserTimer(getTimerDepth() + 1, repeat, delay, () => {
+      if (!repeat) pending.delete(id);
+      try {
+        callback(...args);
+      } catch (e) {
+        reportException(e);
+      }
+    });
+    pending.add(id);
+    return id;
+  };
+
+  const clear = (id) => {
+    if (pending.delete(id)) cancelTimer(id);
+  };
+
+  globalThis.setTimeout = (callback, delay = 0, ...args) => schedule(false, callback, delay, args);
+  globalThis.setInterval = (callback, delay = 0, ...args) => schedule(true, callback, delay, args);
+  globalThis.clearTimeout = clear;
+  globalThis.clearInterval = clear;
+
+  cancelPendingTimers = () => {
+    for (const id of pending) cancelTimer(id);
+    pending.clear();
+  };
+}
+
+// Browser-only frame/idle callbacks stay stubs that never fire
+let timerId = 0;
+
+// requestAnimationFrame - browser-only, stub
+globalThis.requestAnimationFrame = (fn) => ++timerId;
+globalThis.cancelAnimationFrame = () => {};
+
+// requestIdleCallback - browser-only, stub
+globalThis.requestIdleCallback = (fn) => ++timerId;
+globalThis.cancelIdleCallback = () => {};
+
+// ============================================================================
+// WebSocket Stub
+// ============================================================================
+
+// Isomorphic code often references WebSocket at module scope (feature checks,
+// `WebSocket.OPEN`), so the global must exist. Only connecting is refused.
+globalThis.WebSocket = class WebSocket extends EventTarget {
+  static CONNECTING = 0;
+  static OPEN = 1;
+  static CLOSING = 2;
+  static CLOSED = 3;
+
+  constructor(url) {
+    super();
+    throw new DOMException(
+      `WebSocket is not available in the SSR sandbox (tried to connect to ${url})`,
+      "NotSupportedError",
+    );
+  }
+};
+
+// ============================================================================
+// WebAssembly (limits set by SandboxConfig.wasm_limits)
+// ============================================================================
+
+// Bytes compiled by renders go through op_wasm_limit, which refuses modules
+// over `max_module_bytes` and caps every memory a module defines or imports at
+// `max_memory_bytes`, so `memory.grow` fails instead of exhausting the heap.
+// `new WebAssembly.Memory()` is capped the same way. The wrapped constructors
+// share the native prototypes, so `instanceof` holds for modules compiled by
+// V8 itself.
+{
+  const wasm = globalThis.WebAssembly;
+  const NativeModule = wasm.Module;
+  const NativeMemory = wasm.Memory;
+  const NativeInstance = wasm.Instance;
+  const nativeCompile = wasm.compile;
+  const nativeInstantiate = wasm.instantiate;
+
+  const limit = (source) => {
+    let bytes;
+    if (ArrayBuffer.isView(source)) {
+      bytes = new Uint8Array(source.buffer, source.byteOffset, source.byteLength);
+    } else if (source instanceof ArrayBuffer) {
+      bytes = new Uint8Array(source);
+    } else {
+      throw new TypeError("WebAssembly source must be an ArrayBuffer or a typed array");
+    }
+    try {
+      return op_wasm_limit(bytes);
+    } catch (e) {
+      throw new wasm.CompileError(e.message);
+    }
+  };
+
+  const Module = function Module(bytes) {
+    if (new.target === undefined) {
+      throw new TypeError("WebAssembly.Module must be called with 'new'");
+    }
+    return Reflect.construct(NativeModule, [limit(bytes)], new.target);
+  };
+
+  const Memory = function Memory(descriptor) {
+    if (new.target === undefined) {
+      throw new TypeError("WebAssembly.Memory must be called with 'new'");
+    }
+    const { max_memory_bytes } = op_wasm_limits();
+    const maxPages = Math.floor(max_memory_bytes / 65536);
+    if (Number(descriptor?.initial) > maxPages) {
+      throw new RangeError(
+        `WebAssembly.Memory of ${descriptor.initial} pages is over the ${max_memory_bytes} byte limit`,
+      );
+    }
+    const maximum = Math.min(descriptor?.maximum ?? maxPages, maxPages);
+    return Reflect.construct(NativeMemory, [{ ...descriptor, maximum }], new.target);
+  };
+
+  for (const [wrapper, native] of [[Module, NativeModule], [Memory, NativeMemory]]) {
+    // Static methods (`WebAssembly.Module.exports()`) are inherited
+    Object.setPrototypeOf(wrapper, native);
+    wrapper.prototype = native.prototype;
+    Object.defineProperty(native.prototype, "constructor", { value: wrapper });
+  }
+
+  const responseBytes = async (source) => {
+    const response = await source;
+    if (!(response instanceof Response)) {
+      throw new TypeError("WebAssembly streaming source must be a Response");
+    }
+    if (!response.ok) {
+      throw new TypeError(`WebAssembly streaming source has status ${response.status}`);
+    }
+    return response.arrayBuffer();
+  };
+
+  const limited = {
+    Module,
+    Memory,
+    async compile(bytes) {
+      return nativeCompile(limit(bytes));
+    },
+    async instantiate(source, imports) {
+      if (source instanceof NativeModule) {
+        return nativeInstantiate(source, imports);
+      }
+      return nativeInstantiate(limit(source), imports);
+    },
+    async compileStreaming(source) {
+      return nativeCompile(limit(await responseBytes(source)));
+    },
+    async instantiateStreaming(source, imports) {
+      return nativeInstantiate(limit(await responseBytes(source)), imports);
+    },
+  };
+  for (const [name, value] of Object.entries(limited)) {
+    Object.defineProperty(wasm, name, {
+      value,
+      writable: true,
+      configurable: true,
+      enumerable: false,
+    });
+  }
+
+  // Instantiates a `.wasm` file imported by a module: the module loader caps
+  // its memories and generates a JS module that calls this with its imports
+  Object.defineProperty(globalThis, "__ssr_internal_wasm__", {
+    value: (specifier, imports) => {
+      const module = new NativeModule(op_wasm_module_bytes(specifier));
+      return new NativeInstance(module, imports).exports;
+    },
+    writable: false,
+    configurable: false,
+    enumerable: false,
+  });
+}
+
+// ============================================================================
+// Deprecated APIs - still work, reported as warnings in the render result
+// ============================================================================
+
+{
+  const deprecated = {
+    escape: "escape() is deprecated, use encodeURIComponent()",
+    unescape: "unescape() is deprecated, use decodeURIComponent()",
+  };
+  for (const [name, message] of Object.entries(deprecated)) {
+    const original = globalThis[name];
+    const wrapper = {
+      [name](value) {
+        op_warn_deprecated(message);
+        return original(value);
+      },
+    }[name];
+    Object.defineProperty(globalThis, name, {
+      value: wrapper,
+      writable: true,
+      configurable: true,
+      enumerable: false,
+    });
+  }
+}
+
+// ============================================================================
+// HTML Escaping (__ssr_escape__)
+// ============================================================================
+
+// Tagged template for hand-written markup: interpolated values are escaped,
+// markup built by the tag itself is inserted as-is, so templates can nest.
+//
+//   __ssr_escape__`<li title="${item.title}">${item.name}</li>`
+//   __ssr_escape__`<ul>${items.map((item) => __ssr_escape__`<li>${item}</li>`)}</ul>`
+//
+// Arrays are joined, null/undefined/false render nothing. Called as a plain
+// function it returns the escaped string. There is deliberately no way to mark
+// an arbitrary string as trusted.
+let unwrapSafeHtml;
+let trustHtml;
+
+{
+  const ESCAPES = {
+    "&": "&amp;", "<": "&lt;", ">": "&gt;", '"': "&quot;", "'": "&#39;", "`": "&#96;",
+  };
+  const escapeHtml = (value) => String(value).replace(/[&<>"'`]/g, (c) => ESCAPES[c]);
+
+  // Markup produced by the tag, only recognized if it is in this map
+  const trusted = new WeakMap();
+  const SafeHtml = Object.freeze({
+    __proto__: null,
+    toString() { return trusted.get(this) ?? ""; },
+  });
+  const trust = (html) => {
+    const value = Object.freeze({ __proto__: SafeHtml });
+    trusted.set(value, html);
+    return value;
+  };
+
+  const interpolate = (value) => {
+    if (value === null || value === undefined || value === false) return "";
+    if (trusted.has(value)) return trusted.get(value);
+    if (Array.isArray(value)) return value.map(interpolate).join("");
+    return escapeHtml(value);
+  };
+
+  const ssrEscape = (strings, ...values) => {
+    if (!Array.isArray(strings) || !Array.isArray(strings.raw)) {
+      return interpolate(strings);
+    }
+    let html = strings[0];
+    for (let i = 0; i < values.length; i++) {
+      html += interpolate(values[i]) + strings[i + 1];
+    }
+    return trust(html);
+  };
+
+  // Render functions may return tag output directly (or as `html`/`head`)
+  unwrapSafeHtml = (value) => (trusted.has(value) ? trusted.get(value) : value);
+  // Markup generated by the runtime itself (island placeholders)
+  trustHtml = trust;
+
+  Object.defineProperty(globalThis, "__ssr_escape__", {
+    value: Object.freeze(ssrEscape),
+    writable: false,
+    configurable: false,
+    enumerable: false,
+  });
+}
+
+// ============================================================================
+// Static Data Files (__ssr_read_data__)
+// ============================================================================
+// Reads a UTF-8 file from the configured data directory (SandboxConfig.data_dir),
+// e.g. JSON.parse(__ssr_read_data__("routes/manifest.json")). Throws when no
+// data directory is configured, the path leaves it or the file is too large.
+
+Object.defineProperty(globalThis, "__ssr_read_data__", {
+  value: function __ssr_read_data__(relpath) {
+    return op_read_data(String(relpath));
+  },
+  writable: false,
+  configurable: false,
+  enumerable: false,
+});
+
+// ============================================================================
+// Host Calls (__ssr_host__)
+// ============================================================================
+// Calls a function the embedder registered in SandboxConfig.host_functions,
+// e.g. const user = await __ssr_host__.call("getUser", props.userId).
+// Arguments and the result are JSON-serialized. Rejects for unregistered
+// names, calls over the host call timeout and errors returned by the host.
+
+Object.defineProperty(globalThis, "__ssr_host__", {
+  value: Object.freeze({
+    async call(name, ...args) {
+      return JSON.parse(await op_host_call(String(name), JSON.stringify(args)));
+    },
+  }),
+  writable: false,
+  configurable: false,
+  enumerable: false,
+});
+
+// ============================================================================
+// Deterministic Mode (SandboxConfig.deterministic)
+// ============================================================================
+// Date.now(), new Date(), performance.now(), Math.random(),
+// crypto.getRandomValues() and crypto.randomUUID() use a fixed clock and a
+// seeded PRNG, both reset at the start of every render. Installed on the first
+// render of a runtime with deterministic mode enabled, so other runtimes keep
+// the real implementations.
+let beginDeterministicRender;
+
+{
+  const RealDate = Date;
+  const realGetRandomValues = crypto.getRandomValues;
+  const MAX_RANDOM_BYTES = 65536;
+  let epochMs = 0;
+  let nextRandom = null;
+  let installed = false;
+
+  // sfc32: small, fast and good enough for anything a render should need
+  const sfc32 = (a, b, c, d) => () => {
+    a >>>= 0; b >>>= 0; c >>>= 0; d >>>= 0;
+    const t = (((a + b) | 0) + d) | 0;
+    d = (d + 1) | 0;
+    a = b ^ (b >>> 9);
+    b = (c + (c << 3)) | 0;
+    c = (c << 21) | (c >>> 11);
+    c = (c + t) | 0;
+    return t >>> 0;
+  };
+
+  const seedRandom = (seed) => {
+    const big = BigInt(seed);
+    nextRandom = sfc32(
+      Number(big & 0xffffffffn),
+      Number((big >> 32n) & 0xffffffffn),
+      0x9e3779b9,
+      1,
+    );
+    // The first outputs are poorly mixed
+    for (let i = 0; i < 12; i++) nextRandom();
+  };
+
+  const fillRandom = (bytes) => {
+    for (let i = 0; i < bytes.length; i += 4) {
+      const value = nextRandom();
+      for (let j = 0; j < 4 && i + j < bytes.length; j++) {
+        bytes[i + j] = (value >>> (8 * j)) & 0xff;
+      }
+    }
+  };
+
+  const isIntegerArray = (array) =>
+    ArrayBuffer.isView(array) && !(array instanceof DataView) &&
+    !(array instanceof Float32Array) && !(array instanceof Float64Array);
+
+  const install = () => {
+    const now = () => epochMs;
+    globalThis.Date = new Proxy(RealDate, {
+      construct: (target, args, newTarget) =>
+        Reflect.construct(target, args.length === 0 ? [epochMs] : args, newTarget),
+      apply: () => new RealDate(epochMs).toString(),
+      get: (target, key, receiver) => (key === "now" ? now : Reflect.get(target, key, receiver)),
+    });
+
+    Object.defineProperty(performance, "now", {
+      value: function now() {
+        return 0;
+      },
+      writable: true,
+      configurable: true,
+    });
+
+    Math.random = function random() {
+      return nextRandom() / 4294967296;
+    };
+
+    Object.defineProperty(crypto, "getRandomValues", {
+      value: function getRandomValues(array) {
+        // Let the real implementation throw for unsupported or oversized arrays
+        if (!isIntegerArray(array) || array.byteLength > MAX_RANDOM_BYTES) {
+          return realGetRandomValues.call(crypto, array);
+        }
+        fillRandom(new Uint8Array(array.buffer, array.byteOffset, array.byteLength));
+        return array;
+      },
+      writable: true,
+      configurable: true,
+    });
+
+    Object.defineProperty(crypto, "randomUUID", {
+      value: function randomUUID() {
+        const bytes = new Uint8Array(16);
+        fillRandom(bytes);
+        bytes[6] = (bytes[6] & 0x0f) | 0x40; // version 4
+        bytes[8] = (bytes[8] & 0x3f) | 0x80; // RFC 4122 variant
+        const hex = Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+        return `${hex.slice(0, 8)}-${hex.slice(8, 12)}-${hex.slice(12, 16)}-${hex.slice(16, 20)}-${hex.slice(20)}`;
+      },
+      writable: true,
+      configurable: true,
+    });
+  };
+
+  beginDeterministicRender = () => {
+    const config = op_determinism();
+    if (!config) return;
+    epochMs = Number(config.epoch_ms);
+    seedRandom(config.seed);
+    if (!installed) {
+      install();
+      installed = true;
+    }
+  };
+}
+
+// ============================================================================
+// Global Reset (SandboxConfig.context_per_request)
+// ============================================================================
+
+// Restores globalThis to the state it had before the first render: globals
+// added by a render are deleted, replaced or deleted built-ins are put back.
+// Only own properties of globalThis are covered - mutations of built-in
+// objects (e.g. `Array.prototype.x = ...`) are not undone.
+let resetGlobals;
+
+{
+  let baseline = null;
+
+  const sameDescriptor = (a, b) =>
+    a.value === b.value && a.get === b.get && a.set === b.set &&
+    a.writable === b.writable && a.enumerable === b.enumerable &&
+    a.configurable === b.configurable;
+
+  resetGlobals = () => {
+    if (baseline === null) {
+      // First render on this runtime: nothing has run yet, remember the state
+      baseline = Object.getOwnPropertyDescriptors(globalThis);
+      return;
+    }
+
+    const stuck = [];
+    for (const key of Reflect.ownKeys(globalThis)) {
+      if (!Object.hasOwn(baseline, key) && !Reflect.deleteProperty(globalThis, key)) {
+        stuck.push(String(key));
+      }
+    }
+    for (const key of Reflect.ownKeys(baseline)) {
+      const current = Object.getOwnPropertyDescriptor(globalThis, key);
+      if (current && sameDescriptor(current, baseline[key])) continue;
+      if (!Reflect.defineProperty(globalThis, key, baseline[key])) {
+        stuck.push(String(key));
+      }
+    }
+
+    if (stuck.length > 0) {
+      throw new Error("Globals could not be reset: " + stuck.join(", "));
+    }
+  };
+}
+
+// ============================================================================
+// Request Context (ExecuteOptions.request)
+// ============================================================================
+
+// `__SSR_REQUEST__` ({ url, headers, locale }, frozen) and `location` describe
+// the request being rendered. Both are undefined outside a render and in renders
+// without a request. Assigning `location` (isomorphic code polyfilling it for
+// SSR) replaces it until the render ends.
+let setRequestContext;
+
+{
+  let request;
+  let location;
+
+  setRequestContext = (context) => {
+    if (context == null) {
+      request = undefined;
+      location = undefined;
+      return;
+    }
+    const headers = {};
+    for (const [name, value] of Object.entries(context.headers ?? {})) {
+      headers[name.toLowerCase()] = value;
+    }
+    request = Object.freeze({
+      url: context.url,
+      headers: Object.freeze(headers),
+      locale: context.locale ?? null,
+    });
+    setLocationHref(context.url);
+    location = locationDescriptor.get();
+  };
+
+  Object.defineProperty(globalThis, "__SSR_REQUEST__", {
+    get: () => request,
+    configurable: false,
+    enumerable: false,
+  });
+  Object.defineProperty(globalThis, "location", {
+    get: () => location,
+    set: (value) => {
+      location = value;
+    },
+    configurable: false,
+    enumerable: false,
+  });
+  Object.defineProperty(globalThis, "Location", locationConstructorDescriptor);
+}
+
+// ============================================================================
+// SSR Internal Render (cached, not accessible to user code)
+// ============================================================================
+
+{
+  // Closure scope - these variables are NOT accessible from user code
+  const renderCache = {};
+  const moduleCache = {};
+  const renderErrors = {};
+  const encoder = new TextEncoder();
+
+  // Read a ReadableStream of string / Uint8Array chunks (e.g. React's renderToReadableStream)
+  const drainStream = async (stream, onChunk) => {
+    const reader = stream.getReader();
+    while (true) {
+      const { done, value } = await reader.read();
+      if (done) return;
+      onChunk(typeof value === "string" ? encoder.encode(value) : value);
+    }
+  };
+
+  // With `streaming`, HTML is handed to Rust chunk by chunk (op_render_chunk) and
+  // the promise resolves to "". Otherwise a returned stream is buffered into a string.
+  // `context` (e.g. `{ nonce }`) is passed to the render function as its second argument.
+  // `exportName` renders that named export as a fragment instead of the default export.
+  // `context.request` isn't passed on, it becomes `__SSR_REQUEST__` and `location`.
+  //
+  // Fast path: a cached render function that synchronously returns a short
+  // string is answered with that string, not a promise, so Rust can skip the
+  // promise and (unless async work is left) the event loop.
+  const ssrInternalRender = (
+    entry,
+    props,
+    streaming = false,
+    { request = null, ...context } = {},
+    exportName = null,
+  ) => {
+    // Storage never carries state from one request to the next
+    resetStorage();
+    setRequestContext(request);
+    context = { ...context, island: deferIsland };
+    if (context.hostFeed) {
+      context.hostFeed = hostFeed;
+    }
+    // Before the global reset, so the replaced Date is part of its baseline
+    beginDeterministicRender();
+
+    const perRequest = op_context_per_request();
+    const cleanUp = () => {
+      // Pending timers would keep the event loop (and the render) running
+      cancelPendingTimers();
+      setRequestContext(null);
+    };
+    const finish = () => {
+      try {
+        if (perRequest) {
+          resetGlobals();
+        }
+      } finally {
+        cleanUp();
+      }
+    };
+
+    // Reset before (covers async work left over from the previous render) and
+    // after, so a render that leaves globals behind fails itself
+    if (perRequest) {
+      try {
+        resetGlobals();
+      } catch (e) {
+        cleanUp();
+        return Promise.reject(e);
+      }
+    }
+
+    const render = exportName === null && !streaming ? renderCache[entry] : undefined;
+    if (render === undefined) {
+      return settle(renderEntry(entry, props, streaming, context, exportName), finish);
+    }
+    op_render_phase("rendering", entry);
+    let result;
+    try {
+      result = unwrapSafeHtml(render(props, Object.freeze(context)));
+    } catch (e) {
+      result = Promise.reject(e);
+    }
+    if (typeof result !== "string" || result.length >= FAST_PATH_MAX_LENGTH) {
+      return settle(renderResult(result, streaming), finish);
+    }
+    try {
+      finish();
+    } catch (e) {
+      return Promise.reject(e);
+    }
+    return result;
+  };
+
+  // Longest string a render may return on the fast path
+  const FAST_PATH_MAX_LENGTH = 4096;
+
+  const settle = async (pending, finish) => {
+    try {
+      return await pending;
+    } finally {
+      finish();
+    }
+  };
+
+  // Items the host pushes during the render (ExecuteOptions.host_feed):
+  // `for await (const item of context.hostFeed)` ends when the host is done
+  const hostFeed = Object.freeze({
+    [Symbol.asyncIterator]() {
+      return this;
+    },
+    next() {
+      return op_host_feed_next();
+    },
+  });
+
+  // `context.island(entry, props)`: defer rendering `entry` (relative to the
+  // chunks dir) to a later request, see SsrResult.islands. Returns the
+  // placeholder markup, which `__ssr_escape__` templates don't escape.
+  const deferIsland = (entry, props = {}) => {
+    const id = op_island_defer(String(entry), props);
+    return trustHtml(`<ssr-island data-id="${id}"></ssr-island>`);
+  };
+
+  // Import an entry module (once per entry)
+  const loadModule = async (entry) => {
+    // Check if we previously failed to load this entry
+    if (renderErrors[entry]) {
+      throw new Error("Module previously failed to load: " + renderErrors[entry]);
+    }
+
+    if (!moduleCache[entry]) {
+      try {
+        // Lets the Rust side report (and time out) slow top-level await
+        op_render_phase("loading", entry);
+        moduleCache[entry] = await import(entry);
+      } catch (e) {
+        // Cache the error so we don't retry failed imports
+        renderErrors[entry] = e.message || String(e);
+        throw e;
+      }
+    }
+    return moduleCache[entry];
+  };
+
+  // Import an entry and cache its render function (once per entry)
+  const loadRender = async (entry) => {
+    if (!renderCache[entry]) {
+      const mod = await loadModule(entry);
+      const render = mod.default || mod.render;
+      if (typeof render !== "function") {
+        // Not cached as a load error: fragments may still use its named exports
+        throw new Error("Module must export a default function or render function");
+      }
+      renderCache[entry] = render;
+    }
+    return renderCache[entry];
+  };
+
+  // A named export rendered as a fragment (ExecuteOptions.export)
+  const loadExport = async (entry, name) => {
+    const mod = await loadModule(entry);
+    if (typeof mod[name] !== "function") {
+      throw new Error(`Module has no exported function '${name}'`);
+    }
+    return mod[name];
+  };
+
+  const renderEntry = async (entry, props, streaming, context, exportName) => {
+    const render = exportName === null
+      ? await loadRender(entry)
+      : await loadExport(entry, exportName);
+
+    // Call the cached render function
+    op_render_phase("rendering", entry);
+    let result;
+    try {
+      result = render(props, Object.freeze(context));
+    } catch (e) {
+      result = Promise.reject(e);
+    }
+    return renderResult(result, streaming);
+  };
+
+  // What a render function returned (or a promise of it), as the render result
+  const renderResult = async (returned, streaming) => {
+    try {
+      let result = unwrapSafeHtml(await returned);
+      if (result !== null && typeof result === "object" && !(result instanceof ReadableStream)) {
+        const html = unwrapSafeHtml(result.html);
+        const head = unwrapSafeHtml(result.head);
+        if (html !== result.html || head !== result.head) {
+          result = { ...result, html, head };
+        }
+      }
+
+      if (result instanceof ReadableStream) {
+        if (streaming) {
+          await drainStream(result, op_render_chunk);
+          return "";
+        }
+        const decoder = new TextDecoder();
+        let html = "";
+        await drainStream(result, (chunk) => {
+          html += decoder.decode(chunk, { stream: true });
+        });
+        return html + decoder.decode();
+      }
+
+      if (streaming && typeof result === "string") {
+        op_render_chunk(encoder.encode(result));
+        return "";
+      }
+      // Structured result ({ html, head, statusCode, headers, redirect }):
+      // when streaming only the html is sent
+      if (streaming && typeof result?.html === "string") {
+        op_render_chunk(encoder.encode(result.html));
+        return "";
+      }
+      return result;
+    } catch (e) {
+      // The original error (and its stack) stays available as `cause`
+      throw new Error("Render error: " + (e.message || String(e)), { cause: e });
+    }
+  };
+
+  // Freeze the function so user code cannot replace it
+  Object.defineProperty(globalThis, "__ssr_internal_render__", {
+    value: ssrInternalRender,
+    writable: false,
+    configurable: false,
+    enumerable: false,
+  });
+
+  // Server startup warmup (preload_modules): load entries without rendering
+  Object.defineProperty(globalThis, "__ssr_internal_preload__", {
+    value: loadRender,
+    writable: false,
+    configurable: false,
+    enumerable: false,
+  });
+}
+
+// ============================================================================
+// Cleanup - freeze Deno namespace (kept for V8 snapshot compatibility)
+// ============================================================================
+// Note: We keep Deno.core for snapshot deserialization to work.
+// User code cannot access it through normal imports since the module loader
+// restricts access to the chunks directory only.
+// Deno.core.ops is frozen by create_runtime, after ops of embedder
+// extensions (which aren't in the snapshot) have been bound to it.
+Object.freeze(globalThis.Deno);
+Object.freeze(globalThis.Deno.core);
@@ -0,0 +1,95 @@
+//! On-disk V8 code cache for chunk modules.
+//!
+//! V8 produces a code cache blob after compiling a module the first time.
+//! Storing it (keyed by a hash of the module source) and handing it back on
+//! later loads skips parsing and compiling, which for large bundles saves
+//! hundreds of milliseconds per cold runtime. Blobs V8 rejects (e.g. after a
+//! V8 upgrade) are simply regenerated and overwritten.
+//!
+//! V8 only does basic sanity checks on cached data, so the cache directory must
+//! not be writable by anyone who shouldn't be able to run code in the sandbox.
+
+use anyhow::{anyhow, Error};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Distinguishes temp files of concurrent writers within the process
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Directory of code cache blobs, one file per module source hash
+#[derive(Debug, Clone)]
+pub struct CodeCache {
+    dir: PathBuf,
+}
+
+impl CodeCache {
+    /// Use `dir` for cache blobs, creating it if needed
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow!("Failed to create code cache dir '{}': {}", dir.display(), e))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Cache key for a module source (first 8 bytes of its SHA-256)
+    pub fn source_hash(code: &str) -> u64 {
+        let digest = Sha256::digest(code.as_bytes());
+        u64::from_be_bytes(digest[..8].try_into().unwrap())
+    }
+
+    /// Cached blob for a source hash, if there is one
+    pub fn get(&self, hash: u64) -> Option<Vec<u8>> {
+        std::fs::read(self.path(hash)).ok()
+    }
+
+    /// Store a blob, replacing any previous one atomically
+    pub fn put(&self, hash: u64, data: &[u8]) -> Result<(), Error> {
+        let temp = self.dir.join(format!(
+            "{:016x}.{}-{}.tmp",
+            hash,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&temp, data)
+            .and_then(|_| std::fs::rename(&temp, self.path(hash)))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&temp);
+                anyhow!("Failed to write code cache for {:016x}: {}", hash, e)
+            })
+    }
+
+    fn path(&self, hash: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.bin", hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = tempdir().unwrap();
+        let cache = CodeCache::new(dir.path().join("v8")).unwrap();
+        let hash = CodeCache::source_hash("export default () => 'hi';");
+
+        assert_eq!(cache.get(hash), None);
+        cache.put(hash, b"blob").unwrap();
+        assert_eq!(cache.get(hash).as_deref(), Some(&b"blob"[..]));
+        cache.put(hash, b"newer").unwrap();
+        assert_eq!(cache.get(hash).as_deref(), Some(&b"newer"[..]));
+
+        // Only the blob is left behind
+        assert_eq!(std::fs::read_dir(dir.path().join("v8")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_source_hash() {
+        assert_eq!(CodeCache::source_hash("a"), CodeCache::source_hash("a"));
+        assert_ne!(CodeCache::source_hash("a"), CodeCache::source_hash("b"));
+    }
+}
@@ -0,0 +1,273 @@
+//! Crash reports, so postmortems of aborted processes aren't blind.
+//!
+//! [`install_crash_hook`] hooks Rust panics and fatal V8 out-of-memory errors.
+//! Before the process goes down the hook gets a [`CrashReport`]: what
+//! happened, the renders in progress with their request ids, and the most
+//! recent events.
+//!
+//! Reporting from a dying process is best effort: state that the crashing
+//! thread holds a lock on is left out of the report rather than waited for.
+//! Fatal signals (e.g. the abort of a V8 fatal error) aren't hooked: a report
+//! allocates and the hook may do anything, neither of which is safe in a
+//! signal handler. The `crash-dumps` feature covers them with a dump written
+//! by async-signal-safe calls only.
+
+use anyhow::{anyhow, Error};
+use deno_core::v8;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Called with the report of a crash, right before the process aborts
+pub type CrashHook = Arc<dyn Fn(&CrashReport) + Send + Sync>;
+
+/// What a crash hook is told about a crash
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashReport {
+    /// What brought the process down, e.g. `panic: ...` or `V8 out of memory: ...`
+    pub reason: String,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub pid: u32,
+    /// Renders in progress, oldest first
+    pub renders: Vec<InFlightRender>,
+    /// Most recent events (renders started and finished, runtimes replaced),
+    /// oldest first
+    pub recent_events: Vec<String>,
+}
+
+/// A render in progress when the process crashed
+#[derive(Debug, Clone, Serialize)]
+pub struct InFlightRender {
+    pub entry: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Thread the render runs on (e.g. `ssr-tenant-<name>`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread: Option<String>,
+    pub elapsed_ms: u64,
+}
+
+/// Events kept for [`CrashReport::recent_events`]
+const MAX_RECENT_EVENTS: usize = 100;
+
+static HOOK: OnceLock<CrashHook> = OnceLock::new();
+/// Set once a V8 out-of-memory error was reported, so it is reported once
+static REPORTED: AtomicBool = AtomicBool::new(false);
+static NEXT_RENDER: AtomicU64 = AtomicU64::new(0);
+static IN_FLIGHT: Mutex<BTreeMap<u64, (InFlightRender, Instant)>> = Mutex::new(BTreeMap::new());
+static RECENT_EVENTS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Call `hook` when the process is about to crash (can only be installed once)
+///
+/// ```rust,ignore
+/// install_crash_hook(write_crash_report("/var/log/ssr-sandbox"))?;
+/// ```
+pub fn install_crash_hook(hook: CrashHook) -> Result<(), Error> {
+    HOOK.set(hook)
+        .map_err(|_| anyhow!("A crash hook is already installed"))?;
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        report(format!("panic: {}", info));
+        previous(info);
+    }));
+    Ok(())
+}
+
+/// Whether [`install_crash_hook`] was called (runtimes then report V8
+/// out-of-memory errors too)
+pub fn crash_hook_installed() -> bool {
+    HOOK.get().is_some()
+}
+
+/// Hook that writes every report to `<dir>/crash-<timestamp_ms>-<pid>.json`
+pub fn write_crash_report(dir: impl Into<PathBuf>) -> CrashHook {
+    let dir = dir.into();
+    Arc::new(move |report| {
+        let path = dir.join(format!("crash-{}-{}.json", report.timestamp_ms, report.pid));
+        let written = serde_json::to_vec_pretty(report)
+            .map_err(Error::from)
+            .and_then(|json| std::fs::write(&path, json).map_err(Error::from));
+        match written {
+            Ok(()) => eprintln!("[ssr-sandbox] Crash report written to {}", path.display()),
+            Err(e) => eprintln!(
+                "[ssr-sandbox] Failed to write crash report {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    })
+}
+
+/// Remember `event` for the next crash report (a no-op without a crash hook)
+pub fn record_event(event: impl Into<String>) {
+    if !crash_hook_installed() {
+        return;
+    }
+    let mut events = RECENT_EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    if events.len() >= MAX_RECENT_EVENTS {
+        events.pop_front();
+    }
+    events.push_back(event.into());
+}
+
+/// Marks a render as in progress until it is dropped, see [`CrashReport::renders`]
+pub(crate) struct InFlight(Option<u64>);
+
+impl InFlight {
+    pub(crate) fn start(entry: &str, request_id: Option<&str>) -> Self {
+        if !crash_hook_installed() {
+            return Self(None);
+        }
+        let id = NEXT_RENDER.fetch_add(1, Ordering::Relaxed);
+        let render = InFlightRender {
+            entry: entry.to_string(),
+            request_id: request_id.map(str::to_string),
+            thread: std::thread::current().name().map(str::to_string),
+            elapsed_ms: 0,
+        };
+        record_event(format!(
+            "render {} started{}",
+            entry,
+            request_suffix(request_id)
+        ));
+        IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, (render, Instant::now()));
+        Self(Some(id))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let Some(id) = self.0 else { return };
+        let finished = IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&id);
+        if let Some((render, started)) = finished {
+            record_event(format!(
+                "render {} finished in {}ms{}",
+                render.entry,
+                started.elapsed().as_millis(),
+                request_suffix(render.request_id.as_deref())
+            ));
+        }
+    }
+}
+
+fn request_suffix(request_id: Option<&str>) -> String {
+    request_id
+        .map(|id| format!(" (request {})", id))
+        .unwrap_or_default()
+}
+
+/// Report of a crash for `reason`, with whatever state isn't locked
+fn snapshot(reason: String) -> CrashReport {
+    let renders = IN_FLIGHT.try_lock().map_or_else(
+        |_| Vec::new(),
+        |renders| {
+            renders
+                .values()
+                .map(|(render, started)| InFlightRender {
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                    ..render.clone()
+                })
+                .collect()
+        },
+    );
+    let recent_events = RECENT_EVENTS
+        .try_lock()
+        .map_or_else(|_| Vec::new(), |events| events.iter().cloned().collect());
+    CrashReport {
+        reason,
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_millis() as u64),
+        pid: std::process::id(),
+        renders,
+        recent_events,
+    }
+}
+
+fn report(reason: String) {
+    if let Some(hook) = HOOK.get() {
+        hook(&snapshot(reason));
+    }
+}
+
+/// V8 out-of-memory callback, set on runtimes while a crash hook is installed
+pub(crate) extern "C" fn on_v8_oom(location: *const c_char, details: &v8::OomDetails) {
+    if REPORTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let text = |ptr: *const c_char| {
+        // SAFETY: V8 passes null or a NUL-terminated string
+        (!ptr.is_null()).then(|| {
+            unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    };
+    let kind = if details.is_heap_oom {
+        "heap"
+    } else {
+        "process"
+    };
+    let mut reason = format!("V8 out of memory ({})", kind);
+    for part in [text(location), text(details.detail)].into_iter().flatten() {
+        reason.push_str(": ");
+        reason.push_str(&part);
+    }
+    report(reason);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_renders_in_flight() {
+        // The hook is process-wide: install it in a child process running
+        // just this test, not in the test binary shared with other tests
+        const CHILD: &str = "SSR_SANDBOX_CRASH_HOOK_TEST";
+        if std::env::var_os(CHILD).is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "crash::tests::test_reports_renders_in_flight"])
+                .env(CHILD, "1")
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success(), "{}", stdout);
+            assert!(stdout.contains("1 passed"), "{}", stdout);
+            return;
+        }
+
+        install_crash_hook(Arc::new(|_| {})).unwrap();
+        assert!(install_crash_hook(Arc::new(|_| {})).is_err());
+
+        let first = InFlight::start("pages/home.js", Some("req-1"));
+        let second = InFlight::start("pages/about.js", None);
+        drop(first);
+
+        let report = snapshot("panic: test".to_string());
+        assert_eq!(report.reason, "panic: test");
+        assert_eq!(report.pid, std::process::id());
+        let entries: Vec<_> = report.renders.iter().map(|r| r.entry.as_str()).collect();
+        assert!(entries.contains(&"pages/about.js"));
+        assert!(!entries.contains(&"pages/home.js"));
+        let finished = "render pages/home.js finished in";
+        assert!(report
+            .recent_events
+            .iter()
+            .any(|event| event.starts_with(finished) && event.ends_with("(request req-1)")));
+        drop(second);
+    }
+}
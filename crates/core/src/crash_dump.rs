@@ -0,0 +1,318 @@
+//! Crash dumps of fatal signals (the `crash-dumps` feature).
+//!
+//! [`install_crash_dumps`] handles SIGSEGV, SIGBUS, SIGFPE, SIGILL, SIGTRAP
+//! and SIGABRT, e.g. a segfault inside V8. Before the process dies it writes
+//! `<dir>/ssr-sandbox-<pid>.dump`, a text file with the crate version, a hash
+//! of the config, the signal and fault address, the program counter, stack
+//! and frame pointers of the crashing thread, and the process memory map
+//! (`/proc/self/maps`), which is what symbolizing the program counter needs.
+//!
+//! The handler then restores the handler it replaced and lets it run, so
+//! V8's WebAssembly trap handler and Rust's stack overflow message keep
+//! working. Everything it does is async-signal-safe: the dump path and
+//! header are prepared up front and the dump is written with plain syscalls.
+
+use crate::admission::props_hash;
+use crate::runtime::SandboxConfig;
+use anyhow::{anyhow, Error};
+use std::ffi::{c_void, CString};
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+type SignalHandler = extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void);
+
+const FATAL_SIGNALS: [(c_int, &str); 6] = [
+    (libc::SIGSEGV, "SIGSEGV"),
+    (libc::SIGBUS, "SIGBUS"),
+    (libc::SIGFPE, "SIGFPE"),
+    (libc::SIGILL, "SIGILL"),
+    (libc::SIGTRAP, "SIGTRAP"),
+    (libc::SIGABRT, "SIGABRT"),
+];
+
+/// Where the dump goes and what it starts with, prepared by [`install_crash_dumps`]
+struct DumpTarget {
+    path: CString,
+    header: Vec<u8>,
+}
+
+static TARGET: OnceLock<DumpTarget> = OnceLock::new();
+/// Handlers replaced by [`install_crash_dumps`], by signal
+static PREVIOUS: OnceLock<Vec<(c_int, libc::sigaction)>> = OnceLock::new();
+/// Only the first fatal signal is dumped
+static DUMPED: AtomicBool = AtomicBool::new(false);
+
+/// Write a crash dump to `dir` when the process dies of a fatal signal
+///
+/// Call it before the first runtime is created, so V8 installs its own signal
+/// handlers on top and falls back to this one. Can only be installed once.
+/// Returns the path the dump will be written to.
+pub fn install_crash_dumps(
+    dir: impl AsRef<Path>,
+    config: &SandboxConfig,
+) -> Result<PathBuf, Error> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        return Err(anyhow!(
+            "Crash dump directory '{}' does not exist",
+            dir.display()
+        ));
+    }
+    let path = dir.join(format!("ssr-sandbox-{}.dump", std::process::id()));
+    let target = DumpTarget {
+        path: CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| anyhow!("Invalid crash dump path '{}'", path.display()))?,
+        header: dump_header(&props_hash(&config.describe())),
+    };
+    if TARGET.set(target).is_err() {
+        return Err(anyhow!("Crash dumps are already installed"));
+    }
+
+    // Save the handlers being replaced before installing any, so a signal
+    // arriving halfway through can always be handed on
+    let mut previous = Vec::new();
+    for (signal, name) in FATAL_SIGNALS {
+        // SAFETY: a zeroed sigaction is valid to receive the current action
+        unsafe {
+            let mut old: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(signal, std::ptr::null(), &mut old) != 0 {
+                return Err(anyhow!("Failed to read the {} handler", name));
+            }
+            previous.push((signal, old));
+        }
+    }
+    let previous = PREVIOUS.get_or_init(|| previous);
+
+    for (installed, (signal, name)) in FATAL_SIGNALS.into_iter().enumerate() {
+        // SAFETY: a zeroed sigaction is valid; the handler has the
+        // SA_SIGINFO signature and the pointers passed are valid
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_fatal_signal as SignalHandler as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                // All or nothing: put back the handlers installed so far
+                for (signal, old) in &previous[..installed] {
+                    libc::sigaction(*signal, old, std::ptr::null_mut());
+                }
+                return Err(anyhow!("Failed to install the {} handler", name));
+            }
+        }
+    }
+    Ok(path)
+}
+
+/// Lines every dump starts with
+fn dump_header(config_hash: &str) -> Vec<u8> {
+    format!(
+        "ssr-sandbox crash dump\nversion: {}\nconfig: {}\npid: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        config_hash,
+        std::process::id()
+    )
+    .into_bytes()
+}
+
+extern "C" fn on_fatal_signal(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+    if let Some(target) = TARGET.get() {
+        if !DUMPED.swap(true, Ordering::SeqCst) {
+            // SAFETY: the kernel passes a valid siginfo and ucontext
+            unsafe { write_dump(target, signal, &*info, context) };
+        }
+    }
+
+    // Hand the signal to the handler that was there before
+    if let Some((_, old)) = PREVIOUS
+        .get()
+        .and_then(|previous| previous.iter().find(|(s, _)| *s == signal))
+    {
+        // SAFETY: `old` is the action sigaction returned for this signal
+        unsafe { libc::sigaction(signal, old, std::ptr::null_mut()) };
+    }
+    // Faults happen again when the handler returns; sent signals must be resent
+    // SAFETY: `info` is valid and `raise` is async-signal-safe
+    if unsafe { (*info).si_code } <= 0 {
+        unsafe { libc::raise(signal) };
+    }
+}
+
+/// Write the dump with async-signal-safe calls only (no allocation, no locks)
+unsafe fn write_dump(
+    target: &DumpTarget,
+    signal: c_int,
+    info: &libc::siginfo_t,
+    context: *mut c_void,
+) {
+    let fd = libc::open(
+        target.path.as_ptr(),
+        libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+        0o600,
+    );
+    if fd < 0 {
+        return;
+    }
+    let out = Out(fd);
+    out.bytes(&target.header);
+
+    let name = FATAL_SIGNALS
+        .iter()
+        .find(|(s, _)| *s == signal)
+        .map_or("unknown", |(_, name)| name);
+    out.field("signal", name);
+    out.number("code", info.si_code as u64);
+    out.address("address", info.si_addr() as usize);
+    #[cfg(target_os = "linux")]
+    {
+        out.number("thread", libc::gettid() as u64);
+        let mut thread_name = [0u8; 16];
+        libc::prctl(libc::PR_GET_NAME, thread_name.as_mut_ptr());
+        let len = thread_name.iter().position(|&b| b == 0).unwrap_or(16);
+        out.str("thread_name: ");
+        out.bytes(&thread_name[..len]);
+        out.str("\n");
+    }
+    for (label, value) in registers(context) {
+        out.address(label, value);
+    }
+
+    out.str("--- maps ---\n");
+    let maps = libc::open(
+        c"/proc/self/maps".as_ptr(),
+        libc::O_RDONLY | libc::O_CLOEXEC,
+    );
+    if maps >= 0 {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = libc::read(maps, buf.as_mut_ptr().cast(), buf.len());
+            if n <= 0 {
+                break;
+            }
+            out.bytes(&buf[..n as usize]);
+        }
+        libc::close(maps);
+    }
+    libc::fsync(fd);
+    libc::close(fd);
+    out.stderr_notice(&target.path);
+}
+
+/// Program counter, stack pointer and frame pointer of the crashing thread
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+unsafe fn registers(context: *mut c_void) -> [(&'static str, usize); 3] {
+    let gregs = &(*context.cast::<libc::ucontext_t>()).uc_mcontext.gregs;
+    [
+        ("pc", gregs[libc::REG_RIP as usize] as usize),
+        ("sp", gregs[libc::REG_RSP as usize] as usize),
+        ("fp", gregs[libc::REG_RBP as usize] as usize),
+    ]
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+unsafe fn registers(context: *mut c_void) -> [(&'static str, usize); 3] {
+    let mcontext = &(*context.cast::<libc::ucontext_t>()).uc_mcontext;
+    [
+        ("pc", mcontext.pc as usize),
+        ("sp", mcontext.sp as usize),
+        ("fp", mcontext.regs[29] as usize),
+    ]
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+unsafe fn registers(_context: *mut c_void) -> [(&'static str, usize); 0] {
+    []
+}
+
+/// Writes to a file descriptor without allocating
+struct Out(c_int);
+
+impl Out {
+    fn bytes(&self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            // SAFETY: `bytes` is a valid buffer of its length
+            let n = unsafe { libc::write(self.0, bytes.as_ptr().cast(), bytes.len()) };
+            if n <= 0 {
+                return;
+            }
+            bytes = &bytes[n as usize..];
+        }
+    }
+
+    fn str(&self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    fn field(&self, label: &str, value: &str) {
+        self.str(label);
+        self.str(": ");
+        self.str(value);
+        self.str("\n");
+    }
+
+    fn number(&self, label: &str, value: u64) {
+        let mut buf = [0u8; 20];
+        self.field(label, format_number(value, 10, &mut buf));
+    }
+
+    fn address(&self, label: &str, value: usize) {
+        let mut buf = [0u8; 20];
+        self.str(label);
+        self.str(": 0x");
+        self.str(format_number(value as u64, 16, &mut buf));
+        self.str("\n");
+    }
+
+    /// Tell stderr where the dump is
+    fn stderr_notice(&self, path: &CString) {
+        let stderr = Out(libc::STDERR_FILENO);
+        stderr.str("[ssr-sandbox] Crash dump written to ");
+        stderr.bytes(path.as_bytes());
+        stderr.str("\n");
+    }
+}
+
+/// `value` in base 10 or 16, formatted into `buf` without allocating
+fn format_number(mut value: u64, base: u64, buf: &mut [u8; 20]) -> &str {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut start = buf.len();
+    loop {
+        start -= 1;
+        buf[start] = DIGITS[(value % base) as usize];
+        value /= base;
+        if value == 0 {
+            break;
+        }
+    }
+    // Only ASCII digits were written
+    std::str::from_utf8(&buf[start..]).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_dump_fields() {
+        let mut buf = [0u8; 20];
+        assert_eq!(format_number(0, 10, &mut buf), "0");
+        assert_eq!(format_number(1234567, 10, &mut buf), "1234567");
+        assert_eq!(
+            format_number(0x7fff_5a3c_10f0, 16, &mut buf),
+            "7fff5a3c10f0"
+        );
+        assert_eq!(
+            format_number(u64::MAX, 10, &mut buf),
+            "18446744073709551615"
+        );
+
+        let header = String::from_utf8(dump_header("abc123")).unwrap();
+        assert!(header.starts_with("ssr-sandbox crash dump\nversion: "));
+        assert!(header.contains("\nconfig: abc123\n"));
+    }
+}
@@ -0,0 +1,143 @@
+//! Content-Security-Policy nonce support.
+//!
+//! The host generates a nonce per request and sends it with the request; the
+//! render function receives it as `context.nonce`. Output from renders that
+//! don't handle the nonce themselves can be post-processed with
+//! [`inject_nonce`], which adds `nonce="..."` to every `<script>` and `<style>`
+//! tag that doesn't have one.
+
+/// Elements that need a nonce under a strict CSP
+const NONCE_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Whether `nonce` is safe to use as an attribute value (base64 / base64url characters)
+pub fn is_valid_nonce(nonce: &str) -> bool {
+    !nonce.is_empty()
+        && nonce.len() <= 256
+        && nonce
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=' | '-' | '_'))
+}
+
+/// Add `nonce="<nonce>"` to every `<script>` and `<style>` start tag without a nonce.
+///
+/// Script and style content is skipped, so markup inside inline JS (e.g. a
+/// `"<script>"` string) is left alone. `nonce` must pass [`is_valid_nonce`].
+pub fn inject_nonce(html: &str, nonce: &str) -> String {
+    let mut out = String::with_capacity(html.len() + 64);
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map(|i| i + 3).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let name = element_name(rest);
+        let Some(element) = NONCE_ELEMENTS.iter().find(|e| name.eq_ignore_ascii_case(e)) else {
+            out.push('<');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let tag_end = find_tag_end(rest);
+        let tag = &rest[..tag_end];
+        out.push('<');
+        out.push_str(&tag[1..1 + name.len()]);
+        if !has_nonce_attribute(tag) {
+            out.push_str(&format!(" nonce=\"{}\"", nonce));
+        }
+        out.push_str(&tag[1 + name.len()..]);
+        rest = &rest[tag_end..];
+
+        // Raw text content runs until the closing tag
+        let close = format!("</{}", element);
+        let content_end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+        out.push_str(&rest[..content_end]);
+        rest = &rest[content_end..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Element name of a start tag at `s[0] == '<'` (empty for end tags, comments, text)
+fn element_name(s: &str) -> &str {
+    let name_len = s[1..]
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .unwrap_or(s.len() - 1);
+    let name = &s[1..1 + name_len];
+    // `<scripts>` is not `<script>`: the name must end at whitespace, `/` or `>`
+    match s[1 + name_len..].chars().next() {
+        Some(c) if c.is_ascii_whitespace() || c == '/' || c == '>' => name,
+        _ => "",
+    }
+}
+
+/// End of the tag starting at `s[0] == '<'`, respecting quoted attribute values
+fn find_tag_end(s: &str) -> usize {
+    let mut quote: Option<char> = None;
+    for (i, c) in s.char_indices().skip(1) {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return i + 1,
+            None => {}
+        }
+    }
+    s.len()
+}
+
+fn has_nonce_attribute(tag: &str) -> bool {
+    let lower = tag.to_ascii_lowercase();
+    lower
+        .match_indices("nonce")
+        .any(|(i, _)| lower[..i].ends_with(|c: char| c.is_ascii_whitespace()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_nonce() {
+        assert!(is_valid_nonce("rAnd0m+/base64=="));
+        assert!(is_valid_nonce("url_safe-nonce"));
+        assert!(!is_valid_nonce(""));
+        assert!(!is_valid_nonce("x\" onload=\"alert(1)"));
+        assert!(!is_valid_nonce(&"a".repeat(300)));
+    }
+
+    #[test]
+    fn test_injects_into_scripts_and_styles() {
+        let html =
+            r#"<head><style>p{}</style><script src="/app.js"></script></head><SCRIPT>x()</SCRIPT>"#;
+        assert_eq!(
+            inject_nonce(html, "abc"),
+            r#"<head><style nonce="abc">p{}</style><script nonce="abc" src="/app.js"></script></head><SCRIPT nonce="abc">x()</SCRIPT>"#
+        );
+    }
+
+    #[test]
+    fn test_keeps_existing_nonce() {
+        let html = r#"<script nonce="own">a()</script><script data-nonce="x">b()</script>"#;
+        assert_eq!(
+            inject_nonce(html, "abc"),
+            r#"<script nonce="own">a()</script><script nonce="abc" data-nonce="x">b()</script>"#
+        );
+    }
+
+    #[test]
+    fn test_skips_script_content_and_other_elements() {
+        let html = r#"<scripts></scripts><script>var s = "<style>";</script><!-- <script> --><p>a < b</p>"#;
+        assert_eq!(
+            inject_nonce(html, "n"),
+            r#"<scripts></scripts><script nonce="n">var s = "<style>";</script><!-- <script> --><p>a < b</p>"#
+        );
+    }
+}
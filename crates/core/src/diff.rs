@@ -0,0 +1,262 @@
+//! Structural HTML diff used by the `diff` CLI command.
+//!
+//! HTML is split into one token per tag / text node, with whitespace collapsed
+//! and tokens indented by nesting depth. The two token lists are then compared
+//! line by line, so formatting-only changes don't show up as differences.
+
+/// Maximum LCS table size (cells) before falling back to a coarse diff
+const MAX_LCS_CELLS: usize = 16 * 1024 * 1024;
+
+/// Elements that never have a closing tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is raw text (not parsed as HTML)
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// A single line of a structural diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+impl DiffLine {
+    pub fn is_change(&self) -> bool {
+        !matches!(self, DiffLine::Same(_))
+    }
+}
+
+/// Compare two HTML documents structurally.
+///
+/// Returns the full token listing with each line marked as unchanged,
+/// removed (only in `old`) or added (only in `new`).
+pub fn diff_html(old: &str, new: &str) -> Vec<DiffLine> {
+    let old_tokens = tokenize_html(old);
+    let new_tokens = tokenize_html(new);
+    diff_tokens(&old_tokens, &new_tokens)
+}
+
+/// Render diff lines in a unified-diff-like format, keeping `context` unchanged
+/// lines around each change. Returns an empty string if there are no changes.
+pub fn format_diff(lines: &[DiffLine], context: usize) -> String {
+    let mut out = String::new();
+    let mut last_printed: Option<usize> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let near_change = lines
+            [i.saturating_sub(context)..(i + context + 1).min(lines.len())]
+            .iter()
+            .any(DiffLine::is_change);
+        if !near_change {
+            continue;
+        }
+
+        if let Some(last) = last_printed {
+            if i > last + 1 {
+                out.push_str("...\n");
+            }
+        }
+        last_printed = Some(i);
+
+        match line {
+            DiffLine::Same(s) => out.push_str(&format!("  {}\n", s)),
+            DiffLine::Removed(s) => out.push_str(&format!("- {}\n", s)),
+            DiffLine::Added(s) => out.push_str(&format!("+ {}\n", s)),
+        }
+    }
+
+    out
+}
+
+/// Split HTML into normalized, depth-indented tokens (one per tag or text node)
+fn tokenize_html(html: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut depth: usize = 0;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map(|i| i + 3).unwrap_or(rest.len());
+            tokens.push(indent(depth, &collapse_whitespace(&rest[..end])));
+            rest = &rest[end..];
+        } else if rest.starts_with('<') {
+            let end = find_tag_end(rest);
+            let tag = collapse_whitespace(&rest[..end]);
+            rest = &rest[end..];
+
+            let name = tag_name(&tag);
+            if tag.starts_with("</") {
+                depth = depth.saturating_sub(1);
+                tokens.push(indent(depth, &tag));
+            } else {
+                tokens.push(indent(depth, &tag));
+                let self_closing = tag.ends_with("/>") || tag.starts_with("<!");
+                if !self_closing && !VOID_ELEMENTS.contains(&name.as_str()) {
+                    depth += 1;
+                }
+
+                // Raw text content runs until the matching closing tag
+                if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+                    let close = format!("</{}", name);
+                    let end = rest.to_ascii_lowercase().find(&close).unwrap_or(rest.len());
+                    let text = collapse_whitespace(&rest[..end]);
+                    if !text.is_empty() {
+                        tokens.push(indent(depth, &text));
+                    }
+                    rest = &rest[end..];
+                }
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = collapse_whitespace(&rest[..end]);
+            if !text.is_empty() {
+                tokens.push(indent(depth, &text));
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    tokens
+}
+
+/// Find the end of a tag starting at `s[0] == '<'`, respecting quoted attributes
+fn find_tag_end(s: &str) -> usize {
+    let mut quote: Option<char> = None;
+    for (i, c) in s.char_indices().skip(1) {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return i + 1,
+            None => {}
+        }
+    }
+    s.len()
+}
+
+/// Lowercased element name of a tag token (`<div class="a">` -> `div`)
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('<')
+        .trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn indent(depth: usize, s: &str) -> String {
+    format!("{}{}", "  ".repeat(depth), s)
+}
+
+/// Line diff via longest common subsequence (after trimming common prefix/suffix)
+fn diff_tokens(old: &[String], new: &[String]) -> Vec<DiffLine> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut lines: Vec<DiffLine> = old[..prefix].iter().cloned().map(DiffLine::Same).collect();
+
+    let (n, m) = (old_mid.len(), new_mid.len());
+    if (n + 1) * (m + 1) > MAX_LCS_CELLS {
+        // Too large for a precise diff - report the whole middle section as changed
+        lines.extend(old_mid.iter().cloned().map(DiffLine::Removed));
+        lines.extend(new_mid.iter().cloned().map(DiffLine::Added));
+    } else {
+        // lcs[i][j] = LCS length of old_mid[i..] and new_mid[j..]
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        let idx = |i: usize, j: usize| i * (m + 1) + j;
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[idx(i, j)] = if old_mid[i] == new_mid[j] {
+                    lcs[idx(i + 1, j + 1)] + 1
+                } else {
+                    lcs[idx(i + 1, j)].max(lcs[idx(i, j + 1)])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if old_mid[i] == new_mid[j] {
+                lines.push(DiffLine::Same(old_mid[i].clone()));
+                i += 1;
+                j += 1;
+            } else if lcs[idx(i + 1, j)] >= lcs[idx(i, j + 1)] {
+                lines.push(DiffLine::Removed(old_mid[i].clone()));
+                i += 1;
+            } else {
+                lines.push(DiffLine::Added(new_mid[j].clone()));
+                j += 1;
+            }
+        }
+        lines.extend(old_mid[i..].iter().cloned().map(DiffLine::Removed));
+        lines.extend(new_mid[j..].iter().cloned().map(DiffLine::Added));
+    }
+
+    lines.extend(old[old.len() - suffix..].iter().cloned().map(DiffLine::Same));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_html() {
+        let html = "<html><body><h1>Hi</h1></body></html>";
+        assert!(!diff_html(html, html).iter().any(DiffLine::is_change));
+    }
+
+    #[test]
+    fn test_ignores_formatting() {
+        let old = "<div><p>Hello   world</p></div>";
+        let new = "<div>\n  <p>\n    Hello world\n  </p>\n</div>\n";
+        assert!(!diff_html(old, new).iter().any(DiffLine::is_change));
+    }
+
+    #[test]
+    fn test_detects_text_change() {
+        let lines = diff_html("<p>old</p>", "<p>new</p>");
+        assert!(lines.contains(&DiffLine::Removed("  old".to_string())));
+        assert!(lines.contains(&DiffLine::Added("  new".to_string())));
+    }
+
+    #[test]
+    fn test_nesting_depth() {
+        let tokens = tokenize_html("<ul><li>a<br></li><li>b</li></ul>");
+        assert_eq!(
+            tokens,
+            vec!["<ul>", "  <li>", "    a", "    <br>", "  </li>", "  <li>", "    b", "  </li>", "</ul>"]
+        );
+    }
+
+    #[test]
+    fn test_script_content_is_raw_text() {
+        let tokens = tokenize_html("<script>if (a < b) {}</script><p>x</p>");
+        assert_eq!(tokens, vec!["<script>", "  if (a < b) {}", "</script>", "<p>", "  x", "</p>"]);
+    }
+
+    #[test]
+    fn test_format_diff_context() {
+        let old = "<a>1</a><a>2</a><a>3</a><a>4</a><a>5</a>";
+        let new = "<a>1</a><a>2</a><a>3</a><a>4</a><a>6</a>";
+        let out = format_diff(&diff_html(old, new), 1);
+        assert_eq!(out, "  <a>\n-   5\n+   6\n  </a>\n");
+        assert_eq!(format_diff(&diff_html(old, old), 3), "");
+    }
+}
@@ -0,0 +1,212 @@
+//! A `Send + Sync` handle to a [`TenantRouter`] on a dedicated thread.
+//!
+//! V8 runtimes are tied to the thread that created them, so a router can't be
+//! shared by the handlers of a multi-threaded web server. [`SsrHandle`] starts
+//! a thread that owns a router, with the tenant pools, quotas and refresh
+//! routes of its config, and renders the jobs sent to it; the handle is a
+//! cheap clone of the channel to that thread, usable from any thread or task.
+//! It brings its own tokio runtime, so it can also be created outside of one.
+//! `SsrService` (the `tower` feature) is a tower service on top of it.
+
+use crate::pool::TenantRouter;
+use crate::runtime::{ExecuteOptions, SandboxConfig, SsrError, SsrErrorKind, SsrResult};
+use anyhow::{anyhow, Error};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+use tokio::sync::{mpsc as async_mpsc, oneshot};
+
+struct Job {
+    entry_point: PathBuf,
+    props: serde_json::Value,
+    options: ExecuteOptions,
+    reply: oneshot::Sender<Result<SsrResult, SsrError>>,
+}
+
+/// Cloneable handle rendering on a dedicated runtime thread
+#[derive(Clone)]
+pub struct SsrHandle {
+    jobs: async_mpsc::UnboundedSender<Job>,
+}
+
+impl SsrHandle {
+    /// Start the render thread with a [`TenantRouter`] for `config` and warm
+    /// up its runtimes (including [`SandboxConfig::warmup`] entries).
+    ///
+    /// The thread exits once every clone of the handle has been dropped.
+    pub fn new(config: SandboxConfig) -> Result<Self, Error> {
+        let (jobs, receiver) = async_mpsc::unbounded_channel::<Job>();
+        let (ready, started) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("ssr-handle".to_string())
+            .spawn(move || {
+                // Default pool renders run on this thread, tenant renders on
+                // the tenant threads; the worker thread keeps their render
+                // watchdogs running while JS blocks them
+                let tokio = tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(1)
+                    .thread_name("ssr-handle-watchdog")
+                    .enable_all()
+                    .build();
+                let tokio = match tokio {
+                    Ok(tokio) => tokio,
+                    Err(e) => {
+                        let _ = ready.send(Err(e.into()));
+                        return;
+                    }
+                };
+                let _guard = tokio.enter();
+                let started_router = TenantRouter::new(config).and_then(|mut router| {
+                    tokio.block_on(router.warm_up())?;
+                    Ok(router)
+                });
+                match started_router {
+                    Ok(router) => {
+                        let _ = ready.send(Ok(()));
+                        tokio.block_on(serve(router, receiver));
+                    }
+                    Err(e) => {
+                        let _ = ready.send(Err(e));
+                    }
+                }
+            })?;
+
+        started
+            .recv()
+            .map_err(|_| anyhow!("SSR handle thread exited"))??;
+        Ok(Self { jobs })
+    }
+
+    /// Render `entry_point` (inside the chunks directory) with the default
+    /// options. Renders run one at a time, in the order they were called.
+    pub async fn render(
+        &self,
+        entry_point: impl Into<PathBuf>,
+        props: serde_json::Value,
+    ) -> Result<SsrResult, SsrError> {
+        self.render_with_options(entry_point, props, ExecuteOptions::default())
+            .await
+    }
+
+    /// Render with per-request options, see [`TenantRouter::render`]
+    pub async fn render_with_options(
+        &self,
+        entry_point: impl Into<PathBuf>,
+        props: serde_json::Value,
+        options: ExecuteOptions,
+    ) -> Result<SsrResult, SsrError> {
+        let exited = || SsrError::new(SsrErrorKind::ThreadExited, "SSR handle thread has exited");
+        let (reply, result) = oneshot::channel();
+        let job = Job {
+            entry_point: entry_point.into(),
+            props,
+            options,
+            reply,
+        };
+        self.jobs.send(job).map_err(|_| exited())?;
+        result.await.map_err(|_| exited())?
+    }
+}
+
+/// Render jobs, and refresh routes when they are due, until every handle is gone
+async fn serve(mut router: TenantRouter, mut jobs: async_mpsc::UnboundedReceiver<Job>) {
+    loop {
+        let due = router.next_refresh();
+        tokio::select! {
+            job = jobs.recv() => {
+                let Some(job) = job else { break };
+                let result = router.render(&job.entry_point, job.props, job.options).await;
+                let _ = job.reply.send(result);
+            }
+            _ = sleep_until(due) => {
+                for (entry, result) in router.refresh_due().await {
+                    if let Err(e) = result {
+                        eprintln!("[ssr-sandbox] Refresh of {} failed: {}", entry, e);
+                    }
+                }
+            }
+        }
+        // Replace used or broken runtimes before the next job
+        if let Err(e) = router.refill() {
+            eprintln!("[ssr-sandbox] Failed to refill runtime pool: {}", e);
+        }
+        if let Err(e) = router.warm_up().await {
+            eprintln!("[ssr-sandbox] Warmup failed: {}", e);
+        }
+    }
+}
+
+async fn sleep_until(at: Option<Instant>) {
+    match at {
+        Some(at) => tokio::time::sleep_until(at.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TenantLimits, TenantQuota};
+
+    #[test]
+    fn test_renders_from_other_threads() {
+        let chunks = tempfile::tempdir().unwrap();
+        let entry = chunks.path().join("entry.js");
+        std::fs::write(&entry, "export default (props) => `<p>${props.n}</p>`;").unwrap();
+        let ssr = SsrHandle::new(SandboxConfig {
+            chunks_dir: chunks.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let renders: Vec<_> = (0..2)
+            .map(|n| {
+                let ssr = ssr.clone();
+                let entry = entry.clone();
+                thread::spawn(move || {
+                    let tokio = tokio::runtime::Builder::new_current_thread()
+                        .build()
+                        .unwrap();
+                    let render = ssr.render(entry, serde_json::json!({ "n": n }));
+                    tokio.block_on(render).unwrap().html
+                })
+            })
+            .collect();
+        for (n, render) in renders.into_iter().enumerate() {
+            assert_eq!(render.join().unwrap(), format!("<p>{}</p>", n));
+        }
+    }
+
+    #[test]
+    fn test_enforces_tenant_quota() {
+        let chunks = tempfile::tempdir().unwrap();
+        let tenant = "handle-quota-test";
+        std::fs::create_dir(chunks.path().join(tenant)).unwrap();
+        let entry = chunks.path().join(tenant).join("entry.js");
+        std::fs::write(&entry, "export default () => '<p>hi</p>';").unwrap();
+        let limits = TenantLimits {
+            quota: TenantQuota {
+                max_renders: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let ssr = SsrHandle::new(SandboxConfig {
+            chunks_dir: chunks.path().to_str().unwrap().to_string(),
+            tenants: [(tenant.to_string(), limits)].into(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let tokio = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let first = tokio.block_on(ssr.render(&entry, serde_json::json!({})));
+        assert_eq!(first.unwrap().html, "<p>hi</p>");
+        let second = tokio.block_on(ssr.render(&entry, serde_json::json!({})));
+        let error = second.unwrap_err();
+        assert!(matches!(error.kind, SsrErrorKind::QuotaExceeded(ref e) if e.limit == 1));
+    }
+}
@@ -0,0 +1,154 @@
+//! Liveness and readiness of server mode.
+//!
+//! A server is alive as soon as the process runs, and ready once its runtimes
+//! are created and warmed up (preloaded entries, connected fetch origins,
+//! filled refresh caches). [`Health`] tracks both, and [`serve_health`]
+//! answers `GET /livez` and `GET /readyz` over plain HTTP/1.1, so load
+//! balancers don't route traffic to a sidecar that is still cold.
+
+use anyhow::{anyhow, Error};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How long a health check connection may take to send its request line
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Liveness and readiness of a server, shared with the health endpoints
+#[derive(Debug, Clone)]
+pub struct Health {
+    started: Instant,
+    ready: Arc<AtomicBool>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Health {
+    /// Alive and not ready yet
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Start (or stop, when shutting down) accepting traffic
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::SeqCst);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Liveness, readiness and uptime as JSON, for the `@health` admin command
+    pub fn describe(&self) -> serde_json::Value {
+        serde_json::json!({
+            "alive": true,
+            "ready": self.is_ready(),
+            "uptime_ms": self.started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Status code and body for a request line like `GET /readyz HTTP/1.1`
+    fn respond(&self, request_line: &str) -> (u16, &'static str) {
+        let mut parts = request_line.split_whitespace();
+        let (method, path) = (parts.next(), parts.next());
+        if !matches!(method, Some("GET" | "HEAD")) {
+            return (405, "method not allowed");
+        }
+        // Query strings are ignored, e.g. `/readyz?verbose`
+        match path.and_then(|path| path.split('?').next()) {
+            Some("/livez") => (200, "alive"),
+            Some("/readyz") if self.is_ready() => (200, "ready"),
+            Some("/readyz") => (503, "not ready"),
+            _ => (404, "not found"),
+        }
+    }
+}
+
+/// Serve `GET /livez` (200 while the process runs) and `GET /readyz` (200
+/// once `health` is ready, 503 before) on `addr`
+///
+/// Connections are handled on spawned tasks, so checks are answered while a
+/// render is running. Returns the address bound (useful with port 0).
+pub async fn serve_health(addr: &str, health: Health) -> Result<SocketAddr, Error> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| anyhow!("Failed to bind the health endpoint to '{}': {}", addr, e))?;
+    let local_addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let health = health.clone();
+            tokio::spawn(async move {
+                // A client that hangs up or stalls only affects its own check
+                let _ = tokio::time::timeout(REQUEST_TIMEOUT, answer(stream, &health)).await;
+            });
+        }
+    });
+    Ok(local_addr)
+}
+
+async fn answer(mut stream: TcpStream, health: &Health) -> std::io::Result<()> {
+    let mut buf = [0u8; 1024];
+    let mut len = 0;
+    // Only the request line matters; headers and bodies are not read
+    while !buf[..len].contains(&b'\n') && len < buf.len() {
+        let n = stream.read(&mut buf[len..]).await?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let request_line = request.lines().next().unwrap_or_default();
+    let (status, body) = health.respond(request_line);
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let body = if request_line.starts_with("HEAD ") { "" } else { body };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_endpoints() {
+        let health = Health::new();
+        assert_eq!(health.respond("GET /livez HTTP/1.1"), (200, "alive"));
+        assert_eq!(health.respond("GET /readyz HTTP/1.1"), (503, "not ready"));
+        assert_eq!(health.describe()["ready"], false);
+
+        health.clone().set_ready(true);
+        assert_eq!(health.respond("GET /readyz?verbose HTTP/1.1"), (200, "ready"));
+        assert_eq!(health.respond("HEAD /readyz HTTP/1.1"), (200, "ready"));
+        assert_eq!(health.describe()["ready"], true);
+
+        assert_eq!(health.respond("GET /metrics HTTP/1.1").0, 404);
+        assert_eq!(health.respond("POST /readyz HTTP/1.1").0, 405);
+        assert_eq!(health.respond("").0, 405);
+    }
+}
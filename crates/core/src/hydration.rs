@@ -0,0 +1,184 @@
+//! Hydration mismatch detection.
+//!
+//! Compares two renders of the same entry/props. Any output that differs
+//! between them depends on something other than props (clock, randomness,
+//! locale, global state) and will most likely differ again when the client
+//! hydrates the page in the browser.
+
+use crate::diff::{diff_html, DiffLine};
+
+/// Likely source of a nondeterministic fragment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchCause {
+    /// Looks like a date, time or epoch timestamp (`Date.now()`, `new Date()`)
+    Clock,
+    /// Looks like a random identifier (`Math.random()`, `crypto.randomUUID()`)
+    Random,
+    /// Differs only in number formatting (`toLocaleString()`, `Intl`)
+    Locale,
+    Unknown,
+}
+
+impl std::fmt::Display for MismatchCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MismatchCause::Clock => write!(f, "clock (Date.now / new Date)"),
+            MismatchCause::Random => write!(f, "randomness (Math.random / crypto.randomUUID)"),
+            MismatchCause::Locale => write!(f, "locale-dependent formatting"),
+            MismatchCause::Unknown => write!(f, "unknown (global state?)"),
+        }
+    }
+}
+
+/// A fragment of output that differs between two renders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HydrationMismatch {
+    pub first: String,
+    pub second: String,
+    pub suspected_cause: MismatchCause,
+}
+
+/// Compare two renders and report every fragment that differs between them
+pub fn find_hydration_mismatches(first: &str, second: &str) -> Vec<HydrationMismatch> {
+    let mut mismatches = Vec::new();
+    let mut removed: Vec<String> = Vec::new();
+    let mut added: Vec<String> = Vec::new();
+
+    // Pair up each run of removed/added lines into one mismatch
+    let mut flush = |removed: &mut Vec<String>, added: &mut Vec<String>| {
+        if removed.is_empty() && added.is_empty() {
+            return;
+        }
+        let first = removed.join("\n");
+        let second = added.join("\n");
+        let suspected_cause = classify(&first, &second);
+        mismatches.push(HydrationMismatch {
+            first,
+            second,
+            suspected_cause,
+        });
+        removed.clear();
+        added.clear();
+    };
+
+    for line in diff_html(first, second) {
+        match line {
+            DiffLine::Removed(s) => removed.push(s.trim().to_string()),
+            DiffLine::Added(s) => added.push(s.trim().to_string()),
+            DiffLine::Same(_) => flush(&mut removed, &mut added),
+        }
+    }
+    flush(&mut removed, &mut added);
+
+    mismatches
+}
+
+/// Guess why two versions of a fragment differ
+fn classify(first: &str, second: &str) -> MismatchCause {
+    let (a, b) = differing_words(first, second);
+
+    if looks_like_time(&a) || looks_like_time(&b) {
+        MismatchCause::Clock
+    } else if looks_like_random(&a) || looks_like_random(&b) {
+        MismatchCause::Random
+    } else if !a.is_empty() && strip_number_formatting(&a) == strip_number_formatting(&b) {
+        MismatchCause::Locale
+    } else {
+        MismatchCause::Unknown
+    }
+}
+
+/// The words that differ between two fragments (common leading/trailing words removed)
+fn differing_words(first: &str, second: &str) -> (String, String) {
+    let a: Vec<&str> = first.split_whitespace().collect();
+    let b: Vec<&str> = second.split_whitespace().collect();
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    (
+        a[prefix..a.len() - suffix].join(" "),
+        b[prefix..b.len() - suffix].join(" "),
+    )
+}
+
+fn looks_like_time(s: &str) -> bool {
+    let digits: Vec<&str> = s
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|d| !d.is_empty())
+        .collect();
+
+    // Epoch seconds / milliseconds
+    if digits.iter().any(|d| d.len() == 10 || d.len() == 13) {
+        return true;
+    }
+    // hh:mm[:ss] or yyyy-mm-dd
+    (s.contains(':') && digits.len() >= 2)
+        || (digits.first().is_some_and(|d| d.len() == 4) && s.contains('-') && digits.len() >= 3)
+}
+
+fn looks_like_random(s: &str) -> bool {
+    s.split(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .any(|word| {
+            let alnum = word.chars().filter(|c| c.is_ascii_alphanumeric()).count();
+            let has_digit = word.chars().any(|c| c.is_ascii_digit());
+            let has_alpha = word.chars().any(|c| c.is_ascii_alphabetic());
+            // UUIDs, hex ids, base36 ids, long decimal fractions from Math.random()
+            alnum >= 8 && ((has_digit && has_alpha) || (word.starts_with('0') && alnum >= 12))
+        })
+        || (s.contains("0.") && s.len() >= 12)
+}
+
+fn strip_number_formatting(s: &str) -> String {
+    s.chars().filter(|c| !matches!(c, ',' | '.' | ' ' | '\u{a0}' | '\'')).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_renders() {
+        let html = "<p>Hello</p>";
+        assert!(find_hydration_mismatches(html, html).is_empty());
+    }
+
+    #[test]
+    fn test_detects_timestamp() {
+        let m = find_hydration_mismatches(
+            "<p>Rendered at 1717171717123</p>",
+            "<p>Rendered at 1717171718456</p>",
+        );
+        assert_eq!(m.len(), 1);
+        assert_eq!(m[0].suspected_cause, MismatchCause::Clock);
+    }
+
+    #[test]
+    fn test_detects_time_of_day() {
+        let m = find_hydration_mismatches("<span>10:42:01</span>", "<span>10:42:02</span>");
+        assert_eq!(m[0].suspected_cause, MismatchCause::Clock);
+    }
+
+    #[test]
+    fn test_detects_random_id() {
+        let m = find_hydration_mismatches(
+            r#"<div id="a3f9c2e1b7">x</div>"#,
+            r#"<div id="9be21f0c44">x</div>"#,
+        );
+        assert_eq!(m.len(), 1);
+        assert_eq!(m[0].suspected_cause, MismatchCause::Random);
+    }
+
+    #[test]
+    fn test_detects_locale_formatting() {
+        assert_eq!(classify("Total: 1,234.5", "Total: 1.234,5"), MismatchCause::Locale);
+    }
+
+    #[test]
+    fn test_unknown_cause() {
+        assert_eq!(classify("count: first", "count: second"), MismatchCause::Unknown);
+    }
+}
@@ -0,0 +1,206 @@
+//! Deno-style import maps, so bundles can keep bare specifiers like `react`
+//! or `#app/utils` and have them resolve to files inside the chunks directory.
+//!
+//! Supports `imports` and `scopes`, with exact keys and `/`-terminated prefix
+//! keys (the longest matching key wins, scopes before top-level imports).
+//! Targets are resolved against the location of the import map file. The
+//! loader checks mapped imports like any other, so a map can't point outside
+//! the chunks directory, at remote URLs or at non-JS files.
+
+use deno_core::{
+    anyhow::{anyhow, Error},
+    ModuleSpecifier,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The `import_map.json` format
+#[derive(Deserialize)]
+struct ImportMapJson {
+    #[serde(default)]
+    imports: BTreeMap<String, String>,
+    #[serde(default)]
+    scopes: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Specifier keys and their resolved targets, longest key first
+type SpecifierMap = Vec<(String, ModuleSpecifier)>;
+
+/// A parsed import map, see [`ImportMap::load`]
+#[derive(Debug, Clone)]
+pub struct ImportMap {
+    imports: SpecifierMap,
+    /// Scope URL prefixes and their specifier maps, longest scope first
+    scopes: Vec<(String, SpecifierMap)>,
+}
+
+impl ImportMap {
+    /// Read the import map at `path` (targets resolve relative to its directory)
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let display = path.display();
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read import map '{}': {}", display, e))?;
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| anyhow!("Failed to canonicalize import map '{}': {}", display, e))?;
+        let base = ModuleSpecifier::from_file_path(&canonical)
+            .map_err(|_| anyhow!("Invalid import map path '{}'", display))?;
+        Self::parse(&json, &base).map_err(|e| anyhow!("Invalid import map '{}': {}", display, e))
+    }
+
+    /// Parse an import map, resolving targets and scopes against `base`
+    pub fn parse(json: &str, base: &ModuleSpecifier) -> Result<Self, Error> {
+        let map: ImportMapJson = serde_json::from_str(json)?;
+        let mut scopes = map
+            .scopes
+            .iter()
+            .map(|(scope, imports)| {
+                let scope =
+                    resolve_target(scope, base).map_err(|e| anyhow!("scope '{}': {}", scope, e))?;
+                Ok((scope.to_string(), specifier_map(imports, base)?))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        scopes.sort_by_key(|(scope, _)| std::cmp::Reverse(scope.len()));
+        Ok(Self {
+            imports: specifier_map(&map.imports, base)?,
+            scopes,
+        })
+    }
+
+    /// Target of `specifier` imported from `referrer`, None if the map doesn't list it
+    pub fn resolve(&self, specifier: &str, referrer: &str) -> Option<ModuleSpecifier> {
+        self.scopes
+            .iter()
+            .filter(|(scope, _)| {
+                referrer == scope || (scope.ends_with('/') && referrer.starts_with(scope.as_str()))
+            })
+            .map(|(_, imports)| imports)
+            .chain(std::iter::once(&self.imports))
+            .find_map(|imports| resolve_in(imports, specifier))
+    }
+}
+
+fn specifier_map(
+    imports: &BTreeMap<String, String>,
+    base: &ModuleSpecifier,
+) -> Result<SpecifierMap, Error> {
+    let mut map = imports
+        .iter()
+        .map(|(key, target)| {
+            let resolved = resolve_target(target, base).map_err(|e| anyhow!("'{}': {}", key, e))?;
+            if key.ends_with('/') && !target.ends_with('/') {
+                return Err(anyhow!(
+                    "'{}': the target of a key ending in '/' must end in '/' too",
+                    key
+                ));
+            }
+            Ok((key.clone(), resolved))
+        })
+        .collect::<Result<SpecifierMap, Error>>()?;
+    map.sort_by_key(|(key, _)| std::cmp::Reverse(key.len()));
+    Ok(map)
+}
+
+/// Resolve a target (or scope) of the map: a path relative to the map or an absolute URL
+fn resolve_target(target: &str, base: &ModuleSpecifier) -> Result<ModuleSpecifier, Error> {
+    if target.starts_with("./") || target.starts_with("../") || target.starts_with('/') {
+        return base
+            .join(target)
+            .map_err(|e| anyhow!("invalid target '{}': {}", target, e));
+    }
+    // Bare targets aren't allowed, anything else must be a URL
+    ModuleSpecifier::parse(target).map_err(|_| {
+        anyhow!(
+            "target '{}' must start with './', '../' or '/', or be a URL",
+            target
+        )
+    })
+}
+
+fn resolve_in(imports: &SpecifierMap, specifier: &str) -> Option<ModuleSpecifier> {
+    imports.iter().find_map(|(key, target)| {
+        if key == specifier {
+            return Some(target.clone());
+        }
+        let rest = specifier.strip_prefix(key.as_str())?;
+        if !key.ends_with('/') {
+            return None;
+        }
+        target.join(rest).ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import_map() -> ImportMap {
+        let base = ModuleSpecifier::parse("file:///app/chunks/import_map.json").unwrap();
+        ImportMap::parse(
+            r##"{
+                "imports": {
+                    "react": "./vendor/react.js",
+                    "#app/": "./src/app/",
+                    "#app/utils": "./src/utils.js"
+                },
+                "scopes": {
+                    "./legacy/": { "react": "./vendor/react-17.js" }
+                }
+            }"##,
+            &base,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_resolves_exact_and_prefix_keys() {
+        let map = import_map();
+        let referrer = "file:///app/chunks/entry.js";
+        let resolve = |specifier| map.resolve(specifier, referrer).map(|url| url.to_string());
+        assert_eq!(
+            resolve("react").as_deref(),
+            Some("file:///app/chunks/vendor/react.js")
+        );
+        assert_eq!(
+            resolve("#app/pages/home.js").as_deref(),
+            Some("file:///app/chunks/src/app/pages/home.js")
+        );
+        // The exact key wins over the shorter prefix key
+        assert_eq!(
+            resolve("#app/utils").as_deref(),
+            Some("file:///app/chunks/src/utils.js")
+        );
+        assert_eq!(resolve("react-dom"), None);
+    }
+
+    #[test]
+    fn test_scopes_override_imports() {
+        let map = import_map();
+        assert_eq!(
+            map.resolve("react", "file:///app/chunks/legacy/page.js")
+                .unwrap()
+                .as_str(),
+            "file:///app/chunks/vendor/react-17.js"
+        );
+        assert_eq!(
+            map.resolve("#app/utils", "file:///app/chunks/legacy/page.js")
+                .unwrap()
+                .as_str(),
+            "file:///app/chunks/src/utils.js"
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_maps() {
+        let base = ModuleSpecifier::parse("file:///app/chunks/import_map.json").unwrap();
+        for json in [
+            r#"{"imports": {"react": "vendor/react.js"}}"#,
+            r##"{"imports": {"#app/": "./src/app"}}"##,
+            r#"{"imports": ["react"]}"#,
+        ] {
+            assert!(ImportMap::parse(json, &base).is_err(), "{}", json);
+        }
+    }
+}
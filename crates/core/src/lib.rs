@@ -0,0 +1,121 @@
+//! # SSR Sandbox
+//!
+//! A minimal, secure runtime for server-side rendering using deno_core.
+//!
+//! ## Security Guarantees
+//!
+//! - **No filesystem access**: Only the specified chunks directory is readable
+//! - **No network access**: All HTTP/HTTPS imports are blocked
+//! - **No environment access**: `process.env`, `Deno.env` don't exist
+//! - **No shell access**: No `child_process`, `Deno.run`, etc.
+//! - **Dynamic imports sandboxed**: `import()` only works within chunks dir
+//!
+//! The `ssr-sandbox` binary and the server mode protocol
+//! (`ssr-sandbox-protocol`) are crates of their own, so embedders only get
+//! the runtime.
+//!
+//! Runtimes can be created on any number of threads: the V8 platform is set
+//! up once per process by [`init`], which the runtime constructors call.
+//!
+//! ## Usage
+//!
+//! ```rust,ignore
+//! use ssr_sandbox_core::{create_runtime, SandboxConfig};
+//! use std::path::Path;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let config = SandboxConfig {
+//!         chunks_dir: "./dist/chunks".into(),
+//!         ..Default::default()
+//!     };
+//!
+//!     let mut runtime = create_runtime(&config).unwrap();
+//!     let result = runtime.render(
+//!         Path::new("./dist/chunks/entry-server.js"),
+//!         serde_json::json!({ "url": "/page" }),
+//!         Some(5000),
+//!     ).await.unwrap();
+//!
+//!     println!("{}", result.html);
+//! }
+//! ```
+
+mod adaptive_timeout;
+mod admission;
+mod affinity;
+mod audit;
+mod code_cache;
+mod crash;
+#[cfg(feature = "crash-dumps")]
+mod crash_dump;
+mod csp;
+mod diff;
+mod handle;
+mod health;
+mod hydration;
+mod import_map;
+mod loader;
+mod metering;
+pub mod ops;
+mod pipeline;
+mod platform;
+mod pool;
+mod profile;
+mod rewrite;
+mod runtime;
+mod sanitize;
+mod source_map;
+#[cfg(feature = "tower")]
+pub mod service;
+mod stats;
+
+pub use adaptive_timeout::{AdaptiveTimeout, EntryTimeout, EntryTimeouts};
+pub use admission::{props_hash, DenyMatch, DenyRule, Denylist};
+pub use affinity::{thread_placements, ThreadAffinity, ThreadPlacement};
+pub use audit::{AuditLog, AuditOutcome, AuditRecord};
+pub use code_cache::CodeCache;
+pub use crash::{
+    crash_hook_installed, install_crash_hook, record_event, write_crash_report, CrashHook,
+    CrashReport, InFlightRender,
+};
+#[cfg(feature = "crash-dumps")]
+pub use crash_dump::install_crash_dumps;
+pub use csp::{inject_nonce, is_valid_nonce};
+pub use diff::{diff_html, format_diff, DiffLine};
+pub use handle::SsrHandle;
+pub use health::{serve_health, Health};
+pub use hydration::{find_hydration_mismatches, HydrationMismatch, MismatchCause};
+pub use import_map::ImportMap;
+pub use loader::{ModuleGraph, ModuleProvider, SandboxedLoader, SharedModuleProvider};
+pub use metering::{
+    tenant_usage, QuotaExceeded, RenderUsage, TenantMeter, TenantQuota, TenantUsage,
+};
+pub use ops::{
+    cache_memory_usage, count_violation, fragment_cache_stats, host_feed,
+    record_violation_exemplar, request_tag, violation_counts, violation_exemplars, CacheBudget,
+    CacheKind, CacheMemoryUsage, CachedResponse, ConsoleEntry, ConsoleLimits, ConsoleOutput,
+    ConsoleSubscriber, ConsoleSubscribers, DataDir, DeterminismConfig, FetchConfig, FetchStats,
+    FragmentCache, FragmentCacheStats, HostFeed, HostFeedSender, HostFunction, HostFunctions,
+    HostFuture, HttpClients, Island, LogLevel, ResponseCache, SandboxViolation, SecretHeaders,
+    StorageMode, TimerLimits, TlsPolicy, TlsVersion, ViolationKind, Warning, WarningCode,
+    WasmLimits, MAX_ISLANDS_PER_RENDER, SLOW_FETCH_THRESHOLD,
+};
+pub use pipeline::{
+    output_processor_stats, props_transformer_stats, OutputProcessor, OutputProcessors,
+    PropsTransformer, PropsTransformers, StageStats,
+};
+pub use platform::{init, is_initialized};
+pub use pool::{RuntimePool, TenantRouter};
+pub use profile::{RuntimeProfile, GLOBAL_GROUPS};
+pub use rewrite::{HtmlRewrites, StreamingRewriter};
+pub use runtime::{
+    analyze_entry, create_runtime, create_runtime_with_extensions, execute_ssr, execute_ssr_stream,
+    execute_ssr_with_options, is_valid_request_id, loaded_modules, preload_modules,
+    verify_snapshot, warm_up_origins, ExecuteOptions, ExtensionFactory, IsolationMode,
+    MemoryReservation, ModuleCost, RefreshRoute, RequestContext, RuntimeExtensions, RuntimeStats,
+    SandboxConfig, SsrError, SsrErrorKind, SsrResult, SsrRuntime, SsrStream, TenantLimits,
+};
+pub use sanitize::{sanitize_props, SanitizePolicy};
+pub use source_map::SourceMaps;
+pub use stats::ViolationMonitor;
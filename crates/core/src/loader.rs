@@ -0,0 +1,987 @@
+//! Sandboxed module loader that only allows loading JS from a specific directory.
+//! Blocks all network access, filesystem escape, and restricts to .js/.mjs files
+//! (plus `.js.map` source maps, which are read but never imported).
+//!
+//! `.wasm` files can be imported too: the loader caps their memories (see
+//! [`WasmLimits`]) and serves a generated JS module that instantiates them with
+//! their imports and re-exports their exports.
+//!
+//! Module code can also come from a [`ModuleProvider`] (e.g. bundles held in
+//! memory), which is asked before the filesystem. Bare specifiers are looked
+//! up in the [`ImportMap`] first, if one is configured.
+
+use deno_core::{
+    anyhow::{anyhow, Error},
+    ModuleLoadResponse, ModuleLoader, ModuleSource, ModuleSourceCode, ModuleSpecifier,
+    ModuleType, RequestedModuleType, ResolutionKind, SourceCodeCacheInfo,
+};
+use crate::code_cache::CodeCache;
+use crate::import_map::ImportMap;
+use crate::ops::{
+    RenderProgress, SandboxViolation, ViolationKind, ViolationLog, WasmLimits, WasmModules,
+};
+use crate::source_map::SourceMaps;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Serves module code from somewhere other than the chunks directory, e.g.
+/// bundles held in memory, an object store cache or an embedded archive.
+///
+/// Paths are relative to the chunks directory, with `/` separators and
+/// without `.` or `..` segments (e.g. `pages/home.js`), and always end in
+/// `.js` or `.mjs`. Modules the provider doesn't have are read from the
+/// chunks directory as usual. The integrity manifest applies to provided
+/// modules too.
+pub trait ModuleProvider: Send + Sync {
+    /// Code of the module at `path`, `Ok(None)` if the provider doesn't have it
+    fn load(&self, path: &str) -> Result<Option<String>, Error>;
+}
+
+/// Modules keyed by path (relative to the chunks directory)
+impl ModuleProvider for HashMap<String, String> {
+    fn load(&self, path: &str) -> Result<Option<String>, Error> {
+        Ok(self.get(path).cloned())
+    }
+}
+
+/// Module provider shared by every runtime of a config
+pub type SharedModuleProvider = Arc<dyn ModuleProvider>;
+
+/// A module loader that restricts all imports to a single directory.
+///
+/// Security guarantees:
+/// - No network access (http/https URLs rejected)
+/// - No filesystem escape (path traversal blocked via canonicalization)
+/// - Only .js, .mjs and .wasm files allowed
+/// - Dynamic imports supported but sandboxed
+///
+/// Every rejection is also recorded in a [`ViolationLog`] so it can be
+/// reported in the render result.
+pub struct SandboxedLoader {
+    allowed_dir: PathBuf,
+    violations: ViolationLog,
+    progress: RenderProgress,
+    /// Expected `sha256-<base64>` hash per module path (relative to `allowed_dir`)
+    integrity: Option<BTreeMap<String, String>>,
+    /// On-disk V8 code cache for loaded modules
+    code_cache: Option<CodeCache>,
+    /// Asked for module code before the filesystem
+    provider: Option<SharedModuleProvider>,
+    /// Resolves bare specifiers before the root of `allowed_dir` is tried
+    import_map: Option<ImportMap>,
+    graph: ModuleGraph,
+    source_maps: SourceMaps,
+    wasm_limits: WasmLimits,
+    /// Capped bytes of loaded `.wasm` files, until their JS module takes them
+    wasm_modules: WasmModules,
+}
+
+/// Imports between the modules of a runtime, used to tell which modules a
+/// render used.
+///
+/// Modules are only resolved and loaded the first time they are imported in a
+/// runtime, so later renders are reconstructed from the graph: the entry and
+/// every module `import()`ed during the render, plus everything they import
+/// statically.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleGraph(Rc<RefCell<GraphState>>);
+
+#[derive(Debug, Default)]
+struct GraphState {
+    /// Resolved imports per referrer (static imports and `import()` calls)
+    imports: BTreeMap<String, BTreeSet<String>>,
+    /// `(referrer, specifier)` pairs that are `import()` calls
+    dynamic: BTreeSet<(String, String)>,
+    /// The current render's entry and the modules it `import()`ed, in order
+    roots: Vec<String>,
+    /// First module that couldn't be found, by the entry whose render needed it
+    missing: BTreeMap<String, String>,
+}
+
+impl ModuleGraph {
+    /// Start collecting modules for a render of `entry`
+    pub fn begin_render(&self, entry: &str) {
+        self.0.borrow_mut().roots = vec![entry.to_string()];
+    }
+
+    /// Modules used by the current render: the entry and its static imports
+    /// first, then each `import()`ed module with its static imports
+    pub fn render_modules(&self) -> Vec<String> {
+        let state = self.0.borrow();
+        let mut seen = BTreeSet::new();
+        let mut modules = vec![];
+        for root in &state.roots {
+            let mut stack = vec![root];
+            while let Some(module) = stack.pop() {
+                if !seen.insert(module) {
+                    continue;
+                }
+                modules.push(module.clone());
+                let imports = state.imports.get(module).into_iter().flatten();
+                let static_imports = imports
+                    .filter(|import| !state.dynamic.contains(&(module.clone(), (*import).clone())));
+                // Reversed so imports are visited in (sorted) order
+                stack.extend(static_imports.collect::<Vec<_>>().into_iter().rev());
+            }
+        }
+        modules
+    }
+
+    /// `entry` and every module it imports statically, dependencies before the
+    /// modules importing them (the order V8 evaluates them in)
+    pub fn evaluation_order(&self, entry: &str) -> Vec<String> {
+        let state = self.0.borrow();
+        let mut seen = BTreeSet::new();
+        let mut order = vec![];
+        // `true` once the module's imports are on the stack above it
+        let mut stack = vec![(entry.to_string(), false)];
+        while let Some((module, expanded)) = stack.pop() {
+            if expanded {
+                order.push(module);
+                continue;
+            }
+            if !seen.insert(module.clone()) {
+                continue;
+            }
+            let imports: Vec<_> = state
+                .imports
+                .get(&module)
+                .into_iter()
+                .flatten()
+                .filter(|import| !state.dynamic.contains(&(module.clone(), (*import).clone())))
+                .map(|import| (import.clone(), false))
+                .collect();
+            stack.push((module, true));
+            // Reversed so imports are visited in (sorted) order
+            stack.extend(imports.into_iter().rev());
+        }
+        order
+    }
+
+    /// Every module imported in the runtime so far, by any render
+    pub fn modules(&self) -> BTreeSet<String> {
+        let state = self.0.borrow();
+        state.imports.values().flatten().cloned().collect()
+    }
+
+    fn record_resolved(&self, referrer: &str, specifier: &str, kind: ResolutionKind) {
+        let mut state = self.0.borrow_mut();
+        state
+            .imports
+            .entry(referrer.to_string())
+            .or_default()
+            .insert(specifier.to_string());
+        // Also used for static imports of modules loaded through `import()`,
+        // and `import()` calls are resolved again on every call
+        if kind == ResolutionKind::DynamicImport {
+            state.roots.push(specifier.to_string());
+        }
+    }
+
+    fn record_dynamic_import(&self, referrer: &str, specifier: &str) {
+        self.0
+            .borrow_mut()
+            .dynamic
+            .insert((referrer.to_string(), specifier.to_string()));
+    }
+
+    fn record_missing(&self, specifier: &str) {
+        let mut state = self.0.borrow_mut();
+        if let Some(entry) = state.roots.first().cloned() {
+            state.missing.entry(entry).or_insert(specifier.to_string());
+        }
+    }
+
+    /// Module the current render's entry needed that couldn't be found. Entries
+    /// that failed to load fail every later render, so this is kept per entry.
+    pub(crate) fn missing_module(&self) -> Option<String> {
+        let state = self.0.borrow();
+        state.missing.get(state.roots.first()?).cloned()
+    }
+}
+
+impl SandboxedLoader {
+    /// Create a new sandboxed loader that only allows loading from `allowed_dir`.
+    ///
+    /// # Panics
+    /// Panics if `allowed_dir` doesn't exist or can't be canonicalized.
+    pub fn new(allowed_dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let canonical = allowed_dir
+            .as_ref()
+            .canonicalize()
+            .map_err(|e| anyhow!("Failed to canonicalize allowed_dir: {}", e))?;
+
+        if !canonical.is_dir() {
+            return Err(anyhow!("allowed_dir must be a directory"));
+        }
+
+        Ok(Self {
+            source_maps: SourceMaps::new(&canonical),
+            allowed_dir: canonical,
+            violations: ViolationLog::default(),
+            progress: RenderProgress::default(),
+            integrity: None,
+            code_cache: None,
+            provider: None,
+            import_map: None,
+            graph: ModuleGraph::default(),
+            wasm_limits: WasmLimits::default(),
+            wasm_modules: WasmModules::default(),
+        })
+    }
+
+    /// Only load modules listed in `manifest` whose content matches the listed hash.
+    ///
+    /// Keys are paths relative to `allowed_dir` using `/` separators, values are
+    /// SRI-style `sha256-<base64>` hashes. Modules missing from the manifest are
+    /// refused as well.
+    pub fn with_integrity(mut self, manifest: BTreeMap<String, String>) -> Result<Self, Error> {
+        if let Some((path, _)) = manifest.iter().find(|(_, hash)| !hash.starts_with("sha256-")) {
+            return Err(anyhow!(
+                "Integrity manifest entry for '{}' must be a sha256-<base64> hash",
+                path
+            ));
+        }
+        self.integrity = Some(manifest);
+        Ok(self)
+    }
+
+    /// Hand V8 code cache blobs from `cache` back on load, and store new ones there
+    pub fn with_code_cache(mut self, cache: CodeCache) -> Self {
+        self.code_cache = Some(cache);
+        self
+    }
+
+    /// Load modules from `provider` when it has them, from `allowed_dir` otherwise
+    ///
+    /// Provided modules don't need to exist in `allowed_dir`, which only
+    /// serves as the root their paths are relative to.
+    pub fn with_provider(mut self, provider: SharedModuleProvider) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Resolve bare specifiers listed in `import_map` to its targets
+    ///
+    /// Mapped imports go through the same checks as any other import.
+    pub fn with_import_map(mut self, import_map: ImportMap) -> Self {
+        self.import_map = Some(import_map);
+        self
+    }
+
+    /// Refuse `.wasm` imports over `limits` and cap their memories
+    pub fn with_wasm_limits(mut self, limits: WasmLimits) -> Self {
+        self.wasm_limits = limits;
+        self
+    }
+
+    /// Handle to the log that rejected imports are recorded in
+    pub fn violation_log(&self) -> ViolationLog {
+        self.violations.clone()
+    }
+
+    /// Handle to the graph that resolved imports are recorded in
+    pub fn module_graph(&self) -> ModuleGraph {
+        self.graph.clone()
+    }
+
+    /// Handle to the render progress that loaded modules are reported to
+    pub fn render_progress(&self) -> RenderProgress {
+        self.progress.clone()
+    }
+
+    /// Handle to the source maps of the chunks (`<chunk>.js.map`)
+    pub fn source_maps(&self) -> SourceMaps {
+        self.source_maps.clone()
+    }
+
+    /// Handle to the `.wasm` modules loaded, for `op_wasm_module_bytes`
+    pub fn wasm_modules(&self) -> WasmModules {
+        self.wasm_modules.clone()
+    }
+
+    /// Record a violation and turn it into the error returned to V8
+    fn reject(
+        &self,
+        kind: ViolationKind,
+        specifier: &str,
+        referrer: Option<&str>,
+        message: String,
+    ) -> Error {
+        self.violations.record(SandboxViolation {
+            kind,
+            specifier: specifier.to_string(),
+            referrer: referrer.map(str::to_string),
+            message: message.clone(),
+            request_id: None,
+        });
+        anyhow!(message)
+    }
+
+    /// Check module content against the integrity manifest (if one is configured)
+    ///
+    /// `relative` is the module path relative to `allowed_dir`, `/`-separated.
+    fn verify_integrity(
+        &self,
+        specifier: &ModuleSpecifier,
+        relative: &str,
+        content: &[u8],
+    ) -> Result<(), Error> {
+        let Some(manifest) = &self.integrity else {
+            return Ok(());
+        };
+
+        let actual = format!(
+            "sha256-{}",
+            base64::engine::general_purpose::STANDARD.encode(Sha256::digest(content))
+        );
+        let message = match manifest.get(relative) {
+            Some(expected) if *expected == actual => return Ok(()),
+            Some(expected) => format!(
+                "Integrity check failed for '{}': expected {}, got {}",
+                relative, expected, actual
+            ),
+            None => format!("Module '{}' is not listed in the integrity manifest", relative),
+        };
+        Err(self.reject(ViolationKind::IntegrityMismatch, specifier.as_str(), None, message))
+    }
+
+    /// Path of a file on disk relative to `allowed_dir` (symlinks resolved), `/`-separated
+    fn canonical_relative(&self, path: &Path) -> String {
+        path.canonicalize()
+            .ok()
+            .and_then(|p| p.strip_prefix(&self.allowed_dir).ok().map(Path::to_path_buf))
+            .map(|p| {
+                p.components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .unwrap_or_default()
+    }
+
+    /// Path to ask the module provider for: `path` relative to `allowed_dir`,
+    /// `/`-separated, None without a provider or if `path` isn't plainly
+    /// inside `allowed_dir`. Symlinks aren't followed: the provider doesn't
+    /// read the filesystem, and files read from disk are checked as usual.
+    fn provider_path(&self, path: &Path) -> Option<String> {
+        self.provider.as_ref()?;
+        let relative = path.strip_prefix(&self.allowed_dir).ok()?;
+        let segments = relative
+            .components()
+            .map(|c| match c {
+                Component::Normal(segment) => segment.to_str(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        (!segments.is_empty()).then(|| segments.join("/"))
+    }
+
+    /// Code of the module at `path` and its path relative to `allowed_dir`,
+    /// from the module provider if it has it, from the filesystem otherwise
+    fn read_module(&self, path: &Path) -> Result<(String, String), Error> {
+        if let (Some(provider), Some(relative)) = (&self.provider, self.provider_path(path)) {
+            if let Some(code) = provider.load(&relative)? {
+                return Ok((code, relative));
+            }
+            if !path.exists() {
+                return Err(anyhow!(
+                    "Module '{}' is neither served by the module provider nor in the chunks directory",
+                    relative
+                ));
+            }
+        }
+
+        // Defense in depth: re-check path is allowed
+        if !self.is_path_allowed(path) {
+            return Err(anyhow!("Access denied: {}", path.display()));
+        }
+        let code = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read '{}': {}", path.display(), e))?;
+        Ok((code, self.canonical_relative(path)))
+    }
+
+    /// Check if a path is within the allowed directory.
+    /// Uses canonicalization to resolve symlinks and prevent traversal.
+    fn is_path_allowed(&self, path: &Path) -> bool {
+        match path.canonicalize() {
+            Ok(canonical) => canonical.starts_with(&self.allowed_dir),
+            Err(_) => false,
+        }
+    }
+
+    /// Validate file extension is allowed (.js, .mjs or .wasm only)
+    fn is_extension_allowed(path: &Path) -> bool {
+        matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("js") | Some("mjs") | Some("wasm")
+        )
+    }
+
+    /// JS module instantiating the `.wasm` file at `path`
+    ///
+    /// `.wasm` files are always read from disk: module providers only serve JS.
+    fn load_wasm(&self, specifier: &ModuleSpecifier, path: &Path) -> Result<String, Error> {
+        // Defense in depth: re-check path is allowed
+        if !self.is_path_allowed(path) {
+            if !path.exists() {
+                self.graph.record_missing(specifier.as_str());
+            }
+            return Err(anyhow!("Access denied: {}", path.display()));
+        }
+
+        let relative = self.canonical_relative(path);
+        let mut bytes = Vec::new();
+        // Read one byte past the limit, so oversized files are refused below
+        std::fs::File::open(path)
+            .and_then(|file| {
+                file.take(self.wasm_limits.max_module_bytes as u64 + 1)
+                    .read_to_end(&mut bytes)
+            })
+            .map_err(|e| anyhow!("Failed to read '{}': {}", path.display(), e))?;
+        let module = self
+            .wasm_limits
+            .apply(&bytes)
+            .map_err(|e| anyhow!("Failed to load '{}': {}", relative, e))?;
+        self.verify_integrity(specifier, &relative, &bytes)?;
+
+        self.progress.module_loaded(specifier.as_str());
+        self.wasm_modules.insert(specifier.to_string(), module.bytes);
+        wasm_module_code(specifier.as_str(), &module.imports, &module.exports)
+    }
+}
+
+/// Code of the JS module standing in for a `.wasm` module: each module the
+/// wasm module imports from is imported as a namespace, and its exports are
+/// re-exported by name (and as the default export, an object of all of them).
+fn wasm_module_code(
+    specifier: &str,
+    imports: &[(String, String)],
+    exports: &[String],
+) -> Result<String, Error> {
+    let mut code = String::new();
+    let mut import_object = Vec::new();
+    let modules: BTreeSet<&str> = imports.iter().map(|(module, _)| module.as_str()).collect();
+    for (i, module) in modules.into_iter().enumerate() {
+        // JSON strings are valid JS string literals
+        let module = serde_json::to_string(module)?;
+        code.push_str(&format!("import * as import{} from {};\n", i, module));
+        import_object.push(format!("{}: import{}", module, i));
+    }
+    code.push_str(&format!(
+        "const exports = __ssr_internal_wasm__({}, {{ {} }});\n",
+        serde_json::to_string(specifier)?,
+        import_object.join(", ")
+    ));
+    if !exports.iter().any(|name| name == "default") {
+        code.push_str("export default exports;\n");
+    }
+    for (i, name) in exports.iter().enumerate() {
+        let name = serde_json::to_string(name)?;
+        code.push_str(&format!(
+            "const export{} = exports[{}];\nexport {{ export{} as {} }};\n",
+            i, name, i, name
+        ));
+    }
+    Ok(code)
+}
+
+impl ModuleLoader for SandboxedLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+        // Block all remote URLs
+        if specifier.starts_with("http://")
+            || specifier.starts_with("https://")
+            || specifier.starts_with("data:")
+            || specifier.starts_with("blob:")
+        {
+            return Err(self.reject(
+                ViolationKind::RemoteImport,
+                specifier,
+                Some(referrer),
+                format!("Remote imports are forbidden: {}", specifier),
+            ));
+        }
+
+        let invalid = |message: String| {
+            self.reject(ViolationKind::InvalidSpecifier, specifier, Some(referrer), message)
+        };
+
+        // Resolve the specifier
+        let resolved = if specifier.starts_with("./") || specifier.starts_with("../") {
+            // Relative import - resolve against referrer
+            let referrer_url = ModuleSpecifier::parse(referrer)
+                .map_err(|e| invalid(format!("Invalid referrer '{}': {}", referrer, e)))?;
+            referrer_url
+                .join(specifier)
+                .map_err(|e| invalid(format!("Failed to resolve '{}': {}", specifier, e)))?
+        } else if specifier.starts_with("file://") {
+            // Absolute file URL
+            ModuleSpecifier::parse(specifier)
+                .map_err(|e| invalid(format!("Invalid file URL '{}': {}", specifier, e)))?
+        } else if specifier.starts_with('/') {
+            // Absolute path - convert to file URL
+            ModuleSpecifier::from_file_path(specifier)
+                .map_err(|_| invalid(format!("Invalid absolute path: {}", specifier)))?
+        } else if let Some(mapped) = self
+            .import_map
+            .as_ref()
+            .and_then(|map| map.resolve(specifier, referrer))
+        {
+            // Bare specifier listed in the import map
+            mapped
+        } else {
+            // Bare specifier - resolve from allowed_dir root
+            // This handles imports like "chunk-abc123.js"
+            ModuleSpecifier::from_file_path(self.allowed_dir.join(specifier))
+                .map_err(|_| invalid(format!("Invalid bare specifier: {}", specifier)))?
+        };
+
+        // Ensure it's a file:// URL
+        if resolved.scheme() != "file" {
+            return Err(self.reject(
+                ViolationKind::RemoteImport,
+                specifier,
+                Some(referrer),
+                format!("Only file:// URLs allowed, got: {}", resolved.scheme()),
+            ));
+        }
+
+        // Get the filesystem path
+        let path = resolved
+            .to_file_path()
+            .map_err(|_| invalid(format!("Failed to convert URL to path: {}", resolved)))?;
+
+        // Security check: path must be within allowed directory (on disk, or
+        // a path the module provider may serve)
+        if !self.is_path_allowed(&path) && self.provider_path(&path).is_none() {
+            let message = format!(
+                "Access denied: '{}' is outside the allowed directory",
+                path.display()
+            );
+            // A missing file inside the directory is not a violation
+            if path.starts_with(&self.allowed_dir) && !path.exists() {
+                self.graph.record_missing(resolved.as_str());
+                return Err(anyhow!(message));
+            }
+            return Err(self.reject(ViolationKind::PathEscape, specifier, Some(referrer), message));
+        }
+
+        // Extension check
+        if !Self::is_extension_allowed(&path) {
+            return Err(self.reject(
+                ViolationKind::ForbiddenExtension,
+                specifier,
+                Some(referrer),
+                format!("Only .js, .mjs and .wasm files allowed, got: {}", path.display()),
+            ));
+        }
+
+        self.graph.record_resolved(referrer, resolved.as_str(), kind);
+        Ok(resolved)
+    }
+
+    fn prepare_load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        maybe_referrer: Option<String>,
+        is_dyn_import: bool,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Error>>>> {
+        // Only called for the module an `import()` asks for, not for the
+        // static imports loaded along with it
+        if let (true, Some(referrer)) = (is_dyn_import, maybe_referrer) {
+            self.graph
+                .record_dynamic_import(&referrer, module_specifier.as_str());
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        _maybe_referrer: Option<&ModuleSpecifier>,
+        _is_dyn_import: bool,
+        _requested_module_type: RequestedModuleType,
+    ) -> ModuleLoadResponse {
+        let specifier = module_specifier.clone();
+
+        // Convert to path
+        let path = match specifier.to_file_path() {
+            Ok(p) => p,
+            Err(_) => {
+                return ModuleLoadResponse::Sync(Err(anyhow!(
+                    "Invalid file path: {}",
+                    specifier
+                )));
+            }
+        };
+
+        // Defense in depth: re-check extension
+        if !Self::is_extension_allowed(&path) {
+            return ModuleLoadResponse::Sync(Err(anyhow!(
+                "Invalid extension: {}",
+                path.display()
+            )));
+        }
+
+        if path.extension().is_some_and(|e| e == "wasm") {
+            let code = self.load_wasm(&specifier, &path);
+            return ModuleLoadResponse::Sync(code.map(|code| {
+                ModuleSource::new(
+                    ModuleType::JavaScript,
+                    ModuleSourceCode::String(code.into()),
+                    &specifier,
+                    None,
+                )
+            }));
+        }
+
+        // Load the module content
+        let (code, relative) = match self.read_module(&path) {
+            Ok(module) => module,
+            Err(e) => {
+                if !path.exists() {
+                    self.graph.record_missing(specifier.as_str());
+                }
+                return ModuleLoadResponse::Sync(Err(e));
+            }
+        };
+
+        // Refuse tampered (or unexpected) chunk files
+        if let Err(e) = self.verify_integrity(&specifier, &relative, code.as_bytes()) {
+            return ModuleLoadResponse::Sync(Err(e));
+        }
+
+        self.progress.module_loaded(specifier.as_str());
+
+        // `data: None` still asks V8 to produce a cache (see `code_cache_ready`)
+        let code_cache = self.code_cache.as_ref().map(|cache| {
+            let hash = CodeCache::source_hash(&code);
+            SourceCodeCacheInfo {
+                hash,
+                data: cache.get(hash).map(Cow::Owned),
+            }
+        });
+
+        ModuleLoadResponse::Sync(Ok(ModuleSource::new(
+            ModuleType::JavaScript,
+            ModuleSourceCode::String(code.into()),
+            &specifier,
+            code_cache,
+        )))
+    }
+
+    fn code_cache_ready(
+        &self,
+        _module_specifier: ModuleSpecifier,
+        hash: u64,
+        code_cache: &[u8],
+    ) -> Pin<Box<dyn Future<Output = ()>>> {
+        if let Some(cache) = &self.code_cache {
+            // A failed write only costs the next runtime a compile
+            if let Err(e) = cache.put(hash, code_cache) {
+                eprintln!("[ssr-sandbox] {}", e);
+            }
+        }
+        Box::pin(async {})
+    }
+
+    fn get_source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+        self.source_maps.raw(file_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_blocks_remote_urls() {
+        let dir = tempdir().unwrap();
+        let loader = SandboxedLoader::new(dir.path()).unwrap();
+
+        let result = loader.resolve("https://evil.com/payload.js", "file:///test.js", ResolutionKind::Import);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Remote imports are forbidden"));
+    }
+
+    #[test]
+    fn test_blocks_path_traversal() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("test.js"), "export default 1;").unwrap();
+        let loader = SandboxedLoader::new(dir.path()).unwrap();
+
+        let entry = format!("file://{}/test.js", dir.path().display());
+        let result = loader.resolve("../../../etc/passwd", &entry, ResolutionKind::Import);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_allows_valid_imports() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("chunk.js"), "export default 1;").unwrap();
+        let loader = SandboxedLoader::new(dir.path()).unwrap();
+
+        let entry = format!("file://{}/entry.js", dir.path().display());
+        let result = loader.resolve("./chunk.js", &entry, ResolutionKind::Import);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_resolves_with_import_map() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("vendor")).unwrap();
+        fs::write(dir.path().join("vendor/react.js"), "export default 1;").unwrap();
+        fs::write(
+            dir.path().join("import_map.json"),
+            r#"{"imports": {"react": "./vendor/react.js", "secrets": "../secrets.js"}}"#,
+        )
+        .unwrap();
+        let import_map = ImportMap::load(dir.path().join("import_map.json")).unwrap();
+        let loader = SandboxedLoader::new(dir.path())
+            .unwrap()
+            .with_import_map(import_map);
+
+        let entry = format!("file://{}/entry.js", dir.path().display());
+        let resolved = loader.resolve("react", &entry, ResolutionKind::Import).unwrap();
+        assert!(resolved.path().ends_with("/vendor/react.js"));
+        // Targets are checked like any other import
+        assert!(loader.resolve("secrets", &entry, ResolutionKind::Import).is_err());
+    }
+
+    #[test]
+    fn test_records_violations() {
+        let dir = tempdir().unwrap();
+        let loader = SandboxedLoader::new(dir.path()).unwrap();
+        let log = loader.violation_log();
+
+        let _ = loader.resolve("https://evil.com/payload.js", "file:///test.js", ResolutionKind::DynamicImport);
+        let violations = log.take();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].kind, ViolationKind::RemoteImport);
+        assert_eq!(violations[0].specifier, "https://evil.com/payload.js");
+        assert_eq!(violations[0].referrer.as_deref(), Some("file:///test.js"));
+
+        // Drained after take
+        assert!(log.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tracks_render_modules() {
+        let dir = tempdir().unwrap();
+        for file in ["entry.js", "a.js", "lazy.js", "b.js"] {
+            fs::write(dir.path().join(file), "export default 1;").unwrap();
+        }
+        let loader = SandboxedLoader::new(dir.path()).unwrap();
+        let graph = loader.module_graph();
+        let url = |file: &str| {
+            ModuleSpecifier::from_file_path(dir.path().canonicalize().unwrap().join(file))
+                .unwrap()
+                .to_string()
+        };
+        let lazy_import = || {
+            let lazy = loader.resolve("./lazy.js", &url("entry.js"), ResolutionKind::DynamicImport);
+            lazy.unwrap()
+        };
+
+        // First render loads everything: entry imports a.js and calls import("./lazy.js")
+        graph.begin_render(&url("entry.js"));
+        let entry = loader
+            .resolve(&url("entry.js"), "ext:bootstrap", ResolutionKind::DynamicImport)
+            .unwrap();
+        let prepared = loader.prepare_load(&entry, Some("ext:bootstrap".to_string()), true);
+        prepared.await.unwrap();
+        loader.resolve("./a.js", &url("entry.js"), ResolutionKind::DynamicImport).unwrap();
+        let prepared = loader.prepare_load(&lazy_import(), Some(url("entry.js")), true);
+        prepared.await.unwrap();
+        loader.resolve("./b.js", &url("lazy.js"), ResolutionKind::DynamicImport).unwrap();
+        let all = vec![url("entry.js"), url("a.js"), url("lazy.js"), url("b.js")];
+        assert_eq!(graph.render_modules(), all);
+
+        // Later renders don't resolve static imports again
+        graph.begin_render(&url("entry.js"));
+        assert_eq!(graph.render_modules(), vec![url("entry.js"), url("a.js")]);
+
+        graph.begin_render(&url("entry.js"));
+        lazy_import();
+        assert_eq!(graph.render_modules(), all);
+        assert_eq!(graph.modules(), all.into_iter().collect());
+    }
+
+    #[test]
+    fn test_evaluation_order() {
+        let graph = ModuleGraph::default();
+        for (referrer, specifier) in [("entry", "b"), ("entry", "a"), ("a", "c"), ("b", "c"), ("c", "a")] {
+            graph.record_resolved(referrer, specifier, ResolutionKind::Import);
+        }
+        graph.record_resolved("entry", "lazy", ResolutionKind::DynamicImport);
+        graph.record_dynamic_import("entry", "lazy");
+
+        // Cycles are cut where they close, `import()`ed modules are left out
+        assert_eq!(graph.evaluation_order("entry"), vec!["c", "a", "b", "entry"]);
+        assert_eq!(graph.evaluation_order("b"), vec!["a", "c", "b"]);
+    }
+
+    #[test]
+    fn test_records_missing_modules_per_entry() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("entry.js"), "import './gone.js';").unwrap();
+        fs::write(dir.path().join("other.js"), "export default 1;").unwrap();
+        let loader = SandboxedLoader::new(dir.path()).unwrap();
+        let graph = loader.module_graph();
+        let url = |file: &str| format!("file://{}/{}", dir.path().display(), file);
+
+        graph.begin_render(&url("entry.js"));
+        let resolved = loader.resolve("./gone.js", &url("entry.js"), ResolutionKind::Import);
+        assert!(resolved.is_err());
+        assert_eq!(graph.missing_module(), Some(url("gone.js")));
+
+        graph.begin_render(&url("other.js"));
+        assert_eq!(graph.missing_module(), None);
+        // The entry's failed load is cached by the runtime, so it stays missing
+        graph.begin_render(&url("entry.js"));
+        assert_eq!(graph.missing_module(), Some(url("gone.js")));
+    }
+
+    #[test]
+    fn test_verifies_integrity() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("chunk.js"), "export default 1;").unwrap();
+        fs::write(dir.path().join("other.js"), "export default 2;").unwrap();
+        let hash = format!(
+            "sha256-{}",
+            base64::engine::general_purpose::STANDARD.encode(Sha256::digest(b"export default 1;"))
+        );
+        let manifest = BTreeMap::from([("chunk.js".to_string(), hash)]);
+        let loader = SandboxedLoader::new(dir.path()).unwrap().with_integrity(manifest).unwrap();
+        let log = loader.violation_log();
+
+        let load = |name: &str| {
+            let url = ModuleSpecifier::from_file_path(dir.path().join(name)).unwrap();
+            match loader.load(&url, None, false, RequestedModuleType::None) {
+                ModuleLoadResponse::Sync(result) => result.map(|_| ()),
+                ModuleLoadResponse::Async(_) => unreachable!(),
+            }
+        };
+
+        assert!(load("chunk.js").is_ok());
+        let err = load("other.js").unwrap_err().to_string();
+        assert!(err.contains("not listed in the integrity manifest"));
+
+        fs::write(dir.path().join("chunk.js"), "export default 666;").unwrap();
+        let err = load("chunk.js").unwrap_err().to_string();
+        assert!(err.contains("Integrity check failed for 'chunk.js'"));
+
+        let violations = log.take();
+        assert_eq!(violations.len(), 2);
+        assert!(violations.iter().all(|v| v.kind == ViolationKind::IntegrityMismatch));
+    }
+
+    #[test]
+    fn test_loads_from_provider() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("disk.js"), "export default 'disk';").unwrap();
+        fs::write(dir.path().join("both.js"), "export default 'disk';").unwrap();
+        let modules = HashMap::from([
+            ("pages/home.js".to_string(), "export default 'memory';".to_string()),
+            ("both.js".to_string(), "export default 'memory';".to_string()),
+        ]);
+        let loader = SandboxedLoader::new(dir.path())
+            .unwrap()
+            .with_provider(Arc::new(modules));
+        let root = dir.path().canonicalize().unwrap();
+        let entry = ModuleSpecifier::from_file_path(root.join("entry.js")).unwrap();
+
+        let load = |specifier: &str| {
+            let url = loader.resolve(specifier, entry.as_str(), ResolutionKind::Import)?;
+            match loader.load(&url, None, false, RequestedModuleType::None) {
+                ModuleLoadResponse::Sync(result) => {
+                    result.map(|source| String::from_utf8_lossy(source.code.as_bytes()).to_string())
+                }
+                ModuleLoadResponse::Async(_) => unreachable!(),
+            }
+        };
+
+        assert_eq!(load("./pages/home.js").unwrap(), "export default 'memory';");
+        assert_eq!(load("./disk.js").unwrap(), "export default 'disk';");
+        // The provider is asked first
+        assert_eq!(load("./both.js").unwrap(), "export default 'memory';");
+        let err = load("./missing.js").unwrap_err().to_string();
+        assert!(err.contains("neither served by the module provider nor in the chunks directory"));
+        // Provided paths still have to be inside the directory
+        assert!(load("../outside.js").is_err());
+        assert!(load("/etc/passwd.js").is_err());
+    }
+
+    #[test]
+    fn test_loads_wasm_modules() {
+        let dir = tempdir().unwrap();
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        // Imports memory "./env.js".mem, exports function 0 as "add"
+        wasm.extend_from_slice(b"\x02\x11\x01\x08./env.js\x03mem\x02\x00\x01");
+        wasm.extend_from_slice(b"\x07\x07\x01\x03add\x00\x00");
+        fs::write(dir.path().join("lib.wasm"), &wasm).unwrap();
+        let loader = SandboxedLoader::new(dir.path()).unwrap().with_wasm_limits(WasmLimits {
+            max_module_bytes: wasm.len(),
+            ..Default::default()
+        });
+        let modules = loader.wasm_modules();
+        let root = dir.path().canonicalize().unwrap();
+        let entry = ModuleSpecifier::from_file_path(root.join("entry.js")).unwrap();
+
+        let url = loader.resolve("./lib.wasm", entry.as_str(), ResolutionKind::Import).unwrap();
+        let ModuleLoadResponse::Sync(Ok(source)) = loader.load(&url, None, false, RequestedModuleType::None)
+        else {
+            panic!("lib.wasm failed to load");
+        };
+        let code = String::from_utf8_lossy(source.code.as_bytes()).to_string();
+        assert!(code.contains("import * as import0 from \"./env.js\";"));
+        assert!(code.contains("export { export0 as \"add\" };"));
+        assert!(code.contains("export default exports;"));
+        // The memory import got a maximum
+        let bytes = modules.take(url.as_str()).unwrap();
+        assert_eq!(bytes.len(), wasm.len() + 2);
+
+        fs::write(dir.path().join("big.wasm"), [wasm.as_slice(), b"\0\x01\0"].concat()).unwrap();
+        let url = loader.resolve("./big.wasm", entry.as_str(), ResolutionKind::Import).unwrap();
+        let ModuleLoadResponse::Sync(Err(e)) = loader.load(&url, None, false, RequestedModuleType::None)
+        else {
+            panic!("big.wasm should be refused");
+        };
+        assert!(e.to_string().contains("byte limit"));
+    }
+
+    #[test]
+    fn test_rejects_malformed_integrity_manifest() {
+        let dir = tempdir().unwrap();
+        let manifest = BTreeMap::from([("chunk.js".to_string(), "md5-abc".to_string())]);
+        assert!(SandboxedLoader::new(dir.path()).unwrap().with_integrity(manifest).is_err());
+    }
+
+    #[test]
+    fn test_blocks_non_js_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("data.json"), "{}").unwrap();
+        let loader = SandboxedLoader::new(dir.path()).unwrap();
+
+        let entry = format!("file://{}/entry.js", dir.path().display());
+        let result = loader.resolve("./data.json", &entry, ResolutionKind::Import);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Only .js, .mjs and .wasm"));
+    }
+}
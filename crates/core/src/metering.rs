@@ -0,0 +1,238 @@
+//! Per-tenant usage metering and quotas.
+//!
+//! [`TenantRouter`](crate::TenantRouter) meters the renders, CPU time, fetched
+//! bytes and cache memory of every tenant, so the sandbox can be billed as a
+//! shared platform (reported by `@stats` and [`tenant_usage`]). Renders of a
+//! tenant that has used up a [`TenantQuota`] for the current period are
+//! refused with an [`SsrErrorKind::QuotaExceeded`](crate::SsrErrorKind::QuotaExceeded)
+//! error carrying the [`QuotaExceeded`] details.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Hard limits on what a tenant uses per period (`TenantLimits::quota`)
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantQuota {
+    /// Length of a quota period in seconds (default: 3600). The first period
+    /// starts with the tenant's first render.
+    pub period_secs: u64,
+    /// Renders per period (default: None, unlimited)
+    pub max_renders: Option<u64>,
+    /// Render CPU time per period, in milliseconds (default: None, unlimited)
+    pub max_cpu_ms: Option<u64>,
+    /// Fetched response body bytes per period (default: None, unlimited)
+    pub max_fetch_bytes: Option<u64>,
+}
+
+impl Default for TenantQuota {
+    fn default() -> Self {
+        Self {
+            period_secs: 3600,
+            max_renders: None,
+            max_cpu_ms: None,
+            max_fetch_bytes: None,
+        }
+    }
+}
+
+impl TenantQuota {
+    /// The quota's limits as (resource, used, limit) for `usage`
+    fn check(&self, usage: &TenantUsage) -> Option<(&'static str, u64, u64)> {
+        [
+            ("renders", usage.renders, self.max_renders),
+            ("cpu_ms", usage.cpu_micros / 1000, self.max_cpu_ms),
+            ("fetch_bytes", usage.fetch_bytes, self.max_fetch_bytes),
+        ]
+        .into_iter()
+        .find_map(|(resource, used, limit)| {
+            let limit = limit?;
+            (used >= limit).then_some((resource, used, limit))
+        })
+    }
+}
+
+/// What one render used, see [`RuntimePool::last_usage`](crate::RuntimePool::last_usage)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RenderUsage {
+    /// CPU time of the thread the render ran on (fetches run elsewhere)
+    pub cpu_micros: u64,
+    /// Fetched response body bytes
+    pub fetch_bytes: u64,
+    /// Bytes held by the runtime's internal caches after the render
+    pub cache_bytes: u64,
+}
+
+/// What a tenant used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TenantUsage {
+    /// Renders started (refused renders don't count)
+    pub renders: u64,
+    pub cpu_micros: u64,
+    pub fetch_bytes: u64,
+    /// Bytes held by the caches of the tenant's latest render
+    pub cache_bytes: u64,
+    /// Renders refused because a quota was used up
+    pub rejected: u64,
+}
+
+impl TenantUsage {
+    fn add(&mut self, usage: &RenderUsage) {
+        self.cpu_micros += usage.cpu_micros;
+        self.fetch_bytes += usage.fetch_bytes;
+        self.cache_bytes = usage.cache_bytes;
+    }
+}
+
+/// Usage of a tenant since the process started, and in its current quota period
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantMeter {
+    pub total: TenantUsage,
+    pub period: TenantUsage,
+    #[serde(skip)]
+    period_start: Instant,
+}
+
+/// Why a render was refused because its tenant used up its [`TenantQuota`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub tenant: String,
+    /// `renders`, `cpu_ms` or `fetch_bytes`
+    pub resource: &'static str,
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Quota exceeded: tenant '{}' used {} of {} {} this period",
+            self.tenant, self.used, self.limit, self.resource
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Process-wide meters, by tenant
+static TENANT_METERS: Mutex<BTreeMap<String, TenantMeter>> = Mutex::new(BTreeMap::new());
+
+/// Run `f` on the meter of `tenant`, starting a new period if the last one is over
+fn with_meter<T>(tenant: &str, quota: &TenantQuota, f: impl FnOnce(&mut TenantMeter) -> T) -> T {
+    let mut meters = TENANT_METERS.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Instant::now();
+    let meter = meters.entry(tenant.to_string()).or_insert_with(|| TenantMeter {
+        total: TenantUsage::default(),
+        period: TenantUsage::default(),
+        period_start: now,
+    });
+    if now.duration_since(meter.period_start) >= Duration::from_secs(quota.period_secs) {
+        meter.period = TenantUsage {
+            cache_bytes: meter.period.cache_bytes,
+            ..TenantUsage::default()
+        };
+        meter.period_start = now;
+    }
+    f(meter)
+}
+
+/// Count a render of `tenant`, unless it has used up `quota`
+pub(crate) fn admit(tenant: &str, quota: &TenantQuota) -> Result<(), QuotaExceeded> {
+    with_meter(tenant, quota, |meter| {
+        if let Some((resource, used, limit)) = quota.check(&meter.period) {
+            meter.total.rejected += 1;
+            meter.period.rejected += 1;
+            return Err(QuotaExceeded {
+                tenant: tenant.to_string(),
+                resource,
+                used,
+                limit,
+            });
+        }
+        meter.total.renders += 1;
+        meter.period.renders += 1;
+        Ok(())
+    })
+}
+
+/// Add what a render of `tenant` used to its meter
+pub(crate) fn record(tenant: &str, quota: &TenantQuota, usage: &RenderUsage) {
+    with_meter(tenant, quota, |meter| {
+        meter.total.add(usage);
+        meter.period.add(usage);
+    });
+}
+
+/// Usage of every tenant that has rendered
+pub fn tenant_usage() -> BTreeMap<String, TenantMeter> {
+    TENANT_METERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// CPU time the calling thread has used
+pub(crate) fn thread_cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid timespec for the call to write to
+    unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_refuses_renders() {
+        let quota = TenantQuota {
+            max_renders: Some(2),
+            max_cpu_ms: Some(5),
+            ..TenantQuota::default()
+        };
+        assert!(admit("test-renders", &quota).is_ok());
+        assert!(admit("test-renders", &quota).is_ok());
+        assert_eq!(
+            admit("test-renders", &quota).unwrap_err().to_string(),
+            "Quota exceeded: tenant 'test-renders' used 2 of 2 renders this period"
+        );
+
+        assert!(admit("test-cpu", &quota).is_ok());
+        let usage = RenderUsage {
+            cpu_micros: 7_000,
+            fetch_bytes: 512,
+            cache_bytes: 64,
+        };
+        record("test-cpu", &quota, &usage);
+        let e = admit("test-cpu", &quota).unwrap_err();
+        assert_eq!((e.resource, e.used, e.limit), ("cpu_ms", 7, 5));
+
+        let meters = tenant_usage();
+        assert_eq!(meters["test-renders"].total.rejected, 1);
+        assert_eq!(
+            meters["test-cpu"].total,
+            TenantUsage {
+                renders: 1,
+                cpu_micros: 7_000,
+                fetch_bytes: 512,
+                cache_bytes: 64,
+                rejected: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_thread_cpu_time_advances() {
+        let started = thread_cpu_time();
+        let mut x = 0u64;
+        for i in 0..1_000_000 {
+            x = std::hint::black_box(x.wrapping_add(i));
+        }
+        assert!(thread_cpu_time() > started);
+    }
+}
@@ -0,0 +1,3610 @@
+//! Shared ops module - used by both build.rs (snapshot) and runtime.rs
+//!
+//! This module contains all custom ops and the extension! macro definition.
+//! It must be importable by both the main crate and the build script.
+
+use deno_core::{op2, OpState};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use serde::{Deserialize, Serialize};
+// Part of server mode responses too, so they live in the protocol crate
+pub use ssr_sandbox_protocol::{Island, Warning, WarningCode};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// ============================================================================
+// Console Output Capture
+// ============================================================================
+
+/// Captured console output from the sandboxed runtime
+#[derive(Debug, Default, Clone)]
+pub struct ConsoleOutput {
+    /// Messages in the order they were written, all levels interleaved
+    pub entries: Vec<ConsoleEntry>,
+    /// Entries dropped because the per-render cap was reached
+    pub dropped: usize,
+    /// Last captured entry (level, original message, repeat count) for deduplication
+    last: Option<(LogLevel, String, usize)>,
+}
+
+/// A captured console message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsoleEntry {
+    pub level: LogLevel,
+    /// The arguments formatted into one line (truncated to
+    /// [`ConsoleLimits::max_message_bytes`]), ending in `(repeated N times)`
+    /// if consecutive identical messages were collapsed into this entry
+    pub message: String,
+    /// When the message was first written, in milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// The arguments as a JSON array, for structured logging. None if they
+    /// can't be serialized or the JSON is longer than `max_message_bytes`.
+    pub args_json: Option<String>,
+}
+
+/// Per-render limits on captured console output
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsoleLimits {
+    /// Maximum number of entries kept across all levels (repeats count once)
+    pub max_entries: usize,
+    /// Messages longer than this many bytes are truncated
+    pub max_message_bytes: usize,
+}
+
+impl Default for ConsoleLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 1000,
+            max_message_bytes: 8 * 1024,
+        }
+    }
+}
+
+/// Console method a message was written with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum LogLevel {
+    Log,
+    Info,
+    Debug,
+    /// `console.trace`, the message is followed by the JS stack
+    Trace,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// Level of a `console` method name, `Log` for unknown names
+    fn from_method(method: &str) -> Self {
+        match method {
+            "info" => Self::Info,
+            "debug" => Self::Debug,
+            "trace" => Self::Trace,
+            "warn" => Self::Warn,
+            "error" => Self::Error,
+            _ => Self::Log,
+        }
+    }
+
+    /// Tag of the level's lines on stderr, e.g. `[LOG]`
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::Log => "[LOG]",
+            Self::Info => "[INFO]",
+            Self::Debug => "[DEBUG]",
+            Self::Trace => "[TRACE]",
+            Self::Warn => "[WARN]",
+            Self::Error => "[ERROR]",
+        }
+    }
+}
+
+impl ConsoleOutput {
+    /// Messages of the `log`, `info`, `debug` and `trace` levels, in order
+    /// (the `logs` field before [`ConsoleEntry`])
+    pub fn logs(&self) -> Vec<String> {
+        self.messages(|level| {
+            matches!(
+                level,
+                LogLevel::Log | LogLevel::Info | LogLevel::Debug | LogLevel::Trace
+            )
+        })
+    }
+
+    /// Messages of the `warn` level, in order
+    pub fn warns(&self) -> Vec<String> {
+        self.messages(|level| level == LogLevel::Warn)
+    }
+
+    /// Messages of the `error` level, in order
+    pub fn errors(&self) -> Vec<String> {
+        self.messages(|level| level == LogLevel::Error)
+    }
+
+    fn messages(&self, include: impl Fn(LogLevel) -> bool) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|entry| include(entry.level))
+            .map(|entry| entry.message.clone())
+            .collect()
+    }
+
+    fn push(
+        &mut self,
+        level: LogLevel,
+        msg: &str,
+        args_json: Option<&str>,
+        limits: &ConsoleLimits,
+    ) {
+        // Collapse consecutive identical messages into one entry with a count
+        if let Some((last_level, last_msg, count)) = &mut self.last {
+            if *last_level == level && last_msg == msg {
+                *count += 1;
+                let message = truncate(msg, limits.max_message_bytes);
+                let message = format!("{} (repeated {} times)", message, count);
+                if let Some(last_entry) = self.entries.last_mut() {
+                    last_entry.message = message;
+                }
+                return;
+            }
+        }
+
+        if self.entries.len() >= limits.max_entries {
+            self.dropped += 1;
+            return;
+        }
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        self.entries.push(ConsoleEntry {
+            level,
+            message: truncate(msg, limits.max_message_bytes),
+            timestamp,
+            args_json: args_json
+                .filter(|json| json.len() <= limits.max_message_bytes)
+                .map(str::to_string),
+        });
+        self.last = Some((level, msg.to_string(), 1));
+    }
+}
+
+/// Truncate a message to at most `max_bytes` (on a char boundary)
+fn truncate(msg: &str, max_bytes: usize) -> String {
+    if msg.len() <= max_bytes {
+        return msg.to_string();
+    }
+    let mut end = max_bytes;
+    while !msg.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... [truncated {} bytes]", &msg[..end], msg.len() - end)
+}
+
+/// Receives console messages as renders write them, see [`ConsoleSubscribers`]
+pub type ConsoleSubscriber = Arc<dyn Fn(LogLevel, &str) + Send + Sync>;
+
+/// Callbacks that get every console message as soon as it is written, e.g.
+/// to forward the logs of long renders to a logging pipeline instead of
+/// reading [`ConsoleOutput`] once the render has finished
+///
+/// Messages are truncated like captured ones, but neither deduplicated nor
+/// capped. Subscribers run on the render's thread while the message is being
+/// written, so they should hand it off (e.g. to a channel) rather than block.
+#[derive(Clone, Default)]
+pub struct ConsoleSubscribers {
+    subscribers: Vec<ConsoleSubscriber>,
+}
+
+impl ConsoleSubscribers {
+    /// Call `subscriber` with the level and text of every console message
+    ///
+    /// ```rust,ignore
+    /// let (sender, mut logs) = tokio::sync::mpsc::unbounded_channel();
+    /// let console = ConsoleSubscribers::default().subscribe(move |level, msg| {
+    ///     let _ = sender.send((level, msg.to_string()));
+    /// });
+    /// ```
+    pub fn subscribe<F>(mut self, subscriber: F) -> Self
+    where
+        F: Fn(LogLevel, &str) + Send + Sync + 'static,
+    {
+        self.subscribers.push(Arc::new(subscriber));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    fn publish(&self, level: LogLevel, msg: &str) {
+        for subscriber in &self.subscribers {
+            subscriber(level, msg);
+        }
+    }
+}
+
+fn capture_console(state: &mut OpState, level: LogLevel, msg: &str, args_json: Option<&str>) {
+    let limits = state.try_borrow::<ConsoleLimits>().cloned().unwrap_or_default();
+    if let Some(subscribers) = state.try_borrow::<ConsoleSubscribers>() {
+        subscribers.publish(level, &truncate(msg, limits.max_message_bytes));
+    }
+    if let Some(output) = state.try_borrow_mut::<ConsoleOutput>() {
+        output.push(level, msg, args_json, &limits);
+    }
+}
+
+/// A `console` call: the method name, the formatted message and the
+/// arguments as JSON ("" if they can't be serialized)
+#[op2(fast)]
+pub fn op_console(
+    state: &mut OpState,
+    #[string] method: &str,
+    #[string] msg: &str,
+    #[string] args_json: &str,
+) {
+    let args_json = (!args_json.is_empty()).then_some(args_json);
+    capture_console(state, LogLevel::from_method(method), msg, args_json);
+}
+
+// ============================================================================
+// Sandbox Violations
+// ============================================================================
+
+/// Category of an action blocked by the sandbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    /// Import of an http(s)/data/blob URL
+    RemoteImport,
+    /// Import of a file outside the chunks directory, or a
+    /// `__ssr_read_data__` read outside the data directory
+    PathEscape,
+    /// Import of a file that isn't .js/.mjs
+    ForbiddenExtension,
+    /// Import specifier that couldn't be resolved to a file URL
+    InvalidSpecifier,
+    /// Module whose content doesn't match the integrity manifest
+    IntegrityMismatch,
+    /// fetch() to an origin or address that isn't allowed (including redirects)
+    FetchBlocked,
+    /// Props containing a prototype pollution key (`__proto__`, `constructor`, ...)
+    ForbiddenPropKey,
+}
+
+/// Process-wide violation counters (never reset, shared by all runtimes)
+static VIOLATION_COUNTS: Mutex<BTreeMap<ViolationKind, u64>> = Mutex::new(BTreeMap::new());
+
+/// Count a blocked action. Violations recorded in a [`ViolationLog`] are counted
+/// automatically; this is for actions blocked outside a render (e.g. props).
+pub fn count_violation(kind: ViolationKind) {
+    let mut counts = VIOLATION_COUNTS.lock().unwrap_or_else(|e| e.into_inner());
+    *counts.entry(kind).or_default() += 1;
+}
+
+/// Number of violations of each kind since the process started
+pub fn violation_counts() -> BTreeMap<ViolationKind, u64> {
+    VIOLATION_COUNTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Request id of the latest violation of each kind, so a spike in the
+/// counters can be traced to a concrete render
+static VIOLATION_EXEMPLARS: Mutex<BTreeMap<ViolationKind, String>> = Mutex::new(BTreeMap::new());
+
+/// Remember the request behind a violation (see [`violation_exemplars`])
+pub fn record_violation_exemplar(kind: ViolationKind, request_id: &str) {
+    VIOLATION_EXEMPLARS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(kind, request_id.to_string());
+}
+
+/// Request id of the latest violation of each kind (renders with a request id only)
+pub fn violation_exemplars() -> BTreeMap<ViolationKind, String> {
+    VIOLATION_EXEMPLARS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// An action blocked by the sandbox during a render
+#[derive(Debug, Clone, Serialize)]
+pub struct SandboxViolation {
+    pub kind: ViolationKind,
+    /// The specifier (or URL) that was rejected
+    pub specifier: String,
+    /// The module that requested it, if known
+    pub referrer: Option<String>,
+    /// Human readable description of the rule that was violated
+    pub message: String,
+    /// Correlation id of the render that caused it, if the request had one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl std::fmt::Display for SandboxViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.referrer {
+            Some(referrer) => write!(f, "{} (imported from {})", self.message, referrer),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Shared log of sandbox violations, drained after each render.
+///
+/// The module loader holds a clone so violations from `import()` can be
+/// attributed to the render that triggered them.
+#[derive(Debug, Clone, Default)]
+pub struct ViolationLog(Rc<RefCell<Vec<SandboxViolation>>>);
+
+impl ViolationLog {
+    pub fn record(&self, violation: SandboxViolation) {
+        count_violation(violation.kind);
+        self.0.borrow_mut().push(violation);
+    }
+
+    pub fn take(&self) -> Vec<SandboxViolation> {
+        std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+// ============================================================================
+// Warnings
+// ============================================================================
+
+/// Fetches taking longer than this are reported as [`WarningCode::SlowFetch`]
+pub const SLOW_FETCH_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Maximum warnings kept per render (repeated warnings are only kept once)
+const MAX_WARNINGS: usize = 100;
+
+/// Shared log of warnings, drained after each render.
+///
+/// Thread-safe because the render watchdog and the near-heap-limit callback
+/// record warnings too.
+#[derive(Debug, Clone, Default)]
+pub struct WarningLog(Arc<Mutex<Vec<Warning>>>);
+
+impl WarningLog {
+    pub fn warn(&self, code: WarningCode, message: impl Into<String>) {
+        let warning = Warning {
+            code,
+            message: message.into(),
+        };
+        let mut warnings = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if warnings.len() < MAX_WARNINGS && !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+
+    pub fn take(&self) -> Vec<Warning> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// Report use of a deprecated API (`message` says what to use instead)
+#[op2(fast)]
+pub fn op_warn_deprecated(state: &mut OpState, #[string] message: &str) {
+    if let Some(log) = state.try_borrow::<WarningLog>() {
+        log.warn(WarningCode::DeprecatedApi, message);
+    }
+}
+
+// ============================================================================
+// Fetch API
+// ============================================================================
+
+/// Configuration for fetch allowlist
+#[derive(Debug, Clone, Default)]
+pub struct FetchConfig {
+    pub allowed_origins: Vec<String>,
+    /// Per-origin TLS overrides, keyed by origin (e.g. `https://api.example.com`)
+    pub tls_policies: HashMap<String, TlsPolicy>,
+    /// Refuse to connect to loopback, private, link-local and cloud metadata
+    /// addresses. Hostnames are resolved up front and the connection is pinned
+    /// to the checked address, so DNS rebinding can't swap it afterwards.
+    pub block_private_networks: bool,
+    /// Longest a single fetch may take, including redirects and reading the
+    /// body (None = only the render timeout applies)
+    pub request_timeout_ms: Option<u64>,
+    /// Total time the fetches of one render may take, added up (None = no
+    /// budget). Keeps one slow upstream from using up the whole render timeout.
+    pub total_fetch_budget_ms: Option<u64>,
+    /// Fetches one render may start (None = unlimited)
+    pub max_requests_per_render: Option<u64>,
+    /// Fetches of one render that may be in flight at once (None = unlimited)
+    pub max_concurrent_requests: Option<u64>,
+    /// Headers added to every fetch to an origin (keyed by origin), out of
+    /// reach of the render code
+    pub secret_headers: HashMap<String, SecretHeaders>,
+    /// HTTP clients kept between fetches, so connections are reused
+    pub clients: HttpClients,
+}
+
+/// Headers the host adds to every `fetch()` to an origin, e.g. the
+/// `Authorization: Bearer …` token of an internal API
+///
+/// They are set on the Rust side, replacing headers of the same name set by
+/// JS, so the render code can call authenticated APIs without ever seeing the
+/// credentials. Values are left out of `Debug` output and serialize as the
+/// header names only (e.g. in `@config`).
+#[derive(Clone, Default)]
+pub struct SecretHeaders(Vec<(String, String)>);
+
+impl SecretHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a header, replacing an earlier one of the same name
+    pub fn with(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.insert(name, value);
+        self
+    }
+
+    /// Add a header, replacing an earlier one of the same name
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.0.retain(|(existing, _)| !existing.eq_ignore_ascii_case(&name));
+        self.0.push((name, value.into()));
+    }
+
+    /// Names of the headers, in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Whether a header of this name is set (names are case-insensitive)
+    pub fn contains(&self, name: &str) -> bool {
+        self.names().any(|existing| existing.eq_ignore_ascii_case(name))
+    }
+
+    fn validate(&self, origin: &str) -> Result<(), anyhow::Error> {
+        for (name, value) in &self.0 {
+            reqwest::header::HeaderName::from_bytes(name.as_bytes()).map_err(|_| {
+                anyhow::anyhow!("Invalid secret header name '{}' for '{}'", name, origin)
+            })?;
+            // Don't echo the value, it's a secret
+            reqwest::header::HeaderValue::from_str(value).map_err(|_| {
+                anyhow::anyhow!("Invalid value of secret header '{}' for '{}'", name, origin)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for SecretHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.names().map(|name| (name, "<redacted>")))
+            .finish()
+    }
+}
+
+impl Serialize for SecretHeaders {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.names())
+    }
+}
+
+/// HTTP clients by origin, shared by every clone (and so by every runtime of a
+/// config), keeping connections open across fetches and renders
+#[derive(Debug, Clone, Default)]
+pub struct HttpClients(Arc<Mutex<HashMap<String, (reqwest::Client, Instant)>>>);
+
+impl HttpClients {
+    /// Clients pinned to a checked address (`block_private_networks`) are
+    /// rebuilt after this long, so DNS changes are picked up and checked again
+    pub const PINNED_MAX_AGE: Duration = Duration::from_secs(60);
+
+    fn get(&self, origin: &str, pinned: bool) -> Option<reqwest::Client> {
+        let clients = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let (client, created) = clients.get(origin)?;
+        (!pinned || created.elapsed() < Self::PINNED_MAX_AGE).then(|| client.clone())
+    }
+
+    fn insert(&self, origin: String, client: reqwest::Client) {
+        let mut clients = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        clients.insert(origin, (client, Instant::now()));
+    }
+}
+
+impl FetchConfig {
+    /// Whether `url` may be fetched: its origin is in `allowed_origins`, or an
+    /// entry like `https://api.example.com/v1/*` has its origin and a prefix
+    /// of its path. Paths are compared after URL parsing has resolved `..`
+    /// segments, so `/v1/../admin` doesn't match `/v1/*`.
+    pub fn is_request_allowed(&self, url: &url::Url) -> bool {
+        let origin = url.origin().ascii_serialization();
+        self.allowed_origins
+            .iter()
+            .any(|entry| match split_allowlist_entry(entry) {
+                (entry_origin, None) => entry_origin == origin,
+                (entry_origin, Some(prefix)) => {
+                    entry_origin == origin && url.path().starts_with(prefix)
+                }
+            })
+    }
+
+    /// Origins of the `allowed_origins` entries, without their path prefixes
+    /// and duplicates
+    pub fn origins(&self) -> Vec<&str> {
+        let mut origins: Vec<&str> = Vec::new();
+        for entry in &self.allowed_origins {
+            let (origin, _) = split_allowlist_entry(entry);
+            if !origins.contains(&origin) {
+                origins.push(origin);
+            }
+        }
+        origins
+    }
+
+    /// How long the next fetch may take, given the fetch time the render has
+    /// already spent (None = no limit)
+    pub fn time_limit(&self, spent: Duration) -> Option<Duration> {
+        let request = self.request_timeout_ms.map(Duration::from_millis);
+        let remaining = self
+            .total_fetch_budget_ms
+            .map(|ms| Duration::from_millis(ms).saturating_sub(spent));
+        match (request, remaining) {
+            (Some(request), Some(remaining)) => Some(request.min(remaining)),
+            (request, remaining) => request.or(remaining),
+        }
+    }
+
+    /// TLS policy for the origin of `url`, if one is configured
+    pub fn tls_policy(&self, url: &url::Url) -> Option<&TlsPolicy> {
+        self.tls_policies.get(&url.origin().ascii_serialization())
+    }
+
+    /// The client for fetches to the origin of `url`, created on first use
+    async fn client(&self, url: &url::Url) -> Result<reqwest::Client, anyhow::Error> {
+        use anyhow::anyhow;
+
+        let origin = url.origin().ascii_serialization();
+        if let Some(client) = self.clients.get(&origin, self.block_private_networks) {
+            return Ok(client);
+        }
+
+        let mut client_builder =
+            reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+        if let Some(policy) = self.tls_policy(url) {
+            client_builder = client_builder.use_preconfigured_tls(policy.client_config()?);
+        }
+        if self.block_private_networks {
+            // Connect to exactly the address that was checked, and not via a proxy
+            client_builder = client_builder.no_proxy();
+            if let (Some(addr), Some(host)) = (resolve_public_address(url).await?, url.host_str()) {
+                client_builder = client_builder.resolve(host, addr);
+            }
+        }
+        let client = client_builder
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
+        self.clients.insert(origin, client.clone());
+        Ok(client)
+    }
+
+    /// Connect to every allowed origin ahead of the first fetch
+    ///
+    /// Sends `HEAD /` to each origin (whatever the status of the response) so
+    /// DNS resolution and the TCP and TLS handshakes are done, and the open
+    /// connection is reused by the first fetches of renders. Returns how long
+    /// each origin took, or why it couldn't be reached.
+    pub async fn warm_up(&self) -> Vec<(String, Result<Duration, anyhow::Error>)> {
+        use anyhow::anyhow;
+
+        let timeout = Duration::from_millis(self.request_timeout_ms.unwrap_or(WARM_UP_TIMEOUT_MS));
+        let connect = |origin: String| async move {
+            let started = Instant::now();
+            let result = async {
+                let url = url::Url::parse(&origin)?;
+                let client = self.client(&url).await?;
+                tokio::time::timeout(timeout, client.head(url).send())
+                    .await
+                    .map_err(|_| anyhow!("timed out after {}ms", timeout.as_millis()))??;
+                Ok(started.elapsed())
+            }
+            .await;
+            (origin, result)
+        };
+        let origins = self.origins().into_iter().map(str::to_string);
+        deno_core::futures::future::join_all(origins.map(connect)).await
+    }
+
+    /// Start a fetch of the current render, unless it would exceed
+    /// `max_requests_per_render` or `max_concurrent_requests`
+    pub fn admit(&self, stats: &mut FetchStats) -> Result<(), String> {
+        let exceeded = match (self.max_requests_per_render, self.max_concurrent_requests) {
+            (Some(max), _) if stats.requests >= max => Some(format!(
+                "the render already made {} of its {} requests",
+                stats.requests, max
+            )),
+            (_, Some(max)) if stats.in_flight >= max => Some(format!(
+                "{} requests of the render are already in flight (limit {})",
+                stats.in_flight, max
+            )),
+            _ => None,
+        };
+        if let Some(reason) = exceeded {
+            stats.rejected += 1;
+            return Err(format!("Fetch limit exceeded: {}", reason));
+        }
+        stats.requests += 1;
+        stats.in_flight += 1;
+        stats.peak_concurrent = stats.peak_concurrent.max(stats.in_flight);
+        Ok(())
+    }
+
+    /// Check all TLS policies so misconfiguration fails at startup instead of on first fetch
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        for entry in &self.allowed_origins {
+            // An entry that doesn't match itself could never allow a fetch
+            let (origin, _) = split_allowlist_entry(entry);
+            let parsed = url::Url::parse(origin).map(|url| url.origin().ascii_serialization());
+            if parsed.ok().as_deref() != Some(origin) {
+                anyhow::bail!(
+                    "Invalid allowed origin '{}' (expected an origin like https://api.example.com, or one with a path prefix like https://api.example.com/v1/*)",
+                    entry
+                );
+            }
+        }
+        for (origin, policy) in &self.tls_policies {
+            policy.validate(origin)?;
+        }
+        for (origin, headers) in &self.secret_headers {
+            if !self.origins().contains(&origin.as_str()) {
+                anyhow::bail!(
+                    "Secret headers for '{}', which is not an allowed origin",
+                    origin
+                );
+            }
+            headers.validate(origin)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `ip` is loopback, private (RFC 1918 / unique local), link-local
+/// (including the 169.254.169.254 cloud metadata endpoint), shared (CGNAT,
+/// used by some metadata services), multicast or otherwise not publicly
+/// routable. IPv6 addresses that embed an IPv4 address (mapped, compatible,
+/// NAT64, 6to4) are judged by the IPv4 address they reach.
+pub fn is_private_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240 // reserved 240.0.0.0/4 and broadcast
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0) // IETF protocol assignments
+                || (a == 198 && (b & 0xfe) == 18) // benchmarking 198.18.0.0/15
+        }
+        IpAddr::V6(ip) => {
+            // ::ffff:a.b.c.d and the deprecated ::a.b.c.d
+            if let Some(embedded) = ip.to_ipv4() {
+                return is_private_address(IpAddr::V4(embedded));
+            }
+            let embedded = |high: u16, low: u16| {
+                is_private_address(IpAddr::V4(Ipv4Addr::from(
+                    (u32::from(high) << 16) | u32::from(low),
+                )))
+            };
+            match ip.segments() {
+                // NAT64 64:ff9b::/96
+                [0x64, 0xff9b, 0, 0, 0, 0, high, low] => embedded(high, low),
+                // 6to4 2002::/16
+                [0x2002, high, low, ..] => embedded(high, low),
+                [first, ..] => {
+                    ip.is_loopback()
+                        || ip.is_unspecified()
+                        || ip.is_multicast()
+                        || (first & 0xfe00) == 0xfc00 // unique local fc00::/7
+                        || (first & 0xffc0) == 0xfe80 // link-local fe80::/10
+                }
+            }
+        }
+    }
+}
+
+/// Origin and path prefix of an allowlist entry: `https://api.example.com/v1/*`
+/// is `https://api.example.com` and `/v1/`. Entries with a path that doesn't
+/// end in `/*` are returned whole (and fail [`FetchConfig::validate`]).
+fn split_allowlist_entry(entry: &str) -> (&str, Option<&str>) {
+    let path_start = entry.find("://").and_then(|scheme_end| {
+        entry[scheme_end + 3..]
+            .find('/')
+            .map(|i| scheme_end + 3 + i)
+    });
+    match path_start {
+        Some(start) if entry.ends_with("/*") => {
+            (&entry[..start], Some(&entry[start..entry.len() - 1]))
+        }
+        _ => (entry, None),
+    }
+}
+
+/// Longest wait for one origin in [`FetchConfig::warm_up`] without a request timeout
+const WARM_UP_TIMEOUT_MS: u64 = 2_000;
+
+/// Resolve the host of `url` and check every address it resolves to.
+///
+/// Returns the address to pin the connection to (None for IP literals, which
+/// are checked but need no pinning).
+async fn resolve_public_address(url: &url::Url) -> Result<Option<SocketAddr>, anyhow::Error> {
+    use anyhow::anyhow;
+
+    let blocked = |ip: IpAddr| {
+        anyhow!(
+            "Fetch blocked: '{}' resolves to private network address {}",
+            url.host_str().unwrap_or_default(),
+            ip
+        )
+    };
+
+    let port = url.port_or_known_default().unwrap_or(443);
+    let host = match url.host() {
+        Some(url::Host::Ipv4(ip)) if is_private_address(ip.into()) => return Err(blocked(ip.into())),
+        Some(url::Host::Ipv6(ip)) if is_private_address(ip.into()) => return Err(blocked(ip.into())),
+        Some(url::Host::Domain(domain)) => domain,
+        Some(_) => return Ok(None),
+        None => return Err(anyhow!("Fetch blocked: URL '{}' has no host", url)),
+    };
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| anyhow!("Failed to resolve '{}': {}", host, e))?
+        .collect();
+    // Any private answer fails the request - a rebinding server may mix them in
+    if let Some(addr) = addrs.iter().find(|addr| is_private_address(addr.ip())) {
+        return Err(blocked(addr.ip()));
+    }
+    addrs
+        .first()
+        .copied()
+        .map(Some)
+        .ok_or_else(|| anyhow!("Failed to resolve '{}': no addresses", host))
+}
+
+/// Time the current render has spent in fetches, added up (see
+/// [`FetchConfig::total_fetch_budget_ms`]), reset before each render
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FetchTimeSpent(pub Duration);
+
+/// Response body bytes the current render has fetched, reset before each render
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FetchBytesReceived(pub u64);
+
+/// Fetches of a render, see [`SsrResult::fetch`](crate::SsrResult::fetch)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FetchStats {
+    /// Fetches started
+    pub requests: u64,
+    /// Fetches rejected by `max_requests_per_render` or `max_concurrent_requests`
+    pub rejected: u64,
+    /// Most fetches in flight at once
+    pub peak_concurrent: u64,
+    #[serde(skip)]
+    in_flight: u64,
+}
+
+/// Request info passed from JS
+#[derive(Debug, Deserialize)]
+pub struct FetchRequest {
+    pub url: String,
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub body: Option<String>,
+    /// Id `op_fetch_abort` aborts the request by (its `AbortSignal`)
+    #[serde(default)]
+    pub abort_id: Option<u32>,
+}
+
+/// Response info returned to JS
+#[derive(Debug, Serialize)]
+pub struct FetchResponse {
+    pub ok: bool,
+    pub status: u16,
+    pub status_text: String,
+    pub headers: HashMap<String, String>,
+    pub url: String,
+    /// Raw bytes (a `Uint8Array` in JS), decoded by the `Response` shim, so
+    /// binary and non-UTF-8 payloads arrive intact
+    pub body: deno_core::ToJsBuffer,
+    /// Size of `body`, for metering (not passed to JS)
+    #[serde(skip)]
+    pub body_size: u64,
+}
+
+#[op2(async)]
+#[serde]
+pub async fn op_fetch(
+    state: Rc<RefCell<OpState>>,
+    #[serde] request: FetchRequest,
+) -> Result<FetchResponse, deno_core::error::AnyError> {
+    use anyhow::anyhow;
+
+    // Get config from state
+    let (config, spent) = {
+        let state_ref = state.borrow();
+        let spent = state_ref.try_borrow::<FetchTimeSpent>().copied().unwrap_or_default();
+        (state_ref.borrow::<FetchConfig>().clone(), spent.0)
+    };
+
+    let url = request.url.clone();
+    let started = Instant::now();
+    let time_limit = config.time_limit(spent);
+    if time_limit == Some(Duration::ZERO) {
+        return Err(anyhow!(
+            "Fetch timed out: the render used up its fetch budget of {}ms",
+            config.total_fetch_budget_ms.unwrap_or_default()
+        ));
+    }
+    if let Some(stats) = state.borrow_mut().try_borrow_mut::<FetchStats>() {
+        config
+            .admit(stats)
+            .map_err(|e| anyhow!("{} ({})", e, url))?;
+    }
+
+    // Delegate to the actual implementation (can be called recursively for redirects).
+    // It runs as its own task, so aborting it drops the connection right away.
+    let abort_id = request.abort_id;
+    let task = tokio::spawn(fetch_within(request, config, time_limit));
+    let pending = state
+        .borrow_mut()
+        .try_borrow_mut::<PendingFetches>()
+        .map(|pending| pending.insert(abort_id, task.abort_handle()));
+    let result = match task.await {
+        Ok(result) => result,
+        Err(e) if e.is_cancelled() => Err(anyhow!("Fetch aborted: {}", url)),
+        Err(e) => Err(anyhow!("Fetch failed: {}", e)),
+    };
+    if let Some(key) = pending {
+        if let Some(pending) = state.borrow_mut().try_borrow_mut::<PendingFetches>() {
+            pending.remove(key);
+        }
+    }
+
+    let elapsed = started.elapsed();
+    if let Some(stats) = state.borrow_mut().try_borrow_mut::<FetchStats>() {
+        stats.in_flight = stats.in_flight.saturating_sub(1);
+    }
+    if let Some(spent) = state.borrow_mut().try_borrow_mut::<FetchTimeSpent>() {
+        spent.0 += elapsed;
+    }
+    if let (Ok(response), Some(received)) = (
+        &result,
+        state.borrow_mut().try_borrow_mut::<FetchBytesReceived>(),
+    ) {
+        received.0 += response.body_size;
+    }
+    if elapsed >= SLOW_FETCH_THRESHOLD {
+        if let Some(log) = state.borrow().try_borrow::<WarningLog>() {
+            log.warn(
+                WarningCode::SlowFetch,
+                format!("fetch({}) took {}ms", url, elapsed.as_millis()),
+            );
+        }
+    }
+
+    if let Err(e) = &result {
+        let message = e.to_string();
+        if message.starts_with("Fetch blocked") {
+            if let Some(log) = state.borrow().try_borrow::<ViolationLog>() {
+                log.record(SandboxViolation {
+                    kind: ViolationKind::FetchBlocked,
+                    specifier: url,
+                    referrer: None,
+                    message,
+                    request_id: None,
+                });
+            }
+        }
+    }
+    result
+}
+
+/// [`do_fetch`], failing once `time_limit` has passed
+async fn fetch_within(
+    request: FetchRequest,
+    config: FetchConfig,
+    time_limit: Option<Duration>,
+) -> Result<FetchResponse, deno_core::error::AnyError> {
+    use anyhow::anyhow;
+
+    let Some(limit) = time_limit else {
+        return do_fetch(request, config).await;
+    };
+    let url = request.url.clone();
+    match tokio::time::timeout(limit, do_fetch(request, config)).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!(
+            "Fetch timed out after {}ms: {}",
+            limit.as_millis(),
+            url
+        )),
+    }
+}
+
+/// `fetch()` requests in flight, so they can be aborted by their
+/// `AbortSignal` or when the render ends
+#[derive(Debug, Default)]
+pub struct PendingFetches {
+    next_key: u64,
+    tasks: HashMap<u64, (Option<u32>, tokio::task::AbortHandle)>,
+}
+
+impl PendingFetches {
+    fn insert(&mut self, abort_id: Option<u32>, task: tokio::task::AbortHandle) -> u64 {
+        self.next_key += 1;
+        self.tasks.insert(self.next_key, (abort_id, task));
+        self.next_key
+    }
+
+    fn remove(&mut self, key: u64) {
+        self.tasks.remove(&key);
+    }
+
+    /// Abort the request of an `AbortSignal`
+    pub fn abort(&mut self, abort_id: u32) {
+        self.tasks.retain(|_, (id, task)| {
+            let aborted = *id == Some(abort_id);
+            if aborted {
+                task.abort();
+            }
+            !aborted
+        });
+    }
+
+    /// Abort every request in flight, returning how many there were
+    pub fn abort_all(&mut self) -> usize {
+        let count = self.tasks.len();
+        for (_, (_, task)) in self.tasks.drain() {
+            task.abort();
+        }
+        count
+    }
+}
+
+impl Drop for PendingFetches {
+    fn drop(&mut self) {
+        // The runtime is gone; its requests must not outlive it
+        self.abort_all();
+    }
+}
+
+/// Abort a `fetch()` whose `AbortSignal` fired
+#[op2(fast)]
+pub fn op_fetch_abort(state: &mut OpState, abort_id: u32) {
+    if let Some(pending) = state.try_borrow_mut::<PendingFetches>() {
+        pending.abort(abort_id);
+    }
+}
+
+/// Internal fetch implementation (can be called recursively for redirects)
+async fn do_fetch(
+    request: FetchRequest,
+    config: FetchConfig,
+) -> Result<FetchResponse, deno_core::error::AnyError> {
+    use anyhow::anyhow;
+    use reqwest::Method;
+    use url::Url;
+
+    // Parse and validate URL
+    let url = Url::parse(&request.url)
+        .map_err(|e| anyhow!("Invalid URL '{}': {}", request.url, e))?;
+
+    if !config.is_request_allowed(&url) {
+        return Err(anyhow!(
+            "Fetch blocked: '{}{}' is not in the allowlist. Allowed: {:?}",
+            url.origin().ascii_serialization(),
+            url.path(),
+            config.allowed_origins
+        ));
+    }
+
+    // Build the request
+    let client = config.client(&url).await?;
+
+    let method = match request.method.as_deref().unwrap_or("GET").to_uppercase().as_str() {
+        "GET" => Method::GET,
+        "POST" => Method::POST,
+        "PUT" => Method::PUT,
+        "DELETE" => Method::DELETE,
+        "PATCH" => Method::PATCH,
+        "HEAD" => Method::HEAD,
+        "OPTIONS" => Method::OPTIONS,
+        other => return Err(anyhow!("Unsupported HTTP method: {}", other)),
+    };
+
+    let mut req_builder = client.request(method, url.clone());
+
+    let secrets = config.secret_headers.get(&url.origin().ascii_serialization());
+    if let Some(ref headers) = request.headers {
+        for (key, value) in headers {
+            // The host's secret headers can't be overridden from JS
+            if secrets.is_some_and(|secrets| secrets.contains(key)) {
+                continue;
+            }
+            req_builder = req_builder.header(key, value);
+        }
+    }
+    for (name, value) in secrets.map_or(&[][..], |secrets| &secrets.0) {
+        let mut value = reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| anyhow!("Invalid value of secret header '{}'", name))?;
+        value.set_sensitive(true);
+        req_builder = req_builder.header(name.as_str(), value);
+    }
+
+    if let Some(body) = request.body {
+        req_builder = req_builder.body(body);
+    }
+
+    let response = req_builder
+        .send()
+        .await
+        .map_err(|e| anyhow!("Fetch failed: {}", e))?;
+
+    let status = response.status();
+    let final_url = response.url().clone();
+
+    // Handle redirects - only allow same-origin
+    if status.is_redirection() {
+        if let Some(location) = response.headers().get("location") {
+            let location_str = location.to_str().map_err(|_| anyhow!("Invalid redirect location"))?;
+            let redirect_url = final_url.join(location_str)
+                .map_err(|e| anyhow!("Invalid redirect URL: {}", e))?;
+
+            if redirect_url.origin() != url.origin() {
+                return Err(anyhow!(
+                    "Fetch blocked: redirect to different origin '{}' (original: '{}')",
+                    redirect_url.origin().ascii_serialization(),
+                    url.origin().ascii_serialization()
+                ));
+            }
+
+            if !config.is_request_allowed(&redirect_url) {
+                return Err(anyhow!(
+                    "Fetch blocked: redirect to '{}{}' is not in the allowlist",
+                    redirect_url.origin().ascii_serialization(),
+                    redirect_url.path()
+                ));
+            }
+
+            // Follow redirect with a recursive call via Box::pin
+            let redirect_request = FetchRequest {
+                url: redirect_url.to_string(),
+                method: Some("GET".to_string()),
+                headers: request.headers.clone(),
+                body: None,
+                abort_id: request.abort_id,
+            };
+
+            return Box::pin(do_fetch(redirect_request, config)).await;
+        }
+    }
+
+    let mut resp_headers = HashMap::new();
+    for (key, value) in response.headers() {
+        if let Ok(v) = value.to_str() {
+            resp_headers.insert(key.to_string(), v.to_string());
+        }
+    }
+
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| anyhow!("Failed to read response body: {}", e))?;
+
+    Ok(FetchResponse {
+        ok: status.is_success(),
+        status: status.as_u16(),
+        status_text: status.canonical_reason().unwrap_or("Unknown").to_string(),
+        headers: resp_headers,
+        url: final_url.to_string(),
+        body: body.to_vec().into(),
+        body_size: body.len() as u64,
+    })
+}
+
+// ============================================================================
+// TLS Policies
+// ============================================================================
+
+/// TLS protocol version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// TLS settings for a single fetch origin (replaces the default client settings for it)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TlsPolicy {
+    /// Refuse connections negotiated below this version
+    pub min_version: Option<TlsVersion>,
+    /// Base64 SHA-256 hashes of accepted server public keys (SPKI). Empty = no pinning
+    pub spki_sha256: Vec<String>,
+    /// PEM file with a self-signed certificate to accept instead of the public roots.
+    /// Only allowed for localhost origins (local test servers).
+    pub self_signed_cert: Option<String>,
+}
+
+impl TlsPolicy {
+    fn validate(&self, origin: &str) -> Result<(), anyhow::Error> {
+        use anyhow::{anyhow, bail};
+
+        let url = url::Url::parse(origin)
+            .map_err(|e| anyhow!("Invalid TLS policy origin '{}': {}", origin, e))?;
+        if url.scheme() != "https" {
+            bail!("TLS policy origin '{}' must use https", origin);
+        }
+        if url.origin().ascii_serialization() != origin {
+            bail!(
+                "TLS policy key '{}' is not an origin (expected '{}')",
+                origin,
+                url.origin().ascii_serialization()
+            );
+        }
+        if self.self_signed_cert.is_some() && !is_localhost(&url) {
+            bail!(
+                "Self-signed certificates are only allowed for localhost origins, not '{}'",
+                origin
+            );
+        }
+        self.pins()?;
+        self.trusted_cert()?;
+        Ok(())
+    }
+
+    fn pins(&self) -> Result<Vec<[u8; 32]>, anyhow::Error> {
+        use base64::Engine;
+
+        self.spki_sha256
+            .iter()
+            .map(|pin| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(pin)
+                    .ok()
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Invalid SPKI pin '{}': expected a base64 SHA-256 hash", pin)
+                    })
+            })
+            .collect()
+    }
+
+    fn trusted_cert(&self) -> Result<Option<CertificateDer<'static>>, anyhow::Error> {
+        use rustls::pki_types::pem::PemObject;
+
+        self.self_signed_cert
+            .as_ref()
+            .map(|path| {
+                CertificateDer::from_pem_file(path).map_err(|e| {
+                    anyhow::anyhow!("Failed to load self-signed certificate '{}': {}", path, e)
+                })
+            })
+            .transpose()
+    }
+
+    /// Build a rustls client config that enforces this policy
+    fn client_config(&self) -> Result<rustls::ClientConfig, anyhow::Error> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let versions: &[&rustls::SupportedProtocolVersion] = match self.min_version {
+            Some(TlsVersion::Tls13) => &[&rustls::version::TLS13],
+            _ => rustls::DEFAULT_VERSIONS,
+        };
+
+        let roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()?;
+        let verifier = PolicyVerifier {
+            webpki,
+            trusted_cert: self.trusted_cert()?,
+            pins: self.pins()?,
+            provider: provider.clone(),
+        };
+
+        Ok(rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(versions)?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth())
+    }
+}
+
+fn is_localhost(url: &url::Url) -> bool {
+    match url.host() {
+        Some(url::Host::Domain(domain)) => domain == "localhost",
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
+/// Certificate verifier for a [`TlsPolicy`]: checks the chain against the public
+/// roots (or the named self-signed cert), then the SPKI pins
+#[derive(Debug)]
+struct PolicyVerifier {
+    webpki: Arc<WebPkiServerVerifier>,
+    trusted_cert: Option<CertificateDer<'static>>,
+    pins: Vec<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PolicyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match &self.trusted_cert {
+            // Exact match - self-signed test certs often can't be used as a trust anchor
+            Some(cert) if cert.as_ref() != end_entity.as_ref() => {
+                return Err(rustls::Error::InvalidCertificate(
+                    rustls::CertificateError::UnknownIssuer,
+                ));
+            }
+            Some(_) => {}
+            None => {
+                self.webpki.verify_server_cert(
+                    end_entity,
+                    intermediates,
+                    server_name,
+                    ocsp_response,
+                    now,
+                )?;
+            }
+        }
+
+        if !self.pins.is_empty() {
+            let hash = spki_sha256(end_entity)?;
+            if !self.pins.contains(&hash) {
+                use base64::Engine;
+                return Err(rustls::Error::General(format!(
+                    "SPKI pin mismatch (server key hash: {})",
+                    base64::engine::general_purpose::STANDARD.encode(hash)
+                )));
+            }
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// SHA-256 of the certificate's DER-encoded SubjectPublicKeyInfo (as used for HPKP-style pins)
+fn spki_sha256(cert: &CertificateDer<'_>) -> Result<[u8; 32], rustls::Error> {
+    use sha2::{Digest, Sha256};
+
+    let cert = webpki::EndEntityCert::try_from(cert)
+        .map_err(|e| rustls::Error::General(format!("Invalid server certificate: {}", e)))?;
+    Ok(Sha256::digest(cert.subject_public_key_info().as_ref()).into())
+}
+
+// ============================================================================
+// Render Progress
+// ============================================================================
+
+/// Phase of the current render, reported by bootstrap.js
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderPhase {
+    #[default]
+    Idle,
+    /// Importing the entry module (including top-level await)
+    Loading,
+    /// Calling the render function
+    Rendering,
+}
+
+#[derive(Debug)]
+struct ProgressState {
+    phase: RenderPhase,
+    entry: String,
+    since: Instant,
+    last_module: Option<String>,
+    interrupted: Option<String>,
+    request_id: Option<String>,
+}
+
+impl Default for ProgressState {
+    fn default() -> Self {
+        Self {
+            phase: RenderPhase::Idle,
+            entry: String::new(),
+            since: Instant::now(),
+            last_module: None,
+            interrupted: None,
+            request_id: None,
+        }
+    }
+}
+
+/// Entry module that is still being evaluated
+#[derive(Debug, Clone)]
+pub struct ModuleProgress {
+    pub entry: String,
+    pub elapsed: Duration,
+    /// Most recently loaded module of the graph, usually the one awaiting
+    pub last_module: Option<String>,
+}
+
+impl std::fmt::Display for ModuleProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "'{}' still evaluating after {}ms", self.entry, self.elapsed.as_millis())?;
+        if let Some(module) = &self.last_module {
+            write!(f, " (last loaded module: {})", module)?;
+        }
+        Ok(())
+    }
+}
+
+/// Progress of the current render.
+///
+/// Updated from JS (phase changes) and the module loader, and read by the
+/// timeout watchdog, which runs on another thread.
+#[derive(Debug, Clone, Default)]
+pub struct RenderProgress(Arc<Mutex<ProgressState>>);
+
+impl RenderProgress {
+    fn state(&self) -> std::sync::MutexGuard<'_, ProgressState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Start tracking a new render
+    pub fn reset(&self) {
+        *self.state() = ProgressState::default();
+    }
+
+    /// Start tracking a new render with a correlation id for its logs
+    pub fn reset_for_request(&self, request_id: Option<&str>) {
+        *self.state() = ProgressState {
+            request_id: request_id.map(str::to_string),
+            ..Default::default()
+        };
+    }
+
+    /// [`request_tag`] of the current render
+    pub fn request_tag(&self) -> String {
+        request_tag(self.state().request_id.as_deref())
+    }
+
+    pub fn set_phase(&self, phase: RenderPhase, entry: &str) {
+        let mut state = self.state();
+        state.phase = phase;
+        state.entry = entry.to_string();
+        state.since = Instant::now();
+    }
+
+    pub fn module_loaded(&self, specifier: &str) {
+        self.state().last_module = Some(specifier.to_string());
+    }
+
+    /// The entry being evaluated, if the render is still importing it
+    pub fn loading(&self) -> Option<ModuleProgress> {
+        let state = self.state();
+        (state.phase == RenderPhase::Loading).then(|| ModuleProgress {
+            entry: state.entry.clone(),
+            elapsed: state.since.elapsed(),
+            last_module: state.last_module.clone(),
+        })
+    }
+
+    /// Record why execution is about to be terminated
+    pub fn interrupt(&self, reason: String) {
+        self.state().interrupted = Some(reason);
+    }
+
+    pub fn take_interrupt(&self) -> Option<String> {
+        self.state().interrupted.take()
+    }
+}
+
+/// ` [req=<id>]` for stderr lines about a render with a correlation id
+/// (empty without one), e.g. `[ssr-sandbox] [req=abc] Slow module evaluation`
+pub fn request_tag(request_id: Option<&str>) -> String {
+    request_id
+        .map(|id| format!(" [req={}]", id))
+        .unwrap_or_default()
+}
+
+#[op2(fast)]
+pub fn op_render_phase(state: &mut OpState, #[string] phase: &str, #[string] entry: &str) {
+    let phase = match phase {
+        "loading" => RenderPhase::Loading,
+        "rendering" => RenderPhase::Rendering,
+        _ => RenderPhase::Idle,
+    };
+    state.borrow::<RenderProgress>().set_phase(phase, entry);
+}
+
+// ============================================================================
+// Streaming Render
+// ============================================================================
+
+/// HTML chunks written by a streaming render, drained by the Rust side as they arrive
+#[derive(Debug, Clone, Default)]
+pub struct RenderChunks(Rc<RefCell<VecDeque<Vec<u8>>>>);
+
+impl RenderChunks {
+    pub fn push(&self, chunk: Vec<u8>) {
+        self.0.borrow_mut().push_back(chunk);
+    }
+
+    pub fn pop(&self) -> Option<Vec<u8>> {
+        self.0.borrow_mut().pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+#[op2(fast)]
+pub fn op_render_chunk(state: &mut OpState, #[buffer(copy)] chunk: Vec<u8>) {
+    if !chunk.is_empty() {
+        state.borrow::<RenderChunks>().push(chunk);
+    }
+}
+
+// ============================================================================
+// Cache Memory Budget (SandboxConfig.cache_memory_budget)
+// ============================================================================
+
+/// Internal caches whose entries count against the memory budget.
+///
+/// Modules and cached render functions live in the V8 heap, which is bounded
+/// by `max_heap_size`, and can't be evicted individually.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKind {
+    /// Responses stored through the Cache API (`caches`)
+    Fetch,
+    /// HTML fragments stored with `__ssr_cache__`
+    Fragment,
+}
+
+impl CacheKind {
+    /// Eviction weight: an entry's idle time is divided by it, so entries of
+    /// higher priority caches are kept longer
+    pub fn priority(self) -> u64 {
+        match self {
+            // Refetching is cheap compared to re-rendering a subtree
+            CacheKind::Fetch => 1,
+            CacheKind::Fragment => 2,
+        }
+    }
+}
+
+/// Cache memory of all runtimes in the process
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CacheMemoryUsage {
+    /// Bytes currently held, per cache
+    pub used: BTreeMap<CacheKind, usize>,
+    /// Entries evicted to stay within a budget, per cache
+    pub evictions: BTreeMap<CacheKind, u64>,
+}
+
+static CACHE_MEMORY: Mutex<CacheMemoryUsage> = Mutex::new(CacheMemoryUsage {
+    used: BTreeMap::new(),
+    evictions: BTreeMap::new(),
+});
+
+/// Cache memory of all runtimes since the process started
+pub fn cache_memory_usage() -> CacheMemoryUsage {
+    CACHE_MEMORY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+fn update_cache_memory(update: impl FnOnce(&mut CacheMemoryUsage)) {
+    update(&mut CACHE_MEMORY.lock().unwrap_or_else(|e| e.into_inner()));
+}
+
+#[derive(Debug)]
+struct BudgetEntry {
+    size: usize,
+    used: u64,
+}
+
+/// One memory budget for all internal caches of a runtime.
+///
+/// Caches report their entries here; when the total goes over the budget the
+/// entry with the longest idle time (weighted by [`CacheKind::priority`]) is
+/// evicted, whichever cache it belongs to. Without a budget, usage is only
+/// tracked for stats.
+#[derive(Debug)]
+pub struct CacheBudget {
+    max_bytes: Option<usize>,
+    entries: HashMap<(CacheKind, String), BudgetEntry>,
+    used: usize,
+    clock: u64,
+}
+
+impl CacheBudget {
+    pub fn new(max_bytes: Option<usize>) -> Self {
+        Self {
+            max_bytes,
+            entries: HashMap::new(),
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Account for a new or replaced entry, returning the entries the caches
+    /// must evict to stay within the budget (possibly the new entry itself)
+    pub fn insert(&mut self, kind: CacheKind, key: &str, size: usize) -> Vec<(CacheKind, String)> {
+        self.remove(kind, key);
+        self.clock += 1;
+        self.entries.insert(
+            (kind, key.to_string()),
+            BudgetEntry {
+                size,
+                used: self.clock,
+            },
+        );
+        self.used += size;
+        update_cache_memory(|usage| *usage.used.entry(kind).or_default() += size);
+
+        let mut evicted = Vec::new();
+        while self.max_bytes.is_some_and(|max| self.used > max) {
+            let Some(victim) = self.victim() else {
+                break;
+            };
+            self.remove(victim.0, &victim.1);
+            update_cache_memory(|usage| *usage.evictions.entry(victim.0).or_default() += 1);
+            evicted.push(victim);
+        }
+        evicted
+    }
+
+    /// Mark an entry as used
+    pub fn touch(&mut self, kind: CacheKind, key: &str) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&(kind, key.to_string())) {
+            entry.used = self.clock;
+        }
+    }
+
+    /// Forget an entry the cache dropped itself
+    pub fn remove(&mut self, kind: CacheKind, key: &str) {
+        if let Some(entry) = self.entries.remove(&(kind, key.to_string())) {
+            self.used -= entry.size;
+            update_cache_memory(|usage| *usage.used.entry(kind).or_default() -= entry.size);
+        }
+    }
+
+    /// Forget every entry, when the caches are cleared
+    pub fn clear(&mut self) {
+        for ((kind, _), entry) in self.entries.drain() {
+            update_cache_memory(|usage| *usage.used.entry(kind).or_default() -= entry.size);
+        }
+        self.used = 0;
+    }
+
+    fn victim(&self) -> Option<(CacheKind, String)> {
+        let weighted_idle = |(kind, _): &(CacheKind, String), entry: &BudgetEntry| {
+            (self.clock - entry.used) as f64 / kind.priority() as f64
+        };
+        self.entries
+            .iter()
+            .max_by(|a, b| weighted_idle(a.0, a.1).total_cmp(&weighted_idle(b.0, b.1)))
+            .map(|(key, _)| key.clone())
+    }
+}
+
+impl Drop for CacheBudget {
+    fn drop(&mut self) {
+        // The runtime (and its caches) are gone
+        update_cache_memory(|usage| {
+            for ((kind, _), entry) in &self.entries {
+                *usage.used.entry(*kind).or_default() -= entry.size;
+            }
+        });
+    }
+}
+
+/// Charge an entry to the budget and evict whatever the budget picks
+fn charge_cache_budget(state: &mut OpState, kind: CacheKind, key: &str, size: usize) {
+    let Some(budget) = state.try_borrow_mut::<CacheBudget>() else {
+        return;
+    };
+    for (kind, key) in budget.insert(kind, key, size) {
+        match kind {
+            CacheKind::Fetch => {
+                if let Some((cache_name, url)) = key.split_once('\n') {
+                    state.borrow_mut::<ResponseCache>().remove(cache_name, url);
+                }
+            }
+            CacheKind::Fragment => state.borrow_mut::<FragmentCache>().remove(&key),
+        }
+    }
+}
+
+fn touch_cache_budget(state: &mut OpState, kind: CacheKind, key: &str) {
+    if let Some(budget) = state.try_borrow_mut::<CacheBudget>() {
+        budget.touch(kind, key);
+    }
+}
+
+fn release_cache_budget(state: &mut OpState, kind: CacheKind, key: &str) {
+    if let Some(budget) = state.try_borrow_mut::<CacheBudget>() {
+        budget.remove(kind, key);
+    }
+}
+
+/// Empty the Cache API store and the fragment cache of a runtime (they stay
+/// enabled), returning how many bytes they held
+pub fn shed_caches(state: &mut OpState) -> usize {
+    if let Some(cache) = state.try_borrow_mut::<ResponseCache>() {
+        cache.clear();
+    }
+    if let Some(cache) = state.try_borrow_mut::<FragmentCache>() {
+        cache.clear();
+    }
+    state.try_borrow_mut::<CacheBudget>().map_or(0, |budget| {
+        let used = budget.used();
+        budget.clear();
+        used
+    })
+}
+
+/// Budget key of a Cache API entry
+fn fetch_cache_key(cache_name: &str, url: &str) -> String {
+    format!("{}\n{}", cache_name, url)
+}
+
+// ============================================================================
+// Cache API
+// ============================================================================
+
+/// A response stored through the Cache API shim
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub headers: HashMap<String, String>,
+    pub url: String,
+    pub body: String,
+}
+
+impl CachedResponse {
+    /// Approximate memory held by the response
+    pub fn size(&self) -> usize {
+        let headers: usize = self.headers.iter().map(|(k, v)| k.len() + v.len()).sum();
+        self.status_text.len() + headers + self.url.len() + self.body.len()
+    }
+}
+
+/// Storage behind the opt-in Cache API shim (`caches.open()`, `caches.match()`).
+///
+/// Lives in the runtime's OpState, so entries are shared by every render on the
+/// same runtime (and lost when it is recreated, e.g. after a timeout). Ordered
+/// maps keep `keys()` deterministic.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseCache {
+    pub enabled: bool,
+    caches: BTreeMap<String, BTreeMap<String, CachedResponse>>,
+}
+
+impl ResponseCache {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Default::default()
+        }
+    }
+
+    /// Look up a URL in one cache, or in every cache (in name order) if `cache_name` is None
+    pub fn lookup(&self, cache_name: Option<&str>, url: &str) -> Option<&CachedResponse> {
+        match cache_name {
+            Some(name) => self.caches.get(name)?.get(url),
+            None => self.caches.values().find_map(|cache| cache.get(url)),
+        }
+    }
+
+    pub fn put(&mut self, cache_name: &str, url: String, response: CachedResponse) {
+        self.caches
+            .entry(cache_name.to_string())
+            .or_default()
+            .insert(url, response);
+    }
+
+    pub fn remove(&mut self, cache_name: &str, url: &str) -> bool {
+        self.caches
+            .get_mut(cache_name)
+            .is_some_and(|cache| cache.remove(url).is_some())
+    }
+
+    /// Drop every stored response (named caches stay open)
+    pub fn clear(&mut self) {
+        for cache in self.caches.values_mut() {
+            cache.clear();
+        }
+    }
+
+    pub fn urls(&self, cache_name: &str) -> Vec<String> {
+        self.caches
+            .get(cache_name)
+            .map(|cache| cache.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[op2(fast)]
+pub fn op_cache_enabled(state: &mut OpState) -> bool {
+    state.borrow::<ResponseCache>().enabled
+}
+
+#[op2]
+#[serde]
+pub fn op_cache_match(
+    state: &mut OpState,
+    #[string] cache_name: &str,
+    #[string] url: &str,
+) -> Option<CachedResponse> {
+    touch_cache_budget(state, CacheKind::Fetch, &fetch_cache_key(cache_name, url));
+    state
+        .borrow::<ResponseCache>()
+        .lookup(Some(cache_name), url)
+        .cloned()
+}
+
+#[op2]
+pub fn op_cache_put(
+    state: &mut OpState,
+    #[string] cache_name: String,
+    #[string] url: String,
+    #[serde] response: CachedResponse,
+) {
+    let key = fetch_cache_key(&cache_name, &url);
+    let size = key.len() + response.size();
+    state
+        .borrow_mut::<ResponseCache>()
+        .put(&cache_name, url, response);
+    charge_cache_budget(state, CacheKind::Fetch, &key, size);
+}
+
+#[op2(fast)]
+pub fn op_cache_delete(state: &mut OpState, #[string] cache_name: &str, #[string] url: &str) -> bool {
+    release_cache_budget(state, CacheKind::Fetch, &fetch_cache_key(cache_name, url));
+    state.borrow_mut::<ResponseCache>().remove(cache_name, url)
+}
+
+#[op2]
+#[serde]
+pub fn op_cache_keys(state: &mut OpState, #[string] cache_name: &str) -> Vec<String> {
+    state.borrow::<ResponseCache>().urls(cache_name)
+}
+
+#[op2(fast)]
+pub fn op_caches_open(state: &mut OpState, #[string] cache_name: &str) {
+    state
+        .borrow_mut::<ResponseCache>()
+        .caches
+        .entry(cache_name.to_string())
+        .or_default();
+}
+
+#[op2(fast)]
+pub fn op_caches_has(state: &mut OpState, #[string] cache_name: &str) -> bool {
+    state.borrow::<ResponseCache>().caches.contains_key(cache_name)
+}
+
+#[op2(fast)]
+pub fn op_caches_delete(state: &mut OpState, #[string] cache_name: &str) -> bool {
+    for url in state.borrow::<ResponseCache>().urls(cache_name) {
+        release_cache_budget(state, CacheKind::Fetch, &fetch_cache_key(cache_name, &url));
+    }
+    state
+        .borrow_mut::<ResponseCache>()
+        .caches
+        .remove(cache_name)
+        .is_some()
+}
+
+#[op2]
+#[serde]
+pub fn op_caches_match(state: &mut OpState, #[string] url: &str) -> Option<CachedResponse> {
+    let cache_name = state
+        .borrow::<ResponseCache>()
+        .caches
+        .iter()
+        .find(|(_, cache)| cache.contains_key(url))
+        .map(|(name, _)| name.clone())?;
+    touch_cache_budget(state, CacheKind::Fetch, &fetch_cache_key(&cache_name, url));
+    state.borrow::<ResponseCache>().lookup(Some(&cache_name), url).cloned()
+}
+
+#[op2]
+#[serde]
+pub fn op_caches_keys(state: &mut OpState) -> Vec<String> {
+    state.borrow::<ResponseCache>().caches.keys().cloned().collect()
+}
+
+// ============================================================================
+// Fragment Cache (__ssr_cache__)
+// ============================================================================
+
+/// Fragment cache counters, process-wide like the violation counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FragmentCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub sets: u64,
+    /// Entries dropped to make room (expired entries don't count)
+    pub evictions: u64,
+}
+
+static FRAGMENT_CACHE_STATS: Mutex<FragmentCacheStats> = Mutex::new(FragmentCacheStats {
+    hits: 0,
+    misses: 0,
+    sets: 0,
+    evictions: 0,
+});
+
+/// Fragment cache counters of all runtimes since the process started
+pub fn fragment_cache_stats() -> FragmentCacheStats {
+    *FRAGMENT_CACHE_STATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+fn count_fragment_cache(update: impl FnOnce(&mut FragmentCacheStats)) {
+    update(&mut FRAGMENT_CACHE_STATS.lock().unwrap_or_else(|e| e.into_inner()));
+}
+
+#[derive(Debug)]
+struct Fragment {
+    html: String,
+    expires: Option<Instant>,
+    /// Position in the LRU order
+    used: u64,
+}
+
+/// Rendered HTML fragments memoized by render code across requests on one
+/// isolate, bounded by the total size of keys and HTML (least recently used
+/// entries are evicted first)
+#[derive(Debug, Default)]
+pub struct FragmentCache {
+    /// None = disabled, `__ssr_cache__` is undefined
+    max_bytes: Option<usize>,
+    entries: HashMap<String, Fragment>,
+    /// LRU position to key, oldest first
+    lru: BTreeMap<u64, String>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl FragmentCache {
+    pub fn new(max_bytes: Option<usize>) -> Self {
+        Self {
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_bytes.is_some()
+    }
+
+    pub fn get(&mut self, key: &str, now: Instant) -> Option<String> {
+        let expired = match self.entries.get(key) {
+            Some(fragment) => fragment.expires.is_some_and(|expires| expires <= now),
+            None => {
+                count_fragment_cache(|stats| stats.misses += 1);
+                return None;
+            }
+        };
+        if expired {
+            self.remove(key);
+            count_fragment_cache(|stats| stats.misses += 1);
+            return None;
+        }
+
+        self.clock += 1;
+        let fragment = self.entries.get_mut(key)?;
+        self.lru.remove(&fragment.used);
+        fragment.used = self.clock;
+        self.lru.insert(self.clock, key.to_string());
+        count_fragment_cache(|stats| stats.hits += 1);
+        Some(fragment.html.clone())
+    }
+
+    /// Store a fragment, returning the keys evicted to make room, or None if
+    /// it is larger than the whole cache
+    pub fn set(
+        &mut self,
+        key: String,
+        html: String,
+        ttl: Option<Duration>,
+        now: Instant,
+    ) -> Option<Vec<String>> {
+        let max_bytes = self.max_bytes?;
+        self.remove(&key);
+        let size = key.len() + html.len();
+        if size > max_bytes {
+            return None;
+        }
+
+        let mut evicted = Vec::new();
+        while self.bytes + size > max_bytes {
+            let Some((_, oldest)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(fragment) = self.entries.remove(&oldest) {
+                self.bytes -= oldest.len() + fragment.html.len();
+            }
+            count_fragment_cache(|stats| stats.evictions += 1);
+            evicted.push(oldest);
+        }
+
+        self.clock += 1;
+        self.bytes += size;
+        self.lru.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            Fragment {
+                html,
+                expires: ttl.map(|ttl| now + ttl),
+                used: self.clock,
+            },
+        );
+        count_fragment_cache(|stats| stats.sets += 1);
+        Some(evicted)
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if let Some(fragment) = self.entries.remove(key) {
+            self.lru.remove(&fragment.used);
+            self.bytes -= key.len() + fragment.html.len();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+        self.bytes = 0;
+    }
+}
+
+#[op2(fast)]
+pub fn op_fragment_cache_enabled(state: &mut OpState) -> bool {
+    state
+        .try_borrow::<FragmentCache>()
+        .is_some_and(FragmentCache::enabled)
+}
+
+#[op2]
+#[string]
+pub fn op_fragment_cache_get(state: &mut OpState, #[string] key: &str) -> Option<String> {
+    let html = state
+        .try_borrow_mut::<FragmentCache>()?
+        .get(key, Instant::now());
+    match &html {
+        Some(_) => touch_cache_budget(state, CacheKind::Fragment, key),
+        // Possibly expired and dropped
+        None => release_cache_budget(state, CacheKind::Fragment, key),
+    }
+    html
+}
+
+#[op2]
+pub fn op_fragment_cache_set(
+    state: &mut OpState,
+    #[string] key: String,
+    #[string] html: String,
+    #[serde] ttl_ms: Option<u64>,
+) -> bool {
+    let Some(cache) = state.try_borrow_mut::<FragmentCache>() else {
+        return false;
+    };
+    let size = key.len() + html.len();
+    match cache.set(key.clone(), html, ttl_ms.map(Duration::from_millis), Instant::now()) {
+        Some(evicted) => {
+            for evicted_key in evicted {
+                release_cache_budget(state, CacheKind::Fragment, &evicted_key);
+            }
+            charge_cache_budget(state, CacheKind::Fragment, &key, size);
+            // The budget may have picked the new entry itself
+            state.borrow::<FragmentCache>().entries.contains_key(&key)
+        }
+        None => {
+            release_cache_budget(state, CacheKind::Fragment, &key);
+            false
+        }
+    }
+}
+
+// ============================================================================
+// Web Storage
+// ============================================================================
+
+/// Behavior of `localStorage` / `sessionStorage` during a render
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageMode {
+    /// Not defined (`typeof localStorage === "undefined"`)
+    #[default]
+    Disabled,
+    /// In-memory Storage, wiped before every render
+    Memory,
+    /// Defined, but every method throws with a clear message
+    Strict,
+}
+
+impl std::str::FromStr for StorageMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disabled" => Ok(StorageMode::Disabled),
+            "memory" => Ok(StorageMode::Memory),
+            "strict" => Ok(StorageMode::Strict),
+            other => Err(anyhow::anyhow!(
+                "Unknown storage mode '{}' (expected disabled, memory or strict)",
+                other
+            )),
+        }
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_storage_mode(state: &mut OpState) -> StorageMode {
+    *state.borrow::<StorageMode>()
+}
+
+// ============================================================================
+// Global Reset (SandboxConfig.context_per_request)
+// ============================================================================
+
+/// Whether globals are reset to their startup state around every render
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContextPerRequest(pub bool);
+
+#[op2(fast)]
+pub fn op_context_per_request(state: &mut OpState) -> bool {
+    state.borrow::<ContextPerRequest>().0
+}
+
+// ============================================================================
+// Timers (setTimeout/setInterval)
+// ============================================================================
+
+/// Limits on timers, which keep a render alive while they are pending
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TimerLimits {
+    /// Maximum timers pending at once; creating another one throws
+    pub max_pending: usize,
+    /// Timers with a longer delay are never scheduled (they could only hold
+    /// the render until its timeout)
+    pub max_delay_ms: u64,
+}
+
+impl Default for TimerLimits {
+    fn default() -> Self {
+        Self {
+            max_pending: 100,
+            max_delay_ms: 5_000,
+        }
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_timer_limits(state: &mut OpState) -> TimerLimits {
+    state.try_borrow::<TimerLimits>().copied().unwrap_or_default()
+}
+
+// ============================================================================
+// Deterministic Mode (SandboxConfig.deterministic)
+// ============================================================================
+
+/// Fixed clock and PRNG seed used by renders in deterministic mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DeterminismConfig {
+    /// What `Date.now()` returns, in milliseconds since the Unix epoch
+    /// (`performance.now()` always returns 0)
+    pub epoch_ms: u64,
+    /// Seed for `Math.random()`, `crypto.getRandomValues()` and `crypto.randomUUID()`
+    pub seed: u64,
+}
+
+impl Default for DeterminismConfig {
+    fn default() -> Self {
+        Self {
+            // 2000-01-01T00:00:00Z
+            epoch_ms: 946_684_800_000,
+            seed: 0,
+        }
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_determinism(state: &mut OpState) -> Option<DeterminismConfig> {
+    state.try_borrow::<DeterminismConfig>().copied()
+}
+
+// ============================================================================
+// Static Data Files (__ssr_read_data__)
+// ============================================================================
+
+/// Read-only directory of static data files (route manifests, precomputed
+/// JSON) that renders can read with `__ssr_read_data__(relpath)`
+#[derive(Debug, Clone)]
+pub struct DataDir {
+    /// Canonical path of the directory
+    root: PathBuf,
+    /// Larger files are refused
+    max_file_size: u64,
+}
+
+impl DataDir {
+    pub const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+    pub fn new(dir: impl AsRef<Path>, max_file_size: u64) -> Result<Self, anyhow::Error> {
+        use anyhow::anyhow;
+
+        let dir = dir.as_ref();
+        let root = dir
+            .canonicalize()
+            .map_err(|e| anyhow!("Invalid data dir '{}': {}", dir.display(), e))?;
+        if !root.is_dir() {
+            return Err(anyhow!("Data dir '{}' is not a directory", dir.display()));
+        }
+        Ok(Self {
+            root,
+            max_file_size,
+        })
+    }
+
+    /// Read a file below the directory as UTF-8 text.
+    ///
+    /// Errors starting with "Data read blocked" are sandbox violations: absolute
+    /// paths, `..` and symlinks leading out of the directory.
+    pub fn read(&self, relpath: &str) -> Result<String, anyhow::Error> {
+        use anyhow::anyhow;
+
+        let relative = Path::new(relpath);
+        let is_relative = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if relpath.is_empty() || !is_relative {
+            return Err(anyhow!(
+                "Data read blocked: '{}' is not a relative path inside the data directory",
+                relpath
+            ));
+        }
+
+        let path = self
+            .root
+            .join(relative)
+            .canonicalize()
+            .map_err(|e| anyhow!("Failed to read data file '{}': {}", relpath, e))?;
+        if !path.starts_with(&self.root) {
+            return Err(anyhow!(
+                "Data read blocked: '{}' resolves outside the data directory",
+                relpath
+            ));
+        }
+
+        let file = std::fs::File::open(&path)
+            .map_err(|e| anyhow!("Failed to read data file '{}': {}", relpath, e))?;
+        let metadata = file
+            .metadata()
+            .map_err(|e| anyhow!("Failed to read data file '{}': {}", relpath, e))?;
+        if !metadata.is_file() {
+            return Err(anyhow!("Data file '{}' is not a file", relpath));
+        }
+
+        // Read one byte past the limit in case the file grew since the stat
+        let mut text = String::new();
+        file.take(self.max_file_size + 1)
+            .read_to_string(&mut text)
+            .map_err(|e| anyhow!("Failed to read data file '{}': {}", relpath, e))?;
+        if metadata.len() > self.max_file_size || text.len() as u64 > self.max_file_size {
+            return Err(anyhow!(
+                "Data file '{}' exceeds the {} byte limit",
+                relpath,
+                self.max_file_size
+            ));
+        }
+        Ok(text)
+    }
+}
+
+#[op2]
+#[string]
+pub fn op_read_data(
+    state: &mut OpState,
+    #[string] relpath: &str,
+) -> Result<String, deno_core::error::AnyError> {
+    let Some(data_dir) = state.try_borrow::<DataDir>() else {
+        return Err(anyhow::anyhow!(
+            "__ssr_read_data__ is disabled: no data directory configured"
+        ));
+    };
+
+    let result = data_dir.read(relpath);
+    if let Err(e) = &result {
+        let message = e.to_string();
+        if message.starts_with("Data read blocked") {
+            if let Some(log) = state.try_borrow::<ViolationLog>() {
+                log.record(SandboxViolation {
+                    kind: ViolationKind::PathEscape,
+                    specifier: relpath.to_string(),
+                    referrer: None,
+                    message,
+                    request_id: None,
+                });
+            }
+        }
+    }
+    result
+}
+
+// ============================================================================
+// Host Calls (__ssr_host__)
+// ============================================================================
+
+/// Future returned by a [`HostFunction`]
+pub type HostFuture =
+    std::pin::Pin<Box<dyn std::future::Future<Output = Result<serde_json::Value, anyhow::Error>>>>;
+
+/// Function registered by the embedder, called with the JSON arguments of
+/// `__ssr_host__.call(name, ...args)`
+pub type HostFunction = Arc<dyn Fn(Vec<serde_json::Value>) -> HostFuture + Send + Sync>;
+
+/// Allowlist of host functions renders can call with `__ssr_host__.call()`,
+/// so Rust hosts can provide data directly instead of through a local HTTP
+/// endpoint. Arguments and results go through serde_json.
+#[derive(Clone)]
+pub struct HostFunctions {
+    functions: BTreeMap<String, HostFunction>,
+    /// Calls taking longer fail with a timeout error
+    timeout: Duration,
+}
+
+impl HostFunctions {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Limit every call to `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Make `function` callable as `__ssr_host__.call(name, ...args)`
+    ///
+    /// ```rust,ignore
+    /// let host = HostFunctions::default().register("getUser", |args| async move {
+    ///     let id = args.first().and_then(|id| id.as_u64()).ok_or_else(|| anyhow!("missing id"))?;
+    ///     Ok(serde_json::to_value(users::find(id).await?)?)
+    /// });
+    /// ```
+    pub fn register<F, Fut>(mut self, name: impl Into<String>, function: F) -> Self
+    where
+        F: Fn(Vec<serde_json::Value>) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<serde_json::Value, anyhow::Error>> + 'static,
+    {
+        let function: HostFunction = Arc::new(move |args| Box::pin(function(args)));
+        self.functions.insert(name.into(), function);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// Names of the registered functions, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(String::as_str)
+    }
+
+    /// Call a registered function, failing if it doesn't return within the timeout
+    pub async fn call(
+        &self,
+        name: &str,
+        args: Vec<serde_json::Value>,
+    ) -> Result<serde_json::Value, anyhow::Error> {
+        use anyhow::anyhow;
+
+        let function = self
+            .functions
+            .get(name)
+            .ok_or_else(|| anyhow!("Host function '{}' is not registered", name))?;
+        tokio::time::timeout(self.timeout, function(args))
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "Host function '{}' timed out after {}ms",
+                    name,
+                    self.timeout.as_millis()
+                )
+            })?
+            .map_err(|e| anyhow!("Host function '{}' failed: {}", name, e))
+    }
+}
+
+impl Default for HostFunctions {
+    fn default() -> Self {
+        Self {
+            functions: BTreeMap::new(),
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl std::fmt::Debug for HostFunctions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HostFunctions")
+            .field("functions", &self.names().collect::<Vec<_>>())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Function names and timeout (for `@config`)
+impl Serialize for HostFunctions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_json::json!({
+            "functions": self.names().collect::<Vec<_>>(),
+            "timeout_ms": self.timeout.as_millis() as u64,
+        })
+        .serialize(serializer)
+    }
+}
+
+/// Arguments and result are JSON text, so values go through `JSON.stringify`
+/// semantics on the way out and serde_json on the host side
+#[op2(async)]
+#[string]
+pub async fn op_host_call(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+    #[string] args_json: String,
+) -> Result<String, deno_core::error::AnyError> {
+    use anyhow::anyhow;
+
+    let Some(host) = state.borrow().try_borrow::<HostFunctions>().cloned() else {
+        return Err(anyhow!("__ssr_host__ is disabled: no host functions registered"));
+    };
+    let args: Vec<serde_json::Value> = serde_json::from_str(&args_json)
+        .map_err(|e| anyhow!("Invalid host call arguments: {}", e))?;
+    let result = host.call(&name, args).await?;
+    Ok(serde_json::to_string(&result)?)
+}
+
+// ============================================================================
+// Host Data Feed (context.hostFeed)
+// ============================================================================
+
+/// Create a channel for pushing data into a render while it runs.
+///
+/// The [`HostFeed`] goes into the render's options and shows up as the async
+/// iterator `context.hostFeed`; items sent with the [`HostFeedSender`] (from
+/// any thread) are yielded in order. Iteration ends once every sender has been
+/// dropped, so a render waiting on the feed waits for the host to finish.
+pub fn host_feed() -> (HostFeedSender, HostFeed) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    (
+        HostFeedSender(sender),
+        HostFeed(Arc::new(tokio::sync::Mutex::new(receiver))),
+    )
+}
+
+/// Sending half of a [`host_feed`]
+#[derive(Debug, Clone)]
+pub struct HostFeedSender(tokio::sync::mpsc::UnboundedSender<serde_json::Value>);
+
+impl HostFeedSender {
+    /// Push an item to the render; fails once the render's feed has been dropped
+    pub fn push(&self, item: serde_json::Value) -> Result<(), anyhow::Error> {
+        self.0
+            .send(item)
+            .map_err(|_| anyhow::anyhow!("Host feed closed: the render has finished"))
+    }
+}
+
+/// Receiving half of a [`host_feed`], read by the render as `context.hostFeed`
+#[derive(Debug, Clone)]
+pub struct HostFeed(Arc<tokio::sync::Mutex<tokio::sync::mpsc::UnboundedReceiver<serde_json::Value>>>);
+
+impl HostFeed {
+    /// Next pushed item, or None once every sender has been dropped
+    pub async fn next(&self) -> Option<serde_json::Value> {
+        self.0.lock().await.recv().await
+    }
+}
+
+/// Next item as an iterator result (`{ done, value }`)
+#[op2(async)]
+#[serde]
+pub async fn op_host_feed_next(state: Rc<RefCell<OpState>>) -> serde_json::Value {
+    let feed = state.borrow().try_borrow::<HostFeed>().cloned();
+    let item = match feed {
+        Some(feed) => feed.next().await,
+        None => None,
+    };
+    match item {
+        Some(value) => serde_json::json!({ "done": false, "value": value }),
+        None => serde_json::json!({ "done": true }),
+    }
+}
+
+// ============================================================================
+// Islands (context.island)
+// ============================================================================
+
+/// Most islands a single render may defer
+pub const MAX_ISLANDS_PER_RENDER: usize = 1000;
+
+/// Islands deferred by the current render, drained after each render
+#[derive(Debug, Default)]
+pub struct RenderIslands(pub Vec<Island>);
+
+/// Whether an island entry is a plain relative path inside the chunks directory
+fn is_valid_island_entry(entry: &str) -> bool {
+    let path = Path::new(entry);
+    !entry.is_empty() && path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Defer an island of the current render, returning its id
+#[op2]
+#[string]
+pub fn op_island_defer(
+    state: &mut OpState,
+    #[string] entry: String,
+    #[serde] props: serde_json::Value,
+) -> Result<String, deno_core::error::AnyError> {
+    use anyhow::anyhow;
+
+    if !is_valid_island_entry(&entry) {
+        return Err(anyhow!(
+            "Invalid island entry '{}' (expected a path relative to the chunks directory)",
+            entry
+        ));
+    }
+    let island = Island::new(entry, props);
+    let id = island.id.clone();
+    let islands = &mut state.borrow_mut::<RenderIslands>().0;
+    if !islands.iter().any(|i| i.id == id) {
+        if islands.len() >= MAX_ISLANDS_PER_RENDER {
+            return Err(anyhow!(
+                "Too many islands (at most {} per render)",
+                MAX_ISLANDS_PER_RENDER
+            ));
+        }
+        islands.push(island);
+    }
+    Ok(id)
+}
+
+// ============================================================================
+// WebAssembly (SandboxConfig.wasm_limits)
+// ============================================================================
+
+/// Size of a WebAssembly memory page
+pub const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+/// Limits on WebAssembly modules, whether imported as `.wasm` files or
+/// compiled from bytes with `WebAssembly.instantiate()`
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct WasmLimits {
+    /// Larger modules are refused
+    pub max_module_bytes: usize,
+    /// Maximum size of each linear memory; `memory.grow` fails past it
+    pub max_memory_bytes: u64,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            max_module_bytes: 16 * 1024 * 1024,
+            max_memory_bytes: 128 * 1024 * 1024,
+        }
+    }
+}
+
+/// A WebAssembly module with its memories capped by [`WasmLimits::apply`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WasmModule {
+    pub bytes: Vec<u8>,
+    /// `(module, name)` of each import, in order
+    pub imports: Vec<(String, String)>,
+    pub exports: Vec<String>,
+}
+
+impl WasmLimits {
+    pub fn max_memory_pages(&self) -> u64 {
+        self.max_memory_bytes / WASM_PAGE_SIZE
+    }
+
+    /// Check a module against the limits and cap the maximum of every memory
+    /// it defines or imports.
+    ///
+    /// Only the section headers, imports, memories and exports are parsed;
+    /// validating the rest is left to V8.
+    pub fn apply(&self, bytes: &[u8]) -> Result<WasmModule, anyhow::Error> {
+        use anyhow::anyhow;
+
+        if bytes.len() > self.max_module_bytes {
+            return Err(anyhow!(
+                "WebAssembly module is {} bytes, over the {} byte limit",
+                bytes.len(),
+                self.max_module_bytes
+            ));
+        }
+        if bytes.len() < 8 || &bytes[..4] != b"\0asm" {
+            return Err(anyhow!("Not a WebAssembly module (bad magic number)"));
+        }
+
+        let mut module = WasmModule {
+            bytes: bytes[..8].to_vec(),
+            ..Default::default()
+        };
+        let mut reader = WasmReader::new(&bytes[8..]);
+        while !reader.is_done() {
+            let id = reader.byte()?;
+            let size = reader.leb()? as usize;
+            let section = reader.take(size)?;
+            let content = match id {
+                2 => self.rewrite_imports(section, &mut module.imports)?,
+                5 => self.rewrite_memories(section)?,
+                7 => {
+                    let mut exports = WasmReader::new(section);
+                    for _ in 0..exports.leb()? {
+                        module.exports.push(exports.name()?);
+                        exports.byte()?;
+                        exports.leb()?;
+                    }
+                    section.to_vec()
+                }
+                _ => section.to_vec(),
+            };
+            module.bytes.push(id);
+            write_leb(&mut module.bytes, content.len() as u64);
+            module.bytes.extend_from_slice(&content);
+        }
+        Ok(module)
+    }
+
+    fn rewrite_imports(
+        &self,
+        section: &[u8],
+        imports: &mut Vec<(String, String)>,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        use anyhow::anyhow;
+
+        let mut reader = WasmReader::new(section);
+        let mut content = Vec::with_capacity(section.len());
+        let mut copied = 0;
+        for _ in 0..reader.leb()? {
+            imports.push((reader.name()?, reader.name()?));
+            match reader.byte()? {
+                // Function or tag
+                0x00 => {
+                    reader.leb()?;
+                }
+                0x04 => {
+                    reader.byte()?;
+                    reader.leb()?;
+                }
+                // Table
+                0x01 => {
+                    reader.value_type()?;
+                    reader.limits()?;
+                }
+                // Memory
+                0x02 => {
+                    content.extend_from_slice(&section[copied..reader.pos]);
+                    self.write_memory(&mut content, reader.limits()?)?;
+                    copied = reader.pos;
+                }
+                // Global
+                0x03 => {
+                    reader.value_type()?;
+                    reader.byte()?;
+                }
+                kind => return Err(anyhow!("Unknown WebAssembly import kind {:#04x}", kind)),
+            }
+        }
+        content.extend_from_slice(&section[copied..]);
+        Ok(content)
+    }
+
+    fn rewrite_memories(&self, section: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut reader = WasmReader::new(section);
+        let count = reader.leb()?;
+        let mut content = Vec::with_capacity(section.len() + 4);
+        write_leb(&mut content, count);
+        for _ in 0..count {
+            self.write_memory(&mut content, reader.limits()?)?;
+        }
+        Ok(content)
+    }
+
+    fn write_memory(
+        &self,
+        out: &mut Vec<u8>,
+        (flags, min, max): (u8, u64, Option<u64>),
+    ) -> Result<(), anyhow::Error> {
+        let cap = self.max_memory_pages();
+        if min > cap {
+            return Err(anyhow::anyhow!(
+                "WebAssembly memory needs {} bytes, over the {} byte limit",
+                min.saturating_mul(WASM_PAGE_SIZE),
+                self.max_memory_bytes
+            ));
+        }
+        // Setting bit 0 adds a maximum to memories declared without one
+        out.push(flags | 0x01);
+        write_leb(out, min);
+        write_leb(out, max.map_or(cap, |max| max.min(cap)));
+        Ok(())
+    }
+}
+
+/// Cursor over the bytes of a WebAssembly module
+struct WasmReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> WasmReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, anyhow::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], anyhow::Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| anyhow::anyhow!("Truncated WebAssembly module"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Unsigned LEB128; also skips signed ones, which only differ in meaning
+    fn leb(&mut self) -> Result<u64, anyhow::Error> {
+        let mut value = 0u64;
+        for shift in (0..70).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow::anyhow!("Malformed LEB128 in WebAssembly module"))
+    }
+
+    fn name(&mut self) -> Result<String, anyhow::Error> {
+        let len = self.leb()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| anyhow::anyhow!("Invalid UTF-8 name in WebAssembly module"))
+    }
+
+    /// Value or reference type; `(ref null? heaptype)` carries a heap type
+    fn value_type(&mut self) -> Result<(), anyhow::Error> {
+        if matches!(self.byte()?, 0x63 | 0x64) {
+            self.leb()?;
+        }
+        Ok(())
+    }
+
+    /// Flags, minimum and optional maximum of a memory or table
+    fn limits(&mut self) -> Result<(u8, u64, Option<u64>), anyhow::Error> {
+        let flags = self.byte()?;
+        if flags > 0x07 {
+            return Err(anyhow::anyhow!("Unknown WebAssembly limits flags {:#04x}", flags));
+        }
+        let min = self.leb()?;
+        let max = if flags & 0x01 != 0 { Some(self.leb()?) } else { None };
+        Ok((flags, min, max))
+    }
+}
+
+fn write_leb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// `.wasm` files read by the module loader, held until the JS module
+/// generated for each one instantiates it
+#[derive(Debug, Clone, Default)]
+pub struct WasmModules(Rc<RefCell<HashMap<String, Vec<u8>>>>);
+
+impl WasmModules {
+    pub fn insert(&self, specifier: String, bytes: Vec<u8>) {
+        self.0.borrow_mut().insert(specifier, bytes);
+    }
+
+    pub fn take(&self, specifier: &str) -> Option<Vec<u8>> {
+        self.0.borrow_mut().remove(specifier)
+    }
+}
+
+#[op2]
+#[serde]
+pub fn op_wasm_limits(state: &mut OpState) -> WasmLimits {
+    state.try_borrow::<WasmLimits>().copied().unwrap_or_default()
+}
+
+/// Bytes passed to `WebAssembly.compile()` and friends, with memories capped
+#[op2]
+#[buffer]
+pub fn op_wasm_limit(
+    state: &mut OpState,
+    #[buffer] bytes: &[u8],
+) -> Result<Vec<u8>, deno_core::error::AnyError> {
+    let limits = state.try_borrow::<WasmLimits>().copied().unwrap_or_default();
+    Ok(limits.apply(bytes)?.bytes)
+}
+
+/// Bytes of an imported `.wasm` file, already capped by the module loader
+#[op2]
+#[buffer]
+pub fn op_wasm_module_bytes(
+    state: &mut OpState,
+    #[string] specifier: &str,
+) -> Result<Vec<u8>, deno_core::error::AnyError> {
+    state
+        .try_borrow::<WasmModules>()
+        .and_then(|modules| modules.take(specifier))
+        .ok_or_else(|| anyhow::anyhow!("WebAssembly module '{}' was not loaded", specifier))
+}
+
+// ============================================================================
+// Extension Definition
+// ============================================================================
+
+deno_core::extension!(
+    ssr_runtime,
+    ops = [
+        op_console,
+        op_fetch,
+        op_fetch_abort,
+        op_render_chunk,
+        op_render_phase,
+        op_cache_enabled,
+        op_cache_match,
+        op_cache_put,
+        op_cache_delete,
+        op_cache_keys,
+        op_caches_open,
+        op_caches_has,
+        op_caches_delete,
+        op_caches_match,
+        op_caches_keys,
+        op_storage_mode,
+        op_context_per_request,
+        op_warn_deprecated,
+        op_timer_limits,
+        op_read_data,
+        op_determinism,
+        op_fragment_cache_enabled,
+        op_fragment_cache_get,
+        op_fragment_cache_set,
+        op_host_call,
+        op_host_feed_next,
+        op_island_defer,
+        op_wasm_limits,
+        op_wasm_limit,
+        op_wasm_module_bytes,
+    ],
+    esm_entry_point = "ext:ssr_runtime/bootstrap.js",
+    esm = ["ext:ssr_runtime/bootstrap.js" = "src/bootstrap.js"],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warning_log_deduplicates() {
+        let log = WarningLog::default();
+        log.warn(WarningCode::DeprecatedApi, "escape() is deprecated");
+        log.warn(WarningCode::DeprecatedApi, "escape() is deprecated");
+        log.warn(WarningCode::SlowFetch, "fetch(https://api.example.com/) took 1500ms");
+        let warnings = log.take();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].to_string(), "deprecated_api: escape() is deprecated");
+        assert!(log.take().is_empty());
+
+        for i in 0..(MAX_WARNINGS + 10) {
+            log.warn(WarningCode::SlowFetch, format!("fetch #{}", i));
+        }
+        assert_eq!(log.take().len(), MAX_WARNINGS);
+    }
+
+    #[test]
+    fn test_fragment_cache_evicts_and_expires() {
+        let now = Instant::now();
+        let mut cache = FragmentCache::new(Some(30));
+        assert_eq!(cache.set("nav".into(), "<nav></nav>".into(), None, now), Some(vec![]));
+        assert_eq!(cache.set("ft".into(), "<footer>".into(), None, now), Some(vec![]));
+        assert_eq!(cache.get("nav", now).as_deref(), Some("<nav></nav>"));
+
+        // "ft" is now the least recently used entry
+        let evicted = cache.set("x".into(), "<p>ab</p>".into(), None, now);
+        assert_eq!(evicted, Some(vec!["ft".to_string()]));
+        assert_eq!(cache.get("ft", now), None);
+        assert!(cache.get("nav", now).is_some());
+
+        assert_eq!(cache.set("big".into(), "x".repeat(30), None, now), None);
+        assert!(cache.get("nav", now).is_some());
+
+        let ttl = Some(Duration::from_millis(100));
+        assert!(cache.set("x".into(), "<p>ab</p>".into(), ttl, now).is_some());
+        assert!(cache.get("x", now + Duration::from_millis(99)).is_some());
+        assert_eq!(cache.get("x", now + Duration::from_millis(100)), None);
+        assert_eq!(cache.bytes, "nav<nav></nav>".len());
+
+        let mut disabled = FragmentCache::new(None);
+        assert!(!disabled.enabled());
+        assert_eq!(disabled.set("nav".into(), "<nav>".into(), None, now), None);
+    }
+
+    #[test]
+    fn test_cache_budget_weighted_eviction() {
+        let mut budget = CacheBudget::new(Some(100));
+        assert!(budget.insert(CacheKind::Fragment, "nav", 40).is_empty());
+        assert!(budget.insert(CacheKind::Fetch, "b", 20).is_empty());
+        assert!(budget.insert(CacheKind::Fetch, "a", 30).is_empty());
+        budget.touch(CacheKind::Fetch, "a");
+
+        // "nav" has been idle longest, but fragments weigh twice as much: "b" goes
+        assert_eq!(
+            budget.insert(CacheKind::Fragment, "ft", 20),
+            vec![(CacheKind::Fetch, "b".to_string())]
+        );
+        assert_eq!(budget.used(), 90);
+
+        // Replacing an entry only charges the difference
+        assert!(budget.insert(CacheKind::Fetch, "a", 40).is_empty());
+        assert_eq!(budget.used(), 100);
+
+        budget.remove(CacheKind::Fragment, "nav");
+        assert_eq!(budget.used(), 60);
+
+        // An entry larger than the budget evicts everything, itself included
+        assert_eq!(budget.insert(CacheKind::Fetch, "huge", 200).len(), 3);
+        assert_eq!(budget.used(), 0);
+
+        let mut unlimited = CacheBudget::new(None);
+        assert!(unlimited.insert(CacheKind::Fetch, "a", 1 << 30).is_empty());
+    }
+
+    #[test]
+    fn test_shed_caches() {
+        let mut state = OpState::new(None);
+        state.put(ResponseCache::new(true));
+        state.put(FragmentCache::new(Some(1024)));
+        state.put(CacheBudget::new(None));
+
+        let now = Instant::now();
+        let response = CachedResponse {
+            status: 200,
+            status_text: "OK".into(),
+            headers: HashMap::new(),
+            url: "https://api.example.com/a".into(),
+            body: "{}".into(),
+        };
+        let key = fetch_cache_key("v1", &response.url);
+        let cache = state.borrow_mut::<ResponseCache>();
+        cache.put("v1", response.url.clone(), response);
+        charge_cache_budget(&mut state, CacheKind::Fetch, &key, 30);
+        let fragments = state.borrow_mut::<FragmentCache>();
+        let evicted = fragments.set("nav".into(), "<nav>".into(), None, now);
+        assert_eq!(evicted, Some(vec![]));
+        charge_cache_budget(&mut state, CacheKind::Fragment, "nav", 8);
+
+        assert_eq!(shed_caches(&mut state), 38);
+        assert_eq!(state.borrow::<CacheBudget>().used(), 0);
+        assert!(state.borrow::<ResponseCache>().urls("v1").is_empty());
+        let fragments = state.borrow_mut::<FragmentCache>();
+        assert!(fragments.enabled());
+        assert_eq!(fragments.get("nav", now), None);
+        assert_eq!(shed_caches(&mut state), 0);
+    }
+
+    #[test]
+    fn test_data_dir_reads_stay_inside() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        std::fs::create_dir_all(data.join("routes")).unwrap();
+        std::fs::write(data.join("routes/manifest.json"), r#"{"/":"home"}"#).unwrap();
+        std::fs::write(data.join("large.json"), "x".repeat(64)).unwrap();
+        std::fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+
+        let data_dir = DataDir::new(&data, 32).unwrap();
+        assert_eq!(data_dir.read("routes/manifest.json").unwrap(), r#"{"/":"home"}"#);
+        assert_eq!(data_dir.read("./routes/manifest.json").unwrap(), r#"{"/":"home"}"#);
+
+        for path in ["../secret.txt", "routes/../../secret.txt", "/etc/passwd", ""] {
+            let err = data_dir.read(path).unwrap_err().to_string();
+            assert!(err.starts_with("Data read blocked"), "{}: {}", path, err);
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("secret.txt"), data.join("link.txt"))
+                .unwrap();
+            let err = data_dir.read("link.txt").unwrap_err().to_string();
+            assert!(err.starts_with("Data read blocked"), "{}", err);
+        }
+
+        assert!(data_dir.read("large.json").unwrap_err().to_string().contains("byte limit"));
+        assert!(data_dir.read("routes").is_err());
+        assert!(data_dir.read("missing.json").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_host_functions() {
+        let host = HostFunctions::default()
+            .with_timeout(Duration::from_millis(50))
+            .register("double", |args| async move {
+                let n = args.first().and_then(|n| n.as_i64()).unwrap_or_default();
+                Ok(serde_json::json!(n * 2))
+            })
+            .register("hang", |_| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(serde_json::Value::Null)
+            });
+        assert_eq!(host.names().collect::<Vec<_>>(), vec!["double", "hang"]);
+
+        assert_eq!(host.call("double", vec![serde_json::json!(21)]).await.unwrap(), 42);
+        let err = host.call("hang", vec![]).await.unwrap_err().to_string();
+        assert_eq!(err, "Host function 'hang' timed out after 50ms");
+        let err = host.call("readFile", vec![]).await.unwrap_err().to_string();
+        assert_eq!(err, "Host function 'readFile' is not registered");
+    }
+
+    #[tokio::test]
+    async fn test_host_feed() {
+        let (sender, feed) = host_feed();
+        let pusher = sender.clone();
+        let task = tokio::spawn(async move {
+            for i in 0..3 {
+                pusher.push(serde_json::json!({ "result": i })).unwrap();
+            }
+        });
+        task.await.unwrap();
+        drop(sender);
+
+        let mut items = vec![];
+        while let Some(item) = feed.next().await {
+            items.push(item["result"].as_i64().unwrap());
+        }
+        assert_eq!(items, vec![0, 1, 2]);
+
+        let (sender, feed) = host_feed();
+        drop(feed);
+        assert!(sender.push(serde_json::json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_islands() {
+        let island = Island::new("islands/cart.js", serde_json::json!({"user": 1, "compact": true}));
+        assert_eq!(island.id.len(), 16);
+        // Same entry and props, same id, whatever the key order
+        assert_eq!(
+            Island::new("islands/cart.js", serde_json::json!({"compact": true, "user": 1})).id,
+            island.id
+        );
+        assert_ne!(Island::new("islands/cart.js", serde_json::json!({"user": 2})).id, island.id);
+
+        assert!(is_valid_island_entry("islands/cart.js"));
+        assert!(!is_valid_island_entry(""));
+        assert!(!is_valid_island_entry("/etc/passwd"));
+        assert!(!is_valid_island_entry("../secret.js"));
+        assert!(is_valid_island_entry("./cart.js"));
+    }
+
+    #[test]
+    fn test_private_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.100.100.200",
+            "0.0.0.0",
+            "::1",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "198.18.0.1",
+            "198.19.255.255",
+            "192.0.0.170",
+            "240.0.0.1",
+            "255.255.255.255",
+            "224.0.0.1",
+            "ff02::1",
+            "::127.0.0.1",
+            "::169.254.169.254",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::10.0.0.1",
+            "2002:a9fe:a9fe::",
+            "2002:7f00:1::1",
+        ] {
+            assert!(is_private_address(ip.parse().unwrap()), "{} should be private", ip);
+        }
+        for ip in [
+            "93.184.216.34",
+            "8.8.8.8",
+            "172.32.0.1",
+            "198.20.0.1",
+            "192.0.1.1",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ] {
+            assert!(!is_private_address(ip.parse().unwrap()), "{} should be public", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocks_private_hosts() {
+        for url in ["http://127.0.0.1:8080/", "http://[::1]/", "http://localhost:3000/"] {
+            let url = url::Url::parse(url).unwrap();
+            let err = resolve_public_address(&url).await.unwrap_err().to_string();
+            assert!(err.contains("private network address"), "{}", err);
+        }
+        let public = url::Url::parse("https://93.184.216.34/").unwrap();
+        assert_eq!(resolve_public_address(&public).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_pending_fetches_abort() {
+        let never = || tokio::spawn(std::future::pending::<()>());
+        let mut pending = PendingFetches::default();
+        let (signalled, other, unsignalled) = (never(), never(), never());
+        pending.insert(Some(1), signalled.abort_handle());
+        pending.insert(Some(2), other.abort_handle());
+        let key = pending.insert(None, unsignalled.abort_handle());
+
+        pending.abort(1);
+        assert!(signalled.await.unwrap_err().is_cancelled());
+        pending.remove(key);
+        assert_eq!(pending.abort_all(), 1);
+        assert!(other.await.unwrap_err().is_cancelled());
+        assert!(!unsignalled.is_finished());
+        unsignalled.abort();
+    }
+
+    #[test]
+    fn test_origin_matching() {
+        let config = FetchConfig {
+            allowed_origins: vec![
+                "https://api.example.com".to_string(),
+                "http://localhost:3000".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        // Allowed
+        assert!(config.is_request_allowed(&url::Url::parse("https://api.example.com/users").unwrap()));
+        assert!(config.is_request_allowed(&url::Url::parse("https://api.example.com/").unwrap()));
+        assert!(config.is_request_allowed(&url::Url::parse("http://localhost:3000/api").unwrap()));
+
+        // Not allowed
+        assert!(!config.is_request_allowed(&url::Url::parse("https://evil.com/api").unwrap()));
+        assert!(!config.is_request_allowed(&url::Url::parse("http://api.example.com/users").unwrap())); // http vs https
+        assert!(!config.is_request_allowed(&url::Url::parse("https://api.example.com:8080/").unwrap())); // different port
+    }
+
+    #[test]
+    fn test_path_prefix_matching() {
+        let config = FetchConfig {
+            allowed_origins: vec![
+                "https://api.example.com/v1/*".to_string(),
+                "https://api.example.com/public/*".to_string(),
+            ],
+            ..Default::default()
+        };
+        let allowed = |url: &str| config.is_request_allowed(&url::Url::parse(url).unwrap());
+
+        assert!(allowed("https://api.example.com/v1/users?page=2"));
+        assert!(allowed("https://api.example.com/public/logo.png"));
+
+        assert!(!allowed("https://api.example.com/v1"));
+        assert!(!allowed("https://api.example.com/v10/users"));
+        assert!(!allowed("https://api.example.com/admin"));
+        assert!(!allowed("https://api.example.com/v1/../admin"));
+        assert!(!allowed("https://api.example.com/v1/%2e%2e/admin"));
+        assert!(!allowed("https://other.example.com/v1/users"));
+        assert_eq!(config.origins(), vec!["https://api.example.com"]);
+
+        assert!(config.validate().is_ok());
+        for entry in [
+            "https://api.example.com/v1",
+            "https://api.example.com/",
+            "api.example.com/*",
+        ] {
+            let config = FetchConfig {
+                allowed_origins: vec![entry.to_string()],
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn test_console_deduplicates_repeats() {
+        let limits = ConsoleLimits::default();
+        let mut output = ConsoleOutput::default();
+        for _ in 0..3 {
+            output.push(LogLevel::Log, "tick", None, &limits);
+        }
+        output.push(LogLevel::Warn, "tick", None, &limits);
+
+        assert_eq!(output.logs(), vec!["tick (repeated 3 times)"]);
+        assert_eq!(output.warns(), vec!["tick"]);
+    }
+
+    #[test]
+    fn test_console_entries_keep_order() {
+        let limits = ConsoleLimits {
+            max_message_bytes: 16,
+            ..Default::default()
+        };
+        let mut output = ConsoleOutput::default();
+        output.push(LogLevel::Info, "loading", Some(r#"["loading"]"#), &limits);
+        output.push(LogLevel::Error, "failed", None, &limits);
+        output.push(LogLevel::Debug, "retrying", Some(r#"["retrying",{"attempt":2}]"#), &limits);
+
+        let levels: Vec<_> = output.entries.iter().map(|entry| entry.level).collect();
+        assert_eq!(levels, vec![LogLevel::Info, LogLevel::Error, LogLevel::Debug]);
+        assert_eq!(output.entries[0].args_json.as_deref(), Some(r#"["loading"]"#));
+        // JSON over max_message_bytes is left out rather than cut into invalid JSON
+        assert_eq!(output.entries[2].args_json, None);
+        assert!(output.entries[0].timestamp > 0);
+        assert_eq!(output.logs(), vec!["loading", "retrying"]);
+        assert_eq!(output.errors(), vec!["failed"]);
+    }
+
+    #[test]
+    fn test_console_entry_cap() {
+        let limits = ConsoleLimits {
+            max_entries: 2,
+            ..Default::default()
+        };
+        let mut output = ConsoleOutput::default();
+        for i in 0..5 {
+            output.push(LogLevel::Error, &format!("error {}", i), None, &limits);
+        }
+
+        assert_eq!(output.errors(), vec!["error 0", "error 1"]);
+        assert_eq!(output.dropped, 3);
+    }
+
+    #[test]
+    fn test_console_truncates_long_messages() {
+        let limits = ConsoleLimits {
+            max_message_bytes: 4,
+            ..Default::default()
+        };
+        let mut output = ConsoleOutput::default();
+        output.push(LogLevel::Log, "héllo world", None, &limits);
+
+        assert_eq!(output.logs(), vec!["hél... [truncated 8 bytes]"]);
+    }
+
+    #[test]
+    fn test_console_subscribers_get_every_message() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        let mut state = OpState::new(None);
+        state.put(ConsoleOutput::default());
+        state.put(ConsoleLimits {
+            max_entries: 1,
+            max_message_bytes: 4,
+        });
+        state.put(ConsoleSubscribers::default().subscribe(move |level, msg| {
+            sink.lock().unwrap().push((level, msg.to_string()));
+        }));
+        for msg in ["tick", "tick", "hello"] {
+            capture_console(&mut state, LogLevel::Warn, msg, None);
+        }
+
+        // Not deduplicated or capped, only truncated
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                (LogLevel::Warn, "tick".to_string()),
+                (LogLevel::Warn, "tick".to_string()),
+                (LogLevel::Warn, "hell... [truncated 1 bytes]".to_string()),
+            ]
+        );
+        assert_eq!(state.borrow::<ConsoleOutput>().warns(), vec!["tick (repeated 2 times)"]);
+    }
+
+    #[test]
+    fn test_fetch_time_limit() {
+        let ms = Duration::from_millis;
+        assert_eq!(FetchConfig::default().time_limit(ms(5_000)), None);
+
+        let config = FetchConfig {
+            request_timeout_ms: Some(1_000),
+            total_fetch_budget_ms: Some(2_500),
+            ..Default::default()
+        };
+        assert_eq!(config.time_limit(ms(0)), Some(ms(1_000)));
+        assert_eq!(config.time_limit(ms(2_000)), Some(ms(500)));
+        assert_eq!(config.time_limit(ms(3_000)), Some(Duration::ZERO));
+
+        let budget_only = FetchConfig {
+            total_fetch_budget_ms: Some(2_500),
+            ..Default::default()
+        };
+        assert_eq!(budget_only.time_limit(ms(1_000)), Some(ms(1_500)));
+    }
+
+    #[test]
+    fn test_fetch_limits() {
+        let config = FetchConfig {
+            max_requests_per_render: Some(3),
+            max_concurrent_requests: Some(2),
+            ..Default::default()
+        };
+        let mut stats = FetchStats::default();
+        assert!(config.admit(&mut stats).is_ok());
+        assert!(config.admit(&mut stats).is_ok());
+        let err = config.admit(&mut stats).unwrap_err();
+        assert_eq!(
+            err,
+            "Fetch limit exceeded: 2 requests of the render are already in flight (limit 2)"
+        );
+
+        stats.in_flight -= 1;
+        assert!(config.admit(&mut stats).is_ok());
+        stats.in_flight = 0;
+        let err = config.admit(&mut stats).unwrap_err();
+        assert_eq!(
+            err,
+            "Fetch limit exceeded: the render already made 3 of its 3 requests"
+        );
+        assert_eq!(
+            (stats.requests, stats.rejected, stats.peak_concurrent),
+            (3, 2, 2)
+        );
+
+        let mut stats = FetchStats::default();
+        for _ in 0..100 {
+            assert!(FetchConfig::default().admit(&mut stats).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_empty_allowlist() {
+        let config = FetchConfig {
+            allowed_origins: vec![],
+            ..Default::default()
+        };
+
+        assert!(!config.is_request_allowed(&url::Url::parse("https://anything.com").unwrap()));
+    }
+
+    #[test]
+    fn test_tls_policy_validation() {
+        let pin = "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_string();
+        let policy = |spki_sha256: Vec<String>, self_signed_cert: Option<&str>| TlsPolicy {
+            min_version: Some(TlsVersion::Tls13),
+            spki_sha256,
+            self_signed_cert: self_signed_cert.map(String::from),
+        };
+        let config = |origin: &str, policy: TlsPolicy| FetchConfig {
+            tls_policies: HashMap::from([(origin.to_string(), policy)]),
+            ..Default::default()
+        };
+
+        assert!(config("https://api.example.com", policy(vec![pin.clone()], None)).validate().is_ok());
+
+        // Not an https origin
+        assert!(config("http://api.example.com", policy(vec![], None)).validate().is_err());
+        assert!(config("https://api.example.com/v1", policy(vec![], None)).validate().is_err());
+
+        // Malformed pin (not 32 bytes)
+        assert!(config("https://api.example.com", policy(vec!["abcd".to_string()], None)).validate().is_err());
+
+        // Self-signed certs are for localhost only
+        let err = config("https://api.example.com", policy(vec![], Some("cert.pem")))
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("only allowed for localhost"));
+        let err = config("https://localhost:8443", policy(vec![], Some("/nonexistent/cert.pem")))
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("Failed to load self-signed certificate"));
+    }
+
+    #[test]
+    fn test_secret_headers_validation() {
+        let config = |origin: &str, headers: SecretHeaders| FetchConfig {
+            allowed_origins: vec!["https://api.example.com".to_string()],
+            secret_headers: HashMap::from([(origin.to_string(), headers)]),
+            ..Default::default()
+        };
+        let token = SecretHeaders::new().with("Authorization", "Bearer s3cret");
+        assert!(config("https://api.example.com", token.clone()).validate().is_ok());
+        assert!(config("https://other.example.com", token.clone()).validate().is_err());
+
+        let err = config("https://api.example.com", SecretHeaders::new().with("X-Key", "a\nb"))
+            .validate()
+            .unwrap_err();
+        assert!(!err.to_string().contains("a\nb"));
+        assert!(config("https://api.example.com", SecretHeaders::new().with("Bad Name", "v"))
+            .validate()
+            .is_err());
+
+        // Values never show up in logs or @config
+        let token = token.with("authorization", "Bearer rotated");
+        assert_eq!(format!("{:?}", token), r#"{"authorization": "<redacted>"}"#);
+        assert_eq!(serde_json::to_string(&token).unwrap(), r#"["authorization"]"#);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_injects_secret_headers() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Answers 200 if the request carried the secret instead of the JS header
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0; 4096];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                let ok = request.contains("authorization: bearer s3cret")
+                    && !request.contains("from-js")
+                    && request.contains("x-trace: 1");
+                let status = if ok { "200 OK" } else { "400 Bad Request" };
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let config = FetchConfig {
+            allowed_origins: vec![origin.clone()],
+            secret_headers: HashMap::from([(
+                origin.clone(),
+                SecretHeaders::new().with("Authorization", "Bearer s3cret"),
+            )]),
+            ..Default::default()
+        };
+        let request = FetchRequest {
+            url: format!("{}/users", origin),
+            method: None,
+            headers: Some(HashMap::from([
+                ("authorization".to_string(), "Bearer from-js".to_string()),
+                ("x-trace".to_string(), "1".to_string()),
+            ])),
+            body: None,
+            abort_id: None,
+        };
+        let response = do_fetch(request, config).await.unwrap();
+        assert_eq!(response.status, 200);
+    }
+
+    #[test]
+    fn test_tls_policy_lookup() {
+        let config = FetchConfig {
+            allowed_origins: vec!["https://api.example.com".to_string()],
+            tls_policies: HashMap::from([("https://api.example.com".to_string(), TlsPolicy::default())]),
+            ..Default::default()
+        };
+
+        assert!(config.tls_policy(&url::Url::parse("https://api.example.com/users").unwrap()).is_some());
+        assert!(config.tls_policy(&url::Url::parse("https://other.example.com/").unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_warm_up() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let up = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = socket.read(&mut buf).await;
+                let _ = socket.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").await;
+            }
+        });
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let down = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+
+        let config = FetchConfig {
+            allowed_origins: vec![up.clone(), down.clone()],
+            request_timeout_ms: Some(1_000),
+            ..Default::default()
+        };
+        let results = config.warm_up().await;
+        assert_eq!(results[0].0, up);
+        assert!(results[0].1.is_ok(), "any response counts as connected");
+        assert_eq!(results[1].0, down);
+        assert!(results[1].1.is_err());
+
+        // Clones share the client (and its open connection)
+        assert!(config.clone().clients.get(&up, false).is_some());
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let response = |body: &str| CachedResponse {
+            status: 200,
+            status_text: "OK".to_string(),
+            headers: HashMap::new(),
+            url: "https://api.example.com/a".to_string(),
+            body: body.to_string(),
+        };
+        let mut cache = ResponseCache::new(true);
+        cache.put("v2", "https://api.example.com/a".to_string(), response("new"));
+        cache.put("v1", "https://api.example.com/a".to_string(), response("old"));
+
+        // Named lookup vs. first match across caches (in name order)
+        assert_eq!(cache.lookup(Some("v2"), "https://api.example.com/a").unwrap().body, "new");
+        assert_eq!(cache.lookup(None, "https://api.example.com/a").unwrap().body, "old");
+        assert!(cache.lookup(Some("v3"), "https://api.example.com/a").is_none());
+
+        assert!(cache.remove("v1", "https://api.example.com/a"));
+        assert!(!cache.remove("v1", "https://api.example.com/a"));
+        assert!(cache.urls("v1").is_empty());
+        assert_eq!(cache.urls("v2"), vec!["https://api.example.com/a"]);
+    }
+
+    #[test]
+    fn test_storage_mode_parsing() {
+        assert_eq!("memory".parse::<StorageMode>().unwrap(), StorageMode::Memory);
+        assert_eq!("strict".parse::<StorageMode>().unwrap(), StorageMode::Strict);
+        assert!("cookies".parse::<StorageMode>().is_err());
+        assert_eq!(serde_json::to_value(StorageMode::Disabled).unwrap(), "disabled");
+    }
+
+    #[test]
+    fn test_render_progress() {
+        let progress = RenderProgress::default();
+        assert!(progress.loading().is_none());
+
+        progress.set_phase(RenderPhase::Loading, "file:///entry.js");
+        progress.module_loaded("file:///data.js");
+        let loading = progress.loading().unwrap();
+        assert_eq!(loading.entry, "file:///entry.js");
+        assert!(loading.to_string().contains("(last loaded module: file:///data.js)"));
+
+        progress.set_phase(RenderPhase::Rendering, "file:///entry.js");
+        assert!(progress.loading().is_none());
+
+        progress.interrupt("stopped".to_string());
+        assert_eq!(progress.take_interrupt().as_deref(), Some("stopped"));
+        assert!(progress.take_interrupt().is_none());
+    }
+
+    #[test]
+    fn test_wasm_limits() {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // Imports memory env.mem with 1 page and no maximum
+        module.extend_from_slice(b"\x02\x0c\x01\x03env\x03mem\x02\x00\x01");
+        // Defines a memory of 2 to 100 pages
+        module.extend_from_slice(b"\x05\x04\x01\x01\x02\x64");
+        // Exports function 0 as "add"
+        module.extend_from_slice(b"\x07\x07\x01\x03add\x00\x00");
+
+        let limits = WasmLimits {
+            max_module_bytes: 1024,
+            max_memory_bytes: 4 * WASM_PAGE_SIZE,
+        };
+        let capped = limits.apply(&module).unwrap();
+        assert_eq!(capped.imports, vec![("env".to_string(), "mem".to_string())]);
+        assert_eq!(capped.exports, vec!["add".to_string()]);
+        assert_eq!(&capped.bytes[8..], &b"\x02\x0d\x01\x03env\x03mem\x02\x01\x01\x04\x05\x04\x01\x01\x02\x04\x07\x07\x01\x03add\x00\x00"[..]);
+        assert_eq!(limits.apply(&capped.bytes).unwrap(), capped);
+
+        let small = WasmLimits {
+            max_memory_bytes: WASM_PAGE_SIZE,
+            ..limits
+        };
+        assert!(small.apply(&module).unwrap_err().to_string().contains("memory needs"));
+        let tiny = WasmLimits {
+            max_module_bytes: 16,
+            ..limits
+        };
+        assert!(tiny.apply(&module).unwrap_err().to_string().contains("byte limit"));
+        assert!(limits.apply(b"<html></html>").is_err());
+        assert!(limits.apply(&module[..20]).is_err());
+    }
+}
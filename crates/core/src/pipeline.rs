@@ -0,0 +1,249 @@
+//! Host-side plugins that run around a render.
+//!
+//! [`PropsTransformers`] run in order between parsing a request and sanitizing
+//! its props, e.g. to decrypt a sealed field, expand a feature-flag bundle or
+//! inject experiment variants, so the render only ever sees the final props.
+//! [`OutputProcessors`] run in order on the HTML of every successful render,
+//! e.g. to minify it, inject a CSP nonce or a beacon, or rewrite links to CDN
+//! hosts, so hosts don't re-parse and rewrite the HTML themselves.
+//!
+//! The latency of every stage is recorded process-wide and reported by
+//! `@stats` (see [`props_transformer_stats`] and [`output_processor_stats`]).
+
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Transformer registered by the embedder: takes the props, returns new props
+pub type PropsTransformer = Arc<dyn Fn(Value) -> Result<Value, Error> + Send + Sync>;
+
+/// Ordered list of named props transformers
+#[derive(Clone, Default)]
+pub struct PropsTransformers {
+    transformers: Vec<(String, PropsTransformer)>,
+}
+
+impl PropsTransformers {
+    /// Run `transformer` after the ones added before it
+    ///
+    /// ```rust,ignore
+    /// let transformers = PropsTransformers::default().add("experiments", |mut props| {
+    ///     props["variant"] = json!(experiments::assign(&props["userId"]));
+    ///     Ok(props)
+    /// });
+    /// ```
+    pub fn add<F>(mut self, name: impl Into<String>, transformer: F) -> Self
+    where
+        F: Fn(Value) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.transformers.push((name.into(), Arc::new(transformer)));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transformers.is_empty()
+    }
+
+    /// Names of the transformers, in the order they run
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.transformers.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Run every transformer in order, stopping at the first failure
+    pub fn apply(&self, mut props: Value) -> Result<Value, Error> {
+        for (name, transformer) in &self.transformers {
+            let started = Instant::now();
+            let result = transformer(props);
+            record_stage(
+                &PROPS_TRANSFORMER_STATS,
+                name,
+                started.elapsed(),
+                result.is_ok(),
+            );
+            props = result.map_err(|e| anyhow!("Props transformer '{}' failed: {}", name, e))?;
+        }
+        Ok(props)
+    }
+}
+
+impl std::fmt::Debug for PropsTransformers {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+/// Transformer names, in order (for `@config`)
+impl Serialize for PropsTransformers {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.names())
+    }
+}
+
+/// Processor registered by the embedder: takes the HTML of a render, returns new HTML
+pub type OutputProcessor = Arc<dyn Fn(String) -> Result<String, Error> + Send + Sync>;
+
+/// Ordered list of named output processors
+#[derive(Clone, Default)]
+pub struct OutputProcessors {
+    processors: Vec<(String, OutputProcessor)>,
+}
+
+impl OutputProcessors {
+    /// Run `processor` after the ones added before it
+    ///
+    /// ```rust,ignore
+    /// let processors = OutputProcessors::default().add("cdn-links", |html| {
+    ///     Ok(html.replace("src=\"/static/", "src=\"https://cdn.example.com/static/"))
+    /// });
+    /// ```
+    pub fn add<F>(mut self, name: impl Into<String>, processor: F) -> Self
+    where
+        F: Fn(String) -> Result<String, Error> + Send + Sync + 'static,
+    {
+        self.processors.push((name.into(), Arc::new(processor)));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    /// Names of the processors, in the order they run
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.processors.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Run every processor in order, stopping at the first failure
+    pub fn apply(&self, mut html: String) -> Result<String, Error> {
+        for (name, processor) in &self.processors {
+            let started = Instant::now();
+            let result = processor(html);
+            record_stage(
+                &OUTPUT_PROCESSOR_STATS,
+                name,
+                started.elapsed(),
+                result.is_ok(),
+            );
+            html = result.map_err(|e| anyhow!("Output processor '{}' failed: {}", name, e))?;
+        }
+        Ok(html)
+    }
+}
+
+impl std::fmt::Debug for OutputProcessors {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+/// Processor names, in order (for `@config`)
+impl Serialize for OutputProcessors {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.names())
+    }
+}
+
+/// Calls and latency of one pipeline stage since the process started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StageStats {
+    pub calls: u64,
+    pub failures: u64,
+    pub total_micros: u64,
+    pub max_micros: u64,
+}
+
+type StageStatsMap = Mutex<BTreeMap<String, StageStats>>;
+
+/// Process-wide stats of the props transformers, by name
+static PROPS_TRANSFORMER_STATS: StageStatsMap = Mutex::new(BTreeMap::new());
+
+/// Process-wide stats of the output processors, by name
+static OUTPUT_PROCESSOR_STATS: StageStatsMap = Mutex::new(BTreeMap::new());
+
+fn record_stage(stats: &StageStatsMap, name: &str, elapsed: Duration, ok: bool) {
+    let micros = elapsed.as_micros() as u64;
+    let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+    let stage = stats.entry(name.to_string()).or_default();
+    stage.calls += 1;
+    stage.failures += u64::from(!ok);
+    stage.total_micros += micros;
+    stage.max_micros = stage.max_micros.max(micros);
+}
+
+/// Calls, failures and latency of every props transformer that has run
+pub fn props_transformer_stats() -> BTreeMap<String, StageStats> {
+    PROPS_TRANSFORMER_STATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Calls, failures and latency of every output processor that has run
+pub fn output_processor_stats() -> BTreeMap<String, StageStats> {
+    OUTPUT_PROCESSOR_STATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_props_transformers_run_in_order() {
+        let transformers = PropsTransformers::default()
+            .add("test-double", |props| {
+                Ok(json!(props.as_i64().unwrap() * 2))
+            })
+            .add("test-increment", |props| {
+                Ok(json!(props.as_i64().unwrap() + 1))
+            });
+        assert_eq!(transformers.apply(json!(5)).unwrap(), json!(11));
+        assert_eq!(
+            serde_json::to_value(&transformers).unwrap(),
+            json!(["test-double", "test-increment"])
+        );
+
+        let failing = PropsTransformers::default()
+            .add("test-reject", |_| Err(anyhow!("sealed field expired")))
+            .add("test-never", |_| panic!("runs after a failure"));
+        assert_eq!(
+            failing.apply(json!({})).unwrap_err().to_string(),
+            "Props transformer 'test-reject' failed: sealed field expired"
+        );
+
+        let stats = props_transformer_stats();
+        assert!(stats["test-double"].calls >= 1);
+        assert!(stats["test-reject"].failures >= 1);
+        assert!(!stats.contains_key("test-never"));
+    }
+
+    #[test]
+    fn test_output_processors_run_in_order() {
+        let processors = OutputProcessors::default()
+            .add("test-nonce", |html| {
+                Ok(html.replace("<script>", "<script nonce=\"abc\">"))
+            })
+            .add("test-beacon", |html| Ok(html + "<img src=\"/beacon\">"));
+        assert_eq!(
+            processors
+                .apply("<script>init()</script>".to_string())
+                .unwrap(),
+            "<script nonce=\"abc\">init()</script><img src=\"/beacon\">"
+        );
+
+        let failing =
+            OutputProcessors::default().add("test-minify", |_| Err(anyhow!("bad markup")));
+        assert_eq!(
+            failing.apply(String::new()).unwrap_err().to_string(),
+            "Output processor 'test-minify' failed: bad markup"
+        );
+        assert!(output_processor_stats()["test-beacon"].calls >= 1);
+        assert!(!props_transformer_stats().contains_key("test-nonce"));
+    }
+}
@@ -0,0 +1,33 @@
+//! V8 platform setup.
+//!
+//! V8 needs its platform (the worker threads it runs GC and background
+//! compilation on) set up once per process, before the first isolate is
+//! created, and a process can't set it up twice. [`init`] does it exactly
+//! once, however many threads call it at the same time. Runtimes call it
+//! before creating their isolate, so embedders only need to call it
+//! themselves to do the setup up front, e.g. before spawning the threads that
+//! create runtimes.
+
+use deno_core::{v8, JsRuntime};
+use std::sync::Once;
+
+static INIT: Once = Once::new();
+
+/// Set up the V8 platform, once per process
+///
+/// Thread-safe: concurrent callers block until the first one has finished
+/// the setup, later calls return right away. [`create_runtime`](crate::create_runtime),
+/// [`RuntimePool::new`](crate::RuntimePool::new) and the other constructors
+/// call it, so calling it is optional.
+pub fn init() {
+    INIT.call_once(|| {
+        // 0 = one worker thread per core, like deno_core's default platform
+        let platform = v8::new_default_platform(0, false).make_shared();
+        JsRuntime::init_platform(Some(platform), false);
+    });
+}
+
+/// Whether [`init`] has set up the V8 platform
+pub fn is_initialized() -> bool {
+    INIT.is_completed()
+}
@@ -0,0 +1,858 @@
+//! Runtime pool used by server mode.
+//!
+//! In the default [`IsolationMode::Isolate`] mode the pool holds a single
+//! runtime that is reused for every render, and only replaced after a render
+//! leaves it in a bad state (timeout, event loop budget exceeded).
+//!
+//! In [`IsolationMode::PerRequest`] mode every render gets a fresh isolate that
+//! is thrown away afterwards, so nothing a render leaves behind (globals,
+//! module state, caches) can be observed by the next one. Isolates are created
+//! ahead of time by [`RuntimePool::refill`], which callers run after the
+//! response has been sent, so the startup cost stays off the request path.
+//! [`RuntimePool::warm_up`] then loads the configured warmup entries into them
+//! (see [`SandboxConfig::warmup`]), so module compilation stays off it too.
+//!
+//! Runtimes reused across renders are recycled after
+//! [`SandboxConfig::max_requests_per_isolate`] renders, or when their heap
+//! stays above [`SandboxConfig::max_resident_heap_bytes`], and replaced by the
+//! next refill, so memory leaked by a bundle doesn't pile up forever.
+//!
+//! Modules can't be taken out of an isolate, so [`RuntimePool::unload`] frees
+//! the modules below a path (e.g. of a tenant that stopped getting traffic) by
+//! dropping the runtimes that loaded them; the next refill replaces them.
+//!
+//! [`TenantRouter`] runs one pool per configured tenant, each on its own
+//! thread with its own limits. V8 isolates on a thread must be dropped in
+//! reverse order of creation, so pools with independent lifetimes can't share
+//! a thread.
+
+use crate::affinity;
+use crate::crash;
+use crate::metering::{self, thread_cpu_time, RenderUsage, TenantQuota};
+use crate::ops::{request_tag, FetchBytesReceived};
+use crate::runtime::{
+    canonical_ancestor, create_runtime, execute_ssr_with_options, loaded_modules, preload_modules,
+    ExecuteOptions, IsolationMode, SandboxConfig, SsrError, SsrErrorKind, SsrResult, SsrRuntime,
+};
+use anyhow::{anyhow, Error};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Pool of ready-to-use sandboxed runtimes
+pub struct RuntimePool {
+    config: SandboxConfig,
+    /// Ready runtimes, newest last. V8 isolates must be dropped in reverse
+    /// order of creation, so runtimes are always taken from the end.
+    spares: Vec<SsrRuntime>,
+    last_usage: RenderUsage,
+}
+
+impl RuntimePool {
+    /// Create the pool and warm it up (V8 cold start happens here)
+    ///
+    /// Fails if the pool's isolates don't fit into
+    /// [`SandboxConfig::memory_budget`].
+    pub fn new(config: SandboxConfig) -> Result<Self, Error> {
+        let mut pool = Self {
+            config: config.reserve_memory()?,
+            spares: Vec::new(),
+            last_usage: RenderUsage::default(),
+        };
+        pool.refill()?;
+        Ok(pool)
+    }
+
+    pub fn config(&self) -> &SandboxConfig {
+        &self.config
+    }
+
+    /// What the latest render used (zero if it was refused before it ran)
+    pub fn last_usage(&self) -> RenderUsage {
+        self.last_usage
+    }
+
+    /// Number of runtimes that are ready for the next render
+    pub fn ready(&self) -> usize {
+        self.spares.len()
+    }
+
+    /// Create runtimes until the pool is back at its target size
+    pub fn refill(&mut self) -> Result<(), Error> {
+        while self.spares.len() < self.target_size() {
+            self.spares.push(create_runtime(&self.config)?);
+        }
+        Ok(())
+    }
+
+    /// Load the configured warmup entries into every ready runtime.
+    ///
+    /// Cheap for runtimes that already have them loaded, so it can run after
+    /// every [`refill`](Self::refill).
+    pub async fn warm_up(&mut self) -> Result<(), Error> {
+        if self.config.warmup.is_empty() {
+            return Ok(());
+        }
+        let entries: Vec<PathBuf> = self
+            .config
+            .warmup
+            .iter()
+            .map(|entry| Path::new(&self.config.chunks_dir).join(entry))
+            .collect();
+        for runtime in &mut self.spares {
+            preload_modules(runtime, &entries).await?;
+        }
+        Ok(())
+    }
+
+    /// Render `entry_point` with a runtime from the pool.
+    ///
+    /// Entries that don't match [`SandboxConfig::allowed_entries`] are refused,
+    /// and the HTML of a successful render goes through
+    /// [`SandboxConfig::output_processors`]. `options.timeout_ms` replaces the
+    /// configured timeout for this render, up to
+    /// [`SandboxConfig::max_request_timeout_ms`] (without it, it can only
+    /// shorten the configured timeout). Uses a fresh
+    /// runtime if the pool is empty (e.g. `refill` wasn't called after the
+    /// previous render).
+    pub async fn render(
+        &mut self,
+        entry_point: &Path,
+        props: serde_json::Value,
+        mut options: ExecuteOptions,
+    ) -> Result<SsrResult, SsrError> {
+        self.last_usage = RenderUsage::default();
+        if !self.config.is_entry_allowed(entry_point) {
+            let message = format!(
+                "Entry '{}' is not on the entry allowlist",
+                entry_point.display()
+            );
+            return Err(SsrError::new(SsrErrorKind::EntryNotAllowed, message));
+        }
+        let mut runtime = match self.spares.pop() {
+            Some(runtime) => runtime,
+            None => create_runtime(&self.config)?,
+        };
+
+        let max_timeout_ms = self
+            .config
+            .max_request_timeout_ms
+            .or(self.config.timeout_ms);
+        let adaptive = self
+            .config
+            .adaptive_timeout
+            .as_ref()
+            .map(|adaptive| (adaptive, self.entry_name(entry_point)));
+        let requested_timeout = options.timeout_ms.is_some();
+        options.timeout_ms = match (max_timeout_ms, options.timeout_ms, &adaptive) {
+            (Some(max), Some(requested), _) => Some(requested.min(max)),
+            (None, Some(requested), _) => Some(requested),
+            // Never beyond the pool's own timeout, e.g. a tenant's `TenantLimits::timeout_ms`
+            (_, None, Some((adaptive, entry))) => {
+                let timeout_ms = self.config.entry_timeouts.timeout_ms(adaptive, entry);
+                Some(timeout_ms.min(self.config.timeout_ms.unwrap_or(u64::MAX)))
+            }
+            (_, None, None) => self.config.timeout_ms,
+        };
+        let started = Instant::now();
+        let cpu_started = thread_cpu_time();
+        let result = execute_ssr_with_options(&mut runtime, entry_point, props, &options).await;
+        self.last_usage = render_usage(&mut runtime, thread_cpu_time() - cpu_started);
+        if let Some((adaptive, entry)) = &adaptive {
+            // Timeouts a request chose itself say nothing about the entry
+            let timed_out = matches!(
+                &result,
+                Err(e) if matches!(e.kind, SsrErrorKind::Timeout { .. })
+            );
+            if result.is_ok() || (timed_out && !requested_timeout) {
+                let elapsed = started.elapsed();
+                self.config.entry_timeouts.record(adaptive, entry, elapsed);
+            }
+        }
+
+        match self.config.isolation {
+            IsolationMode::PerRequest => {
+                // Dropped here, before any newer isolate is created
+                drop(runtime);
+            }
+            _ => match &result {
+                Err(e) if e.kind.needs_fresh_runtime() => {
+                    eprintln!(
+                        "[ssr-sandbox]{} Discarding runtime: {}",
+                        request_tag(options.request_id.as_deref()),
+                        first_line(e)
+                    );
+                    crash::record_event(format!("runtime discarded: {}", first_line(e)));
+                    drop(runtime);
+                }
+                _ => match self.recycle_reason(&mut runtime) {
+                    Some(reason) => {
+                        eprintln!(
+                            "[ssr-sandbox]{} Recycling runtime: {}",
+                            request_tag(options.request_id.as_deref()),
+                            reason
+                        );
+                        crash::record_event(format!("runtime recycled: {}", reason));
+                        drop(runtime);
+                    }
+                    None => self.spares.push(runtime),
+                },
+            },
+        }
+
+        let mut result = result?;
+        let html = std::mem::take(&mut result.html);
+        match self.config.output_processors.apply(html) {
+            Ok(html) => result.html = html,
+            Err(e) => {
+                return Err(SsrError {
+                    request_id: result.request_id,
+                    console: result.console,
+                    ..SsrError::new(SsrErrorKind::Other, e)
+                })
+            }
+        }
+        Ok(result)
+    }
+
+    /// Drop the ready runtimes if any of them loaded a module below `prefix`
+    /// (a directory or module path relative to the chunks dir), so the memory
+    /// of those modules is reclaimed. Returns the number of runtimes dropped.
+    ///
+    /// Call [`refill`](Self::refill) and [`warm_up`](Self::warm_up) afterwards,
+    /// like after a render; `warmup` entries below `prefix` are loaded again.
+    /// The adaptive timeouts of entries below `prefix` start over.
+    pub fn unload(&mut self, prefix: &Path) -> usize {
+        self.config.entry_timeouts.forget(prefix);
+        let Some(prefix) = canonical_ancestor(&Path::new(&self.config.chunks_dir).join(prefix))
+        else {
+            return 0;
+        };
+        let loaded = self.spares.iter_mut().any(|runtime| {
+            loaded_modules(runtime)
+                .iter()
+                .any(|module| module.starts_with(&prefix))
+        });
+        if !loaded {
+            return 0;
+        }
+        // All of them, newest first, since isolates must be dropped in reverse order of creation
+        let dropped = self.spares.len();
+        while let Some(runtime) = self.spares.pop() {
+            drop(runtime);
+        }
+        dropped
+    }
+
+    /// Why a runtime that just rendered should be replaced, if it should be
+    fn recycle_reason(&self, runtime: &mut SsrRuntime) -> Option<String> {
+        let renders = runtime.stats().renders;
+        if let Some(max) = self.config.max_requests_per_isolate {
+            if renders >= max {
+                return Some(format!("served {} renders", renders));
+            }
+        }
+
+        let max_bytes = self.config.max_resident_heap_bytes?;
+        if runtime.stats().heap_resident_bytes <= max_bytes {
+            return None;
+        }
+        // Only recycle if the memory is still in use after a full GC
+        runtime.js_runtime().v8_isolate().low_memory_notification();
+        let resident = runtime.stats().heap_resident_bytes;
+        (resident > max_bytes).then(|| {
+            format!(
+                "heap uses {} bytes after GC (limit {})",
+                resident, max_bytes
+            )
+        })
+    }
+
+    /// Name of an entry in adaptive timeout stats: its path relative to the chunks dir
+    fn entry_name(&self, entry_point: &Path) -> String {
+        entry_point
+            .strip_prefix(&self.config.chunks_dir)
+            .unwrap_or(entry_point)
+            .display()
+            .to_string()
+    }
+
+    fn target_size(&self) -> usize {
+        self.config.isolates_per_pool()
+    }
+}
+
+/// What the render a runtime just finished used
+fn render_usage(runtime: &mut SsrRuntime, cpu_time: Duration) -> RenderUsage {
+    RenderUsage {
+        cpu_micros: cpu_time.as_micros() as u64,
+        fetch_bytes: runtime
+            .with_state(|r: &mut FetchBytesReceived| r.0)
+            .unwrap_or(0),
+        cache_bytes: runtime.stats().cache_bytes as u64,
+    }
+}
+
+/// Work handed to a tenant's thread
+enum TenantJob {
+    Render {
+        entry_point: PathBuf,
+        props: serde_json::Value,
+        options: Box<ExecuteOptions>,
+        reply: oneshot::Sender<Result<SsrResult, SsrError>>,
+    },
+    /// [`RuntimePool::unload`], replying with the number of runtimes dropped
+    Unload {
+        prefix: PathBuf,
+        reply: oneshot::Sender<usize>,
+    },
+}
+
+/// Routes renders to per-tenant pools, see [`SandboxConfig::tenants`].
+///
+/// Entries outside of a tenant directory are rendered by a pool on the
+/// calling thread with the default limits. Renders of tenants are metered
+/// and refused once the tenant is over its [`TenantLimits::quota`](crate::TenantLimits::quota)
+/// (see [`tenant_usage`](crate::tenant_usage)). The router also keeps the schedule
+/// of [`SandboxConfig::refresh_routes`], which callers render with
+/// [`TenantRouter::refresh_due`] when [`TenantRouter::next_refresh`] comes.
+pub struct TenantRouter {
+    default: RuntimePool,
+    tenants: HashMap<String, mpsc::Sender<TenantJob>>,
+    quotas: HashMap<String, TenantQuota>,
+    /// When each of [`SandboxConfig::refresh_routes`] is due next
+    refresh_due: Vec<Instant>,
+}
+
+impl TenantRouter {
+    /// Start the default pool and one thread per tenant.
+    ///
+    /// Fails if the isolates of all pools don't fit into
+    /// [`SandboxConfig::memory_budget`].
+    ///
+    /// Must be called from within a multi-threaded tokio runtime: tenant
+    /// threads drive their renders with its handle, so render watchdogs keep
+    /// running on the runtime's worker threads.
+    pub fn new(config: SandboxConfig) -> Result<Self, Error> {
+        let config = config.reserve_memory()?;
+        if let Some(route) = config.refresh_routes.iter().find(|r| r.interval_secs == 0) {
+            return Err(anyhow!(
+                "Refresh route '{}' needs an interval of at least 1 second",
+                route.entry
+            ));
+        }
+        let handle = tokio::runtime::Handle::current();
+        let mut tenants = HashMap::new();
+        let mut cpu_sets = config
+            .thread_affinity
+            .plan(config.tenants.len())?
+            .map(Vec::into_iter);
+
+        for (name, limits) in &config.tenants {
+            let tenant_config = config.for_tenant(name);
+            let tenant = name.clone();
+            let quota = limits.quota.clone();
+            let (jobs, receiver) = mpsc::channel::<TenantJob>();
+            let (ready, started) = mpsc::channel();
+            let handle = handle.clone();
+            let cpu_set = cpu_sets.as_mut().and_then(Iterator::next);
+
+            thread::Builder::new()
+                .name(format!("ssr-tenant-{}", name))
+                .spawn(move || {
+                    // Pinned before the isolate exists, so its heap is allocated on the thread's node
+                    let started_pool = affinity::place_current_thread(&tenant, cpu_set.as_ref())
+                        .and_then(|()| RuntimePool::new(tenant_config))
+                        .and_then(|mut pool| {
+                            handle.block_on(pool.warm_up())?;
+                            Ok(pool)
+                        });
+                    let mut pool = match started_pool {
+                        Ok(pool) => {
+                            let _ = ready.send(Ok(()));
+                            pool
+                        }
+                        Err(e) => {
+                            let _ = ready.send(Err(e));
+                            return;
+                        }
+                    };
+                    for job in receiver {
+                        match job {
+                            TenantJob::Render {
+                                entry_point,
+                                props,
+                                options,
+                                reply,
+                            } => {
+                                let result =
+                                    handle.block_on(pool.render(&entry_point, props, *options));
+                                metering::record(&tenant, &quota, &pool.last_usage());
+                                let _ = reply.send(result);
+                            }
+                            TenantJob::Unload { prefix, reply } => {
+                                let _ = reply.send(pool.unload(&prefix));
+                            }
+                        }
+                        if let Err(e) = pool.refill() {
+                            eprintln!("[ssr-sandbox] Failed to refill runtime pool: {}", e);
+                        }
+                        if let Err(e) = handle.block_on(pool.warm_up()) {
+                            eprintln!("[ssr-sandbox] Warmup failed: {}", e);
+                        }
+                    }
+                })?;
+
+            started
+                .recv()
+                .map_err(|_| anyhow!("Tenant '{}' runtime thread exited", name))?
+                .map_err(|e| anyhow!("Tenant '{}': {}", name, e))?;
+            tenants.insert(name.clone(), jobs);
+        }
+
+        // Refresh routes are due right away, so their caches are warm from the start
+        let refresh_due = vec![Instant::now(); config.refresh_routes.len()];
+        Ok(Self {
+            default: RuntimePool::new(config.for_tenant(""))?,
+            tenants,
+            quotas: config
+                .tenants
+                .iter()
+                .map(|(name, limits)| (name.clone(), limits.quota.clone()))
+                .collect(),
+            refresh_due,
+        })
+    }
+
+    /// Render `entry_point` in the pool of the tenant it belongs to
+    pub async fn render(
+        &mut self,
+        entry_point: &Path,
+        props: serde_json::Value,
+        options: ExecuteOptions,
+    ) -> Result<SsrResult, SsrError> {
+        match self.dispatch(entry_point, props, options) {
+            Dispatched::Tenant(reply) => tenant_result(reply).await,
+            Dispatched::Refused(e) => Err(e),
+            Dispatched::Default(props, options) => {
+                self.default.render(entry_point, props, *options).await
+            }
+        }
+    }
+
+    /// Render several entries for one request (e.g. the header, body and
+    /// footer of a composite page), results in the order of `renders`.
+    ///
+    /// Entries of tenants render in parallel on their tenants' threads, while
+    /// the entries of the default pool render one after another on the calling
+    /// thread (a runtime only runs one render at a time).
+    pub async fn render_many(
+        &mut self,
+        renders: Vec<(PathBuf, serde_json::Value, ExecuteOptions)>,
+    ) -> Vec<Result<SsrResult, SsrError>> {
+        let dispatched: Vec<_> = renders
+            .into_iter()
+            .map(|(entry_point, props, options)| {
+                let dispatched = self.dispatch(&entry_point, props, options);
+                (entry_point, dispatched)
+            })
+            .collect();
+
+        // Default pool renders run while the tenant threads work on theirs
+        let mut results = Vec::with_capacity(dispatched.len());
+        let mut replies = vec![];
+        for (i, (entry_point, dispatched)) in dispatched.into_iter().enumerate() {
+            match dispatched {
+                Dispatched::Tenant(reply) => {
+                    replies.push((i, reply));
+                    results.push(None);
+                }
+                Dispatched::Refused(e) => results.push(Some(Err(e))),
+                Dispatched::Default(props, options) => {
+                    results.push(Some(self.default.render(&entry_point, props, *options).await));
+                }
+            }
+        }
+        for (i, reply) in replies {
+            results[i] = Some(tenant_result(reply).await);
+        }
+        results.into_iter().flatten().collect()
+    }
+
+    /// Hand a render to its tenant's thread (unless the tenant is over quota),
+    /// or give it back for the default pool
+    fn dispatch(
+        &self,
+        entry_point: &Path,
+        props: serde_json::Value,
+        options: ExecuteOptions,
+    ) -> Dispatched {
+        let Some((tenant, jobs)) = self
+            .tenant_of(entry_point)
+            .and_then(|t| Some((t, self.tenants.get(t)?)))
+        else {
+            return Dispatched::Default(props, Box::new(options));
+        };
+        if let Err(e) = metering::admit(tenant, &self.quotas[tenant]) {
+            let message = e.to_string();
+            return Dispatched::Refused(SsrError::new(SsrErrorKind::QuotaExceeded(e), message));
+        }
+
+        let (reply, result) = oneshot::channel();
+        // If the thread has exited, the reply sender is dropped with the job
+        let _ = jobs.send(TenantJob::Render {
+            entry_point: entry_point.to_path_buf(),
+            props,
+            options: Box::new(options),
+            reply,
+        });
+        Dispatched::Tenant(result)
+    }
+
+    /// Unload the modules below `prefix` (relative to the chunks dir) from
+    /// every pool, see [`RuntimePool::unload`]. Returns the number of runtimes
+    /// dropped; tenant pools refill on their threads, the default pool on the
+    /// next [`refill`](Self::refill). The caches of the refresh routes went
+    /// with the runtimes, so they are due again right away.
+    pub async fn unload(&mut self, prefix: impl AsRef<Path>) -> Result<usize, Error> {
+        let prefix = prefix.as_ref();
+        let mut replies = vec![];
+        for (tenant, jobs) in &self.tenants {
+            let (reply, result) = oneshot::channel();
+            let job = TenantJob::Unload {
+                prefix: prefix.to_path_buf(),
+                reply,
+            };
+            if jobs.send(job).is_ok() {
+                replies.push((tenant, result));
+            }
+        }
+        let mut dropped = self.default.unload(prefix);
+        for (tenant, result) in replies {
+            dropped += result
+                .await
+                .map_err(|_| anyhow!("Tenant '{}' runtime thread has exited", tenant))?;
+        }
+        if dropped > 0 {
+            self.refresh_due.fill(Instant::now());
+        }
+        Ok(dropped)
+    }
+
+    /// Refill the default pool (tenant threads refill their own pools)
+    pub fn refill(&mut self) -> Result<(), Error> {
+        self.default.refill()
+    }
+
+    /// Warm up the default pool (tenant threads warm up their own pools, on
+    /// startup and after every refill)
+    pub async fn warm_up(&mut self) -> Result<(), Error> {
+        self.default.warm_up().await
+    }
+
+    /// When the next of [`SandboxConfig::refresh_routes`] is due, None without
+    /// refresh routes
+    pub fn next_refresh(&self) -> Option<Instant> {
+        self.refresh_due.iter().min().copied()
+    }
+
+    /// Render the refresh routes that are due, returning the entry and render
+    /// time of each. Like after any render, call `refill` afterwards.
+    pub async fn refresh_due(&mut self) -> Vec<(String, Result<Duration, SsrError>)> {
+        let now = Instant::now();
+        let routes = self.default.config().refresh_routes.clone();
+        let mut results = vec![];
+        for (i, route) in routes.into_iter().enumerate() {
+            if self.refresh_due[i] > now {
+                continue;
+            }
+            let entry_point = Path::new(&self.default.config().chunks_dir).join(&route.entry);
+            let started = Instant::now();
+            let result = self
+                .render(&entry_point, route.props, ExecuteOptions::default())
+                .await
+                .map(|_| started.elapsed());
+            // Scheduled from the render's end, so slow renders can't pile up
+            self.refresh_due[i] = Instant::now() + Duration::from_secs(route.interval_secs);
+            results.push((route.entry, result));
+        }
+        results
+    }
+
+    /// Tenant name: first directory of the entry path below the chunks dir
+    fn tenant_of<'a>(&self, entry_point: &'a Path) -> Option<&'a str> {
+        let relative = entry_point
+            .strip_prefix(&self.default.config().chunks_dir)
+            .ok()?;
+        let mut components = relative.components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), Some(_)) => name.to_str(),
+            _ => None,
+        }
+    }
+}
+
+/// Where [`TenantRouter::dispatch`] sent a render
+enum Dispatched {
+    /// Running on a tenant's thread
+    Tenant(oneshot::Receiver<Result<SsrResult, SsrError>>),
+    /// Not rendered, the tenant is over quota
+    Refused(SsrError),
+    /// To be rendered by the default pool
+    Default(serde_json::Value, Box<ExecuteOptions>),
+}
+
+async fn tenant_result(
+    reply: oneshot::Receiver<Result<SsrResult, SsrError>>,
+) -> Result<SsrResult, SsrError> {
+    match reply.await {
+        Ok(result) => result,
+        Err(_) => Err(SsrError::new(
+            SsrErrorKind::ThreadExited,
+            "Tenant runtime thread has exited",
+        )),
+    }
+}
+
+fn first_line(e: &impl std::fmt::Display) -> String {
+    e.to_string().lines().next().unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AdaptiveTimeout, RefreshRoute, TenantLimits};
+
+    const ENTRY: &str = "export default () => '<p>hi</p>';";
+
+    /// Pool of one reused runtime rendering `entry.js` of a temporary chunks dir
+    fn test_pool(config: SandboxConfig) -> (tempfile::TempDir, RuntimePool) {
+        let chunks = tempfile::tempdir().unwrap();
+        std::fs::write(chunks.path().join("entry.js"), ENTRY).unwrap();
+        let config = SandboxConfig {
+            chunks_dir: chunks.path().to_str().unwrap().to_string(),
+            ..config
+        };
+        (chunks, RuntimePool::new(config).unwrap())
+    }
+
+    /// Router over a temporary chunks dir with an `entry.js` at the top and
+    /// one in the directory of each tenant
+    fn test_router(config: SandboxConfig) -> (tempfile::TempDir, TenantRouter) {
+        let chunks = tempfile::tempdir().unwrap();
+        let tenant_dirs = config.tenants.keys().map(|name| chunks.path().join(name));
+        for dir in std::iter::once(chunks.path().to_path_buf()).chain(tenant_dirs) {
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("entry.js"), ENTRY).unwrap();
+        }
+        let config = SandboxConfig {
+            chunks_dir: chunks.path().to_str().unwrap().to_string(),
+            ..config
+        };
+        (chunks, TenantRouter::new(config).unwrap())
+    }
+
+    async fn render(pool: &mut RuntimePool, chunks: &tempfile::TempDir) {
+        let entry = chunks.path().join("entry.js");
+        let result = pool.render(&entry, serde_json::json!({}), ExecuteOptions::default());
+        assert_eq!(result.await.unwrap().html, "<p>hi</p>");
+    }
+
+    #[tokio::test]
+    async fn test_recycles_after_max_requests() {
+        let (chunks, mut pool) = test_pool(SandboxConfig {
+            max_requests_per_isolate: Some(2),
+            ..Default::default()
+        });
+        render(&mut pool, &chunks).await;
+        assert_eq!(pool.ready(), 1);
+        render(&mut pool, &chunks).await;
+        assert_eq!(pool.ready(), 0);
+
+        // The replacement starts counting from zero
+        pool.refill().unwrap();
+        render(&mut pool, &chunks).await;
+        assert_eq!(pool.ready(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_recycles_above_max_resident_heap() {
+        // Any heap is above one byte, even after a full GC
+        let (chunks, mut pool) = test_pool(SandboxConfig {
+            max_resident_heap_bytes: Some(1),
+            ..Default::default()
+        });
+        render(&mut pool, &chunks).await;
+        assert_eq!(pool.ready(), 0);
+
+        let (chunks, mut pool) = test_pool(SandboxConfig {
+            max_resident_heap_bytes: Some(usize::MAX),
+            ..Default::default()
+        });
+        render(&mut pool, &chunks).await;
+        render(&mut pool, &chunks).await;
+        assert_eq!(pool.ready(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_timeout_keeps_tenant_timeout() {
+        let limits = TenantLimits {
+            timeout_ms: Some(50),
+            ..Default::default()
+        };
+        let config = SandboxConfig {
+            adaptive_timeout: Some(AdaptiveTimeout::default()),
+            tenants: [("capped".to_string(), limits)].into(),
+            ..Default::default()
+        };
+        let (chunks, mut pool) = test_pool(config.for_tenant("capped"));
+        let entry = chunks.path().join("hang.js");
+        std::fs::write(&entry, "export default () => { while (true) {} };").unwrap();
+
+        // An entry without history gets the adaptive max (30s), cut to the tenant's 50ms
+        let result = pool.render(&entry, serde_json::json!({}), ExecuteOptions::default());
+        let error = result.await.unwrap_err();
+        assert_eq!(error.kind, SsrErrorKind::Timeout { timeout_ms: 50 });
+    }
+
+    #[tokio::test]
+    async fn test_unload_drops_runtimes_below_prefix() {
+        let (_chunks, mut pool) = test_pool(SandboxConfig {
+            isolation: IsolationMode::PerRequest,
+            pool_size: 2,
+            warmup: vec!["entry.js".to_string()],
+            ..Default::default()
+        });
+        pool.warm_up().await.unwrap();
+
+        // Prefixes match whole path components
+        assert_eq!(pool.unload(Path::new("entry")), 0);
+        assert_eq!(pool.unload(Path::new("pages")), 0);
+        assert_eq!(pool.ready(), 2);
+        assert_eq!(pool.unload(Path::new("entry.js")), 2);
+        assert_eq!(pool.ready(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_router_unload_drops_pools_below_prefix() {
+        let tenant = "router-unload-test";
+        let (chunks, mut router) = test_router(SandboxConfig {
+            tenants: [(tenant.to_string(), TenantLimits::default())].into(),
+            refresh_routes: vec![RefreshRoute {
+                entry: "entry.js".to_string(),
+                props: serde_json::json!({}),
+                interval_secs: 3600,
+            }],
+            ..Default::default()
+        });
+        // Loads `entry.js` into the default pool and `<tenant>/entry.js` into the tenant's
+        let refreshed = router.refresh_due().await;
+        assert!(refreshed[0].1.is_ok());
+        let entry = chunks.path().join(tenant).join("entry.js");
+        let result = router.render(&entry, serde_json::json!({}), ExecuteOptions::default());
+        assert_eq!(result.await.unwrap().html, "<p>hi</p>");
+        let scheduled = router.next_refresh();
+
+        assert_eq!(router.unload("missing").await.unwrap(), 0);
+        assert_eq!(router.next_refresh(), scheduled);
+
+        assert_eq!(router.unload(tenant).await.unwrap(), 1);
+        assert_eq!(router.default.ready(), 1);
+        assert!(router.next_refresh().unwrap() <= Instant::now());
+
+        // The tenant's pool refilled without the module
+        assert_eq!(router.unload("entry.js").await.unwrap(), 1);
+        assert_eq!(router.default.ready(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_render_many_keeps_order() {
+        let (open, closed) = ("render-many-open", "render-many-closed");
+        let over_quota = TenantLimits {
+            quota: TenantQuota {
+                max_renders: Some(0),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (chunks, mut router) = test_router(SandboxConfig {
+            tenants: [
+                (open.to_string(), TenantLimits::default()),
+                (closed.to_string(), over_quota),
+            ]
+            .into(),
+            ..Default::default()
+        });
+        let numbered = chunks.path().join("n.js");
+        let tenant_numbered = chunks.path().join(open).join("n.js");
+        for path in [&numbered, &tenant_numbered] {
+            std::fs::write(path, "export default (props) => `<p>${props.n}</p>`;").unwrap();
+        }
+
+        let entries = [
+            tenant_numbered.clone(),
+            numbered.clone(),
+            chunks.path().join(closed).join("entry.js"),
+            tenant_numbered,
+            numbered,
+        ];
+        let renders = entries
+            .into_iter()
+            .enumerate()
+            .map(|(n, entry)| {
+                let props = serde_json::json!({ "n": n });
+                (entry, props, ExecuteOptions::default())
+            })
+            .collect();
+        let results = router.render_many(renders).await;
+
+        assert_eq!(results.len(), 5);
+        for (n, result) in results.into_iter().enumerate() {
+            match n {
+                2 => assert!(matches!(
+                    result.unwrap_err().kind,
+                    SsrErrorKind::QuotaExceeded(ref e) if e.tenant == closed
+                )),
+                _ => assert_eq!(result.unwrap().html, format!("<p>{}</p>", n)),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_refresh_routes_reschedule_after_render() {
+        let (chunks, mut router) = test_router(SandboxConfig {
+            refresh_routes: vec![RefreshRoute {
+                entry: "slow.js".to_string(),
+                props: serde_json::json!({}),
+                interval_secs: 60,
+            }],
+            ..Default::default()
+        });
+        std::fs::write(
+            chunks.path().join("slow.js"),
+            "export default () => { const end = Date.now() + 50; while (Date.now() < end) {} return ''; };",
+        )
+        .unwrap();
+
+        // Due right away
+        assert!(router.next_refresh().unwrap() <= Instant::now());
+        let started = Instant::now();
+        let refreshed = router.refresh_due().await;
+        let ended = Instant::now();
+        assert_eq!(refreshed.len(), 1);
+        assert_eq!(refreshed[0].0, "slow.js");
+        let render_time = *refreshed[0].1.as_ref().unwrap();
+        assert!(render_time >= Duration::from_millis(50));
+
+        // Counted from the end of the render, not its start
+        let interval = Duration::from_secs(60);
+        let next = router.next_refresh().unwrap();
+        assert!(next >= started + render_time + interval);
+        assert!(next <= ended + interval);
+        assert!(router.refresh_due().await.is_empty());
+        assert_eq!(router.next_refresh(), Some(next));
+    }
+}
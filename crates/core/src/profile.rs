@@ -0,0 +1,169 @@
+//! Runtime profiles: which optional sandbox globals a deployment needs.
+//!
+//! `ssr-sandbox analyze --write-profile` scans the modules of an entry for
+//! references to the globals of each [`GLOBAL_GROUPS`] feature and writes the
+//! unreferenced features to a profile, and `--runtime-profile` deletes their
+//! globals from every runtime before the first render. The V8 snapshot is
+//! shared by all deployments, so this narrows what a bundle can reach rather
+//! than the snapshot size.
+//!
+//! Detection is textual and errs on keeping globals: any occurrence of a name
+//! as a whole word (in code, strings or comments) counts as a reference.
+//! Globals reached through computed names (`globalThis["set" + "Timeout"]`)
+//! and modules only loaded with `import()` are not seen.
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Optional sandbox globals by feature, the unit a profile omits
+///
+/// Globals the runtime itself relies on (streams, `TextDecoder`, `URL`,
+/// events) are not optional.
+pub const GLOBAL_GROUPS: &[(&str, &[&str])] = &[
+    ("crypto", &["crypto", "Crypto", "CryptoKey", "SubtleCrypto"]),
+    (
+        "timers",
+        &[
+            "setTimeout",
+            "setInterval",
+            "clearTimeout",
+            "clearInterval",
+            "requestAnimationFrame",
+            "cancelAnimationFrame",
+            "requestIdleCallback",
+            "cancelIdleCallback",
+        ],
+    ),
+    // Also used by the Cache API (`SandboxConfig::cache_api`)
+    ("fetch", &["fetch", "Request", "Response", "Headers"]),
+    ("wasm", &["WebAssembly"]),
+    ("compression", &["CompressionStream", "DecompressionStream"]),
+    ("messaging", &["MessageChannel", "MessagePort"]),
+    ("websocket", &["WebSocket"]),
+    ("image-data", &["ImageData"]),
+];
+
+/// Features whose globals are deleted from every runtime
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeProfile {
+    /// Names from [`GLOBAL_GROUPS`]
+    pub omit: BTreeSet<String>,
+}
+
+impl RuntimeProfile {
+    /// Omit every feature none of `sources` refers to
+    pub fn detect<'a>(sources: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut omit: BTreeSet<String> = GLOBAL_GROUPS
+            .iter()
+            .map(|(group, _)| group.to_string())
+            .collect();
+        for source in sources {
+            omit.retain(|group| {
+                !group_globals(group)
+                    .iter()
+                    .any(|name| references(source, name))
+            });
+        }
+        Self { omit }
+    }
+
+    /// Read a profile written by [`RuntimeProfile::save`] (`{"omit": [...]}`)
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read runtime profile '{}': {}", path.display(), e))?;
+        let profile: Self = serde_json::from_str(&json)
+            .map_err(|e| anyhow!("Invalid runtime profile '{}': {}", path.display(), e))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n").map_err(|e| {
+            anyhow!(
+                "Failed to write runtime profile '{}': {}",
+                path.display(),
+                e
+            )
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        match self
+            .omit
+            .iter()
+            .find(|group| group_globals(group).is_empty())
+        {
+            Some(group) => Err(anyhow!(
+                "Unknown runtime profile feature '{}' (expected one of: {})",
+                group,
+                GLOBAL_GROUPS
+                    .iter()
+                    .map(|(group, _)| *group)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Globals to delete, in [`GLOBAL_GROUPS`] order
+    pub fn omitted_globals(&self) -> Vec<&'static str> {
+        GLOBAL_GROUPS
+            .iter()
+            .filter(|(group, _)| self.omit.contains(*group))
+            .flat_map(|(_, names)| names.iter().copied())
+            .collect()
+    }
+}
+
+fn group_globals(group: &str) -> &'static [&'static str] {
+    GLOBAL_GROUPS
+        .iter()
+        .find(|(name, _)| *name == group)
+        .map_or(&[], |(_, names)| names)
+}
+
+/// Whether `name` occurs in `source` as a whole JS identifier
+fn references(source: &str, name: &str) -> bool {
+    let is_identifier = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$';
+    source.match_indices(name).any(|(start, _)| {
+        let before = source[..start].chars().next_back();
+        let after = source[start + name.len()..].chars().next();
+        !before.is_some_and(is_identifier) && !after.is_some_and(is_identifier)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_unreferenced_features() {
+        let profile = RuntimeProfile::detect([
+            "const res = await fetch(url); setTimeout(done, 10);",
+            "export const id = () => crypto.randomUUID();",
+            // Not whole identifiers
+            "const mySetTimeoutHelper = 1; obj.WebAssemblyish = 2;",
+        ]);
+        assert!(!profile.omit.contains("fetch"));
+        assert!(!profile.omit.contains("timers"));
+        assert!(!profile.omit.contains("crypto"));
+        assert!(profile.omit.contains("wasm"));
+        assert!(profile.omitted_globals().contains(&"WebAssembly"));
+        assert!(!profile.omitted_globals().contains(&"fetch"));
+
+        let unknown = RuntimeProfile {
+            omit: BTreeSet::from(["dom".to_string()]),
+        };
+        assert!(unknown
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown runtime profile feature 'dom'"));
+    }
+}
@@ -0,0 +1,207 @@
+//! Streaming HTML rewrites with lol_html.
+//!
+//! [`HtmlRewrites`] are applied to rendered HTML without buffering the whole
+//! document: streaming renders rewrite each chunk as it is produced, other
+//! renders rewrite their HTML once it is complete. Supported rewrites:
+//! - CSP nonce injection on `<script>` and `<style>` tags without a nonce
+//! - Moving root-relative asset URLs (`src`, `href`) to a CDN origin
+
+use anyhow::{anyhow, Error};
+use bytes::Bytes;
+use lol_html::{element, HtmlRewriter, Settings};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Rewrites applied to rendered HTML (`SandboxConfig::html_rewrites`)
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HtmlRewrites {
+    /// Origin to serve assets from, e.g. `https://cdn.example.com` (default:
+    /// None, asset URLs are left alone)
+    pub asset_origin: Option<String>,
+    /// Root-relative URL prefixes of the assets served from `asset_origin`,
+    /// e.g. `/static/` (default: none)
+    pub asset_prefixes: Vec<String>,
+}
+
+impl HtmlRewrites {
+    /// Whether there is nothing to rewrite without a nonce
+    pub fn is_empty(&self) -> bool {
+        self.asset_origin.is_none() || self.asset_prefixes.is_empty()
+    }
+
+    /// Check that the asset origin is an http(s) origin and the prefixes are root-relative paths
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(origin) = &self.asset_origin {
+            let url = url::Url::parse(origin)
+                .map_err(|e| anyhow!("Invalid asset origin '{}': {}", origin, e))?;
+            if !matches!(url.scheme(), "http" | "https")
+                || url.origin().ascii_serialization() != origin.trim_end_matches('/')
+            {
+                return Err(anyhow!(
+                    "Invalid asset origin '{}' (expected e.g. https://cdn.example.com)",
+                    origin
+                ));
+            }
+        }
+        for prefix in &self.asset_prefixes {
+            if !prefix.starts_with('/') || prefix.starts_with("//") {
+                return Err(anyhow!(
+                    "Invalid asset prefix '{}' (expected a root-relative path like /static/)",
+                    prefix
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Start rewriting a document, adding `nonce` to scripts and styles if given
+    pub fn rewriter(&self, nonce: Option<&str>) -> StreamingRewriter {
+        let mut handlers = Vec::new();
+        if let Some(nonce) = nonce {
+            let nonce = nonce.to_string();
+            handlers.push(element!("script, style", move |el| {
+                if !el.has_attribute("nonce") {
+                    el.set_attribute("nonce", &nonce)?;
+                }
+                Ok(())
+            }));
+        }
+        if let (false, Some(origin)) = (self.is_empty(), &self.asset_origin) {
+            let origin = origin.trim_end_matches('/').to_string();
+            let prefixes = self.asset_prefixes.clone();
+            handlers.push(element!("[src], [href]", move |el| {
+                for name in ["src", "href"] {
+                    let Some(value) = el.get_attribute(name) else {
+                        continue;
+                    };
+                    if prefixes
+                        .iter()
+                        .any(|prefix| value.starts_with(prefix.as_str()))
+                    {
+                        el.set_attribute(name, &format!("{}{}", origin, value))?;
+                    }
+                }
+                Ok(())
+            }));
+        }
+
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let sink = output.clone();
+        let sink: OutputSink =
+            Box::new(move |chunk: &[u8]| sink.borrow_mut().extend_from_slice(chunk));
+        let rewriter = HtmlRewriter::new(
+            Settings {
+                element_content_handlers: handlers,
+                ..Settings::new()
+            },
+            sink,
+        );
+        StreamingRewriter {
+            rewriter: Some(rewriter),
+            output,
+        }
+    }
+
+    /// Rewrite a whole document
+    pub fn rewrite(&self, html: &str, nonce: Option<&str>) -> Result<String, Error> {
+        let mut rewriter = self.rewriter(nonce);
+        let mut out = rewriter.write(html.as_bytes())?.to_vec();
+        out.extend_from_slice(&rewriter.end()?);
+        String::from_utf8(out).map_err(|e| anyhow!("Rewritten HTML is not UTF-8: {}", e))
+    }
+}
+
+type OutputSink = Box<dyn FnMut(&[u8])>;
+
+/// Rewrites a document chunk by chunk, see [`HtmlRewrites::rewriter`]
+///
+/// Output is returned as soon as the rewriter has seen enough of the input,
+/// which may be less than the chunk written (e.g. a tag split across chunks).
+pub struct StreamingRewriter {
+    rewriter: Option<HtmlRewriter<'static, OutputSink>>,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl StreamingRewriter {
+    /// Feed the next chunk of the document, returning the rewritten output so far
+    pub fn write(&mut self, chunk: &[u8]) -> Result<Bytes, Error> {
+        if let Some(rewriter) = &mut self.rewriter {
+            rewriter
+                .write(chunk)
+                .map_err(|e| anyhow!("HTML rewrite failed: {}", e))?;
+        }
+        Ok(self.take())
+    }
+
+    /// Finish the document, returning the rest of the output
+    pub fn end(&mut self) -> Result<Bytes, Error> {
+        if let Some(rewriter) = self.rewriter.take() {
+            rewriter
+                .end()
+                .map_err(|e| anyhow!("HTML rewrite failed: {}", e))?;
+        }
+        Ok(self.take())
+    }
+
+    fn take(&self) -> Bytes {
+        Bytes::from(std::mem::take(&mut *self.output.borrow_mut()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cdn() -> HtmlRewrites {
+        HtmlRewrites {
+            asset_origin: Some("https://cdn.example.com".to_string()),
+            asset_prefixes: vec!["/static/".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_rewrite_assets_and_nonce() {
+        let html = r#"<link href="/static/app.css"><a href="/about">About</a><script src="/static/app.js"></script><script nonce="own">var s = "<script>";</script>"#;
+        assert_eq!(
+            cdn().rewrite(html, Some("abc")).unwrap(),
+            r#"<link href="https://cdn.example.com/static/app.css"><a href="/about">About</a><script src="https://cdn.example.com/static/app.js" nonce="abc"></script><script nonce="own">var s = "<script>";</script>"#
+        );
+        assert_eq!(HtmlRewrites::default().rewrite(html, None).unwrap(), html);
+    }
+
+    #[test]
+    fn test_rewrite_chunks() {
+        let mut rewriter = cdn().rewriter(None);
+        let mut out = Vec::new();
+        for chunk in ["<img sr", "c=\"/static/a.png\">", "<p>done</p>"] {
+            out.extend_from_slice(&rewriter.write(chunk.as_bytes()).unwrap());
+        }
+        out.extend_from_slice(&rewriter.end().unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"<img src="https://cdn.example.com/static/a.png"><p>done</p>"#
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(cdn().validate().is_ok());
+        for origin in [
+            "ftp://cdn.example.com",
+            "https://cdn.example.com/assets",
+            "cdn",
+        ] {
+            let rewrites = HtmlRewrites {
+                asset_origin: Some(origin.to_string()),
+                ..cdn()
+            };
+            assert!(rewrites.validate().is_err(), "{}", origin);
+        }
+        let rewrites = HtmlRewrites {
+            asset_prefixes: vec!["//evil.example/".to_string()],
+            ..cdn()
+        };
+        assert!(rewrites.validate().is_err());
+    }
+}
@@ -0,0 +1,2734 @@
+//! SSR Runtime - executes JavaScript in a sandboxed V8 isolate.
+//!
+//! Provides only the minimal APIs needed for SSR:
+//! - console.log/info/debug/trace/warn/error (captured, not printed)
+//! - URL, URLSearchParams
+//! - TextEncoder, TextDecoder
+//! - atob, btoa
+//! - crypto.randomUUID, crypto.getRandomValues, crypto.subtle (digest, HMAC
+//!   importKey/sign/verify, ...)
+//! - Module loading from allowed directory only
+//! - No fs, net, env, or other system access
+
+use crate::adaptive_timeout::{AdaptiveTimeout, EntryTimeouts};
+use crate::admission::glob_match;
+use crate::affinity::ThreadAffinity;
+use crate::code_cache::CodeCache;
+use crate::crash::{self, InFlight};
+use crate::csp;
+use crate::import_map::ImportMap;
+use crate::loader::{ModuleGraph, SandboxedLoader, SharedModuleProvider};
+use crate::metering::{QuotaExceeded, TenantQuota};
+use crate::pipeline::{OutputProcessors, PropsTransformers};
+use crate::platform;
+use crate::profile::RuntimeProfile;
+use crate::rewrite::{HtmlRewrites, StreamingRewriter};
+use crate::sanitize::SanitizePolicy;
+use crate::source_map::SourceMaps;
+use crate::ops::{
+    record_violation_exemplar, shed_caches, ssr_runtime, CacheBudget, ConsoleLimits, ConsoleOutput,
+    ConsoleSubscribers, ContextPerRequest, DataDir, DeterminismConfig, FetchBytesReceived,
+    FetchConfig, FetchStats, FetchTimeSpent, FragmentCache, HostFeed, HostFunctions, HttpClients,
+    Island, PendingFetches, RenderChunks, RenderIslands, RenderProgress, ResponseCache,
+    SandboxViolation, SecretHeaders, StorageMode, TimerLimits, TlsPolicy, ViolationLog, Warning,
+    WarningCode, WarningLog, WasmLimits,
+};
+use anyhow::{anyhow, Error};
+use bytes::Bytes;
+use deno_core::futures::Stream;
+use deno_core::{
+    v8, Extension, JsRuntime, ModuleSpecifier, OpState, PollEventLoopOptions, RuntimeOptions,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+pub use ssr_sandbox_protocol::RequestContext;
+use ssr_sandbox_protocol::Response;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::future::poll_fn;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// V8 snapshot created at build time (contains pre-compiled extension JS)
+static RUNTIME_SNAPSHOT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/SSR_SNAPSHOT.bin"));
+/// SHA-256 (hex) of the snapshot, computed by build.rs when it wrote it
+const RUNTIME_SNAPSHOT_SHA256: &str = env!("SSR_SNAPSHOT_SHA256");
+
+/// Result of an SSR render
+#[derive(Debug)]
+pub struct SsrResult {
+    pub html: String,
+    /// Markup for the document `<head>`, if the render returned one separately
+    pub head: Option<String>,
+    /// HTTP status requested by the render (302 if only `redirect` was set)
+    pub status_code: Option<u16>,
+    /// HTTP response headers requested by the render
+    pub headers: BTreeMap<String, String>,
+    /// Redirect location requested by the render
+    pub redirect: Option<String>,
+    pub console: ConsoleOutput,
+    /// Actions blocked by the sandbox during this render (e.g. rejected imports)
+    pub sandbox_violations: Vec<SandboxViolation>,
+    /// Problems noticed by the runtime that didn't fail the render (e.g. slow
+    /// fetches, heap close to the limit, deprecated API use)
+    pub warnings: Vec<Warning>,
+    /// Specifiers (`file://` URLs) of the modules this render used, entry
+    /// first: static imports and `import()`ed chunks, e.g. for
+    /// `<link rel="modulepreload">` tags
+    pub loaded_modules: Vec<String>,
+    /// Correlation id passed in [`ExecuteOptions::request_id`]
+    pub request_id: Option<String>,
+    /// Islands the render deferred with `context.island(entry, props)`, in
+    /// the order of their placeholders. Render each one's entry with its props
+    /// to get the markup for its `<ssr-island data-id="...">` placeholder.
+    pub islands: Vec<Island>,
+    /// `fetch()` calls of the render, including those rejected by
+    /// [`SandboxConfig::max_fetch_requests`] and [`SandboxConfig::max_concurrent_fetches`]
+    pub fetch: FetchStats,
+}
+
+impl From<&SsrResult> for Response {
+    fn from(result: &SsrResult) -> Self {
+        Self {
+            ok: true,
+            body: result.html.clone(),
+            head: result.head.clone(),
+            status_code: result.status_code,
+            error_code: None,
+            headers: result.headers.clone(),
+            redirect: result.redirect.clone(),
+            warnings: result.warnings.clone(),
+            request_id: result.request_id.clone(),
+            islands: result.islands.clone(),
+            parts: vec![],
+        }
+    }
+}
+
+/// A failed render, or a runtime that couldn't be created.
+///
+/// Returned by [`create_runtime`], [`execute_ssr`], [`execute_ssr_with_options`],
+/// [`execute_ssr_stream`] (and as the last item of its stream),
+/// [`preload_modules`], [`analyze_entry`] and the render methods of
+/// [`RuntimePool`](crate::RuntimePool), [`TenantRouter`](crate::TenantRouter)
+/// and [`SsrHandle`](crate::SsrHandle).
+/// Match on [`SsrError::kind`] to handle failure modes; the console output
+/// captured before the failure isn't lost:
+///
+/// ```rust,ignore
+/// match execute_ssr(&mut runtime, entry, props, Some(5000)).await {
+///     Err(SsrError { kind: SsrErrorKind::Timeout { .. }, .. }) => retry(),
+///     Err(e) => eprintln!("{}\n{}", e, e.stack.as_deref().unwrap_or_default()),
+///     Ok(result) => send(result.html),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct SsrError {
+    pub kind: SsrErrorKind,
+    pub message: String,
+    /// JS stack trace of the exception that failed the render, if there was one
+    pub stack: Option<String>,
+    /// Specifier (`file://` URL) of the entry module (empty if no render was started)
+    pub entry: String,
+    /// Console output captured up to the failure
+    pub console: ConsoleOutput,
+    /// Correlation id passed in [`ExecuteOptions::request_id`]
+    pub request_id: Option<String>,
+}
+
+impl SsrError {
+    pub(crate) fn new(kind: SsrErrorKind, message: impl std::fmt::Display) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+            stack: None,
+            entry: String::new(),
+            console: ConsoleOutput::default(),
+            request_id: None,
+        }
+    }
+}
+
+impl std::fmt::Display for SsrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SsrError {}
+
+/// Why a render failed, see [`SsrError::kind`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SsrErrorKind {
+    /// The render ran past its timeout
+    Timeout { timeout_ms: u64 },
+    /// Evaluating the entry's modules ran past [`SandboxConfig::module_timeout_ms`]
+    ModuleTimeout { timeout_ms: u64 },
+    /// The render failed after the heap came close to [`SandboxConfig::max_heap_size`]
+    HeapLimit,
+    /// The render ran past [`SandboxConfig::max_event_loop_turns`]
+    EventLoopBudget { max_turns: u64 },
+    /// The render's heap use went above [`ExecuteOptions::max_heap_size`]
+    HeapCap { max_bytes: usize },
+    /// The render produced more HTML than [`SandboxConfig::max_html_bytes`]
+    OutputTooLarge { max_bytes: usize },
+    /// The entry, or a module it imports, doesn't exist
+    ModuleNotFound { specifier: String },
+    /// The entry couldn't be loaded otherwise: an import was rejected by the
+    /// sandbox, a module threw while being evaluated, or the entry doesn't
+    /// export the function to render
+    ModuleFailed,
+    /// The render function threw or its promise rejected
+    RenderThrew {
+        message: String,
+        stack: Option<String>,
+    },
+    /// The render left globals behind that couldn't be reset (with
+    /// [`SandboxConfig::context_per_request`])
+    GlobalsNotReset,
+    /// The render returned something other than HTML or a valid structured result
+    InvalidOutput,
+    /// The props couldn't be passed to the render function
+    InvalidProps,
+    /// Invalid [`ExecuteOptions`] (CSP nonce, request id or export name)
+    InvalidOptions,
+    /// The runtime couldn't be created from the [`SandboxConfig`]
+    Config,
+    /// The entry doesn't match [`SandboxConfig::allowed_entries`]
+    EntryNotAllowed,
+    /// The entry's tenant used up its [`TenantQuota`]
+    QuotaExceeded(QuotaExceeded),
+    /// The thread that was to run the render (a tenant's, or the one of an
+    /// [`SsrHandle`](crate::SsrHandle)) has exited
+    ThreadExited,
+    /// Anything else
+    Other,
+}
+
+impl SsrErrorKind {
+    /// Whether the runtime may have been left in a bad state and should be
+    /// replaced: after a timeout the isolate may be in a bad state, after an
+    /// exceeded event loop budget or heap cap the abandoned render still has
+    /// work queued, oversized output likely filled the heap (and an abandoned
+    /// stream keeps rendering), and globals that could not be reset would leak
+    /// into every later render.
+    pub fn needs_fresh_runtime(&self) -> bool {
+        matches!(
+            self,
+            Self::Timeout { .. }
+                | Self::ModuleTimeout { .. }
+                | Self::HeapLimit
+                | Self::EventLoopBudget { .. }
+                | Self::HeapCap { .. }
+                | Self::OutputTooLarge { .. }
+                | Self::GlobalsNotReset
+        )
+    }
+}
+
+/// Error whose [`SsrErrorKind`] is known where it is raised
+#[derive(Debug)]
+struct KindError {
+    kind: SsrErrorKind,
+    message: String,
+}
+
+impl std::fmt::Display for KindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for KindError {}
+
+fn kind_error(kind: SsrErrorKind, message: impl std::fmt::Display) -> Error {
+    Error::new(KindError {
+        kind,
+        message: message.to_string(),
+    })
+}
+
+/// Exception thrown (or rejected) by the render function
+#[derive(Debug)]
+struct RenderException {
+    message: String,
+    stack: Option<String>,
+    /// Thrown by the render function itself (wrapped as `Render error: ...`
+    /// by bootstrap.js), rather than while loading the entry
+    from_render: bool,
+}
+
+impl std::fmt::Display for RenderException {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Render function threw: {}", self.message)
+    }
+}
+
+impl std::error::Error for RenderException {}
+
+/// Structured value a render function may return instead of a plain string:
+/// `{ html, head, statusCode, headers, redirect }`
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RenderOutput {
+    #[serde(default)]
+    html: String,
+    head: Option<String>,
+    status_code: Option<u16>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    redirect: Option<String>,
+}
+
+impl RenderOutput {
+    fn validate(&self) -> Result<(), Error> {
+        if let Some(status) = self.status_code {
+            if !(100..=599).contains(&status) {
+                return Err(anyhow!("Render returned invalid statusCode {}", status));
+            }
+        }
+        // Values end up in HTTP headers (and the line protocol), so no line breaks
+        let has_line_break = |s: &str| s.contains(['\r', '\n']);
+        for (name, value) in &self.headers {
+            if name.is_empty() || has_line_break(name) || has_line_break(value) {
+                return Err(anyhow!("Render returned invalid header '{}'", name.escape_debug()));
+            }
+        }
+        if self.redirect.as_deref().is_some_and(has_line_break) {
+            return Err(anyhow!("Render returned invalid redirect location"));
+        }
+        Ok(())
+    }
+}
+
+/// How renders are isolated from each other
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IsolationMode {
+    /// All renders share one isolate and one global object (fastest, JIT stays warm)
+    #[default]
+    Isolate,
+    /// A fresh isolate for every render, discarded afterwards, so no state can
+    /// leak between requests (for untrusted third-party code). Isolates are
+    /// pre-warmed by [`RuntimePool`](crate::RuntimePool) to amortize startup cost.
+    PerRequest,
+    /// Experimental: one realm (global object) per request in a shared isolate,
+    /// so concurrent renders could share compiled modules while isolating globals.
+    ///
+    /// Not available yet: deno_core 0.311 has no public API to create additional
+    /// realms, so [`create_runtime`] rejects this mode.
+    Realm,
+}
+
+impl std::str::FromStr for IsolationMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "isolate" => Ok(IsolationMode::Isolate),
+            "per-request" => Ok(IsolationMode::PerRequest),
+            "realm" => Ok(IsolationMode::Realm),
+            other => Err(anyhow!(
+                "Unknown isolation mode '{}' (expected isolate, per-request or realm)",
+                other
+            )),
+        }
+    }
+}
+
+/// Limits for one tenant, overriding the defaults of [`SandboxConfig`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TenantLimits {
+    /// Maximum heap size in bytes (None = `SandboxConfig::max_heap_size`)
+    pub max_heap_size: Option<usize>,
+    /// Maximum time for a single render in milliseconds (None = `SandboxConfig::timeout_ms`)
+    pub timeout_ms: Option<u64>,
+    /// Usage limits per period, renders over them are refused with
+    /// [`QuotaExceeded`](crate::QuotaExceeded) (default: unlimited, usage is
+    /// only metered)
+    pub quota: TenantQuota,
+}
+
+/// Isolates of one pool and their heap limit, see [`SandboxConfig::reserve_memory`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryReservation {
+    /// Tenant the pool serves ("" for the default pool)
+    pub tenant: String,
+    /// Isolates the pool keeps alive at once
+    pub isolates: usize,
+    /// Heap limit of each isolate in bytes (None = unlimited)
+    pub max_heap_size: Option<usize>,
+}
+
+/// Smallest heap [`SandboxConfig::reserve_memory`] gives an isolate
+const MIN_RESERVED_HEAP: usize = 16 * 1024 * 1024;
+
+/// A route rendered on a schedule, see [`SandboxConfig::refresh_routes`]
+#[derive(Debug, Clone, Serialize)]
+pub struct RefreshRoute {
+    /// Entry point, relative to `chunks_dir`
+    pub entry: String,
+    /// Props of the render, as the render should see them (they don't go
+    /// through the props transformers)
+    pub props: serde_json::Value,
+    /// Seconds between renders
+    pub interval_secs: u64,
+}
+
+/// Configuration for the SSR sandbox
+#[derive(Clone, Serialize)]
+pub struct SandboxConfig {
+    /// Directory containing the JS chunks (only this dir is accessible)
+    pub chunks_dir: String,
+    /// Maximum heap size in bytes (default: 64MB, None = unlimited)
+    pub max_heap_size: Option<usize>,
+    /// Soft limit as a fraction of `max_heap_size` (e.g. 0.8). A render that
+    /// starts with more heap in use first clears the runtime's caches (Cache
+    /// API responses, `__ssr_cache__` fragments) and runs a full garbage
+    /// collection, and gets a [`WarningCode::HeapWatermark`] warning
+    /// (default: None, renders only fail at the hard limit)
+    pub heap_watermark: Option<f64>,
+    /// Bytes of V8 heap all isolates of a pool (or of a [`TenantRouter`](crate::TenantRouter)
+    /// and its tenants' pools) may reserve together, see
+    /// [`SandboxConfig::reserve_memory`] (default: None, no budget). Leave
+    /// headroom for the rest of the process.
+    pub memory_budget: Option<usize>,
+    /// Maximum time for a single render in milliseconds (default: 30000ms, None = unlimited)
+    pub timeout_ms: Option<u64>,
+    /// Longest timeout a render in a [`RuntimePool`](crate::RuntimePool) may
+    /// ask for with [`ExecuteOptions::timeout_ms`], so heavy pages can get
+    /// more time than `timeout_ms` (default: None, renders can only shorten
+    /// `timeout_ms`)
+    pub max_request_timeout_ms: Option<u64>,
+    /// Give renders in a [`RuntimePool`](crate::RuntimePool) that don't ask
+    /// for a timeout one derived from the recent render times of their entry,
+    /// instead of `timeout_ms`, which still caps it (default: None)
+    pub adaptive_timeout: Option<AdaptiveTimeout>,
+    /// Recent render times of the entries rendered with `adaptive_timeout`,
+    /// shared by every pool created from this config or its clones
+    #[serde(skip)]
+    pub entry_timeouts: EntryTimeouts,
+    /// Allowed origins for fetch() (empty = fetch disabled). An entry like
+    /// `https://api.example.com/v1/*` only allows paths below `/v1/` of the origin.
+    pub allowed_origins: Vec<String>,
+    /// TLS overrides for individual fetch origins (keyed by origin)
+    pub tls_policies: HashMap<String, TlsPolicy>,
+    /// Refuse fetch() to loopback, private, link-local and cloud metadata
+    /// addresses, even for allowed origins (default: false). Guards against
+    /// SSRF through DNS rebinding of an allowed hostname.
+    pub block_private_networks: bool,
+    /// Longest a single `fetch()` may take in milliseconds; slower fetches
+    /// reject with an `AbortError` (default: None, only `timeout_ms` applies)
+    pub fetch_request_timeout_ms: Option<u64>,
+    /// Total milliseconds the `fetch()` calls of one render may take, added
+    /// up (default: None, no budget). Fetches beyond it reject with an `AbortError`.
+    pub total_fetch_budget_ms: Option<u64>,
+    /// `fetch()` calls one render may make (default: None, unlimited). Calls
+    /// beyond it reject; see [`SsrResult::fetch`].
+    pub max_fetch_requests: Option<u64>,
+    /// `fetch()` calls of one render that may be in flight at once (default:
+    /// None, unlimited). Calls beyond it reject instead of waiting.
+    pub max_concurrent_fetches: Option<u64>,
+    /// Headers (e.g. `Authorization`) added to every `fetch()` to an allowed
+    /// origin, keyed by origin. The render code never sees their values.
+    pub fetch_secret_headers: HashMap<String, SecretHeaders>,
+    /// Open connections to fetch origins, shared by every runtime created from
+    /// this config or its clones, see [`warm_up_origins`]
+    #[serde(skip)]
+    pub http_clients: HttpClients,
+    /// Per-render caps on captured console output
+    pub console_limits: ConsoleLimits,
+    /// Callbacks that get console messages as renders write them, in
+    /// addition to the per-render [`ConsoleOutput`] (default: none)
+    #[serde(skip)]
+    pub console_subscribers: ConsoleSubscribers,
+    /// Caps on pending `setTimeout`/`setInterval` timers and their delay
+    pub timer_limits: TimerLimits,
+    /// Caps on the size and memory of WebAssembly modules, whether imported
+    /// as `.wasm` files or compiled with `WebAssembly.instantiate()`
+    pub wasm_limits: WasmLimits,
+    /// Optional globals (crypto, timers, fetch, ...) deleted from every
+    /// runtime, usually written by `ssr-sandbox analyze --write-profile`
+    /// (default: None, all globals are defined)
+    pub runtime_profile: Option<RuntimeProfile>,
+    /// Expose the Cache API (`caches`) backed by an in-memory store shared by
+    /// all renders on the runtime (default: false, `caches` is undefined)
+    pub cache_api: bool,
+    /// `localStorage` / `sessionStorage` behavior (default: disabled, not defined)
+    pub storage: StorageMode,
+    /// Maximum event loop turns for a single render (default: None = unlimited).
+    /// Fails runaway async loops quickly instead of spinning until the timeout.
+    pub max_event_loop_turns: Option<u64>,
+    /// Maximum bytes of HTML (`html` and `head`) a render may return, or
+    /// stream in total (default: None = unlimited). Larger output fails the
+    /// render with [`SsrErrorKind::OutputTooLarge`] instead of being copied
+    /// out of V8; runaway string building is still only stopped by the heap limit.
+    pub max_html_bytes: Option<usize>,
+    /// Maximum time for importing the entry module, including top-level await
+    /// (default: None = only `timeout_ms` applies). Slow imports are logged
+    /// to stderr every second while they are pending.
+    pub module_timeout_ms: Option<u64>,
+    /// How renders are isolated from each other (default: one shared isolate)
+    pub isolation: IsolationMode,
+    /// Pre-warmed isolates kept ready in per-request isolation mode (default: 2)
+    pub pool_size: usize,
+    /// Reset `globalThis` before and after every render (default: false), so one
+    /// render can't stash data on the global object for another to read.
+    ///
+    /// This is an approximation of a fresh V8 context per render: deno_core 0.311
+    /// can't create additional contexts, so globals added by a render are deleted
+    /// and replaced ones restored instead. Mutations of built-in objects and
+    /// module-level state are not reset; use [`IsolationMode::PerRequest`] when
+    /// that matters. A render that leaves behind globals which cannot be deleted
+    /// fails, and the runtime should be discarded.
+    pub context_per_request: bool,
+    /// Tenants with their own limits, keyed by the first directory of the entry
+    /// path (`<chunks_dir>/<tenant>/entry.js`). In server mode each tenant gets
+    /// its own isolates, so one tenant can't use up another's heap (default: none)
+    pub tenants: BTreeMap<String, TenantLimits>,
+    /// Pinning of the tenants' threads to CPUs or NUMA nodes (default:
+    /// unpinned, Linux only), see [`ThreadAffinity`](crate::ThreadAffinity)
+    pub thread_affinity: ThreadAffinity,
+    /// Integrity manifest: chunk path (relative to `chunks_dir`, `/`-separated)
+    /// to `sha256-<base64>` hash. When set, modules that don't match or aren't
+    /// listed are refused (default: None, no verification)
+    pub integrity: Option<BTreeMap<String, String>>,
+    /// Path of a Deno-style import map (`{"imports": {...}, "scopes": {...}}`)
+    /// that resolves bare specifiers like `react` or `#app/utils` to files in
+    /// `chunks_dir`, relative to the map's location (default: None, bare
+    /// specifiers resolve from the root of `chunks_dir`)
+    pub import_map: Option<String>,
+    /// Props checks and log redaction (default: no redaction)
+    pub sanitize: SanitizePolicy,
+    /// Host-side transformers server mode runs on request props, in order,
+    /// before they are sanitized (default: none)
+    pub props_transformers: PropsTransformers,
+    /// Host-side processors the runtime pool runs on the HTML of every
+    /// successful render, in order (default: none)
+    pub output_processors: OutputProcessors,
+    /// Rewrites of asset URLs in rendered HTML, applied chunk by chunk to
+    /// streaming renders (default: none)
+    pub html_rewrites: HtmlRewrites,
+    /// Directory for V8 code cache blobs of loaded modules (default: None, no
+    /// caching). Saves parsing and compiling chunks in every new runtime. Must
+    /// only be writable by the host: V8 trusts the cached code.
+    pub code_cache_dir: Option<String>,
+    /// Read-only directory of static data files renders can read with
+    /// `__ssr_read_data__(relpath)` (default: None, reads throw)
+    pub data_dir: Option<String>,
+    /// Largest file `__ssr_read_data__` will read, in bytes (default: 1MB)
+    pub max_data_file_size: u64,
+    /// Make clocks and randomness reproducible (default: None). Every render
+    /// starts from the same fixed time and PRNG seed, so the same props render
+    /// the same HTML (snapshot tests, reproducible static builds)
+    pub deterministic: Option<DeterminismConfig>,
+    /// Size limit in bytes of the `__ssr_cache__` fragment cache, kept per
+    /// isolate across renders (default: None, `__ssr_cache__` is undefined)
+    pub fragment_cache_size: Option<usize>,
+    /// One memory limit in bytes for all internal caches of a runtime (Cache
+    /// API responses, `__ssr_cache__` fragments). Over the limit, the entry
+    /// idle the longest (weighted by cache priority) is evicted, whichever
+    /// cache holds it (default: None, only the per-cache limits apply)
+    pub cache_memory_budget: Option<usize>,
+    /// Functions renders can call with `__ssr_host__.call(name, ...args)`
+    /// (default: none, calls reject)
+    pub host_functions: HostFunctions,
+    /// Serves module code before the chunks directory is read, e.g. from
+    /// memory (default: None, modules are read from `chunks_dir`)
+    #[serde(skip)]
+    pub module_provider: Option<SharedModuleProvider>,
+    /// Extra deno_core extensions added to every runtime, see
+    /// [`create_runtime_with_extensions`] (default: none)
+    pub extensions: RuntimeExtensions,
+    /// Entry points (relative to `chunks_dir`) that pools load into their
+    /// runtimes ahead of the first request, see [`preload_modules`]
+    /// (default: none)
+    pub warmup: Vec<String>,
+    /// Routes [`TenantRouter`](crate::TenantRouter) renders again every
+    /// `interval_secs` in the background, so the caches of their isolates
+    /// (`__ssr_cache__` fragments, Cache API responses) stay warm for
+    /// user-facing renders of the same pages (default: none). The output is
+    /// discarded.
+    pub refresh_routes: Vec<RefreshRoute>,
+    /// Replace a pooled runtime after it has served this many renders
+    /// (default: None, runtimes are reused until a render breaks them)
+    pub max_requests_per_isolate: Option<u64>,
+    /// Replace a pooled runtime when its V8 heap (not the process RSS) stays
+    /// above this many resident bytes after a render, even after a full
+    /// garbage collection (default: None). Keeps leaky bundles from slowly
+    /// growing the process.
+    pub max_resident_heap_bytes: Option<usize>,
+    /// Entry modules pools may render, as patterns relative to `chunks_dir`
+    /// (`/`-separated, `*` matches any run of characters including `/`).
+    /// Other files are refused even inside `chunks_dir` (default: None, any
+    /// module in `chunks_dir` can be an entry)
+    pub allowed_entries: Option<Vec<String>>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            chunks_dir: String::from("./chunks"),
+            max_heap_size: Some(64 * 1024 * 1024), // 64MB default
+            heap_watermark: None,
+            memory_budget: None,
+            timeout_ms: Some(30_000), // 30 seconds default
+            max_request_timeout_ms: None,
+            adaptive_timeout: None,
+            entry_timeouts: EntryTimeouts::default(),
+            allowed_origins: vec![], // fetch disabled by default
+            tls_policies: HashMap::new(),
+            block_private_networks: false,
+            fetch_secret_headers: HashMap::new(),
+            fetch_request_timeout_ms: None,
+            total_fetch_budget_ms: None,
+            max_fetch_requests: None,
+            max_concurrent_fetches: None,
+            http_clients: HttpClients::default(),
+            console_limits: ConsoleLimits::default(),
+            console_subscribers: ConsoleSubscribers::default(),
+            timer_limits: TimerLimits::default(),
+            wasm_limits: WasmLimits::default(),
+            runtime_profile: None,
+            cache_api: false,
+            storage: StorageMode::default(),
+            max_event_loop_turns: None,
+            max_html_bytes: None,
+            module_timeout_ms: None,
+            isolation: IsolationMode::default(),
+            pool_size: 2,
+            context_per_request: false,
+            tenants: BTreeMap::new(),
+            thread_affinity: ThreadAffinity::default(),
+            integrity: None,
+            import_map: None,
+            sanitize: SanitizePolicy::default(),
+            props_transformers: PropsTransformers::default(),
+            output_processors: OutputProcessors::default(),
+            html_rewrites: HtmlRewrites::default(),
+            code_cache_dir: None,
+            data_dir: None,
+            max_data_file_size: DataDir::DEFAULT_MAX_FILE_SIZE,
+            deterministic: None,
+            fragment_cache_size: None,
+            cache_memory_budget: None,
+            host_functions: HostFunctions::default(),
+            module_provider: None,
+            extensions: RuntimeExtensions::default(),
+            warmup: vec![],
+            refresh_routes: vec![],
+            max_requests_per_isolate: None,
+            max_resident_heap_bytes: None,
+            allowed_entries: None,
+        }
+    }
+}
+
+impl SandboxConfig {
+    /// Describe the effective configuration as JSON (used by the `@config` admin command).
+    ///
+    /// Paths are resolved so operators can see exactly which directory is being served.
+    pub fn describe(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        let resolved_chunks_dir = Path::new(&self.chunks_dir)
+            .canonicalize()
+            .map(|p| p.display().to_string())
+            .ok();
+        value["resolved_chunks_dir"] = serde_json::json!(resolved_chunks_dir);
+        value["version"] = serde_json::json!(env!("CARGO_PKG_VERSION"));
+        value
+    }
+
+    /// The config for runtimes serving `tenant` (falls back to the defaults for unknown tenants)
+    pub fn for_tenant(&self, tenant: &str) -> SandboxConfig {
+        let limits = self.tenants.get(tenant).cloned().unwrap_or_default();
+        SandboxConfig {
+            max_heap_size: limits.max_heap_size.or(self.max_heap_size),
+            timeout_ms: limits.timeout_ms.or(self.timeout_ms),
+            // Each pool only warms up the entries it will render
+            warmup: self
+                .warmup
+                .iter()
+                .filter(|entry| self.tenant_of_entry(entry).unwrap_or_default() == tenant)
+                .cloned()
+                .collect(),
+            tenants: BTreeMap::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Isolates a pool created from this config keeps alive at once
+    pub(crate) fn isolates_per_pool(&self) -> usize {
+        match self.isolation {
+            IsolationMode::PerRequest => self.pool_size.max(1),
+            _ => 1,
+        }
+    }
+
+    /// Heap the pools of a [`TenantRouter`](crate::TenantRouter) built from
+    /// this config reserve: the default pool first, then one pool per tenant
+    pub fn memory_reservations(&self) -> Vec<MemoryReservation> {
+        std::iter::once(String::new())
+            .chain(self.tenants.keys().cloned())
+            .map(|tenant| {
+                let config = self.for_tenant(&tenant);
+                MemoryReservation {
+                    isolates: config.isolates_per_pool(),
+                    max_heap_size: config.max_heap_size,
+                    tenant,
+                }
+            })
+            .collect()
+    }
+
+    /// Fit the heap limits of all pools into [`SandboxConfig::memory_budget`].
+    ///
+    /// Pools without a heap limit split what the pools with one leave of the
+    /// budget, evenly per isolate: the returned config has
+    /// [`SandboxConfig::max_heap_size`] set to that share. Fails when the heap
+    /// limits add up to more than the budget, or the share would be under
+    /// 16MB. Without a budget the config is returned as is.
+    pub fn reserve_memory(&self) -> Result<SandboxConfig, Error> {
+        let Some(budget) = self.memory_budget else {
+            return Ok(self.clone());
+        };
+        let mb = |bytes: usize| bytes / (1024 * 1024);
+        let pools = self.memory_reservations();
+        let reserved: usize = pools
+            .iter()
+            .filter_map(|pool| Some(pool.max_heap_size? * pool.isolates))
+            .sum();
+        if reserved > budget {
+            return Err(anyhow!(
+                "Memory budget of {}MB is over-committed: the heap limits of {} isolates add up to {}MB",
+                mb(budget),
+                pools.iter().map(|pool| pool.isolates).sum::<usize>(),
+                mb(reserved)
+            ));
+        }
+
+        let unlimited: usize = pools
+            .iter()
+            .filter(|pool| pool.max_heap_size.is_none())
+            .map(|pool| pool.isolates)
+            .sum();
+        if unlimited == 0 {
+            return Ok(self.clone());
+        }
+        // Only pools that inherit the default heap limit can be without one
+        let share = (budget - reserved) / unlimited;
+        if share < MIN_RESERVED_HEAP {
+            return Err(anyhow!(
+                "Memory budget of {}MB leaves {}MB of heap per isolate for {} isolates (at least {}MB needed)",
+                mb(budget),
+                mb(share),
+                unlimited,
+                mb(MIN_RESERVED_HEAP)
+            ));
+        }
+        Ok(SandboxConfig {
+            max_heap_size: Some(share),
+            ..self.clone()
+        })
+    }
+
+    /// Whether `entry_point` (a path below `chunks_dir`) matches
+    /// [`SandboxConfig::allowed_entries`]
+    pub fn is_entry_allowed(&self, entry_point: &Path) -> bool {
+        let Some(patterns) = &self.allowed_entries else {
+            return true;
+        };
+        let Ok(relative) = entry_point.strip_prefix(&self.chunks_dir) else {
+            return false;
+        };
+        let mut parts = vec![];
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => match part.to_str() {
+                    Some(part) => parts.push(part),
+                    None => return false,
+                },
+                Component::CurDir => {}
+                // `..` could climb back out of a matching directory
+                _ => return false,
+            }
+        }
+        let entry = parts.join("/");
+        patterns
+            .iter()
+            .any(|pattern| glob_match(pattern.trim_start_matches("./"), &entry))
+    }
+
+    /// Configured tenant of an entry path relative to `chunks_dir`
+    fn tenant_of_entry<'a>(&self, entry: &'a str) -> Option<&'a str> {
+        let (dir, _) = entry.split_once('/')?;
+        self.tenants.contains_key(dir).then_some(dir)
+    }
+}
+
+/// How often the watchdog checks on a running render
+const WATCHDOG_TICK: Duration = Duration::from_millis(10);
+
+/// Interval between "slow module evaluation" log lines
+const SLOW_MODULE_REPORT_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Per-render limits from the config, kept in OpState
+#[derive(Clone, Copy)]
+struct RenderLimits {
+    max_event_loop_turns: Option<u64>,
+    module_timeout_ms: Option<u64>,
+    max_html_bytes: Option<usize>,
+    /// Heap bytes above which caches are shed before a render
+    heap_watermark: Option<usize>,
+}
+
+/// Builds a fresh instance of an embedder's deno_core extension
+pub type ExtensionFactory = Arc<dyn Fn() -> Extension + Send + Sync>;
+
+/// Embedder-provided deno_core extensions, built again for every runtime
+/// (an [`Extension`] can only be used once)
+#[derive(Clone, Default)]
+pub struct RuntimeExtensions {
+    factories: Vec<ExtensionFactory>,
+}
+
+impl RuntimeExtensions {
+    /// Add the extension built by `factory` to every runtime
+    ///
+    /// ```rust,ignore
+    /// deno_core::extension!(data_loader, ops = [op_load_user]);
+    ///
+    /// let extensions = RuntimeExtensions::default().register(data_loader::init_ops);
+    /// ```
+    pub fn register<F>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> Extension + Send + Sync + 'static,
+    {
+        self.factories.push(Arc::new(factory));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.factories.is_empty()
+    }
+
+    /// Build one instance of every extension, in the order they were added
+    pub fn build(&self) -> Vec<Extension> {
+        self.factories.iter().map(|factory| factory()).collect()
+    }
+
+    /// Names of the extensions, in order
+    pub fn names(&self) -> Vec<&'static str> {
+        self.build().iter().map(|extension| extension.name).collect()
+    }
+}
+
+impl std::fmt::Debug for RuntimeExtensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+/// Extension names, in order (for `@config`)
+impl Serialize for RuntimeExtensions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.names())
+    }
+}
+
+/// Check that embedder extensions can be added on top of the snapshot
+///
+/// The snapshot was built with the crate's own extensions only, so extra
+/// extensions may add ops and op state, but no JS sources (they would never be
+/// evaluated) and no external references or middlewares (they would shift the
+/// references the snapshot was serialized with). Op names must be unique, so
+/// an extension can't replace a sandboxed op such as `op_fetch`.
+fn check_extensions(builtin: &[Extension], custom: &[Extension]) -> Result<(), Error> {
+    let mut op_names: HashMap<&str, &str> = HashMap::new();
+    for extension in builtin.iter().chain(custom) {
+        for op in extension.ops.iter() {
+            if let Some(other) = op_names.insert(op.name, extension.name) {
+                return Err(anyhow!(
+                    "Extension '{}' registers op '{}', which extension '{}' already provides",
+                    extension.name,
+                    op.name,
+                    other
+                ));
+            }
+        }
+    }
+    for extension in custom {
+        if !extension.js_files.is_empty()
+            || !extension.esm_files.is_empty()
+            || !extension.lazy_loaded_esm_files.is_empty()
+        {
+            return Err(anyhow!(
+                "Extension '{}' has JS sources, which can't be loaded on top of the runtime snapshot",
+                extension.name
+            ));
+        }
+        if !extension.external_references.is_empty()
+            || extension.global_template_middleware.is_some()
+            || extension.global_object_middleware.is_some()
+        {
+            return Err(anyhow!(
+                "Extension '{}' has external references or global middlewares, which the runtime snapshot doesn't support",
+                extension.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn fetch_config(config: &SandboxConfig) -> FetchConfig {
+    FetchConfig {
+        allowed_origins: config.allowed_origins.clone(),
+        tls_policies: config.tls_policies.clone(),
+        block_private_networks: config.block_private_networks,
+        request_timeout_ms: config.fetch_request_timeout_ms,
+        total_fetch_budget_ms: config.total_fetch_budget_ms,
+        max_requests_per_render: config.max_fetch_requests,
+        max_concurrent_requests: config.max_concurrent_fetches,
+        secret_headers: config.fetch_secret_headers.clone(),
+        clients: config.http_clients.clone(),
+    }
+}
+
+/// Connect to every allowed fetch origin ahead of the first render
+///
+/// DNS resolution and the TCP and TLS handshakes happen here instead of in
+/// the first render's fetches; runtimes created from `config` (or a clone)
+/// reuse the connections. Each origin gets a `HEAD /` request. Returns how
+/// long each origin took to answer, or why it couldn't be reached.
+pub async fn warm_up_origins(config: &SandboxConfig) -> Vec<(String, Result<Duration, Error>)> {
+    fetch_config(config).warm_up().await
+}
+
+/// Check that the embedded V8 snapshot is intact
+///
+/// Compares its hash with the one build.rs recorded when it created the
+/// snapshot. Returns the snapshot size in bytes.
+pub fn verify_snapshot() -> Result<usize, Error> {
+    if RUNTIME_SNAPSHOT.is_empty() {
+        return Err(anyhow!("V8 snapshot is empty"));
+    }
+    let sha256: String = Sha256::digest(RUNTIME_SNAPSHOT)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    if sha256 != RUNTIME_SNAPSHOT_SHA256 {
+        return Err(anyhow!(
+            "V8 snapshot hash {} does not match the build ({})",
+            sha256,
+            RUNTIME_SNAPSHOT_SHA256
+        ));
+    }
+    Ok(RUNTIME_SNAPSHOT.len())
+}
+
+/// A sandboxed JS runtime, created by [`create_runtime`].
+///
+/// Wraps the deno_core `JsRuntime`, so embedders don't depend on deno_core
+/// types and deno_core upgrades aren't breaking changes of this crate. Like
+/// the isolate it owns, it can't leave the thread that created it; see
+/// [`RuntimePool`](crate::RuntimePool) for serving renders from a pool.
+///
+/// Console output is scoped to a render: each render starts with an empty
+/// capture and returns what it logged in [`SsrResult::console`] (or
+/// [`SsrError::console`]), so nothing carries over to the next request.
+pub struct SsrRuntime {
+    js: JsRuntime,
+    /// Renders started on this runtime, see [`RuntimeStats::renders`]
+    renders: u64,
+}
+
+/// Memory and usage of a runtime, see [`SsrRuntime::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RuntimeStats {
+    /// Renders started on the runtime, failed ones included
+    pub renders: u64,
+    /// Modules loaded into the isolate, see [`loaded_modules`]
+    pub loaded_modules: usize,
+    /// Bytes of the V8 heap used by objects
+    pub heap_used_bytes: usize,
+    /// Bytes of memory the V8 heap takes up, including external allocations
+    /// (e.g. `ArrayBuffer` contents)
+    pub heap_resident_bytes: usize,
+    /// Heap limit of the isolate ([`SandboxConfig::max_heap_size`] or V8's default)
+    pub heap_limit_bytes: usize,
+    /// Bytes held by the Cache API and `__ssr_cache__` fragment caches
+    pub cache_bytes: usize,
+}
+
+impl SsrRuntime {
+    /// Render `entry_point` with `props`, see [`execute_ssr`]
+    pub async fn render(
+        &mut self,
+        entry_point: &Path,
+        props: serde_json::Value,
+        timeout_ms: Option<u64>,
+    ) -> Result<SsrResult, SsrError> {
+        execute_ssr(self, entry_point, props, timeout_ms).await
+    }
+
+    /// Render with per-request options, see [`execute_ssr_with_options`]
+    pub async fn render_with_options(
+        &mut self,
+        entry_point: &Path,
+        props: serde_json::Value,
+        options: &ExecuteOptions,
+    ) -> Result<SsrResult, SsrError> {
+        execute_ssr_with_options(self, entry_point, props, options).await
+    }
+
+    /// Render and stream the HTML as it is produced, see [`execute_ssr_stream`]
+    pub async fn render_stream(
+        &mut self,
+        entry_point: &Path,
+        props: serde_json::Value,
+        timeout_ms: Option<u64>,
+    ) -> Result<SsrStream<'_>, SsrError> {
+        execute_ssr_stream(self, entry_point, props, timeout_ms).await
+    }
+
+    /// Load entry modules ahead of the first render, see [`preload_modules`]
+    pub async fn preload(&mut self, entry_points: &[PathBuf]) -> Result<(), SsrError> {
+        preload_modules(self, entry_points).await
+    }
+
+    /// Paths of the modules loaded into the runtime, see [`loaded_modules`]
+    pub fn loaded_modules(&mut self) -> Vec<PathBuf> {
+        loaded_modules(self)
+    }
+
+    /// Current memory and usage of the runtime
+    pub fn stats(&mut self) -> RuntimeStats {
+        let loaded_modules = loaded_modules(self).len();
+        let mut heap = v8::HeapStatistics::default();
+        self.js.v8_isolate().get_heap_statistics(&mut heap);
+        let state = self.js.op_state();
+        let state = state.borrow();
+        RuntimeStats {
+            renders: self.renders,
+            loaded_modules,
+            heap_used_bytes: heap.used_heap_size(),
+            heap_resident_bytes: heap.total_physical_size() + heap.external_memory(),
+            heap_limit_bytes: heap.heap_size_limit(),
+            cache_bytes: state
+                .try_borrow::<CacheBudget>()
+                .map_or(0, |budget| budget.used()),
+        }
+    }
+
+    /// Empty the runtime's caches (Cache API responses, `__ssr_cache__`
+    /// fragments) and collect garbage, e.g. after the data they were built
+    /// from changed. Returns the number of bytes the caches held.
+    ///
+    /// Loaded modules stay: V8 can't unload them, so drop the runtime to load
+    /// changed modules again (see [`RuntimePool::unload`](crate::RuntimePool::unload)).
+    pub fn invalidate(&mut self) -> usize {
+        let cache_bytes = shed_caches(&mut self.js.op_state().borrow_mut());
+        self.js.v8_isolate().low_memory_notification();
+        cache_bytes
+    }
+
+    /// Run a classic script in the global scope (e.g. to check or set up
+    /// globals), discarding its completion value
+    pub fn execute_script(
+        &mut self,
+        name: &'static str,
+        source: impl Into<String>,
+    ) -> Result<(), Error> {
+        self.js.execute_script(name, source.into())?;
+        Ok(())
+    }
+
+    /// Store `value` in the runtime's host-side state, replacing any earlier
+    /// value of its type. Ops of embedder extensions (see
+    /// [`SandboxConfig::extensions`]) read it from their `OpState`.
+    pub fn put_state<T: 'static>(&mut self, value: T) {
+        self.js.op_state().borrow_mut().put(value);
+    }
+
+    /// Remove the value of type `T` from the runtime's host-side state
+    pub fn take_state<T: 'static>(&mut self) -> Option<T> {
+        self.js.op_state().borrow_mut().try_take::<T>()
+    }
+
+    /// Call `f` with the value of type `T` in the runtime's host-side state,
+    /// None if there is none
+    pub fn with_state<T: 'static, R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.js.op_state().borrow_mut().try_borrow_mut::<T>().map(f)
+    }
+
+    pub(crate) fn js_runtime(&mut self) -> &mut JsRuntime {
+        &mut self.js
+    }
+}
+
+/// Create a sandboxed JS runtime for SSR
+// SsrError carries the console output of failed renders; it's only built on failure
+#[allow(clippy::result_large_err)]
+pub fn create_runtime(config: &SandboxConfig) -> Result<SsrRuntime, SsrError> {
+    create_runtime_with_extensions(config, Vec::new())
+}
+
+/// Create a sandboxed JS runtime with extra deno_core extensions
+///
+/// `extensions` are added after the ones of [`SandboxConfig::extensions`].
+/// They can add ops and op state on top of the snapshot, but no JS sources,
+/// external references or middlewares, and no op an earlier extension already
+/// provides. Their ops are callable from renders as `Deno.core.ops.<name>`.
+#[allow(clippy::result_large_err)]
+pub fn create_runtime_with_extensions(
+    config: &SandboxConfig,
+    extensions: Vec<Extension>,
+) -> Result<SsrRuntime, SsrError> {
+    let js = new_runtime(config, extensions).map_err(|e| SsrError::new(SsrErrorKind::Config, e))?;
+    Ok(SsrRuntime { js, renders: 0 })
+}
+
+fn new_runtime(config: &SandboxConfig, mut extensions: Vec<Extension>) -> Result<JsRuntime, Error> {
+    platform::init();
+    if config.isolation == IsolationMode::Realm {
+        return Err(anyhow!(
+            "Realm isolation is not supported: deno_core 0.311 cannot create additional realms"
+        ));
+    }
+
+    if config
+        .heap_watermark
+        .is_some_and(|watermark| !(watermark > 0.0 && watermark < 1.0))
+    {
+        return Err(anyhow!("heap_watermark must be between 0 and 1"));
+    }
+    let fetch_config = fetch_config(config);
+    fetch_config.validate()?;
+    config.html_rewrites.validate()?;
+
+    let mut loader = SandboxedLoader::new(&config.chunks_dir)?.with_wasm_limits(config.wasm_limits);
+    if let Some(manifest) = &config.integrity {
+        loader = loader.with_integrity(manifest.clone())?;
+    }
+    if let Some(path) = &config.import_map {
+        loader = loader.with_import_map(ImportMap::load(path)?);
+    }
+    if let Some(dir) = &config.code_cache_dir {
+        loader = loader.with_code_cache(CodeCache::new(dir)?);
+    }
+    if let Some(provider) = &config.module_provider {
+        loader = loader.with_provider(provider.clone());
+    }
+    let violation_log = loader.violation_log();
+    let render_progress = loader.render_progress();
+    let module_graph = loader.module_graph();
+    let source_maps = loader.source_maps();
+    let wasm_modules = loader.wasm_modules();
+
+    // Configure V8 heap limits if specified
+    let create_params = config.max_heap_size.map(|max_bytes| {
+        deno_core::v8::Isolate::create_params().heap_limits(0, max_bytes)
+    });
+
+    // Create blob store for deno_web (required for Blob API)
+    let blob_store = Arc::new(deno_web::BlobStore::default());
+
+    let mut builtin = vec![
+        deno_webidl::deno_webidl::init_ops(),
+        deno_console::deno_console::init_ops(),
+        deno_url::deno_url::init_ops(),
+        deno_web::deno_web::init_ops::<deno_permissions::PermissionsContainer>(blob_store, None),
+        deno_crypto::deno_crypto::init_ops(None),
+        // Our custom extension
+        ssr_runtime::init_ops(),
+    ];
+    let mut custom = config.extensions.build();
+    custom.append(&mut extensions);
+    check_extensions(&builtin, &custom)?;
+    let skip_op_registration = custom.is_empty();
+    builtin.append(&mut custom);
+
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+        module_loader: Some(Rc::new(loader)),
+        // Use pre-built snapshot for fast startup (JS already parsed/compiled)
+        startup_snapshot: Some(RUNTIME_SNAPSHOT),
+        // Skip op JS binding registration - they're already in the snapshot
+        // But we still need to register ops for external references to match.
+        // Ops of embedder extensions aren't in the snapshot, so they need it.
+        skip_op_registration,
+        extensions: builtin,
+        create_params,
+        ..Default::default()
+    });
+    // The snapshot leaves `Deno.core.ops` writable so ops of embedder
+    // extensions can be bound above; renders must not replace them
+    runtime.execute_script("<ssr-init>", "Object.freeze(Deno.core.ops);")?;
+    if let Some(profile) = &config.runtime_profile {
+        profile.validate()?;
+        if config.cache_api && profile.omit.contains("fetch") {
+            return Err(anyhow!(
+                "The runtime profile omits fetch, which the Cache API needs"
+            ));
+        }
+        // Before the first render, so `context_per_request` resets keep them deleted
+        let globals = serde_json::to_string(&profile.omitted_globals())?;
+        runtime.execute_script(
+            "<ssr-profile>",
+            format!("for (const name of {}) delete globalThis[name];", globals),
+        )?;
+    }
+
+    let warning_log = WarningLog::default();
+
+    // Report fatal OOMs to the crash hook before V8 aborts the process
+    if crash::crash_hook_installed() {
+        runtime.v8_isolate().set_oom_error_handler(crash::on_v8_oom);
+    }
+
+    // Add near-heap-limit callback to gracefully handle OOM
+    if config.max_heap_size.is_some() {
+        let warning_log = warning_log.clone();
+        let render_progress = render_progress.clone();
+        runtime.add_near_heap_limit_callback(move |current, initial| {
+            // Don't increase the limit - let V8 terminate gracefully
+            // Return current limit to trigger OOM error instead of crash
+            let message = format!(
+                "Near heap limit: current={}MB, initial={}MB",
+                current / (1024 * 1024),
+                initial / (1024 * 1024)
+            );
+            eprintln!("[ssr-sandbox]{} {}", render_progress.request_tag(), message);
+            warning_log.warn(WarningCode::NearHeapLimit, message);
+            current
+        });
+    }
+
+    // Initialize console output capture in state
+    runtime.op_state().borrow_mut().put(ConsoleOutput::default());
+    runtime.op_state().borrow_mut().put(config.console_limits.clone());
+    if !config.console_subscribers.is_empty() {
+        runtime.op_state().borrow_mut().put(config.console_subscribers.clone());
+    }
+    runtime.op_state().borrow_mut().put(config.timer_limits);
+    runtime.op_state().borrow_mut().put(config.wasm_limits);
+    runtime.op_state().borrow_mut().put(wasm_modules);
+
+    // Share the loader's violation log so renders can report rejected imports
+    runtime.op_state().borrow_mut().put(violation_log);
+
+    // Initialize fetch config
+    runtime.op_state().borrow_mut().put(fetch_config);
+    runtime.op_state().borrow_mut().put(config.html_rewrites.clone());
+    if let Some(provider) = &config.module_provider {
+        runtime.op_state().borrow_mut().put(provider.clone());
+    }
+    runtime
+        .op_state()
+        .borrow_mut()
+        .put(ResponseCache::new(config.cache_api));
+    runtime
+        .op_state()
+        .borrow_mut()
+        .put(FragmentCache::new(config.fragment_cache_size));
+    runtime
+        .op_state()
+        .borrow_mut()
+        .put(CacheBudget::new(config.cache_memory_budget));
+    runtime.op_state().borrow_mut().put(RenderChunks::default());
+    runtime.op_state().borrow_mut().put(RenderIslands::default());
+    runtime.op_state().borrow_mut().put(FetchTimeSpent::default());
+    runtime.op_state().borrow_mut().put(FetchBytesReceived::default());
+    runtime.op_state().borrow_mut().put(FetchStats::default());
+    runtime.op_state().borrow_mut().put(PendingFetches::default());
+    runtime.op_state().borrow_mut().put(config.storage);
+    runtime
+        .op_state()
+        .borrow_mut()
+        .put(ContextPerRequest(config.context_per_request));
+    if let Some(dir) = &config.data_dir {
+        let data_dir = DataDir::new(dir, config.max_data_file_size)?;
+        runtime.op_state().borrow_mut().put(data_dir);
+    }
+    if !config.host_functions.is_empty() {
+        runtime
+            .op_state()
+            .borrow_mut()
+            .put(config.host_functions.clone());
+    }
+    if let Some(deterministic) = config.deterministic {
+        runtime.op_state().borrow_mut().put(deterministic);
+    }
+    runtime.op_state().borrow_mut().put(render_progress);
+    runtime.op_state().borrow_mut().put(module_graph);
+    runtime.op_state().borrow_mut().put(source_maps);
+    runtime.op_state().borrow_mut().put(warning_log);
+    runtime.op_state().borrow_mut().put(RenderLimits {
+        max_event_loop_turns: config.max_event_loop_turns,
+        module_timeout_ms: config.module_timeout_ms,
+        max_html_bytes: config.max_html_bytes,
+        heap_watermark: config
+            .heap_watermark
+            .zip(config.max_heap_size)
+            .map(|(watermark, max_bytes)| (max_bytes as f64 * watermark) as usize),
+    });
+
+    Ok(runtime)
+}
+
+/// Execute SSR render and return HTML result
+///
+/// # Arguments
+/// * `runtime` - The sandboxed runtime
+/// * `entry_point` - Path to the entry JS file (must be within chunks_dir)
+/// * `props` - JSON props to pass to the render function
+/// * `timeout_ms` - Optional timeout in milliseconds (None = no timeout)
+///
+/// # Expected JS module format
+/// The entry module should export a default function or a `render` function:
+/// ```js
+/// export default function render(props) {
+///   return "<html>...</html>";
+/// }
+/// // or
+/// export function render(props) {
+///   return "<html>...</html>";
+/// }
+/// ```
+pub async fn execute_ssr(
+    runtime: &mut SsrRuntime,
+    entry_point: &Path,
+    props: serde_json::Value,
+    timeout_ms: Option<u64>,
+) -> Result<SsrResult, SsrError> {
+    let options = ExecuteOptions {
+        timeout_ms,
+        ..Default::default()
+    };
+    execute_ssr_with_options(runtime, entry_point, props, &options).await
+}
+
+/// Per-render options for [`execute_ssr_with_options`]
+#[derive(Debug, Clone, Default)]
+pub struct ExecuteOptions {
+    /// Timeout in milliseconds (None = no timeout)
+    pub timeout_ms: Option<u64>,
+    /// CSP nonce for this request, passed to the render function as `context.nonce`
+    pub csp_nonce: Option<String>,
+    /// Add the nonce to `<script>`/`<style>` tags in the output that don't have one
+    pub inject_nonce: bool,
+    /// Correlation id (e.g. from the host's tracing) attached to the result,
+    /// errors, sandbox violations and stderr logs of this render, and passed
+    /// to the render function as `context.requestId`
+    pub request_id: Option<String>,
+    /// Data the host pushes while the render runs, read by the render
+    /// function with `for await (const item of context.hostFeed)` (see
+    /// [`host_feed`](crate::host_feed))
+    pub host_feed: Option<HostFeed>,
+    /// Render a fragment: call this named export of the entry module instead
+    /// of its default export. It is called like a render function and only
+    /// its output is returned, e.g. for edge-side includes or HTMX partials
+    /// served from the same bundle.
+    pub export: Option<String>,
+    /// Soft cap in bytes on the V8 heap for this render, below the isolate's
+    /// [`SandboxConfig::max_heap_size`]. Checked between event loop turns, so
+    /// synchronous code can go past it until it yields; the render then fails
+    /// with [`SsrErrorKind::HeapCap`] (default: None, only the isolate's
+    /// limit applies)
+    pub max_heap_size: Option<usize>,
+    /// The HTTP request being rendered, exposed to JS as
+    /// `globalThis.__SSR_REQUEST__` and `location`, so routers can read the
+    /// current URL without it being passed through props
+    pub request: Option<RequestContext>,
+}
+
+/// Whether a request id is safe to put in logs and line protocol headers:
+/// 1-128 printable ASCII characters without whitespace
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_graphic())
+}
+
+/// Whether a fragment export name is a plain JS identifier (ASCII only)
+fn is_valid_export_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && name.len() <= 128
+}
+
+/// Execute SSR render like [`execute_ssr`], with per-request options.
+///
+/// The render function receives a context object as its second argument:
+/// ```js
+/// export default function render(props, { nonce }) {
+///   return `<script nonce="${nonce}">...</script>`;
+/// }
+/// ```
+pub async fn execute_ssr_with_options(
+    runtime: &mut SsrRuntime,
+    entry_point: &Path,
+    props: serde_json::Value,
+    options: &ExecuteOptions,
+) -> Result<SsrResult, SsrError> {
+    runtime.renders += 1;
+    let runtime = &mut runtime.js;
+    let invalid = |message: &str| SsrError {
+        entry: entry_specifier(entry_point),
+        request_id: options.request_id.clone(),
+        ..SsrError::new(SsrErrorKind::InvalidOptions, message)
+    };
+    if let Some(nonce) = &options.csp_nonce {
+        if !csp::is_valid_nonce(nonce) {
+            return Err(invalid(
+                "Invalid CSP nonce (expected 1-256 base64 characters)",
+            ));
+        }
+    }
+    if let Some(request_id) = &options.request_id {
+        if !is_valid_request_id(request_id) {
+            return Err(SsrError {
+                request_id: None,
+                ..invalid("Invalid request id (expected 1-128 printable characters without spaces)")
+            });
+        }
+    }
+    if let Some(export) = &options.export {
+        if !is_valid_export_name(export) {
+            return Err(invalid(
+                "Invalid export name (expected a JS identifier of up to 128 characters)",
+            ));
+        }
+    }
+    if let Some(request) = &options.request {
+        if ModuleSpecifier::parse(&request.url).is_err() {
+            return Err(invalid("Invalid request URL (expected an absolute URL)"));
+        }
+    }
+    let context = RenderContext {
+        nonce: options.csp_nonce.as_deref(),
+        request_id: options.request_id.as_deref(),
+        host_feed: options.host_feed.is_some(),
+        export: options.export.as_deref(),
+        max_heap_size: options.max_heap_size,
+        request: options.request.as_ref(),
+    };
+
+    let progress = render_progress(runtime);
+    progress.reset_for_request(options.request_id.as_deref());
+    let _in_flight = InFlight::start(&entry_point.display().to_string(), context.request_id);
+    if let Some(feed) = &options.host_feed {
+        runtime.op_state().borrow_mut().put(feed.clone());
+    }
+
+    // Spawn a task that will terminate execution after timeout
+    let watchdog = spawn_watchdog(runtime, options.timeout_ms);
+
+    let result = execute_ssr_inner(runtime, entry_point, props, &context).await;
+    abort_fetches(&runtime.op_state(), &progress);
+    // The next render must not read this render's feed
+    runtime.op_state().borrow_mut().try_take::<HostFeed>();
+
+    // Cancel the watchdog if we finished in time
+    if let Some(handle) = watchdog {
+        handle.abort();
+    }
+
+    let mut result = result.map_err(|mut e| {
+        let interrupted = interrupted_error(anyhow!(e.message), &progress, options.timeout_ms);
+        if let Some(kind) = error_kind(&interrupted) {
+            e.kind = kind;
+        }
+        e.message = interrupted.to_string();
+        e
+    })?;
+    if let (true, Some(nonce)) = (options.inject_nonce, &options.csp_nonce) {
+        result.html = csp::inject_nonce(&result.html, nonce);
+        result.head = result.head.map(|head| csp::inject_nonce(&head, nonce));
+    }
+    if let Err(e) = rewrite_output(&mut result, &html_rewrites(runtime)) {
+        return Err(SsrError {
+            entry: entry_specifier(entry_point),
+            request_id: result.request_id,
+            console: result.console,
+            ..SsrError::new(SsrErrorKind::Other, e)
+        });
+    }
+    Ok(result)
+}
+
+/// Apply [`SandboxConfig::html_rewrites`] to the output of a render
+fn rewrite_output(result: &mut SsrResult, rewrites: &HtmlRewrites) -> Result<(), Error> {
+    if !rewrites.is_empty() {
+        result.html = rewrites.rewrite(&result.html, None)?;
+        let head = result.head.take();
+        result.head = head.map(|head| rewrites.rewrite(&head, None)).transpose()?;
+    }
+    Ok(())
+}
+
+/// Paths of every module imported in `runtime` so far (canonical, symlinks resolved)
+///
+/// V8 keeps a module until its isolate is dropped, so these are also the
+/// modules whose code and state the runtime holds on to.
+pub fn loaded_modules(runtime: &mut SsrRuntime) -> Vec<PathBuf> {
+    let op_state = runtime.js.op_state();
+    let state = op_state.borrow();
+    let Some(graph) = state.try_borrow::<ModuleGraph>() else {
+        return Vec::new();
+    };
+    graph
+        .modules()
+        .iter()
+        .filter_map(|module| ModuleSpecifier::parse(module).ok()?.to_file_path().ok())
+        .collect()
+}
+
+/// Load, compile and evaluate entry modules ahead of the first render.
+///
+/// Each entry is imported the way a render imports it and its render function
+/// is cached, so the first request doesn't pay for compiling the module graph.
+/// Entries that are already loaded return right away. `--module-timeout`
+/// applies to top-level await; console output of module code is discarded.
+pub async fn preload_modules(
+    runtime: &mut SsrRuntime,
+    entry_points: &[PathBuf],
+) -> Result<(), SsrError> {
+    let runtime = &mut runtime.js;
+    for entry_point in entry_points {
+        let entry_path =
+            entry_path(runtime, entry_point).map_err(|e| load_error(runtime, entry_point, e))?;
+        let Ok(module_specifier) = ModuleSpecifier::from_file_path(&entry_path) else {
+            let message = "Failed to create module specifier";
+            return Err(SsrError::new(SsrErrorKind::Other, message));
+        };
+
+        let progress = render_progress(runtime);
+        progress.reset_for_request(None);
+        let watchdog = spawn_watchdog(runtime, None);
+        let result = preload_entry(runtime, &module_specifier).await;
+        if let Some(handle) = watchdog {
+            handle.abort();
+        }
+        runtime.op_state().borrow_mut().put(ConsoleOutput::default());
+
+        if let Err(e) = result {
+            let e = interrupted_error(e, &progress, None);
+            return Err(SsrError {
+                message: format!("Failed to preload '{}': {}", entry_point.display(), e),
+                ..load_error(runtime, entry_point, e)
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Error of loading the modules of `entry_point` without rendering it
+fn load_error(runtime: &mut JsRuntime, entry_point: &Path, e: Error) -> SsrError {
+    let kind = error_kind(&e).unwrap_or_else(|| {
+        let missing = runtime
+            .op_state()
+            .borrow()
+            .borrow::<ModuleGraph>()
+            .missing_module();
+        match missing {
+            Some(specifier) => SsrErrorKind::ModuleNotFound { specifier },
+            None => SsrErrorKind::ModuleFailed,
+        }
+    });
+    SsrError {
+        stack: js_stack(&e),
+        entry: entry_specifier(entry_point),
+        ..SsrError::new(kind, e)
+    }
+}
+
+async fn preload_entry(runtime: &mut JsRuntime, module_specifier: &ModuleSpecifier) -> Result<(), Error> {
+    let promise = runtime.execute_script(
+        "<ssr-preload>",
+        format!(r#"globalThis.__ssr_internal_preload__("{}")"#, module_specifier),
+    )?;
+    runtime
+        .run_event_loop(PollEventLoopOptions::default())
+        .await?;
+
+    let scope = &mut runtime.handle_scope();
+    let local = v8::Local::new(scope, &promise);
+    let promise = v8::Local::<v8::Promise>::try_from(local)?;
+    match promise.state() {
+        v8::PromiseState::Fulfilled => Ok(()),
+        v8::PromiseState::Rejected => {
+            let exception = promise.result(scope);
+            Err(anyhow!(exception.to_rust_string_lossy(scope)))
+        }
+        v8::PromiseState::Pending => Err(anyhow!("Module never finished loading")),
+    }
+}
+
+/// Cold start cost of one module in an entry's import graph, see [`analyze_entry`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModuleCost {
+    /// Path relative to the chunks directory, `/`-separated
+    pub path: String,
+    /// Size of the module's code in bytes
+    pub size: u64,
+    /// Time to read, compile and link the module
+    pub compile_ms: f64,
+    /// Time to run the module's top-level code, its imports excluded
+    pub eval_ms: f64,
+}
+
+/// Measure what each module `entry_point` imports statically costs a cold start.
+///
+/// A scratch runtime imports the entry to find its module graph; a second one
+/// then loads and evaluates the modules one at a time, dependencies first, so
+/// each module is timed without its imports. Modules of an import cycle are
+/// timed together, with the first of them that is loaded (the others report
+/// 0). Returned in evaluation order, the entry last.
+pub async fn analyze_entry(
+    config: &SandboxConfig,
+    entry_point: &Path,
+) -> Result<Vec<ModuleCost>, SsrError> {
+    let mut runtime = create_runtime(config)?;
+    preload_modules(&mut runtime, &[entry_point.to_path_buf()]).await?;
+    let order = evaluation_order(&mut runtime.js, entry_point);
+    let order = order.map_err(|e| load_error(&mut runtime.js, entry_point, e))?;
+    drop(runtime);
+
+    let mut runtime = create_runtime(config)?.js;
+    let costs = module_costs(config, &mut runtime, order).await;
+    costs.map_err(|e| load_error(&mut runtime, entry_point, e))
+}
+
+/// Modules of the import graph of `entry_point` (already loaded), dependencies first
+fn evaluation_order(runtime: &mut JsRuntime, entry_point: &Path) -> Result<Vec<String>, Error> {
+    let entry = ModuleSpecifier::from_file_path(entry_path(runtime, entry_point)?)
+        .map_err(|_| anyhow!("Failed to create module specifier"))?;
+    Ok(module_graph(runtime)?.evaluation_order(entry.as_str()))
+}
+
+/// Load and evaluate the modules in `order`, timing each one
+async fn module_costs(
+    config: &SandboxConfig,
+    runtime: &mut JsRuntime,
+    order: Vec<String>,
+) -> Result<Vec<ModuleCost>, Error> {
+    let chunks_dir = Path::new(&config.chunks_dir).canonicalize()?;
+    let graph = module_graph(runtime)?;
+    let mut costs = Vec::with_capacity(order.len());
+    for module in order {
+        let specifier = ModuleSpecifier::parse(&module)?;
+        let path = specifier
+            .to_file_path()
+            .map_err(|_| anyhow!("Invalid module path: {}", specifier))?;
+        let relative = path
+            .strip_prefix(&chunks_dir)
+            .map(|relative| relative.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_else(|_| path.display().to_string());
+        let size = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => match &config.module_provider {
+                Some(provider) => provider
+                    .load(&relative)?
+                    .map_or(0, |code| code.len() as u64),
+                None => 0,
+            },
+        };
+        let mut cost = ModuleCost {
+            path: relative,
+            size,
+            compile_ms: 0.0,
+            eval_ms: 0.0,
+        };
+
+        // Already loaded as an import of an earlier module in its cycle
+        if graph.modules().contains(&module) {
+            costs.push(cost);
+            continue;
+        }
+
+        let started = Instant::now();
+        let id = runtime.load_side_es_module(&specifier).await?;
+        cost.compile_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+        let started = Instant::now();
+        let evaluation = runtime.mod_evaluate(id);
+        runtime
+            .with_event_loop_future(evaluation, PollEventLoopOptions::default())
+            .await
+            .map_err(|e| anyhow!("Failed to evaluate '{}': {}", cost.path, e))?;
+        cost.eval_ms = started.elapsed().as_secs_f64() * 1000.0;
+        costs.push(cost);
+    }
+    Ok(costs)
+}
+
+fn module_graph(runtime: &mut JsRuntime) -> Result<ModuleGraph, Error> {
+    runtime
+        .op_state()
+        .borrow()
+        .try_borrow::<ModuleGraph>()
+        .cloned()
+        .ok_or_else(|| anyhow!("Runtime has no module graph"))
+}
+
+/// Execute a streaming SSR render, yielding HTML chunks as the render produces them
+///
+/// The render function may return a `ReadableStream` of strings or `Uint8Array`s
+/// (e.g. React 18's `renderToReadableStream`); each chunk is yielded as soon as
+/// it is written instead of buffering the whole document. A render returning a
+/// plain string yields a single chunk.
+///
+/// Errors (including timeouts) are yielded as the last item of the stream.
+/// Console output and sandbox violations are available from the stream once it
+/// has ended.
+pub async fn execute_ssr_stream<'a>(
+    runtime: &'a mut SsrRuntime,
+    entry_point: &Path,
+    props: serde_json::Value,
+    timeout_ms: Option<u64>,
+) -> Result<SsrStream<'a>, SsrError> {
+    runtime.renders += 1;
+    let runtime = &mut runtime.js;
+    let violation_log = runtime
+        .op_state()
+        .borrow()
+        .borrow::<ViolationLog>()
+        .clone();
+    violation_log.take();
+    let warning_log = warning_log(runtime);
+    warning_log.take();
+    runtime.op_state().borrow_mut().put(ConsoleOutput::default());
+    runtime.op_state().borrow_mut().put(FetchTimeSpent::default());
+    runtime.op_state().borrow_mut().put(FetchBytesReceived::default());
+    runtime.op_state().borrow_mut().put(FetchStats::default());
+    shed_above_watermark(runtime, &warning_log);
+
+    let chunks = runtime
+        .op_state()
+        .borrow()
+        .borrow::<RenderChunks>()
+        .clone();
+    chunks.clear();
+
+    let progress = render_progress(runtime);
+    progress.reset();
+    let in_flight = InFlight::start(&entry_point.display().to_string(), None);
+    let entry = entry_specifier(entry_point);
+
+    let timeout_handle = spawn_watchdog(runtime, timeout_ms);
+
+    let context = RenderContext::default();
+    let pending = match start_render(runtime, entry_point, props, true, &context) {
+        Ok(pending) => pending,
+        Err(e) => {
+            if let Some(handle) = timeout_handle {
+                handle.abort();
+            }
+            let e = interrupted_error(e, &progress, timeout_ms);
+            let (warnings, violations) = (warning_log.take(), violation_log.take());
+            return Err(stream_error(runtime, e, entry, &warnings, &violations));
+        }
+    };
+
+    let limits = render_limits(runtime);
+    let rewrites = html_rewrites(runtime);
+    let rewriter = (!rewrites.is_empty()).then(|| rewrites.rewriter(None));
+
+    Ok(SsrStream {
+        op_state: runtime.op_state(),
+        progress,
+        max_turns: limits.max_event_loop_turns,
+        turns: 0,
+        max_html_bytes: limits.max_html_bytes,
+        html_bytes: 0,
+        runtime,
+        chunks,
+        pending,
+        violation_log,
+        sandbox_violations: Vec::new(),
+        warning_log,
+        warnings: Vec::new(),
+        timeout_ms,
+        timeout_handle,
+        rewriter,
+        entry,
+        in_flight: Some(in_flight),
+        done: false,
+    })
+}
+
+/// Error that ends a streaming render, with the console output captured up to it
+fn stream_error(
+    runtime: &mut JsRuntime,
+    e: Error,
+    entry: String,
+    warnings: &[Warning],
+    sandbox_violations: &[SandboxViolation],
+) -> SsrError {
+    let stack = js_stack(&e).map(|stack| source_maps(runtime).translate_stack(&stack));
+    let kind = failure_kind(runtime, &e, warnings, &stack);
+    let console = runtime
+        .op_state()
+        .borrow()
+        .borrow::<ConsoleOutput>()
+        .clone();
+    SsrError {
+        kind,
+        stack,
+        message: with_violations(e, sandbox_violations).to_string(),
+        entry,
+        console,
+        request_id: None,
+    }
+}
+
+/// HTML chunks of a streaming render, see [`execute_ssr_stream`]
+pub struct SsrStream<'a> {
+    runtime: &'a mut JsRuntime,
+    op_state: Rc<RefCell<OpState>>,
+    chunks: RenderChunks,
+    /// Promise returned by the internal render function
+    pending: v8::Global<v8::Value>,
+    violation_log: ViolationLog,
+    sandbox_violations: Vec<SandboxViolation>,
+    warning_log: WarningLog,
+    warnings: Vec<Warning>,
+    progress: RenderProgress,
+    timeout_ms: Option<u64>,
+    timeout_handle: Option<tokio::task::JoinHandle<()>>,
+    max_turns: Option<u64>,
+    turns: u64,
+    max_html_bytes: Option<usize>,
+    /// Bytes of the chunks streamed so far, before rewrites
+    html_bytes: usize,
+    /// Rewrites chunks before they are yielded, see [`SandboxConfig::html_rewrites`]
+    rewriter: Option<StreamingRewriter>,
+    /// Specifier of the entry module, for errors
+    entry: String,
+    /// Reported to the crash hook until the render ends
+    in_flight: Option<InFlight>,
+    done: bool,
+}
+
+impl SsrStream<'_> {
+    /// Add `nonce="<nonce>"` to every `<script>` and `<style>` tag of the
+    /// streamed HTML that doesn't have one, see [`csp::inject_nonce`]
+    ///
+    /// Call before reading the first chunk.
+    #[allow(clippy::result_large_err)]
+    pub fn with_nonce(mut self, nonce: &str) -> Result<Self, SsrError> {
+        if !csp::is_valid_nonce(nonce) {
+            let message = "Invalid CSP nonce (expected 1-256 base64 characters)";
+            return Err(SsrError {
+                entry: self.entry.clone(),
+                ..SsrError::new(SsrErrorKind::InvalidOptions, message)
+            });
+        }
+        let rewrites = self.op_state.borrow().borrow::<HtmlRewrites>().clone();
+        self.rewriter = Some(rewrites.rewriter(Some(nonce)));
+        Ok(self)
+    }
+
+    /// Console output captured so far (complete once the stream has ended)
+    pub fn console(&self) -> ConsoleOutput {
+        self.op_state.borrow().borrow::<ConsoleOutput>().clone()
+    }
+
+    /// Actions blocked by the sandbox during this render (available once the stream has ended)
+    pub fn sandbox_violations(&self) -> &[SandboxViolation] {
+        &self.sandbox_violations
+    }
+
+    /// Warnings noticed during this render (available once the stream has ended)
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Modules used by this render so far (complete once the stream has ended),
+    /// see [`SsrResult::loaded_modules`]
+    pub fn loaded_modules(&self) -> Vec<String> {
+        self.op_state.borrow().borrow::<ModuleGraph>().render_modules()
+    }
+
+    /// `fetch()` calls of this render so far (complete once the stream has
+    /// ended), see [`SsrResult::fetch`]
+    pub fn fetch_stats(&self) -> FetchStats {
+        *self.op_state.borrow().borrow::<FetchStats>()
+    }
+
+    fn finish(&mut self) {
+        self.done = true;
+        self.in_flight = None;
+        if let Some(handle) = self.timeout_handle.take() {
+            handle.abort();
+        }
+        abort_fetches(&self.op_state, &self.progress);
+        self.sandbox_violations = self.violation_log.take();
+        self.warnings = self.warning_log.take();
+    }
+
+    /// `e` as the error that ends the stream, see [`stream_error`]
+    fn error(&mut self, e: Error) -> SsrError {
+        let (entry, violations) = (self.entry.clone(), &self.sandbox_violations);
+        stream_error(self.runtime, e, entry, &self.warnings, violations)
+    }
+}
+
+impl Stream for SsrStream<'_> {
+    type Item = Result<Bytes, SsrError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(chunk) = this.chunks.pop() {
+                this.html_bytes += chunk.len();
+                if let Some(max_bytes) = this.max_html_bytes.filter(|&max| this.html_bytes > max) {
+                    this.finish();
+                    this.chunks.clear();
+                    this.rewriter = None;
+                    let e = output_too_large(max_bytes, this.html_bytes);
+                    return Poll::Ready(Some(Err(this.error(e))));
+                }
+                let Some(rewriter) = &mut this.rewriter else {
+                    return Poll::Ready(Some(Ok(Bytes::from(chunk))));
+                };
+                match rewriter.write(&chunk) {
+                    // The rewriter may hold back a tag split across chunks
+                    Ok(html) if html.is_empty() => continue,
+                    Ok(html) => return Poll::Ready(Some(Ok(html))),
+                    Err(e) => {
+                        this.finish();
+                        this.chunks.clear();
+                        this.rewriter = None;
+                        return Poll::Ready(Some(Err(this.error(e))));
+                    }
+                }
+            }
+            if this.done {
+                return match this.rewriter.take().map(|mut rewriter| rewriter.end()) {
+                    Some(Ok(html)) if !html.is_empty() => Poll::Ready(Some(Ok(html))),
+                    Some(Err(e)) => Poll::Ready(Some(Err(this.error(e)))),
+                    _ => Poll::Ready(None),
+                };
+            }
+
+            this.turns += 1;
+            let poll = match this.max_turns {
+                Some(max) if this.turns > max => Poll::Ready(Err(budget_exceeded(max))),
+                _ => this
+                    .runtime
+                    .poll_event_loop(cx, PollEventLoopOptions::default()),
+            };
+
+            match poll {
+                // Keep polling while the render is producing chunks
+                Poll::Pending if this.chunks.is_empty() => return Poll::Pending,
+                Poll::Pending => {}
+                Poll::Ready(result) => {
+                    let result = result.and_then(|()| settled_output(this.runtime, &this.pending));
+                    this.finish();
+                    if let Err(e) = result {
+                        this.chunks.clear();
+                        this.rewriter = None;
+                        let e = interrupted_error(e, &this.progress, this.timeout_ms);
+                        return Poll::Ready(Some(Err(this.error(e))));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SsrStream<'_> {
+    fn drop(&mut self) {
+        if let Some(handle) = self.timeout_handle.take() {
+            handle.abort();
+        }
+        if !self.done {
+            abort_fetches(&self.op_state, &self.progress);
+        }
+    }
+}
+
+/// Abort the `fetch()` requests a render left in flight (it timed out, threw,
+/// or returned without awaiting them), so their connections are closed now
+/// rather than whenever the event loop runs next
+fn abort_fetches(op_state: &RefCell<OpState>, progress: &RenderProgress) {
+    let aborted = op_state
+        .borrow_mut()
+        .try_borrow_mut::<PendingFetches>()
+        .map_or(0, PendingFetches::abort_all);
+    if aborted > 0 {
+        eprintln!(
+            "[ssr-sandbox]{} Aborted {} fetch(es) still in flight after the render",
+            progress.request_tag(),
+            aborted
+        );
+    }
+}
+
+fn render_progress(runtime: &mut JsRuntime) -> RenderProgress {
+    runtime
+        .op_state()
+        .borrow()
+        .borrow::<RenderProgress>()
+        .clone()
+}
+
+fn html_rewrites(runtime: &mut JsRuntime) -> HtmlRewrites {
+    runtime.op_state().borrow().borrow::<HtmlRewrites>().clone()
+}
+
+fn warning_log(runtime: &mut JsRuntime) -> WarningLog {
+    runtime.op_state().borrow().borrow::<WarningLog>().clone()
+}
+
+fn fetch_stats(runtime: &mut JsRuntime) -> FetchStats {
+    *runtime.op_state().borrow().borrow::<FetchStats>()
+}
+
+fn render_limits(runtime: &mut JsRuntime) -> RenderLimits {
+    *runtime.op_state().borrow().borrow::<RenderLimits>()
+}
+
+/// Clear the runtime's caches and collect garbage if its heap is above
+/// [`SandboxConfig::heap_watermark`], so the render has room to work with
+fn shed_above_watermark(runtime: &mut JsRuntime, warning_log: &WarningLog) {
+    let Some(watermark) = render_limits(runtime).heap_watermark else {
+        return;
+    };
+    let used = heap_used_bytes(runtime);
+    if used <= watermark {
+        return;
+    }
+    let cache_bytes = shed_caches(&mut runtime.op_state().borrow_mut());
+    runtime.v8_isolate().low_memory_notification();
+    let message = format!(
+        "Heap above watermark: used={}MB, watermark={}MB, {}MB after clearing {}KB of caches",
+        used / (1024 * 1024),
+        watermark / (1024 * 1024),
+        heap_used_bytes(runtime) / (1024 * 1024),
+        cache_bytes / 1024
+    );
+    eprintln!("[ssr-sandbox] {}", message);
+    crash::record_event(message.clone());
+    warning_log.warn(WarningCode::HeapWatermark, message);
+}
+
+fn heap_used_bytes(runtime: &mut JsRuntime) -> usize {
+    let mut stats = v8::HeapStatistics::default();
+    runtime.v8_isolate().get_heap_statistics(&mut stats);
+    stats.used_heap_size()
+}
+
+/// Watch a render and terminate JS execution when it runs past the render
+/// timeout, or spends longer than the module timeout importing its entry.
+/// Slow imports (e.g. heavy top-level await) are logged while pending.
+fn spawn_watchdog(
+    runtime: &mut JsRuntime,
+    timeout_ms: Option<u64>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let module_timeout_ms = render_limits(runtime).module_timeout_ms;
+    if timeout_ms.is_none() && module_timeout_ms.is_none() {
+        return None;
+    }
+
+    let progress = render_progress(runtime);
+    let warnings = warning_log(runtime);
+    // Get a handle to terminate execution if needed
+    let isolate_handle = runtime.v8_isolate().thread_safe_handle();
+
+    Some(tokio::spawn(async move {
+        let started = Instant::now();
+        let mut next_report = SLOW_MODULE_REPORT_INTERVAL;
+        loop {
+            tokio::time::sleep(WATCHDOG_TICK).await;
+
+            if let Some(ms) = timeout_ms {
+                if started.elapsed() >= Duration::from_millis(ms) {
+                    let mut reason = format!("Render timed out after {}ms", ms);
+                    if let Some(module) = progress.loading() {
+                        reason = format!("{} ({})", reason, module);
+                    }
+                    progress.interrupt(reason);
+                    isolate_handle.terminate_execution();
+                    return;
+                }
+            }
+
+            let Some(module) = progress.loading() else {
+                continue;
+            };
+            if let Some(ms) = module_timeout_ms {
+                if module.elapsed >= Duration::from_millis(ms) {
+                    progress.interrupt(format!("{} after {}ms: {}", MODULE_TIMED_OUT, ms, module));
+                    isolate_handle.terminate_execution();
+                    return;
+                }
+            }
+            if module.elapsed >= next_report {
+                eprintln!("[ssr-sandbox]{} Slow module evaluation: {}", progress.request_tag(), module);
+                if next_report == SLOW_MODULE_REPORT_INTERVAL {
+                    warnings.warn(WarningCode::SlowModule, format!("Slow module evaluation: {}", module));
+                }
+                next_report += SLOW_MODULE_REPORT_INTERVAL;
+            }
+        }
+    }))
+}
+
+/// Start of the reason the watchdog gives for a module evaluation timeout
+const MODULE_TIMED_OUT: &str = "Module evaluation timed out";
+
+/// Turn the error of a render terminated by the watchdog into the reason it was stopped
+fn interrupted_error(e: Error, progress: &RenderProgress, timeout_ms: Option<u64>) -> Error {
+    if let Some(reason) = progress.take_interrupt() {
+        let module_timeout_ms = reason
+            .strip_prefix(MODULE_TIMED_OUT)
+            .and_then(|rest| rest.strip_prefix(" after "))
+            .and_then(|rest| rest.split_once("ms"))
+            .and_then(|(ms, _)| ms.parse().ok());
+        return match (module_timeout_ms, timeout_ms) {
+            (Some(timeout_ms), _) => kind_error(SsrErrorKind::ModuleTimeout { timeout_ms }, reason),
+            (None, Some(timeout_ms)) => kind_error(SsrErrorKind::Timeout { timeout_ms }, reason),
+            (None, None) => anyhow!(reason),
+        };
+    }
+    match timeout_ms {
+        Some(ms) => timeout_error(e, ms),
+        None => e,
+    }
+}
+
+/// Check if we were terminated due to timeout.
+/// V8 termination can manifest as various errors
+fn timeout_error(e: Error, ms: u64) -> Error {
+    let err_str = e.to_string();
+    if err_str.contains("terminated")
+        || err_str.contains("unresolved promise")
+        || err_str.contains("Uncaught Error: execution terminated")
+    {
+        kind_error(
+            SsrErrorKind::Timeout { timeout_ms: ms },
+            format!("Render timed out after {}ms", ms),
+        )
+    } else {
+        e
+    }
+}
+
+fn heap_cap_exceeded(max_bytes: usize) -> Error {
+    kind_error(
+        SsrErrorKind::HeapCap { max_bytes },
+        format!("Render heap cap exceeded ({}MB)", max_bytes / (1024 * 1024)),
+    )
+}
+
+fn output_too_large(max_bytes: usize, bytes: usize) -> Error {
+    kind_error(
+        SsrErrorKind::OutputTooLarge { max_bytes },
+        format!("Render output too large ({} bytes, limit {} bytes)", bytes, max_bytes),
+    )
+}
+
+fn budget_exceeded(max_turns: u64) -> Error {
+    kind_error(
+        SsrErrorKind::EventLoopBudget { max_turns },
+        format!("Event loop budget exceeded ({} turns)", max_turns),
+    )
+}
+
+/// Kind of an error raised with [`kind_error`]
+fn error_kind(e: &Error) -> Option<SsrErrorKind> {
+    e.downcast_ref::<KindError>().map(|e| e.kind.clone())
+}
+
+/// Source maps of the runtime's chunks
+fn source_maps(runtime: &mut JsRuntime) -> SourceMaps {
+    runtime.op_state().borrow().borrow::<SourceMaps>().clone()
+}
+
+/// Stack trace of a JS exception behind a failed render
+fn js_stack(e: &Error) -> Option<String> {
+    if let Some(exception) = e.downcast_ref::<RenderException>() {
+        return exception.stack.clone();
+    }
+    e.downcast_ref::<deno_core::error::JsError>()
+        .and_then(|js_error| js_error.stack.clone())
+}
+
+/// Absolute path of an entry point: the file on disk, or for entries the
+/// module provider may serve, the path as given (checked by the loader)
+fn entry_path(runtime: &mut JsRuntime, entry_point: &Path) -> Result<PathBuf, Error> {
+    let on_disk = entry_point.canonicalize();
+    if on_disk.is_err() && runtime.op_state().borrow().has::<SharedModuleProvider>() {
+        if let Some(path) = canonical_ancestor(entry_point) {
+            return Ok(path);
+        }
+    }
+    on_disk.map_err(|e| {
+        kind_error(
+            SsrErrorKind::ModuleNotFound {
+                specifier: entry_point.display().to_string(),
+            },
+            format!("Invalid entry point '{}': {}", entry_point.display(), e),
+        )
+    })
+}
+
+/// `path` with symlinks resolved in the part of it that exists on disk (like
+/// the loader's root directory), None if the rest contains `..`
+pub(crate) fn canonical_ancestor(path: &Path) -> Option<PathBuf> {
+    let mut missing = Vec::new();
+    let mut existing = std::path::absolute(path).ok()?;
+    while !existing.exists() {
+        match existing.components().next_back()? {
+            Component::Normal(name) => missing.push(name.to_os_string()),
+            _ => return None,
+        }
+        existing.pop();
+    }
+    let mut resolved = existing.canonicalize().ok()?;
+    resolved.extend(missing.iter().rev());
+    Some(resolved)
+}
+
+fn entry_specifier(entry_point: &Path) -> String {
+    entry_point
+        .canonicalize()
+        .ok()
+        .and_then(|path| ModuleSpecifier::from_file_path(path).ok())
+        .map(|specifier| specifier.to_string())
+        .unwrap_or_else(|| entry_point.display().to_string())
+}
+
+/// Surface the sandbox rule behind a generic JS rejection
+fn with_violations(e: Error, sandbox_violations: &[SandboxViolation]) -> Error {
+    if sandbox_violations.is_empty() {
+        return e;
+    }
+    let details: Vec<String> = sandbox_violations
+        .iter()
+        .map(|v| format!("  - {}", v))
+        .collect();
+    anyhow!("{}\nSandbox violations:\n{}", e, details.join("\n"))
+}
+
+async fn execute_ssr_inner(
+    runtime: &mut JsRuntime,
+    entry_point: &Path,
+    props: serde_json::Value,
+    context: &RenderContext<'_>,
+) -> Result<SsrResult, SsrError> {
+    let violation_log = runtime
+        .op_state()
+        .borrow()
+        .borrow::<ViolationLog>()
+        .clone();
+    let warning_log = warning_log(runtime);
+    // Discard anything left over from a previous render
+    violation_log.take();
+    warning_log.take();
+    take_islands(runtime);
+    runtime.op_state().borrow_mut().put(ConsoleOutput::default());
+    runtime.op_state().borrow_mut().put(FetchTimeSpent::default());
+    runtime.op_state().borrow_mut().put(FetchBytesReceived::default());
+    runtime.op_state().borrow_mut().put(FetchStats::default());
+    shed_above_watermark(runtime, &warning_log);
+
+    let result = render_html(runtime, entry_point, props, context).await;
+    let mut sandbox_violations = violation_log.take();
+    let warnings = warning_log.take();
+    let islands = take_islands(runtime);
+    let fetch = fetch_stats(runtime);
+    let request_id = context.request_id.map(str::to_string);
+    if let Some(id) = &request_id {
+        for violation in &mut sandbox_violations {
+            violation.request_id = Some(id.clone());
+            record_violation_exemplar(violation.kind, id);
+        }
+    }
+
+    // Extract captured console output, leaving the next render an empty one
+    let console = runtime
+        .op_state()
+        .borrow_mut()
+        .try_take::<ConsoleOutput>()
+        .unwrap_or_default();
+
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => {
+            let source_maps = source_maps(runtime);
+            let stack = js_stack(&e);
+            // Point the message at the original source of the innermost frame
+            // (deno_core already source maps the frames of its own JsErrors)
+            let location = stack
+                .as_deref()
+                .filter(|_| e.is::<RenderException>())
+                .and_then(|stack| source_maps.first_original_location(stack));
+            let stack = stack.map(|stack| source_maps.translate_stack(&stack));
+            let kind = failure_kind(runtime, &e, &warnings, &stack);
+            let e = match location {
+                Some(location) => anyhow!("{} (at {})", e, location),
+                None => e,
+            };
+            return Err(SsrError {
+                kind,
+                stack,
+                message: with_violations(e, &sandbox_violations).to_string(),
+                entry: entry_specifier(entry_point),
+                console,
+                request_id,
+            })
+        }
+    };
+
+    let loaded_modules = runtime
+        .op_state()
+        .borrow()
+        .borrow::<ModuleGraph>()
+        .render_modules();
+
+    let status_code = match (output.status_code, &output.redirect) {
+        (None, Some(_)) => Some(302),
+        (status, _) => status,
+    };
+
+    Ok(SsrResult {
+        html: output.html,
+        head: output.head,
+        status_code,
+        headers: output.headers,
+        redirect: output.redirect,
+        console,
+        sandbox_violations,
+        warnings,
+        loaded_modules,
+        request_id,
+        islands,
+        fetch,
+    })
+}
+
+/// Kind of the error `e` of a failed render
+fn failure_kind(
+    runtime: &mut JsRuntime,
+    e: &Error,
+    warnings: &[Warning],
+    stack: &Option<String>,
+) -> SsrErrorKind {
+    if let Some(kind) = error_kind(e) {
+        return kind;
+    }
+    if warnings
+        .iter()
+        .any(|w| w.code == WarningCode::NearHeapLimit)
+    {
+        return SsrErrorKind::HeapLimit;
+    }
+    let exception = e.downcast_ref::<RenderException>();
+    if let Some(exception) = exception.filter(|exception| exception.from_render) {
+        return SsrErrorKind::RenderThrew {
+            message: exception.message.clone(),
+            stack: stack.clone(),
+        };
+    }
+    let message = exception.map_or_else(|| e.to_string(), |exception| exception.message.clone());
+    if message.contains("Globals could not be reset") {
+        return SsrErrorKind::GlobalsNotReset;
+    }
+    let missing = runtime
+        .op_state()
+        .borrow()
+        .borrow::<ModuleGraph>()
+        .missing_module();
+    match (missing, exception) {
+        (Some(specifier), _) => SsrErrorKind::ModuleNotFound { specifier },
+        (None, Some(_)) => SsrErrorKind::ModuleFailed,
+        // Uncaught in async work of the render (e.g. a timer callback)
+        (None, None) => match e.downcast_ref::<deno_core::error::JsError>() {
+            Some(js_error) => SsrErrorKind::RenderThrew {
+                message: js_error.exception_message.clone(),
+                stack: stack.clone(),
+            },
+            None => SsrErrorKind::Other,
+        },
+    }
+}
+
+/// Islands deferred since the last call
+fn take_islands(runtime: &mut JsRuntime) -> Vec<Island> {
+    std::mem::take(&mut runtime.op_state().borrow_mut().borrow_mut::<RenderIslands>().0)
+}
+
+async fn render_html(
+    runtime: &mut JsRuntime,
+    entry_point: &Path,
+    props: serde_json::Value,
+    context: &RenderContext<'_>,
+) -> Result<RenderOutput, Error> {
+    let html_global = start_render(runtime, entry_point, props, false, context)?;
+
+    // Fast path (see bootstrap.js): a synchronous string result is done unless
+    // the render left async work (e.g. a fetch) behind. Polled once without a
+    // waker, so the await below only happens for renders that need it.
+    let max_turns = render_limits(runtime).max_event_loop_turns;
+    let mut turns = 0;
+    let returned_string = {
+        let scope = &mut runtime.handle_scope();
+        v8::Local::new(scope, &html_global).is_string()
+    };
+    let within_limits = max_turns != Some(0)
+        && !context
+            .max_heap_size
+            .is_some_and(|max| heap_used_bytes(runtime) > max);
+    if returned_string && within_limits {
+        let cx = &mut Context::from_waker(deno_core::futures::task::noop_waker_ref());
+        if let Poll::Ready(done) = runtime.poll_event_loop(cx, PollEventLoopOptions::default()) {
+            done?;
+            return settled_output(runtime, &html_global);
+        }
+        turns = 1;
+    }
+
+    // Run event loop to handle any promises/dynamic imports
+    poll_fn(|cx| {
+        turns += 1;
+        if let Some(max) = max_turns.filter(|&max| turns > max) {
+            return Poll::Ready(Err(budget_exceeded(max)));
+        }
+        if let Some(max) = context.max_heap_size {
+            if heap_used_bytes(runtime) > max {
+                return Poll::Ready(Err(heap_cap_exceeded(max)));
+            }
+        }
+        runtime.poll_event_loop(cx, PollEventLoopOptions::default())
+    })
+    .await?;
+
+    settled_output(runtime, &html_global)
+}
+
+/// Second argument of the render function
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RenderContext<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
+    /// Replaced by the `context.hostFeed` async iterator in bootstrap.js
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    host_feed: bool,
+    /// Taken out of the context by bootstrap.js, which exposes it as
+    /// `__SSR_REQUEST__` and `location` instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request: Option<&'a RequestContext>,
+    /// Named export to call instead of the render function (not part of the context)
+    #[serde(skip)]
+    export: Option<&'a str>,
+    /// [`ExecuteOptions::max_heap_size`] (not part of the context)
+    #[serde(skip)]
+    max_heap_size: Option<usize>,
+}
+
+/// Kick off the internal render function, returning its (usually pending) result
+fn start_render(
+    runtime: &mut JsRuntime,
+    entry_point: &Path,
+    props: serde_json::Value,
+    streaming: bool,
+    context: &RenderContext,
+) -> Result<v8::Global<v8::Value>, Error> {
+    let entry_path = entry_path(runtime, entry_point)?;
+
+    let module_specifier = ModuleSpecifier::from_file_path(&entry_path)
+        .map_err(|_| anyhow!("Failed to create module specifier"))?;
+    runtime
+        .op_state()
+        .borrow()
+        .borrow::<ModuleGraph>()
+        .begin_render(module_specifier.as_str());
+
+    // Call the internal render function (defined in bootstrap.js with closure-protected
+    // cache) directly, with props and context converted straight to V8 values
+    let render = render_function(runtime)?;
+    let scope = &mut runtime.handle_scope();
+    let scope = &mut v8::TryCatch::new(scope);
+    let props = deno_core::serde_v8::to_v8(scope, &props)
+        .map_err(|e| kind_error(SsrErrorKind::InvalidProps, format!("Invalid props: {}", e)))?;
+    let context_value = deno_core::serde_v8::to_v8(scope, context)?;
+    let args = [
+        v8::String::new(scope, module_specifier.as_str())
+            .ok_or_else(|| anyhow!("Failed to create module specifier"))?
+            .into(),
+        props,
+        v8::Boolean::new(scope, streaming).into(),
+        context_value,
+        deno_core::serde_v8::to_v8(scope, context.export)?,
+    ];
+    let render = v8::Local::new(scope, render);
+    let receiver = v8::undefined(scope).into();
+    match render.call(scope, receiver, &args) {
+        Some(result) => Ok(v8::Global::new(scope, result)),
+        None => Err(call_exception(scope)),
+    }
+}
+
+/// `__ssr_internal_render__`, looked up once per runtime
+#[derive(Clone)]
+struct RenderFunction(v8::Global<v8::Function>);
+
+fn render_function(runtime: &mut JsRuntime) -> Result<v8::Global<v8::Function>, Error> {
+    if let Some(RenderFunction(render)) = runtime.op_state().borrow().try_borrow() {
+        return Ok(render.clone());
+    }
+    let render = {
+        let scope = &mut runtime.handle_scope();
+        let global = scope.get_current_context().global(scope).into();
+        get_property(scope, global, "__ssr_internal_render__")
+            .and_then(|render| v8::Local::<v8::Function>::try_from(render).ok())
+            .map(|render| v8::Global::new(scope, render))
+            .ok_or_else(|| anyhow!("Render function missing from the snapshot"))?
+    };
+    runtime
+        .op_state()
+        .borrow_mut()
+        .put(RenderFunction(render.clone()));
+    Ok(render)
+}
+
+/// Error for a call that threw (or was terminated by the watchdog)
+fn call_exception(scope: &mut v8::TryCatch<v8::HandleScope>) -> Error {
+    match scope.exception() {
+        // The watchdog's termination stays in effect until the render is torn down
+        Some(exception) if !scope.has_terminated() && !exception.is_null_or_undefined() => {
+            deno_core::error::JsError::from_v8_exception(scope, exception).into()
+        }
+        _ => anyhow!("Uncaught Error: execution terminated"),
+    }
+}
+
+/// Resolve the render result once the event loop has finished
+fn settled_output(
+    runtime: &mut JsRuntime,
+    html_global: &v8::Global<v8::Value>,
+) -> Result<RenderOutput, Error> {
+    let max_html_bytes = render_limits(runtime).max_html_bytes;
+    let scope = &mut runtime.handle_scope();
+    let local = v8::Local::new(scope, html_global);
+
+    if let Ok(promise) = v8::Local::<v8::Promise>::try_from(local) {
+        match promise.state() {
+            v8::PromiseState::Fulfilled => {
+                let result = promise.result(scope);
+                check_output_size(scope, result, max_html_bytes)?;
+                render_output(scope, result)
+            }
+            v8::PromiseState::Rejected => {
+                let exception = promise.result(scope);
+                let from_render = get_property(scope, exception, "message")
+                    .is_some_and(|m| m.to_rust_string_lossy(scope).starts_with("Render error: "));
+                Err(Error::new(RenderException {
+                    message: exception.to_rust_string_lossy(scope),
+                    stack: exception_stack(scope, exception),
+                    from_render,
+                }))
+            }
+            v8::PromiseState::Pending => Err(anyhow!("Render function returned unresolved promise")),
+        }
+    } else {
+        check_output_size(scope, local, max_html_bytes)?;
+        render_output(scope, local)
+    }
+}
+
+/// Fail a render whose `html` and `head` are over `max_bytes` (UTF-8) before
+/// they are copied out of V8
+fn check_output_size<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<'s, v8::Value>,
+    max_bytes: Option<usize>,
+) -> Result<(), Error> {
+    let Some(max_bytes) = max_bytes else {
+        return Ok(());
+    };
+    let bytes = if value.is_string() {
+        utf8_length(scope, Some(value))
+    } else {
+        let html = get_property(scope, value, "html");
+        let head = get_property(scope, value, "head");
+        utf8_length(scope, html) + utf8_length(scope, head)
+    };
+    if bytes > max_bytes {
+        return Err(output_too_large(max_bytes, bytes));
+    }
+    Ok(())
+}
+
+/// UTF-8 length of a string value (0 for anything else)
+fn utf8_length(scope: &mut v8::HandleScope, value: Option<v8::Local<v8::Value>>) -> usize {
+    value
+        .and_then(|value| v8::Local::<v8::String>::try_from(value).ok())
+        .map_or(0, |string| string.utf8_length(scope))
+}
+
+/// `stack` of a thrown error, or of its `cause` (bootstrap.js wraps render errors)
+fn exception_stack<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    exception: v8::Local<'s, v8::Value>,
+) -> Option<String> {
+    let error = get_property(scope, exception, "cause")
+        .filter(|cause| cause.is_native_error())
+        .unwrap_or(exception);
+    let stack = get_property(scope, error, "stack").filter(|stack| stack.is_string())?;
+    Some(stack.to_rust_string_lossy(scope))
+}
+
+fn get_property<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<'s, v8::Value>,
+    key: &str,
+) -> Option<v8::Local<'s, v8::Value>> {
+    let object = v8::Local::<v8::Object>::try_from(value).ok()?;
+    let key = v8::String::new(scope, key)?;
+    object.get(scope, key.into())
+}
+
+/// Convert the value returned by the render function (string or structured object)
+fn render_output(
+    scope: &mut v8::HandleScope,
+    value: v8::Local<v8::Value>,
+) -> Result<RenderOutput, Error> {
+    if value.is_string() {
+        return Ok(RenderOutput {
+            html: value.to_rust_string_lossy(scope),
+            ..Default::default()
+        });
+    }
+    if !value.is_object() {
+        return Err(kind_error(
+            SsrErrorKind::InvalidOutput,
+            "Render function must return a string or an { html, head, statusCode, headers, redirect } object",
+        ));
+    }
+
+    let output: RenderOutput = deno_core::serde_v8::from_v8(scope, value).map_err(|e| {
+        kind_error(
+            SsrErrorKind::InvalidOutput,
+            format!("Render function returned an invalid result: {}", e),
+        )
+    })?;
+    output
+        .validate()
+        .map_err(|e| kind_error(SsrErrorKind::InvalidOutput, e))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Runtime for `config` with `code` as `entry.js` of a temporary chunks
+    /// dir, preloaded so its renders can take the fast path
+    async fn preloaded_entry(
+        config: SandboxConfig,
+        code: &str,
+    ) -> (tempfile::TempDir, PathBuf, SsrRuntime) {
+        let chunks = tempfile::tempdir().unwrap();
+        let entry = chunks.path().join("entry.js");
+        std::fs::write(&entry, code).unwrap();
+        let config = SandboxConfig {
+            chunks_dir: chunks.path().to_str().unwrap().to_string(),
+            ..config
+        };
+        let mut runtime = create_runtime(&config).unwrap();
+        runtime.preload(std::slice::from_ref(&entry)).await.unwrap();
+        (chunks, entry, runtime)
+    }
+
+    #[tokio::test]
+    async fn test_fast_path_keeps_max_html_bytes() {
+        let config = SandboxConfig {
+            max_html_bytes: Some(10),
+            ..Default::default()
+        };
+        let code = "export default (props) => 'x'.repeat(props.n);";
+        let (_chunks, entry, mut runtime) = preloaded_entry(config, code).await;
+        let result = execute_ssr(&mut runtime, &entry, json!({ "n": 10 }), None).await;
+        assert_eq!(result.unwrap().html, "x".repeat(10));
+
+        let result = execute_ssr(&mut runtime, &entry, json!({ "n": 11 }), None).await;
+        let kind = result.unwrap_err().kind;
+        assert_eq!(kind, SsrErrorKind::OutputTooLarge { max_bytes: 10 });
+    }
+
+    #[tokio::test]
+    async fn test_fast_path_keeps_max_event_loop_turns() {
+        let config = SandboxConfig {
+            max_event_loop_turns: Some(0),
+            ..Default::default()
+        };
+        let code = "export default () => '<p>hi</p>';";
+        let (_chunks, entry, mut runtime) = preloaded_entry(config, code).await;
+        let result = execute_ssr(&mut runtime, &entry, json!({}), None).await;
+        let kind = result.unwrap_err().kind;
+        assert_eq!(kind, SsrErrorKind::EventLoopBudget { max_turns: 0 });
+    }
+
+    #[tokio::test]
+    async fn test_fast_path_keeps_heap_cap() {
+        let code = "export default () => '<p>hi</p>';";
+        let (_chunks, entry, mut runtime) = preloaded_entry(SandboxConfig::default(), code).await;
+        // Any heap is above one byte
+        let options = ExecuteOptions {
+            max_heap_size: Some(1),
+            ..Default::default()
+        };
+        let result = execute_ssr_with_options(&mut runtime, &entry, json!({}), &options).await;
+        let kind = result.unwrap_err().kind;
+        assert_eq!(kind, SsrErrorKind::HeapCap { max_bytes: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_fast_path_resets_globals() {
+        let config = SandboxConfig {
+            context_per_request: true,
+            ..Default::default()
+        };
+        let code = "export default (props) => {
+            if (props.leak) {
+                Object.defineProperty(globalThis, 'stuck', { value: 1, configurable: false });
+            }
+            return '<p>hi</p>';
+        };";
+        let (_chunks, entry, mut runtime) = preloaded_entry(config, code).await;
+        let result = execute_ssr(&mut runtime, &entry, json!({ "leak": false }), None).await;
+        assert_eq!(result.unwrap().html, "<p>hi</p>");
+
+        let result = execute_ssr(&mut runtime, &entry, json!({ "leak": true }), None).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.kind, SsrErrorKind::GlobalsNotReset);
+        assert!(error.message.contains("Globals could not be reset: stuck"));
+    }
+
+    #[tokio::test]
+    async fn test_fast_path_reports_sync_throws() {
+        let code = "export default () => { throw new Error('boom'); };";
+        let (_chunks, entry, mut runtime) = preloaded_entry(SandboxConfig::default(), code).await;
+        let result = execute_ssr(&mut runtime, &entry, json!({}), None).await;
+        let kind = result.unwrap_err().kind;
+        assert!(
+            matches!(&kind, SsrErrorKind::RenderThrew { message, .. } if message.contains("boom")),
+            "{:?}",
+            kind
+        );
+    }
+}
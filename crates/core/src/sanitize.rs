@@ -2,10 +2,18 @@
 //!
 //! Removes dangerous keys like `__proto__`, `constructor`, and `prototype`
 //! that could be used to pollute Object.prototype in user render functions.
+//!
+//! [`SanitizePolicy`] additionally redacts sensitive props before they are
+//! written to logs.
 
+use crate::admission::glob_match;
 use anyhow::{anyhow, Result};
+use serde::Serialize;
 use serde_json::{Map, Value};
 
+/// Replacement for redacted prop values
+const REDACTED: &str = "[REDACTED]";
+
 /// Maximum recursion depth for nested objects/arrays
 const MAX_DEPTH: usize = 32;
 
@@ -62,6 +70,73 @@ fn sanitize_recursive(value: Value, depth: usize) -> Result<Value> {
     }
 }
 
+/// How props are checked and what is hidden when they are logged
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SanitizePolicy {
+    /// Props to redact in logs. A pattern with dots is a path from the root
+    /// (`user.email`, arrays are transparent); without dots it matches keys
+    /// at any depth (`*token*`). `*` is a wildcard, matching is case-insensitive.
+    pub redact: Vec<String>,
+}
+
+impl SanitizePolicy {
+    /// Reject dangerous props, see [`sanitize_props`]
+    pub fn sanitize(&self, props: Value) -> Result<Value> {
+        sanitize_props(props)
+    }
+
+    /// Copy of `props` that is safe to log, with redacted values replaced
+    pub fn redact(&self, props: &Value) -> Value {
+        if self.redact.is_empty() {
+            return props.clone();
+        }
+        let patterns: Vec<Vec<String>> = self
+            .redact
+            .iter()
+            .map(|p| p.to_ascii_lowercase().split('.').map(str::to_string).collect())
+            .collect();
+        let mut path = Vec::new();
+        redact_recursive(props, &patterns, &mut path)
+    }
+}
+
+fn redact_recursive(value: &Value, patterns: &[Vec<String>], path: &mut Vec<String>) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut redacted = Map::new();
+            for (key, val) in map {
+                path.push(key.to_ascii_lowercase());
+                let value = if patterns.iter().any(|p| matches_path(p, path)) {
+                    Value::from(REDACTED)
+                } else {
+                    redact_recursive(val, patterns, path)
+                };
+                path.pop();
+                redacted.insert(key.clone(), value);
+            }
+            Value::Object(redacted)
+        }
+        Value::Array(arr) => Value::Array(
+            arr.iter()
+                .map(|v| redact_recursive(v, patterns, path))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Whether a redaction pattern (split on dots) matches the path of a prop
+fn matches_path(pattern: &[String], path: &[String]) -> bool {
+    match pattern {
+        // Key pattern: matches at any depth
+        [key] => path.last().is_some_and(|last| glob_match(key, last)),
+        _ => {
+            pattern.len() == path.len()
+                && pattern.iter().zip(path).all(|(p, key)| glob_match(p, key))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -134,6 +209,30 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_redact() {
+        let policy = SanitizePolicy {
+            redact: vec!["user.email".to_string(), "*token*".to_string()],
+        };
+        let props = json!({
+            "user": {"name": "Alice", "email": "alice@example.com"},
+            "accessToken": "abc",
+            "sessions": [{"refresh_token": "def", "id": 1}],
+            "email": "kept@example.com"
+        });
+
+        assert_eq!(
+            policy.redact(&props),
+            json!({
+                "user": {"name": "Alice", "email": "[REDACTED]"},
+                "accessToken": "[REDACTED]",
+                "sessions": [{"refresh_token": "[REDACTED]", "id": 1}],
+                "email": "kept@example.com"
+            })
+        );
+        assert_eq!(SanitizePolicy::default().redact(&props), props);
+    }
+
     #[test]
     fn test_depth_limit() {
         // Create deeply nested object
@@ -0,0 +1,95 @@
+//! Renders as a [`tower_service::Service`] (`tower` feature).
+//!
+//! [`SsrService`] lets SSR sit in an existing axum/hyper stack, so timeouts,
+//! retries, concurrency limits and load shedding come from ordinary tower
+//! layers. V8 runtimes can't move between threads, so the service wraps an
+//! [`SsrHandle`] to a dedicated render thread with a
+//! [`TenantRouter`](crate::TenantRouter); the service itself is a cheap,
+//! cloneable handle to that thread. Renders run one at a time in the order
+//! they were called, so put a concurrency limit or load shedding layer in
+//! front of it rather than letting the queue grow.
+//!
+//! ```rust,ignore
+//! use ssr_sandbox_core::service::{RenderRequest, SsrService};
+//! use tower::{Service, ServiceExt};
+//!
+//! let mut service = SsrService::new(config)?;
+//! let result = service
+//!     .ready()
+//!     .await?
+//!     .call(RenderRequest::new("./dist/chunks/entry-server.js", props))
+//!     .await?;
+//! ```
+
+use crate::handle::SsrHandle;
+use crate::runtime::{ExecuteOptions, SandboxConfig, SsrError, SsrResult};
+use anyhow::Error;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// A single render handled by [`SsrService`]
+#[derive(Debug, Clone)]
+pub struct RenderRequest {
+    /// Path of the entry module, inside the chunks directory
+    pub entry_point: PathBuf,
+    pub props: serde_json::Value,
+    /// Per-request options; the timeout is capped by
+    /// `SandboxConfig::max_request_timeout_ms` (or `timeout_ms` without it)
+    pub options: ExecuteOptions,
+}
+
+impl RenderRequest {
+    pub fn new(entry_point: impl Into<PathBuf>, props: serde_json::Value) -> Self {
+        Self {
+            entry_point: entry_point.into(),
+            props,
+            options: ExecuteOptions::default(),
+        }
+    }
+}
+
+/// Tower service rendering [`RenderRequest`]s on a dedicated runtime thread
+#[derive(Clone)]
+pub struct SsrService {
+    handle: SsrHandle,
+}
+
+impl SsrService {
+    /// Start the render thread and warm up its runtimes, see [`SsrHandle::new`]
+    pub fn new(config: SandboxConfig) -> Result<Self, Error> {
+        Ok(Self::from(SsrHandle::new(config)?))
+    }
+}
+
+impl From<SsrHandle> for SsrService {
+    fn from(handle: SsrHandle) -> Self {
+        Self { handle }
+    }
+}
+
+impl tower_service::Service<RenderRequest> for SsrService {
+    type Response = SsrResult;
+    type Error = SsrError;
+    type Future = Pin<Box<dyn Future<Output = Result<SsrResult, SsrError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), SsrError>> {
+        // Requests queue on the render thread; limit them with tower layers
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RenderRequest) -> Self::Future {
+        let handle = self.handle.clone();
+        Box::pin(async move {
+            let RenderRequest {
+                entry_point,
+                props,
+                options,
+            } = request;
+            handle
+                .render_with_options(entry_point, props, options)
+                .await
+        })
+    }
+}
@@ -0,0 +1,163 @@
+//! Source maps for chunk modules.
+//!
+//! Bundlers write `<chunk>.js.map` next to each chunk. The loader hands them
+//! to deno_core for module errors, and [`SourceMaps::translate_stack`] maps the
+//! `file://` frames of JS stack traces back to the original TS/JSX sources, so
+//! failed renders point at `src/App.tsx:12:5` instead of column 48213 of a
+//! minified chunk. Maps are only read from inside the chunks directory.
+
+use deno_core::ModuleSpecifier;
+use sourcemap::SourceMap;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Parsed source maps of the chunks of one runtime, loaded on first use
+#[derive(Debug, Clone)]
+pub struct SourceMaps(Rc<SourceMapsState>);
+
+#[derive(Debug)]
+struct SourceMapsState {
+    /// Canonical chunks dir; maps outside of it are never read
+    allowed_dir: PathBuf,
+    /// Parsed map per chunk URL (None if it has no usable map)
+    maps: RefCell<HashMap<String, Option<Rc<SourceMap>>>>,
+}
+
+impl SourceMaps {
+    /// Source maps of chunks in `allowed_dir` (which must be canonical)
+    pub fn new(allowed_dir: impl Into<PathBuf>) -> Self {
+        Self(Rc::new(SourceMapsState {
+            allowed_dir: allowed_dir.into(),
+            maps: RefCell::new(HashMap::new()),
+        }))
+    }
+
+    /// Raw `<chunk>.map` file of a chunk URL, if it exists inside the chunks dir
+    pub fn raw(&self, chunk_url: &str) -> Option<Vec<u8>> {
+        let chunk = ModuleSpecifier::parse(chunk_url).ok()?.to_file_path().ok()?;
+        let mut map_path = chunk.into_os_string();
+        map_path.push(".map");
+        let map_path = Path::new(&map_path).canonicalize().ok()?;
+        let is_map = matches!(
+            map_path.to_str(),
+            Some(path) if path.ends_with(".js.map") || path.ends_with(".mjs.map")
+        );
+        if !is_map || !map_path.starts_with(&self.0.allowed_dir) {
+            return None;
+        }
+        std::fs::read(map_path).ok()
+    }
+
+    /// Original `source:line:column` of a 1-based position in a chunk
+    pub fn original_location(&self, chunk_url: &str, line: u32, column: u32) -> Option<String> {
+        let map = self.map(chunk_url)?;
+        let token = map.lookup_token(line.checked_sub(1)?, column.checked_sub(1)?)?;
+        Some(format!(
+            "{}:{}:{}",
+            token.get_source()?,
+            token.get_src_line() + 1,
+            token.get_src_col() + 1
+        ))
+    }
+
+    /// Rewrite the chunk positions of a V8 stack trace to original source positions.
+    ///
+    /// Frames without a source map are left as they are.
+    pub fn translate_stack(&self, stack: &str) -> String {
+        stack
+            .lines()
+            .map(|line| self.translate_frame(line).unwrap_or_else(|| line.to_string()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Original location of the innermost mapped frame of a stack trace
+    pub fn first_original_location(&self, stack: &str) -> Option<String> {
+        stack.lines().find_map(|line| {
+            let (_, url, line, column) = frame_location(line)?;
+            self.original_location(url, line, column)
+        })
+    }
+
+    fn translate_frame(&self, frame: &str) -> Option<String> {
+        let (start, url, line, column) = frame_location(frame)?;
+        let location = self.original_location(url, line, column)?;
+        let end = start + format!("{}:{}:{}", url, line, column).len();
+        Some(format!("{}{}{}", &frame[..start], location, &frame[end..]))
+    }
+
+    fn map(&self, chunk_url: &str) -> Option<Rc<SourceMap>> {
+        let mut maps = self.0.maps.borrow_mut();
+        maps.entry(chunk_url.to_string())
+            .or_insert_with(|| {
+                let raw = self.raw(chunk_url)?;
+                SourceMap::from_slice(&raw).ok().map(Rc::new)
+            })
+            .clone()
+    }
+}
+
+/// `(offset, url, line, column)` of the `file://` location in a stack frame
+/// like `    at render (file:///app/chunks/entry.js:1:4821)`
+fn frame_location(frame: &str) -> Option<(usize, &str, u32, u32)> {
+    let start = frame.find("file://")?;
+    let location = frame[start..].trim_end_matches(')');
+    let mut parts = location.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    let url = parts.next()?;
+    Some((start, url, line, column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    // `entry.js` line 1: `function render(){throw new Error("boom")}`, from src/App.tsx
+    const MAP: &str = r#"{"version":3,"sources":["../src/App.tsx"],"names":[],"mappings":"AAAA,kBACE"}"#;
+
+    #[test]
+    fn test_translates_mapped_frames() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::write(root.join("entry.js"), "function render(){throw new Error(\"boom\")}").unwrap();
+        fs::write(root.join("entry.js.map"), MAP).unwrap();
+        fs::write(root.join("other.js"), "").unwrap();
+        let maps = SourceMaps::new(&root);
+
+        let entry = ModuleSpecifier::from_file_path(root.join("entry.js")).unwrap();
+        let other = ModuleSpecifier::from_file_path(root.join("other.js")).unwrap();
+        let stack = format!(
+            "Error: boom\n    at render ({}:1:19)\n    at {}:3:7",
+            entry, other
+        );
+        assert_eq!(
+            maps.translate_stack(&stack),
+            format!("Error: boom\n    at render (../src/App.tsx:2:3)\n    at {}:3:7", other)
+        );
+        assert_eq!(
+            maps.first_original_location(&stack).as_deref(),
+            Some("../src/App.tsx:2:3")
+        );
+    }
+
+    #[test]
+    fn test_only_reads_maps_inside_chunks_dir() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("chunks")).unwrap();
+        fs::write(root.join("outside.js.map"), MAP).unwrap();
+        fs::write(root.join("chunks/notes.txt.map"), MAP).unwrap();
+        let maps = SourceMaps::new(root.join("chunks"));
+
+        let outside = ModuleSpecifier::from_file_path(root.join("outside.js")).unwrap();
+        let notes = ModuleSpecifier::from_file_path(root.join("chunks/notes.txt")).unwrap();
+        assert_eq!(maps.raw(outside.as_str()), None);
+        assert_eq!(maps.raw(notes.as_str()), None);
+        assert_eq!(maps.original_location(outside.as_str(), 1, 19), None);
+    }
+}
@@ -0,0 +1,144 @@
+//! Sandbox violation metrics for server mode.
+//!
+//! Violations are counted process-wide by [`ViolationLog`](crate::ops::ViolationLog)
+//! (see [`violation_counts`]). The monitor turns those counters into the
+//! `@stats` response and raises an alert when violations spike, since a burst
+//! of blocked imports or fetches usually means someone is probing the sandbox.
+
+use crate::adaptive_timeout::EntryTimeouts;
+use crate::affinity::thread_placements;
+use crate::metering::tenant_usage;
+use crate::ops::{
+    cache_memory_usage, fragment_cache_stats, violation_counts, violation_exemplars, ViolationKind,
+};
+use crate::pipeline::{output_processor_stats, props_transformer_stats};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Window for the "recent violations" count and alerts
+const ALERT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Tracks violation counters over time and detects spikes
+#[derive(Debug)]
+pub struct ViolationMonitor {
+    /// Alert when more than this many violations happen within [`ALERT_WINDOW`]
+    alert_threshold: Option<u64>,
+    /// Total seen at the last observation
+    last_total: u64,
+    /// New violations per observation within the window (oldest first)
+    recent: VecDeque<(Instant, u64)>,
+    /// Whether the current spike was already reported
+    alerting: bool,
+}
+
+impl ViolationMonitor {
+    pub fn new(alert_threshold: Option<u64>) -> Self {
+        Self {
+            alert_threshold,
+            last_total: 0,
+            recent: VecDeque::new(),
+            alerting: false,
+        }
+    }
+
+    /// Pick up violations counted since the last call, returning an alert
+    /// message when the threshold was just crossed
+    pub fn observe(&mut self) -> Option<String> {
+        let total = violation_counts().values().sum();
+        self.observe_at(total, Instant::now())
+    }
+
+    fn observe_at(&mut self, total: u64, now: Instant) -> Option<String> {
+        let new = total.saturating_sub(self.last_total);
+        self.last_total = total;
+        if new > 0 {
+            self.recent.push_back((now, new));
+        }
+        while let Some(&(at, _)) = self.recent.front() {
+            if now.duration_since(at) <= ALERT_WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+
+        let threshold = self.alert_threshold?;
+        let recent = self.recent_count();
+        if recent <= threshold {
+            self.alerting = false;
+            return None;
+        }
+        if self.alerting {
+            return None;
+        }
+        self.alerting = true;
+        Some(format!(
+            "{} sandbox violations in the last {}s (threshold {})",
+            recent,
+            ALERT_WINDOW.as_secs(),
+            threshold
+        ))
+    }
+
+    /// Violations within the alert window, as of the last observation
+    pub fn recent_count(&self) -> u64 {
+        self.recent.iter().map(|(_, n)| n).sum()
+    }
+
+    /// Counters for the `@stats` admin command, with the adaptive timeouts of
+    /// the server's config
+    pub fn describe(&self, entry_timeouts: &EntryTimeouts) -> serde_json::Value {
+        let counts: BTreeMap<ViolationKind, u64> = violation_counts();
+        serde_json::json!({
+            "violations": counts,
+            "violations_total": counts.values().sum::<u64>(),
+            "violations_last_minute": self.recent_count(),
+            // Request id of the latest violation of each kind, to find its logs
+            "violation_exemplars": violation_exemplars(),
+            "alert_threshold": self.alert_threshold,
+            "fragment_cache": fragment_cache_stats(),
+            "cache_memory": cache_memory_usage(),
+            "props_transformers": props_transformer_stats(),
+            "output_processors": output_processor_stats(),
+            "tenants": tenant_usage(),
+            "threads": thread_placements(),
+            "adaptive_timeouts": entry_timeouts.by_entry(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alerts_once_per_spike() {
+        let mut monitor = ViolationMonitor::new(Some(3));
+        let start = Instant::now();
+
+        assert_eq!(monitor.observe_at(2, start), None);
+        let alert = monitor.observe_at(4, start + Duration::from_secs(1));
+        assert!(alert.unwrap().starts_with("4 sandbox violations"));
+        // Still above the threshold: no repeated alert
+        assert_eq!(monitor.observe_at(6, start + Duration::from_secs(2)), None);
+        assert_eq!(monitor.recent_count(), 6);
+
+        // Window has passed: back to normal, then a new spike alerts again
+        assert_eq!(monitor.observe_at(6, start + Duration::from_secs(120)), None);
+        assert_eq!(monitor.recent_count(), 0);
+        assert!(monitor.observe_at(10, start + Duration::from_secs(121)).is_some());
+    }
+
+    #[test]
+    fn test_no_alerts_without_threshold() {
+        let mut monitor = ViolationMonitor::new(None);
+        assert_eq!(monitor.observe_at(100, Instant::now()), None);
+        assert_eq!(monitor.recent_count(), 100);
+    }
+
+    #[test]
+    fn test_describe_counts_by_kind() {
+        crate::ops::count_violation(ViolationKind::ForbiddenPropKey);
+        let stats = ViolationMonitor::new(None).describe(&EntryTimeouts::default());
+        assert!(stats["violations"]["forbidden_prop_key"].as_u64().unwrap() >= 1);
+    }
+}
@@ -0,0 +1,1096 @@
+//! Server mode wire protocols.
+//!
+//! This crate only has the framing and the types of requests and responses,
+//! so clients can read and write the protocol without depending on the
+//! runtime (`ssr-sandbox-core`) or the CLI.
+//!
+//! `line` (default): a request is an entry line followed by a props line, the
+//! response is `Status:`/`Length:` header lines, a blank line and the body.
+//! Props can't contain newlines and responses must come back in order. Optional
+//! `Csp-Nonce:<nonce>`, `Request-Id:<id>`, `Export:<name>`, `Timeout:<ms>`,
+//! `Max-Heap:<MB>`, `Url:<url>`, `Locale:<locale>` and `Header:<name>: <value>`
+//! (repeatable) lines may come between the entry and props lines; the request
+//! id is echoed back as a `Request-Id:` header.
+//!
+//! Entries starting with `@` are reserved for admin commands. Commands are a
+//! single line, arguments included (`@fragments` and its fragments line is
+//! the only exception), so a server skips commands it doesn't know without
+//! losing its place in the input, and answers them with an error whose
+//! `error_code` is [`UNSUPPORTED_COMMAND`] instead of rendering them.
+//!
+//! Lines are read with a size limit ([`MAX_HEADER_LINE_BYTES`] for entry and
+//! header lines, [`MAX_JSON_LINE_BYTES`] for props, fragments and JSON
+//! requests) and must be UTF-8, and a request has at most [`MAX_HEADER_LINES`]
+//! header lines. A request breaking these is skipped to its end and answered
+//! with an error whose `error_code` is [`ProtocolError::code`], so a
+//! misbehaving host can't make the server buffer unbounded input or lose its
+//! place. [`read_request_async`] also gives up on a request whose next line is
+//! late, so a host that stops halfway through a request can't stall it either.
+//!
+//! `json`: one NDJSON object per line in both directions. Requests carry an
+//! `id` that is echoed in the response, so clients can match responses to
+//! requests without relying on order:
+//!
+//! ```text
+//! {"id":1,"entry":"entry.js","props":{"page":"home"},"timeout_ms":2000,"max_heap_mb":32,"csp_nonce":"r4nd0m","request_id":"req-42"}
+//! {"id":1,"ok":true,"body":"<html>...","statusCode":200,"requestId":"req-42"}
+//! ```
+
+mod types;
+
+pub use types::{Island, RequestContext, Warning, WarningCode};
+
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read, Write};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Framing of requests and responses on stdin/stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    /// Entry line + props line in, `Status:`/`Length:` framed responses out
+    #[default]
+    Line,
+    /// One JSON object per line in both directions, matched by `id`
+    Json,
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line" => Ok(Protocol::Line),
+            "json" => Ok(Protocol::Json),
+            other => Err(anyhow!("Unknown protocol '{}' (expected line or json)", other)),
+        }
+    }
+}
+
+/// A render (or admin command, if `entry` starts with `@`) request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    /// Echoed in the response (json protocol only)
+    #[serde(default)]
+    pub id: serde_json::Value,
+    pub entry: String,
+    /// Unsanitized props (`{}` if missing)
+    #[serde(default = "empty_props")]
+    pub props: serde_json::Value,
+    /// Render timeout for this request, capped by `--max-request-timeout`
+    /// (or the configured timeout without it)
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Soft heap cap for this request in megabytes (`ExecuteOptions::max_heap_size`
+    /// of the render)
+    #[serde(default)]
+    pub max_heap_mb: Option<usize>,
+    /// CSP nonce generated by the host for this response
+    #[serde(default)]
+    pub csp_nonce: Option<String>,
+    /// Correlation id for logs, violations and the response
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// Named export to render as a fragment instead of the default export
+    #[serde(default)]
+    pub export: Option<String>,
+    /// Fragments to render for a `@fragments` request
+    #[serde(default)]
+    pub fragments: Vec<Fragment>,
+    /// URL, headers and locale of the HTTP request being rendered
+    #[serde(default)]
+    pub request: Option<RequestContext>,
+}
+
+/// Admin commands this server understands, see [`Response::unsupported_command`]
+pub const ADMIN_COMMANDS: &[&str] = &[
+    "@config",
+    "@stats",
+    "@health",
+    "@denylist",
+    "@deny",
+    "@allow",
+    "@unload",
+    "@island",
+    "@fragments",
+];
+
+/// `error_code` of the response to an admin command the server doesn't know,
+/// so clients can tell an older server from a failed command
+pub const UNSUPPORTED_COMMAND: &str = "unsupported_command";
+
+/// `error_code` of the responses to requests read but not rendered because
+/// the server got SIGTERM or SIGINT
+pub const SHUTTING_DOWN: &str = "shutting_down";
+
+/// Most fragments a single `@fragments` request may ask for
+pub const MAX_FRAGMENTS: usize = 32;
+
+/// One render of a `@fragments` request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fragment {
+    pub entry: String,
+    #[serde(default = "empty_props")]
+    pub props: serde_json::Value,
+    #[serde(default)]
+    pub export: Option<String>,
+}
+
+fn empty_props() -> serde_json::Value {
+    serde_json::json!({})
+}
+
+/// Longest entry or header line of the line protocol, in bytes
+pub const MAX_HEADER_LINE_BYTES: usize = 8 * 1024;
+
+/// Longest props or fragments line of the line protocol, and request of the
+/// json protocol, in bytes
+pub const MAX_JSON_LINE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Most header lines of a line protocol request (most `request.headers` of
+/// a json protocol request)
+pub const MAX_HEADER_LINES: usize = 64;
+
+/// Why a request couldn't be read, see [`Incoming::Invalid`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// A line is longer than [`MAX_HEADER_LINE_BYTES`] or [`MAX_JSON_LINE_BYTES`]
+    LineTooLong { max_bytes: usize },
+    /// A request has more than [`MAX_HEADER_LINES`] header lines
+    TooManyHeaders { max_lines: usize },
+    /// A line isn't valid UTF-8
+    InvalidUtf8,
+    /// A header line has an invalid value, or needs another one
+    InvalidHeader(String),
+    /// Props, fragments or (json protocol) the request aren't valid JSON, or
+    /// don't match what is expected
+    InvalidJson(String),
+    /// A line of the request didn't arrive in time, see [`read_request_async`]
+    IncompleteRequest { timeout_ms: u64 },
+}
+
+impl ProtocolError {
+    /// `error_code` of the response, see [`Response::invalid_request`]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::LineTooLong { .. } => "line_too_long",
+            Self::TooManyHeaders { .. } => "too_many_headers",
+            Self::InvalidUtf8 => "invalid_utf8",
+            Self::InvalidHeader(_) => "invalid_header",
+            Self::InvalidJson(_) => "invalid_json",
+            Self::IncompleteRequest { .. } => "incomplete_request",
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::LineTooLong { max_bytes } => write!(f, "Line longer than {} bytes", max_bytes),
+            Self::TooManyHeaders { max_lines } => write!(f, "More than {} header lines", max_lines),
+            Self::InvalidUtf8 => write!(f, "Line is not valid UTF-8"),
+            Self::InvalidHeader(message) | Self::InvalidJson(message) => write!(f, "{}", message),
+            Self::IncompleteRequest { timeout_ms } => {
+                write!(f, "Incomplete request: no complete line within {}ms", timeout_ms)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// What was read from the input
+// Read one at a time and handled right away, so the size doesn't add up
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum Incoming {
+    Request(Request),
+    /// A request that couldn't be parsed; answered with an error response
+    Invalid {
+        id: serde_json::Value,
+        error: ProtocolError,
+    },
+}
+
+/// One line of input, see [`read_line`]
+enum Line {
+    End,
+    Text(String),
+    /// Over the limit; holds the bytes up to it, the rest of the line is skipped
+    TooLong(Vec<u8>),
+    InvalidUtf8(Vec<u8>),
+}
+
+impl Line {
+    /// The line's text, or the error it is; an empty line at end of input
+    fn text(self, max_bytes: usize) -> Result<String, ProtocolError> {
+        match self {
+            Line::End => Ok(String::new()),
+            Line::Text(text) => Ok(text),
+            Line::TooLong(_) => Err(ProtocolError::LineTooLong { max_bytes }),
+            Line::InvalidUtf8(_) => Err(ProtocolError::InvalidUtf8),
+        }
+    }
+
+    /// The bytes of the line that were kept
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Line::End => &[],
+            Line::Text(text) => text.as_bytes(),
+            Line::TooLong(bytes) | Line::InvalidUtf8(bytes) => bytes,
+        }
+    }
+
+    fn is_command(&self) -> bool {
+        self.bytes().trim_ascii_start().starts_with(b"@")
+    }
+
+    /// Whether this is a header line of the line protocol, even if it is too
+    /// long or not UTF-8
+    fn is_header(&self) -> bool {
+        let bytes = self.bytes().trim_ascii_start();
+        bytes
+            .iter()
+            .position(|&b| b == b':')
+            .is_some_and(|end| HEADER_NAMES.iter().any(|name| name.as_bytes() == &bytes[..end]))
+    }
+}
+
+/// Turn what [`read_line`] read (line break included, if any) into a
+/// [`Line`], and whether the rest of the line still has to be skipped
+fn to_line(mut buf: Vec<u8>, max_bytes: usize) -> (Line, bool) {
+    if buf.is_empty() {
+        return (Line::End, false);
+    }
+    let complete = buf.last() == Some(&b'\n');
+    if complete {
+        buf.pop();
+        if buf.last() == Some(&b'\r') {
+            buf.pop();
+        }
+    }
+    if buf.len() > max_bytes {
+        buf.truncate(max_bytes);
+        return (Line::TooLong(buf), !complete);
+    }
+    match String::from_utf8(buf) {
+        Ok(text) => (Line::Text(text), false),
+        Err(e) => (Line::InvalidUtf8(e.into_bytes()), false),
+    }
+}
+
+/// Read a line of at most `max_bytes` (line break excluded) without
+/// buffering more of a longer one
+fn read_line(reader: &mut impl BufRead, max_bytes: usize) -> std::io::Result<Line> {
+    let mut buf = Vec::new();
+    // Room for the limit and a CRLF
+    reader.by_ref().take(max_bytes as u64 + 2).read_until(b'\n', &mut buf)?;
+    let (line, skip) = to_line(buf, max_bytes);
+    if skip {
+        skip_line(reader)?;
+    }
+    Ok(line)
+}
+
+/// Consume the input up to and including the next line break
+fn skip_line(reader: &mut impl BufRead) -> std::io::Result<()> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(());
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// [`read_line`] for async readers
+async fn read_line_async(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    max_bytes: usize,
+) -> std::io::Result<Line> {
+    let mut buf = Vec::new();
+    (&mut *reader).take(max_bytes as u64 + 2).read_until(b'\n', &mut buf).await?;
+    let (line, skip) = to_line(buf, max_bytes);
+    if skip {
+        skip_line_async(reader).await?;
+    }
+    Ok(line)
+}
+
+/// [`skip_line`] for async readers
+async fn skip_line_async(reader: &mut (impl AsyncBufRead + Unpin)) -> std::io::Result<()> {
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(());
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// The lines of one request, read up to its end before it is parsed, so an
+/// invalid request doesn't leave lines behind
+struct Frame {
+    protocol: Protocol,
+    lines: Vec<Line>,
+}
+
+impl Frame {
+    fn new(protocol: Protocol) -> Self {
+        Self {
+            protocol,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Limit of the next line of the request, or None once it is complete
+    fn next_line_bytes(&self) -> Option<usize> {
+        let (Some(first), Some(last)) = (self.lines.first(), self.lines.last()) else {
+            return Some(match self.protocol {
+                Protocol::Line => MAX_HEADER_LINE_BYTES,
+                Protocol::Json => MAX_JSON_LINE_BYTES,
+            });
+        };
+        let more = match self.protocol {
+            Protocol::Json => false,
+            // Commands are a single line, `@fragments` and its fragments line aside
+            Protocol::Line if first.is_command() => {
+                self.lines.len() == 1 && first.bytes().trim_ascii() == b"@fragments"
+            }
+            // Header lines up to the props line
+            Protocol::Line => !matches!(first, Line::End) && (self.lines.len() == 1 || last.is_header()),
+        };
+        more.then_some(MAX_JSON_LINE_BYTES)
+    }
+
+    fn push(&mut self, line: Line) {
+        // Header lines past the limit aren't kept, one is enough to report it
+        if self.lines.len() > MAX_HEADER_LINES + 1 && line.is_header() {
+            return;
+        }
+        self.lines.push(line);
+    }
+
+    /// The request the lines make up, or None at end of input
+    fn parse(self) -> Option<Incoming> {
+        let mut lines = self.lines.into_iter();
+        let first = match lines.next() {
+            None | Some(Line::End) => return None,
+            Some(line) => line,
+        };
+        Some(match self.protocol {
+            Protocol::Line => parse_line_request(first, lines),
+            Protocol::Json => parse_json_request(first),
+        })
+    }
+}
+
+/// Read the next request, or None at end of input
+///
+/// Only I/O errors are returned as errors: a request that breaks the limits
+/// above or can't be parsed is consumed up to its end (the props line, for
+/// the line protocol) and returned as [`Incoming::Invalid`], so the next
+/// request is read from its first line.
+pub fn read_request(protocol: Protocol, reader: &mut impl BufRead) -> Result<Option<Incoming>, Error> {
+    let mut frame = Frame::new(protocol);
+    while let Some(max_bytes) = frame.next_line_bytes() {
+        frame.push(read_line(reader, max_bytes)?);
+    }
+    Ok(frame.parse())
+}
+
+/// [`read_request`] for async readers, giving up on a request whose next line
+/// doesn't arrive within `line_timeout`
+///
+/// The timeout starts once the first byte of a request is there, so hosts can
+/// stay idle between requests as long as they like. A request that times out
+/// is returned as [`ProtocolError::IncompleteRequest`] and what was read of
+/// it is dropped; what arrives after that is read as the next request.
+pub async fn read_request_async(
+    protocol: Protocol,
+    reader: &mut (impl AsyncBufRead + Unpin),
+    line_timeout: Option<Duration>,
+) -> Result<Option<Incoming>, Error> {
+    if reader.fill_buf().await?.is_empty() {
+        return Ok(None);
+    }
+    let mut frame = Frame::new(protocol);
+    while let Some(max_bytes) = frame.next_line_bytes() {
+        let line = read_line_async(reader, max_bytes);
+        let line = match line_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, line).await {
+                Ok(line) => line?,
+                Err(_) => {
+                    return Ok(Some(Incoming::Invalid {
+                        id: serde_json::Value::Null,
+                        error: ProtocolError::IncompleteRequest {
+                            timeout_ms: timeout.as_millis() as u64,
+                        },
+                    }))
+                }
+            },
+            None => line.await?,
+        };
+        frame.push(line);
+    }
+    Ok(frame.parse())
+}
+
+/// Parse a line protocol request from its entry line and the lines after it
+fn parse_line_request(entry: Line, mut lines: impl Iterator<Item = Line>) -> Incoming {
+    let invalid = |error| Incoming::Invalid {
+        id: serde_json::Value::Null,
+        error,
+    };
+    let entry = match entry.text(MAX_HEADER_LINE_BYTES) {
+        Ok(entry) => entry.trim().to_string(),
+        Err(error) => return invalid(error),
+    };
+    // The fragments of a `@fragments` request are on the next line
+    let mut fragments = vec![];
+    if entry == "@fragments" {
+        let fragments_line = match lines.next().unwrap_or(Line::End).text(MAX_JSON_LINE_BYTES) {
+            Ok(line) => line,
+            Err(error) => return invalid(error),
+        };
+        fragments = match serde_json::from_str(fragments_line.trim()) {
+            Ok(fragments) => fragments,
+            Err(e) => return invalid(ProtocolError::InvalidJson(format!("Invalid fragments JSON: {}", e))),
+        };
+    }
+    // Admin commands are a single line
+    if entry.starts_with('@') {
+        return Incoming::Request(Request {
+            id: serde_json::Value::Null,
+            entry,
+            props: empty_props(),
+            timeout_ms: None,
+            max_heap_mb: None,
+            csp_nonce: None,
+            request_id: None,
+            export: None,
+            fragments,
+            request: None,
+        });
+    }
+
+    // Header lines aren't valid JSON, so they can't be confused with a props line
+    let mut csp_nonce = None;
+    let mut request_id = None;
+    let mut export = None;
+    let mut timeout_ms = None;
+    let mut max_heap_mb = None;
+    let mut url = None;
+    let mut locale = None;
+    let mut headers = BTreeMap::new();
+    let mut header_lines = 0;
+    let mut props_line = String::new();
+    for line in lines {
+        let is_header = line.is_header();
+        let line = match line.text(MAX_JSON_LINE_BYTES) {
+            Ok(line) => line,
+            Err(error) => return invalid(error),
+        };
+        let line = line.trim();
+        if !is_header {
+            props_line = line.to_string();
+            break;
+        }
+        header_lines += 1;
+        if header_lines > MAX_HEADER_LINES {
+            return invalid(ProtocolError::TooManyHeaders {
+                max_lines: MAX_HEADER_LINES,
+            });
+        }
+        if line.len() > MAX_HEADER_LINE_BYTES {
+            return invalid(ProtocolError::LineTooLong {
+                max_bytes: MAX_HEADER_LINE_BYTES,
+            });
+        }
+        let invalid_header = |name: &str, value: &str| {
+            let message = format!("Invalid {} line '{}'", name, value.trim());
+            invalid(ProtocolError::InvalidHeader(message))
+        };
+        if let Some(nonce) = line.strip_prefix("Csp-Nonce:") {
+            csp_nonce = Some(nonce.trim().to_string());
+        } else if let Some(id) = line.strip_prefix("Request-Id:") {
+            request_id = Some(id.trim().to_string());
+        } else if let Some(name) = line.strip_prefix("Export:") {
+            export = Some(name.trim().to_string());
+        } else if let Some(ms) = line.strip_prefix("Timeout:") {
+            match ms.trim().parse() {
+                Ok(ms) => timeout_ms = Some(ms),
+                Err(_) => return invalid_header("Timeout", ms),
+            }
+        } else if let Some(mb) = line.strip_prefix("Max-Heap:") {
+            match mb.trim().parse() {
+                Ok(mb) => max_heap_mb = Some(mb),
+                Err(_) => return invalid_header("Max-Heap", mb),
+            }
+        } else if let Some(value) = line.strip_prefix("Url:") {
+            url = Some(value.trim().to_string());
+        } else if let Some(value) = line.strip_prefix("Locale:") {
+            locale = Some(value.trim().to_string());
+        } else if let Some(header) = line.strip_prefix("Header:") {
+            match header.split_once(':') {
+                Some((name, value)) => {
+                    headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+                }
+                None => return invalid_header("Header", header),
+            }
+        }
+    }
+    if url.is_none() && (locale.is_some() || !headers.is_empty()) {
+        return invalid(ProtocolError::InvalidHeader(
+            "Header and Locale lines need a Url line".to_string(),
+        ));
+    }
+    let props = if props_line.is_empty() {
+        empty_props()
+    } else {
+        match serde_json::from_str(&props_line) {
+            Ok(props) => props,
+            Err(e) => return invalid(ProtocolError::InvalidJson(format!("Invalid props JSON: {}", e))),
+        }
+    };
+    Incoming::Request(Request {
+        id: serde_json::Value::Null,
+        entry,
+        props,
+        timeout_ms,
+        max_heap_mb,
+        csp_nonce,
+        request_id,
+        export,
+        fragments: vec![],
+        request: url.map(|url| RequestContext {
+            url,
+            headers,
+            locale,
+        }),
+    })
+}
+
+/// Parse a json protocol request line
+fn parse_json_request(line: Line) -> Incoming {
+    let invalid = |id, error| Incoming::Invalid { id, error };
+    let line = match line.text(MAX_JSON_LINE_BYTES) {
+        Ok(line) => line,
+        Err(error) => return invalid(serde_json::Value::Null, error),
+    };
+    let value: serde_json::Value = match serde_json::from_str(line.trim()) {
+        Ok(value) => value,
+        Err(e) => {
+            let error = ProtocolError::InvalidJson(format!("Invalid request JSON: {}", e));
+            return invalid(serde_json::Value::Null, error);
+        }
+    };
+    let id = value.get("id").cloned().unwrap_or_default();
+    match serde_json::from_value::<Request>(value) {
+        Ok(Request {
+            request: Some(context), ..
+        }) if context.headers.len() > MAX_HEADER_LINES => {
+            let error = ProtocolError::TooManyHeaders {
+                max_lines: MAX_HEADER_LINES,
+            };
+            invalid(id, error)
+        }
+        Ok(request) => Incoming::Request(request),
+        Err(e) => invalid(id, ProtocolError::InvalidJson(format!("Invalid request: {}", e))),
+    }
+}
+
+/// Names of the header lines of the line protocol (`<name>:<value>`)
+const HEADER_NAMES: &[&str] = &[
+    "Csp-Nonce",
+    "Request-Id",
+    "Export",
+    "Timeout",
+    "Max-Heap",
+    "Url",
+    "Locale",
+    "Header",
+];
+
+/// Response to one request
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Response {
+    pub ok: bool,
+    /// HTML for renders, output for admin commands, message for errors
+    pub body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub head: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_code: Option<u16>,
+    /// Kind of error, for errors clients handle differently (e.g.
+    /// [`UNSUPPORTED_COMMAND`])
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
+    /// Correlation id of the request, for renders and render errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Islands deferred by the render (`SsrResult::islands`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub islands: Vec<Island>,
+    /// Responses of the fragments of a `@fragments` request, in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<Response>,
+}
+
+impl Response {
+    pub fn ok(body: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            body: body.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            body: message.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Error for an `@` command that isn't in [`ADMIN_COMMANDS`]
+    pub fn unsupported_command(command: &str) -> Self {
+        let name = command.split_whitespace().next().unwrap_or(command);
+        Self {
+            error_code: Some(UNSUPPORTED_COMMAND.to_string()),
+            ..Self::error(format!(
+                "Unsupported command '{}' (supported: {})",
+                name,
+                ADMIN_COMMANDS.join(", ")
+            ))
+        }
+    }
+
+    /// Error for a request that couldn't be read, with the error's code
+    pub fn invalid_request(error: &ProtocolError) -> Self {
+        Self {
+            error_code: Some(error.code().to_string()),
+            ..Self::error(error.to_string())
+        }
+    }
+
+    /// Error for a request that arrived after shutdown began
+    pub fn shutting_down() -> Self {
+        Self {
+            error_code: Some(SHUTTING_DOWN.to_string()),
+            ..Self::error("Server is shutting down")
+        }
+    }
+
+    /// Line protocol lines for the structured fields and warnings of a render result (only those that are set)
+    pub fn header_lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        if let Some(id) = &self.request_id {
+            lines.push(format!("Request-Id:{}", id));
+        }
+        if !self.parts.is_empty() {
+            lines.push(format!("Parts:{}", self.parts.len()));
+        }
+        if let Some(code) = &self.error_code {
+            lines.push(format!("Error-Code:{}", code));
+        }
+        if let Some(status) = self.status_code {
+            lines.push(format!("Http-Status:{}", status));
+        }
+        if let Some(location) = &self.redirect {
+            lines.push(format!("Redirect:{}", location));
+        }
+        for (name, value) in &self.headers {
+            lines.push(format!("Header:{}: {}", name, value));
+        }
+        if let Some(head) = &self.head {
+            // JSON string encoding keeps multi-line markup on one line
+            lines.push(format!("Head:{}", serde_json::Value::from(head.as_str())));
+        }
+        for warning in &self.warnings {
+            lines.push(format!("Warning:{} {}", warning.code, warning.message.replace('\n', " ")));
+        }
+        for island in &self.islands {
+            let island = serde_json::to_string(island).unwrap_or_default();
+            lines.push(format!("Island:{}", island));
+        }
+        lines
+    }
+}
+
+/// Write a response in the given protocol (`id` is only used by the json protocol)
+pub fn write_response(
+    protocol: Protocol,
+    out: &mut impl Write,
+    id: &serde_json::Value,
+    response: &Response,
+) -> Result<(), Error> {
+    match protocol {
+        Protocol::Line => {
+            // The body of a multi-part response is its framed parts
+            let mut body = response.body.as_bytes().to_vec();
+            for part in &response.parts {
+                write_response(protocol, &mut body, id, part)?;
+            }
+            let status = if response.ok { "Ok" } else { "Error" };
+            writeln!(out, "Status:{}", status)?;
+            writeln!(out, "Length:{}", body.len())?;
+            for line in response.header_lines() {
+                writeln!(out, "{}", line)?;
+            }
+            writeln!(out)?; // Empty line separator
+            out.write_all(&body)?;
+        }
+        Protocol::Json => {
+            let mut value = serde_json::to_value(response)?;
+            value["id"] = id.clone();
+            writeln!(out, "{}", value)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn read_all(protocol: Protocol, input: impl AsRef<[u8]>) -> Vec<Incoming> {
+        let mut reader = input.as_ref();
+        std::iter::from_fn(|| read_request(protocol, &mut reader).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_read_line_requests() {
+        let requests = read_all(Protocol::Line, "entry.js\n{\"page\":\"home\"}\n@config\nother.js\n\n");
+        assert_eq!(
+            requests,
+            vec![
+                Incoming::Request(Request {
+                    id: json!(null),
+                    entry: "entry.js".to_string(),
+                    props: json!({"page": "home"}),
+                    timeout_ms: None,
+                    max_heap_mb: None,
+                    csp_nonce: None,
+                    request_id: None,
+                    export: None,
+                    fragments: vec![],
+                    request: None,
+                }),
+                Incoming::Request(Request {
+                    id: json!(null),
+                    entry: "@config".to_string(),
+                    props: json!({}),
+                    timeout_ms: None,
+                    max_heap_mb: None,
+                    csp_nonce: None,
+                    request_id: None,
+                    export: None,
+                    fragments: vec![],
+                    request: None,
+                }),
+                Incoming::Request(Request {
+                    id: json!(null),
+                    entry: "other.js".to_string(),
+                    props: json!({}),
+                    timeout_ms: None,
+                    max_heap_mb: None,
+                    csp_nonce: None,
+                    request_id: None,
+                    export: None,
+                    fragments: vec![],
+                    request: None,
+                }),
+            ]
+        );
+
+        let with_nonce = read_all(Protocol::Line, "entry.js\nCsp-Nonce:abc123\n{}\n");
+        assert!(matches!(&with_nonce[..], [Incoming::Request(r)] if r.csp_nonce.as_deref() == Some("abc123")));
+
+        let with_headers = read_all(Protocol::Line, "entry.js\nRequest-Id:req-1\nCsp-Nonce:n\n{}\n");
+        assert!(matches!(&with_headers[..], [Incoming::Request(r)]
+            if r.request_id.as_deref() == Some("req-1") && r.csp_nonce.as_deref() == Some("n")));
+
+        let limits = read_all(Protocol::Line, "entry.js\nTimeout:10000\nMax-Heap:32\n{}\n");
+        assert!(matches!(&limits[..], [Incoming::Request(r)]
+            if r.timeout_ms == Some(10_000) && r.max_heap_mb == Some(32)));
+        let bad_limit = read_all(Protocol::Line, "entry.js\nTimeout:soon\n{}\n@stats\n");
+        assert!(matches!(&bad_limit[0], Incoming::Invalid { error, .. }
+            if error.to_string() == "Invalid Timeout line 'soon'"));
+        assert!(matches!(&bad_limit[1], Incoming::Request(r) if r.entry == "@stats"));
+
+        let with_request = read_all(
+            Protocol::Line,
+            "entry.js\nUrl:https://example.com/a?b=1\nHeader:Accept-Language: de, en\nLocale:de\n{}\n",
+        );
+        assert!(matches!(&with_request[..], [Incoming::Request(r)] if r.request == Some(RequestContext {
+            url: "https://example.com/a?b=1".to_string(),
+            headers: BTreeMap::from([("accept-language".to_string(), "de, en".to_string())]),
+            locale: Some("de".to_string()),
+        })));
+        let headers_only = read_all(Protocol::Line, "entry.js\nHeader:Cookie: a=1\n{}\n");
+        assert!(matches!(&headers_only[0], Incoming::Invalid { error, .. }
+            if error.to_string() == "Header and Locale lines need a Url line"));
+
+        let fragment = read_all(Protocol::Line, "entry.js\nExport:Footer\n{}\n");
+        assert!(matches!(&fragment[..], [Incoming::Request(r)] if r.export.as_deref() == Some("Footer")));
+
+        let fragments = read_all(
+            Protocol::Line,
+            "@fragments\n[{\"entry\":\"header.js\"},{\"entry\":\"parts.js\",\"export\":\"Footer\"}]\n@stats\n",
+        );
+        assert_eq!(fragments.len(), 2);
+        assert!(matches!(&fragments[0], Incoming::Request(r) if r.entry == "@fragments" && r.fragments == vec![
+            Fragment { entry: "header.js".to_string(), props: json!({}), export: None },
+            Fragment { entry: "parts.js".to_string(), props: json!({}), export: Some("Footer".to_string()) },
+        ]));
+
+        let invalid = read_all(Protocol::Line, "entry.js\n{oops\n");
+        assert!(matches!(&invalid[0], Incoming::Invalid { error, .. } if error.to_string().starts_with("Invalid props JSON")));
+    }
+
+    #[tokio::test]
+    async fn test_read_request_line_timeout() {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut host, input) = tokio::io::duplex(1024);
+        let mut reader = tokio::io::BufReader::new(input);
+        let timeout = Some(Duration::from_millis(50));
+
+        // Idle between requests doesn't time out
+        let idle = read_request_async(Protocol::Line, &mut reader, timeout);
+        assert!(tokio::time::timeout(Duration::from_millis(200), idle).await.is_err());
+
+        host.write_all(b"entry.js\nUrl:/a\n").await.unwrap();
+        let incoming = read_request_async(Protocol::Line, &mut reader, timeout).await.unwrap();
+        assert_eq!(
+            incoming,
+            Some(Incoming::Invalid {
+                id: json!(null),
+                error: ProtocolError::IncompleteRequest { timeout_ms: 50 },
+            })
+        );
+
+        host.write_all(b"@stats\n").await.unwrap();
+        drop(host);
+        let incoming = read_request_async(Protocol::Line, &mut reader, timeout).await.unwrap();
+        assert!(matches!(incoming, Some(Incoming::Request(r)) if r.entry == "@stats"));
+        assert_eq!(read_request_async(Protocol::Line, &mut reader, timeout).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_request_limits() {
+        let long_entry = format!("{}.js
+{{}}
+@stats
+", "a".repeat(MAX_HEADER_LINE_BYTES));
+        let requests = read_all(Protocol::Line, long_entry);
+        assert!(matches!(&requests[0], Incoming::Invalid { error, .. }
+            if *error == ProtocolError::LineTooLong { max_bytes: MAX_HEADER_LINE_BYTES }));
+        assert!(matches!(&requests[1], Incoming::Request(r) if r.entry == "@stats"));
+
+        // Commands have no props line to skip
+        let long_command = format!("@deny {}
+@stats
+", "a".repeat(MAX_HEADER_LINE_BYTES));
+        let requests = read_all(Protocol::Line, long_command);
+        assert_eq!(requests.len(), 2);
+        assert!(matches!(&requests[1], Incoming::Request(r) if r.entry == "@stats"));
+
+        let long_header = format!("entry.js
+Url:/{}
+{{}}
+@stats
+", "a".repeat(MAX_HEADER_LINE_BYTES));
+        let requests = read_all(Protocol::Line, long_header);
+        assert!(matches!(&requests[0], Incoming::Invalid { error, .. } if error.code() == "line_too_long"));
+        assert!(matches!(&requests[1], Incoming::Request(r) if r.entry == "@stats"));
+
+        let many_headers = format!("entry.js
+{}{{}}
+@stats
+", "Request-Id:x\n".repeat(MAX_HEADER_LINES + 1));
+        let requests = read_all(Protocol::Line, many_headers);
+        assert!(matches!(&requests[0], Incoming::Invalid { error, .. }
+            if *error == ProtocolError::TooManyHeaders { max_lines: MAX_HEADER_LINES }));
+        assert!(matches!(&requests[1], Incoming::Request(r) if r.entry == "@stats"));
+
+        let requests = read_all(Protocol::Line, b"entry.js\n{\"a\":\"\xff\"}\n@stats\n");
+        assert!(matches!(&requests[0], Incoming::Invalid { error, .. } if *error == ProtocolError::InvalidUtf8));
+        assert!(matches!(&requests[1], Incoming::Request(r) if r.entry == "@stats"));
+
+        let requests = read_all(Protocol::Json, b"{\"id\":1,\"entry\":\"\xff\"}\n{\"id\":2,\"entry\":\"@stats\"}\n");
+        assert!(matches!(&requests[0], Incoming::Invalid { error, .. } if error.code() == "invalid_utf8"));
+        assert!(matches!(&requests[1], Incoming::Request(r) if r.entry == "@stats"));
+
+        let response = Response::invalid_request(&ProtocolError::InvalidUtf8);
+        assert_eq!(response.error_code.as_deref(), Some("invalid_utf8"));
+        assert_eq!(response.body, "Line is not valid UTF-8");
+    }
+
+    #[test]
+    fn test_read_json_requests() {
+        let input = concat!(
+            r#"{"id":"a","entry":"entry.js","props":{"text":"line1\nline2"},"timeout_ms":500,"max_heap_mb":16}"#,
+            "\n",
+            r#"{"id":2,"entry":"@stats"}"#,
+            "\n",
+            r#"{"id":3,"props":{}}"#,
+            "\n",
+            "not json\n",
+        );
+        let requests = read_all(Protocol::Json, input);
+        assert_eq!(requests.len(), 4);
+        assert_eq!(
+            requests[0],
+            Incoming::Request(Request {
+                id: json!("a"),
+                entry: "entry.js".to_string(),
+                props: json!({"text": "line1\nline2"}),
+                timeout_ms: Some(500),
+                max_heap_mb: Some(16),
+                csp_nonce: None,
+                request_id: None,
+                export: None,
+                fragments: vec![],
+                request: None,
+            })
+        );
+        assert!(matches!(&requests[1], Incoming::Request(r) if r.entry == "@stats" && r.props == json!({})));
+        assert!(matches!(&requests[2], Incoming::Invalid { id, error } if *id == json!(3) && error.to_string().contains("entry")));
+        assert!(matches!(&requests[3], Incoming::Invalid { id, .. } if id.is_null()));
+    }
+
+    #[test]
+    fn test_write_line_response() {
+        let mut out = Vec::new();
+        let response = Response {
+            request_id: Some("req-1".to_string()),
+            status_code: Some(302),
+            redirect: Some("/login".to_string()),
+            warnings: vec![Warning {
+                code: WarningCode::SlowFetch,
+                message: "fetch(https://api.example.com/) took 1500ms".to_string(),
+            }],
+            islands: vec![Island {
+                id: "0123456789abcdef".to_string(),
+                entry: "islands/cart.js".to_string(),
+                props: json!({"user": 1}),
+            }],
+            ..Response::ok("")
+        };
+        write_response(Protocol::Line, &mut out, &json!(null), &response).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "Status:Ok\nLength:0\nRequest-Id:req-1\nHttp-Status:302\nRedirect:/login\n",
+                "Warning:slow_fetch fetch(https://api.example.com/) took 1500ms\n",
+                "Island:{\"id\":\"0123456789abcdef\",\"entry\":\"islands/cart.js\",\"props\":{\"user\":1}}\n\n"
+            )
+        );
+
+        let mut out = Vec::new();
+        write_response(Protocol::Line, &mut out, &json!(null), &Response::error("boom")).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Status:Error\nLength:4\n\nboom");
+
+        let mut out = Vec::new();
+        let response = Response::unsupported_command("@reload entry.js");
+        write_response(Protocol::Line, &mut out, &json!(null), &response).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("Status:Error\n"));
+        assert!(text.contains("\nError-Code:unsupported_command\n\nUnsupported command '@reload'"));
+
+        let mut out = Vec::new();
+        let response = Response {
+            parts: vec![Response::ok("<header>"), Response::error("boom")],
+            ..Response::ok("")
+        };
+        write_response(Protocol::Line, &mut out, &json!(null), &response).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Status:Ok\nLength:55\nParts:2\n\nStatus:Ok\nLength:8\n\n<header>Status:Error\nLength:4\n\nboom"
+        );
+    }
+
+    #[test]
+    fn test_write_json_response() {
+        let mut out = Vec::new();
+        let response = Response {
+            head: Some("<title>x</title>".to_string()),
+            ..Response::ok("<p>\n</p>")
+        };
+        write_response(Protocol::Json, &mut out, &json!(7), &response).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            json!({"id": 7, "ok": true, "body": "<p>\n</p>", "head": "<title>x</title>"})
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        // Supervisor mode forwards requests to renderers and reads their responses as JSON
+        let request = Request {
+            id: json!(3),
+            entry: "entry.js".to_string(),
+            props: json!({"page": "home"}),
+            timeout_ms: Some(2000),
+            max_heap_mb: None,
+            csp_nonce: Some("r4nd0m".to_string()),
+            request_id: Some("req-3".to_string()),
+            export: None,
+            fragments: vec![],
+            request: None,
+        };
+        let line = format!("{}\n", serde_json::to_string(&request).unwrap());
+        assert_eq!(
+            read_all(Protocol::Json, &line),
+            vec![Incoming::Request(request)]
+        );
+
+        let response = Response {
+            status_code: Some(404),
+            warnings: vec![Warning {
+                code: WarningCode::SlowFetch,
+                message: "slow".to_string(),
+            }],
+            request_id: Some("req-3".to_string()),
+            parts: vec![Response::error("boom")],
+            ..Response::ok("<p>Not found</p>")
+        };
+        let mut out = Vec::new();
+        write_response(Protocol::Json, &mut out, &json!(3), &response).unwrap();
+        assert_eq!(serde_json::from_slice::<Response>(&out).unwrap(), response);
+    }
+}
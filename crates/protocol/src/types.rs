@@ -0,0 +1,111 @@
+//! Data types that are part of responses and requests, and of the render
+//! results of `ssr-sandbox-core`, which re-exports them.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What a runtime-originated warning is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningCode {
+    /// The V8 heap is close to `max_heap_size`
+    NearHeapLimit,
+    /// The V8 heap was above `heap_watermark` when the render started, so
+    /// caches were cleared and garbage collected
+    HeapWatermark,
+    /// Importing the entry module (top-level await) is taking long
+    SlowModule,
+    /// A fetch took longer than a second (`SLOW_FETCH_THRESHOLD`)
+    SlowFetch,
+    /// The bundle called a deprecated API
+    DeprecatedApi,
+}
+
+impl std::fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let code = match self {
+            WarningCode::NearHeapLimit => "near_heap_limit",
+            WarningCode::HeapWatermark => "heap_watermark",
+            WarningCode::SlowModule => "slow_module",
+            WarningCode::SlowFetch => "slow_fetch",
+            WarningCode::DeprecatedApi => "deprecated_api",
+        };
+        f.write_str(code)
+    }
+}
+
+/// A problem noticed by the runtime that didn't fail the render
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub message: String,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+/// Part of a page deferred by `context.island(entry, props)`: the render
+/// outputs an `<ssr-island data-id="...">` placeholder instead, and rendering
+/// `entry` with `props` later produces the markup that replaces it.
+///
+/// This is the serialized continuation of the island. The id is a hash of
+/// entry and props, so it stays the same across renders of the same page and
+/// cached shells keep pointing at the right island.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Island {
+    pub id: String,
+    /// Entry module of the island, relative to the chunks directory
+    pub entry: String,
+    pub props: serde_json::Value,
+}
+
+impl Island {
+    pub fn new(entry: impl Into<String>, props: serde_json::Value) -> Self {
+        use sha2::{Digest, Sha256};
+
+        let entry = entry.into();
+        let mut hasher = Sha256::new();
+        hasher.update(entry.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(sorted_keys(&props).to_string().as_bytes());
+        let digest = hasher.finalize();
+        let id = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        Self { id, entry, props }
+    }
+}
+
+/// Copy of a JSON value with object keys in sorted order, so equal props hash
+/// equally (deno_core turns on serde_json's `preserve_order`)
+fn sorted_keys(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.clone(), sorted_keys(value)))
+                .collect()
+        }
+        serde_json::Value::Array(items) => items.iter().map(sorted_keys).collect(),
+        other => other.clone(),
+    }
+}
+
+/// The HTTP request a render is for (`ExecuteOptions::request`)
+///
+/// Renders see it as a frozen `globalThis.__SSR_REQUEST__` (`{url, headers,
+/// locale}`, header names lowercased) and as a read-only `location`. Both are
+/// undefined in renders without one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RequestContext {
+    /// Absolute URL, e.g. `https://example.com/products?page=2`
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Preferred locale, e.g. `en-US` (`null` in JS if not set)
+    #[serde(default)]
+    pub locale: Option<String>,
+}
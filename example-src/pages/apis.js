@@ -8,6 +8,28 @@ export async function render(props) {
     // Encoding
     ["TextEncoder", () => typeof TextEncoder !== "undefined"],
     ["TextDecoder", () => typeof TextDecoder !== "undefined"],
+    ["TextDecoder labels", () => {
+      if (new TextDecoder("UTF8").encoding !== "utf-8") return false;
+      try {
+        new TextDecoder("not-an-encoding");
+        return false;
+      } catch (e) {
+        return e instanceof RangeError;
+      }
+    }],
+    ["TextDecoder fatal", () => {
+      try {
+        new TextDecoder("utf-8", { fatal: true }).decode(new Uint8Array([0xff]));
+        return false;
+      } catch (e) {
+        return e instanceof TypeError;
+      }
+    }],
+    ["TextDecoder stream", () => {
+      const bytes = new TextEncoder().encode("é");
+      const decoder = new TextDecoder();
+      return decoder.decode(bytes.subarray(0, 1), { stream: true }) + decoder.decode(bytes.subarray(1)) === "é";
+    }],
     ["atob/btoa", () => typeof atob !== "undefined" && typeof btoa !== "undefined"],
 
     // URL
@@ -29,6 +51,24 @@ export async function render(props) {
     ["crypto.subtle", () => typeof crypto?.subtle !== "undefined"],
     ["crypto.randomUUID", () => typeof crypto?.randomUUID !== "undefined"],
     ["crypto.getRandomValues", () => typeof crypto?.getRandomValues !== "undefined"],
+    // RFC 4231 test case 2, e.g. for signed preview URLs and cookies
+    ...Object.entries({
+      "SHA-256": "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+      "SHA-384": "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649",
+      "SHA-512": "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+    }).map(([hash, expected]) => [`crypto.subtle HMAC ${hash} sign/verify`, async () => {
+      const encoder = new TextEncoder();
+      const key = await crypto.subtle.importKey(
+        "raw", encoder.encode("Jefe"), { name: "HMAC", hash }, false, ["sign", "verify"],
+      );
+      const data = encoder.encode("what do ya want for nothing?");
+      const signature = new Uint8Array(await crypto.subtle.sign("HMAC", key, data));
+      const hex = Array.from(signature, (b) => b.toString(16).padStart(2, "0")).join("");
+      const tampered = encoder.encode("what do ya want for nothing!");
+      return hex === expected
+        && await crypto.subtle.verify("HMAC", key, signature, data)
+        && !await crypto.subtle.verify("HMAC", key, signature, tampered);
+    }]),
 
     // Timers
     ["setTimeout", () => typeof setTimeout !== "undefined"],
@@ -40,6 +80,7 @@ export async function render(props) {
     ["requestIdleCallback", () => typeof requestIdleCallback !== "undefined"],
     ["cancelIdleCallback", () => typeof cancelIdleCallback !== "undefined"],
     ["queueMicrotask", () => typeof queueMicrotask !== "undefined"],
+    ["reportError", () => typeof reportError !== "undefined"],
 
     // Streams
     ["ReadableStream", () => typeof ReadableStream !== "undefined"],
@@ -75,6 +116,7 @@ export async function render(props) {
 
     // Other Web APIs
     ["fetch", () => typeof fetch !== "undefined"],
+    ["WebSocket (stub)", () => typeof WebSocket !== "undefined" && WebSocket.OPEN === 1],
     ["Request", () => typeof Request !== "undefined"],
     ["Response", () => typeof Response !== "undefined"],
     ["Headers", () => typeof Headers !== "undefined"],
@@ -82,10 +124,20 @@ export async function render(props) {
     ["AbortSignal", () => typeof AbortSignal !== "undefined"],
     ["Blob", () => typeof Blob !== "undefined"],
     ["File", () => typeof File !== "undefined"],
+    ["FileReader", () => typeof FileReader !== "undefined"],
+    ["URL.createObjectURL", () => typeof URL.createObjectURL !== "undefined"],
     ["FormData", () => typeof FormData !== "undefined"],
     ["Event", () => typeof Event !== "undefined"],
     ["EventTarget", () => typeof EventTarget !== "undefined"],
     ["CustomEvent", () => typeof CustomEvent !== "undefined"],
+    ["globalThis.addEventListener", () => typeof globalThis.addEventListener !== "undefined"],
+    ["EventTarget dispatch", () => {
+      const target = new EventTarget();
+      let detail;
+      target.addEventListener("ping", (e) => { detail = e.detail; });
+      target.dispatchEvent(new CustomEvent("ping", { detail: 42 }));
+      return detail === 42;
+    }],
 
     // Console
     ["console", () => typeof console !== "undefined"],
@@ -107,7 +159,7 @@ export async function render(props) {
 
   for (const [name, check] of checks) {
     try {
-      const available = check();
+      const available = await check();
       apis.push({ name, available });
     } catch (e) {
       apis.push({ name, available: false, error: e.message });
@@ -0,0 +1,464 @@
+//! An axum host rendering with the library's runtime pool.
+//!
+//! V8 runtimes can't move between threads, so a dedicated render thread owns
+//! a [`RuntimePool`] and the handlers send it jobs. Three routes:
+//!
+//! - `GET /{page}` renders the page in full, and caches the HTML for
+//!   [`CACHE_TTL`] per page and `?user=`
+//! - `GET /stream/{page}` streams the HTML chunk by chunk as it is rendered
+//! - `GET /metrics` returns the host's counters as JSON
+//!
+//! Render errors become error pages with a status for their kind (404 for a
+//! missing module, 504 for a timeout, 500 otherwise); the message only goes to
+//! stderr. Every request is logged with its status, duration and cache use.
+//!
+//! ```bash
+//! cargo run --example axum_host
+//! curl http://127.0.0.1:3000/home?user=Alice
+//! curl -N http://127.0.0.1:3000/stream/about
+//! curl http://127.0.0.1:3000/metrics
+//! ```
+
+use anyhow::{anyhow, Error};
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use deno_core::futures::{stream, StreamExt};
+use ssr_sandbox_core::{
+    create_runtime, execute_ssr_stream, ExecuteOptions, RuntimePool, SandboxConfig, SsrError,
+    SsrErrorKind, SsrResult,
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Bundle the example renders (run `./build-ssr.sh` to render the bundled one)
+const CHUNKS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../example-src");
+const ENTRY: &str = "entry.js";
+/// How long a rendered page is served from the cache
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+enum Job {
+    Render {
+        props: serde_json::Value,
+        options: ExecuteOptions,
+        reply: oneshot::Sender<Result<SsrResult, Error>>,
+    },
+    /// Chunks are sent as they are rendered; an error ends the stream
+    Stream {
+        props: serde_json::Value,
+        options: ExecuteOptions,
+        chunks: tokio::sync::mpsc::Sender<Result<Bytes, Error>>,
+    },
+}
+
+/// Handle to the render thread
+#[derive(Clone)]
+struct Renderer {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Renderer {
+    /// Start the render thread and fill its pool; must be called from within
+    /// a multi-threaded tokio runtime
+    fn start(config: SandboxConfig) -> Result<Self, Error> {
+        let handle = tokio::runtime::Handle::current();
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let (ready, started) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("renderer".to_string())
+            .spawn(move || {
+                let _guard = handle.enter();
+                let mut pool = match RuntimePool::new(config.clone()) {
+                    Ok(pool) => {
+                        let _ = ready.send(Ok(()));
+                        pool
+                    }
+                    Err(e) => {
+                        let _ = ready.send(Err(e));
+                        return;
+                    }
+                };
+                let entry = std::path::Path::new(&config.chunks_dir).join(ENTRY);
+                for job in receiver {
+                    match job {
+                        Job::Render {
+                            props,
+                            options,
+                            reply,
+                        } => {
+                            let result = handle.block_on(pool.render(&entry, props, options));
+                            let _ = reply.send(result.map_err(Error::from));
+                            // Replace the used runtime while the host waits for the next request
+                            if let Err(e) = pool.refill() {
+                                eprintln!("[axum-host] Failed to refill runtime pool: {}", e);
+                            }
+                        }
+                        Job::Stream {
+                            props,
+                            options,
+                            chunks,
+                        } => {
+                            let streamed = stream_render(&config, &entry, props, options, &chunks);
+                            if let Err(e) = handle.block_on(streamed) {
+                                let _ = handle.block_on(chunks.send(Err(e)));
+                            }
+                        }
+                    }
+                }
+            })?;
+        started
+            .recv()
+            .map_err(|_| anyhow!("Render thread exited"))??;
+        Ok(Self { jobs })
+    }
+
+    async fn render(
+        &self,
+        props: serde_json::Value,
+        options: ExecuteOptions,
+    ) -> Result<SsrResult, Error> {
+        let (reply, result) = oneshot::channel();
+        self.jobs
+            .send(Job::Render {
+                props,
+                options,
+                reply,
+            })
+            .map_err(|_| anyhow!("Render thread exited"))?;
+        result.await.map_err(|_| anyhow!("Render thread exited"))?
+    }
+
+    fn stream(
+        &self,
+        props: serde_json::Value,
+        options: ExecuteOptions,
+    ) -> Result<tokio::sync::mpsc::Receiver<Result<Bytes, Error>>, Error> {
+        let (chunks, received) = tokio::sync::mpsc::channel(16);
+        self.jobs
+            .send(Job::Stream {
+                props,
+                options,
+                chunks,
+            })
+            .map_err(|_| anyhow!("Render thread exited"))?;
+        Ok(received)
+    }
+}
+
+/// Stream a render in a runtime of its own (the pool only renders in full)
+async fn stream_render(
+    config: &SandboxConfig,
+    entry: &std::path::Path,
+    props: serde_json::Value,
+    options: ExecuteOptions,
+    chunks: &tokio::sync::mpsc::Sender<Result<Bytes, Error>>,
+) -> Result<(), Error> {
+    let mut runtime = create_runtime(config)?;
+    let mut rendered = execute_ssr_stream(&mut runtime, entry, props, options.timeout_ms).await?;
+    while let Some(chunk) = rendered.next().await {
+        // The client went away, stop rendering
+        if chunks.send(Ok(chunk?)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Counters of the host, served by `GET /metrics`
+#[derive(Default)]
+struct Metrics {
+    requests: AtomicU64,
+    cache_hits: AtomicU64,
+    renders: AtomicU64,
+    streams: AtomicU64,
+    errors: AtomicU64,
+    render_micros: AtomicU64,
+}
+
+impl Metrics {
+    fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct CachedPage {
+    rendered_at: Instant,
+    html: String,
+    status: StatusCode,
+}
+
+struct AppState {
+    renderer: Renderer,
+    cache: Mutex<HashMap<String, CachedPage>>,
+    metrics: Metrics,
+}
+
+fn app(renderer: Renderer) -> Router {
+    let state = Arc::new(AppState {
+        renderer,
+        cache: Mutex::new(HashMap::new()),
+        metrics: Metrics::default(),
+    });
+    Router::new()
+        .route("/metrics", get(metrics))
+        .route("/stream/{page}", get(stream_page))
+        .route("/{page}", get(page))
+        .with_state(state)
+}
+
+fn props(page: &str, query: &HashMap<String, String>) -> serde_json::Value {
+    serde_json::json!({
+        "page": page,
+        "title": format!("SSR Sandbox - {}", page),
+        "user": query.get("user"),
+    })
+}
+
+/// Options of a render, tagged so its sandbox logs can be matched to the request
+fn options(state: &AppState) -> ExecuteOptions {
+    ExecuteOptions {
+        request_id: Some(format!(
+            "req-{}",
+            state.metrics.requests.load(Ordering::Relaxed)
+        )),
+        ..Default::default()
+    }
+}
+
+async fn page(
+    State(state): State<Arc<AppState>>,
+    Path(page): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let started = Instant::now();
+    Metrics::count(&state.metrics.requests);
+    let key = format!(
+        "{}?user={}",
+        page,
+        query.get("user").map_or("", String::as_str)
+    );
+    let cached = state
+        .cache
+        .lock()
+        .unwrap()
+        .get(&key)
+        .filter(|cached| cached.rendered_at.elapsed() < CACHE_TTL)
+        .map(|cached| (cached.status, cached.html.clone()));
+    if let Some((status, html)) = cached {
+        Metrics::count(&state.metrics.cache_hits);
+        log_request(&format!("/{}", page), status, started, "cache hit");
+        return html_response(status, html, "hit");
+    }
+
+    Metrics::count(&state.metrics.renders);
+    let result = state
+        .renderer
+        .render(props(&page, &query), options(&state))
+        .await;
+    state
+        .metrics
+        .render_micros
+        .fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+    match result {
+        Ok(result) => {
+            if let Some(location) = result.redirect {
+                log_request(
+                    &format!("/{}", page),
+                    StatusCode::FOUND,
+                    started,
+                    "redirect",
+                );
+                return (StatusCode::FOUND, [(header::LOCATION, location)]).into_response();
+            }
+            let status = result
+                .status_code
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .unwrap_or(StatusCode::OK);
+            state.cache.lock().unwrap().insert(
+                key,
+                CachedPage {
+                    rendered_at: Instant::now(),
+                    html: result.html.clone(),
+                    status,
+                },
+            );
+            log_request(&format!("/{}", page), status, started, "cache miss");
+            html_response(status, result.html, "miss")
+        }
+        Err(e) => error_response(&state, &format!("/{}", page), started, &e),
+    }
+}
+
+async fn stream_page(
+    State(state): State<Arc<AppState>>,
+    Path(page): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let started = Instant::now();
+    Metrics::count(&state.metrics.requests);
+    Metrics::count(&state.metrics.streams);
+    let path = format!("/stream/{}", page);
+    let mut chunks = match state.renderer.stream(props(&page, &query), options(&state)) {
+        Ok(chunks) => chunks,
+        Err(e) => return error_response(&state, &path, started, &e),
+    };
+    // Errors before the first chunk still get an error page; later ones cut the response short
+    let first = match chunks.recv().await {
+        Some(Ok(chunk)) => chunk,
+        Some(Err(e)) => return error_response(&state, &path, started, &e),
+        None => Bytes::new(),
+    };
+    log_request(&path, StatusCode::OK, started, "first chunk");
+    let rest = stream::unfold(chunks, |mut chunks| async move {
+        chunks.recv().await.map(|chunk| (chunk, chunks))
+    });
+    let body = Body::from_stream(stream::once(async { Ok(first) }).chain(rest));
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], body).into_response()
+}
+
+async fn metrics(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let metrics = &state.metrics;
+    let renders = metrics.renders.load(Ordering::Relaxed);
+    let render_micros = metrics.render_micros.load(Ordering::Relaxed);
+    Json(serde_json::json!({
+        "requests": metrics.requests.load(Ordering::Relaxed),
+        "cacheHits": metrics.cache_hits.load(Ordering::Relaxed),
+        "renders": renders,
+        "streams": metrics.streams.load(Ordering::Relaxed),
+        "errors": metrics.errors.load(Ordering::Relaxed),
+        "avgRenderMs": if renders > 0 { render_micros as f64 / renders as f64 / 1000.0 } else { 0.0 },
+    }))
+}
+
+fn html_response(status: StatusCode, html: String, cache: &'static str) -> Response {
+    (
+        status,
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::HeaderName::from_static("x-cache"), cache),
+        ],
+        html,
+    )
+        .into_response()
+}
+
+/// Error page for a failed render; the message is only logged, it may
+/// contain details of the bundle
+fn error_response(state: &AppState, path: &str, started: Instant, error: &Error) -> Response {
+    Metrics::count(&state.metrics.errors);
+    let status = match error.downcast_ref::<SsrError>().map(|e| &e.kind) {
+        Some(SsrErrorKind::ModuleNotFound { .. }) => StatusCode::NOT_FOUND,
+        Some(SsrErrorKind::Timeout { .. }) => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    log_request(path, status, started, &format!("error: {}", error));
+    let html = format!(
+        "<!DOCTYPE html><title>{}</title><h1>{}</h1>",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or("Error")
+    );
+    html_response(status, html, "miss")
+}
+
+fn log_request(path: &str, status: StatusCode, started: Instant, note: &str) {
+    eprintln!(
+        "[axum-host] GET {} {} {:.1}ms ({})",
+        path,
+        status.as_u16(),
+        started.elapsed().as_secs_f64() * 1000.0,
+        note
+    );
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let config = SandboxConfig {
+        chunks_dir: CHUNKS_DIR.to_string(),
+        ..Default::default()
+    };
+    let renderer = Renderer::start(config)?;
+    let addr = std::env::var("ADDR").unwrap_or_else(|_| "127.0.0.1:3000".to_string());
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    eprintln!("[axum-host] Listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, app(renderer)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serve the example on a free port, rendering `chunks_dir`
+    async fn serve(chunks_dir: &str) -> String {
+        let config = SandboxConfig {
+            chunks_dir: chunks_dir.to_string(),
+            timeout_ms: Some(500),
+            ..Default::default()
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let renderer = Renderer::start(config).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app(renderer)).await });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_renders_caches_and_streams() {
+        let base = serve(CHUNKS_DIR).await;
+
+        let first = reqwest::get(format!("{}/home?user=Alice", base))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), 200);
+        assert_eq!(first.headers()["x-cache"], "miss");
+        assert!(first.text().await.unwrap().contains("User: Alice"));
+        let second = reqwest::get(format!("{}/home?user=Alice", base))
+            .await
+            .unwrap();
+        assert_eq!(second.headers()["x-cache"], "hit");
+
+        let streamed = reqwest::get(format!("{}/stream/about", base))
+            .await
+            .unwrap();
+        assert_eq!(streamed.status(), 200);
+        assert!(streamed.text().await.unwrap().contains("About Us"));
+
+        let metrics: serde_json::Value = reqwest::get(format!("{}/metrics", base))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(metrics["requests"], 3);
+        assert_eq!(metrics["cacheHits"], 1);
+        assert_eq!(metrics["renders"], 1);
+        assert_eq!(metrics["streams"], 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_render_errors_become_error_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(ENTRY),
+            "export default function render(props) { if (props.page === 'slow') for (;;) {} throw new Error('secret detail'); }",
+        )
+        .unwrap();
+        let base = serve(dir.path().to_str().unwrap()).await;
+
+        let failed = reqwest::get(format!("{}/broken", base)).await.unwrap();
+        assert_eq!(failed.status(), 500);
+        assert!(!failed.text().await.unwrap().contains("secret detail"));
+        let slow = reqwest::get(format!("{}/slow", base)).await.unwrap();
+        assert_eq!(slow.status(), 504);
+        let streamed = reqwest::get(format!("{}/stream/broken", base))
+            .await
+            .unwrap();
+        assert_eq!(streamed.status(), 500);
+    }
+}
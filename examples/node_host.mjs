@@ -0,0 +1,297 @@
+#!/usr/bin/env node
+/**
+ * SSR Sandbox Node host
+ *
+ * Runs ssr-sandbox in server mode and talks to it over the line protocol on
+ * stdin/stdout, behind a small node:http server:
+ *
+ *   GET /<page>          full render, cached for CACHE_TTL_MS per page and ?user=
+ *   GET /stream/<page>   the body is piped to the client as it comes off stdout
+ *   GET /metrics         the host's counters as JSON
+ *
+ * Responses come back in request order, so pending requests are a FIFO queue.
+ * The body is `Length` *bytes* (not characters) after the blank line, so stdout
+ * is parsed as a Buffer. Errors (`Status:Error`, with an optional
+ * `Error-Code:`) become error pages; if the process dies, pending requests
+ * fail and it is restarted with a backoff.
+ *
+ *   node examples/node_host.mjs            # serve on 127.0.0.1:3000
+ *   node examples/node_host.mjs --check    # self-test, exits with 1 on failure
+ *
+ * SSR_SANDBOX_BIN picks the binary (default: ./target/release/ssr-sandbox)
+ * and SSR_CHUNKS_DIR the bundle (default: ./example-src).
+ */
+
+import { spawn } from "node:child_process";
+import { createServer } from "node:http";
+
+const BINARY = process.env.SSR_SANDBOX_BIN || "./target/release/ssr-sandbox";
+const CHUNKS_DIR = process.env.SSR_CHUNKS_DIR || "./example-src";
+const CACHE_TTL_MS = 30_000;
+const MAX_RESTART_DELAY_MS = 30_000;
+
+class SsrSandbox {
+  constructor(binary, chunksDir, args = []) {
+    this.binary = binary;
+    this.chunksDir = chunksDir;
+    this.args = args;
+    // Requests waiting for their response, oldest first
+    this.pending = [];
+    this.stdout = Buffer.alloc(0);
+    this.restartDelay = 100;
+    this.stopped = false;
+    this.metrics = { renders: 0, errors: 0, restarts: 0, renderMs: 0 };
+  }
+
+  /** Start the process; resolves once it reports State:ready */
+  start() {
+    this.process = spawn(this.binary, ["--server", this.chunksDir, ...this.args], {
+      stdio: ["pipe", "pipe", "pipe"],
+    });
+    this.stdout = Buffer.alloc(0);
+    this.process.stdout.on("data", (data) => this.onStdout(data));
+    this.process.on("exit", (code, signal) => this.onExit(code, signal));
+    return new Promise((resolve, reject) => {
+      let stderr = "";
+      this.process.stderr.on("data", (data) => {
+        const text = data.toString();
+        process.stderr.write(text);
+        stderr += text;
+        if (stderr.includes("[ssr-sandbox] State:ready")) {
+          this.restartDelay = 100;
+          resolve();
+        }
+      });
+      this.process.on("error", reject);
+    });
+  }
+
+  /** Close stdin; the server answers what it has read and exits */
+  stop() {
+    this.stopped = true;
+    this.process?.stdin.end();
+  }
+
+  /**
+   * Render `entry` with `props`. `onChunk` gets the body as it arrives;
+   * resolves with { ok, status, headers, errorCode, redirect, body }.
+   */
+  render(entry, props, { requestId, onChunk } = {}) {
+    return new Promise((resolve, reject) => {
+      const started = performance.now();
+      this.pending.push({ resolve, reject, onChunk, started, response: null });
+      const lines = [entry];
+      if (requestId) lines.push(`Request-Id:${requestId}`);
+      lines.push(JSON.stringify(props));
+      this.process.stdin.write(lines.join("\n") + "\n");
+    });
+  }
+
+  onStdout(data) {
+    this.stdout = Buffer.concat([this.stdout, data]);
+    while (this.pending.length > 0) {
+      const request = this.pending[0];
+      if (!request.response) {
+        // Header lines up to the blank line
+        const end = this.stdout.indexOf("\n\n");
+        if (end === -1) return;
+        request.response = parseHeaders(this.stdout.subarray(0, end).toString());
+        request.remaining = request.response.length;
+        request.chunks = [];
+        this.stdout = this.stdout.subarray(end + 2);
+      }
+      const chunk = this.stdout.subarray(0, request.remaining);
+      this.stdout = this.stdout.subarray(chunk.length);
+      request.remaining -= chunk.length;
+      if (chunk.length > 0) {
+        request.chunks.push(chunk);
+        if (request.response.ok) request.onChunk?.(chunk);
+      }
+      if (request.remaining > 0) return;
+
+      this.pending.shift();
+      const response = request.response;
+      response.body = Buffer.concat(request.chunks).toString();
+      this.metrics.renders += 1;
+      this.metrics.renderMs += performance.now() - request.started;
+      if (!response.ok) this.metrics.errors += 1;
+      request.resolve(response);
+    }
+  }
+
+  onExit(code, signal) {
+    const pending = this.pending;
+    this.pending = [];
+    for (const request of pending) {
+      request.reject(new Error(`ssr-sandbox exited (${signal || code}) before answering`));
+    }
+    if (this.stopped) return;
+    // Restart with a growing delay, so a crash loop doesn't spin
+    this.metrics.restarts += 1;
+    console.error(`[node-host] ssr-sandbox exited (${signal || code}), restarting in ${this.restartDelay}ms`);
+    setTimeout(() => this.start().catch((e) => console.error("[node-host] Restart failed:", e)), this.restartDelay);
+    this.restartDelay = Math.min(this.restartDelay * 2, MAX_RESTART_DELAY_MS);
+  }
+}
+
+function parseHeaders(text) {
+  const response = { ok: false, length: 0, status: 200, headers: [], errorCode: null, redirect: null };
+  for (const line of text.split("\n")) {
+    const colon = line.indexOf(":");
+    const name = line.slice(0, colon);
+    const value = line.slice(colon + 1);
+    if (name === "Status") response.ok = value === "Ok";
+    else if (name === "Length") response.length = Number(value);
+    else if (name === "Http-Status") response.status = Number(value);
+    else if (name === "Redirect") response.redirect = value;
+    else if (name === "Error-Code") response.errorCode = value;
+    else if (name === "Header") {
+      const [headerName, ...rest] = value.split(": ");
+      response.headers.push([headerName, rest.join(": ")]);
+    }
+    // Clients should ignore header lines they don't know
+  }
+  return response;
+}
+
+/** Status of an error response: the sandbox's error codes are for the host, not the browser */
+function errorStatus(response) {
+  if (response.errorCode === "shutting_down") return 503;
+  if (/timed out|timeout/i.test(response.body)) return 504;
+  return 500;
+}
+
+function createHost(sandbox) {
+  const cache = new Map();
+  const metrics = { requests: 0, cacheHits: 0, streams: 0 };
+  let nextId = 0;
+
+  const log = (path, status, started, note) =>
+    console.error(`[node-host] GET ${path} ${status} ${(performance.now() - started).toFixed(1)}ms (${note})`);
+
+  const sendError = (res, path, started, status, message) => {
+    log(path, status, started, `error: ${message}`);
+    res.writeHead(status, { "content-type": "text/html; charset=utf-8" });
+    res.end(`<!DOCTYPE html><title>${status}</title><h1>Error ${status}</h1>`);
+  };
+
+  return createServer(async (req, res) => {
+    const started = performance.now();
+    const url = new URL(req.url, "http://localhost");
+    if (url.pathname === "/metrics") {
+      res.writeHead(200, { "content-type": "application/json" });
+      res.end(JSON.stringify({ ...metrics, ...sandbox.metrics }));
+      return;
+    }
+    metrics.requests += 1;
+
+    const streaming = url.pathname.startsWith("/stream/");
+    const page = url.pathname.slice(streaming ? "/stream/".length : 1) || "home";
+    const props = { page, title: `SSR Sandbox - ${page}`, user: url.searchParams.get("user") };
+    const requestId = `req-${++nextId}`;
+
+    try {
+      if (streaming) {
+        metrics.streams += 1;
+        let headersSent = false;
+        const response = await sandbox.render("entry.js", props, {
+          requestId,
+          onChunk: (chunk) => {
+            if (!headersSent) {
+              res.writeHead(200, { "content-type": "text/html; charset=utf-8" });
+              headersSent = true;
+              log(url.pathname, 200, started, "first chunk");
+            }
+            res.write(chunk);
+          },
+        });
+        if (!response.ok) return sendError(res, url.pathname, started, errorStatus(response), response.body);
+        if (!headersSent) res.writeHead(200, { "content-type": "text/html; charset=utf-8" });
+        res.end();
+        return;
+      }
+
+      const key = `${page}?user=${props.user ?? ""}`;
+      const cached = cache.get(key);
+      if (cached && cached.expires > Date.now()) {
+        metrics.cacheHits += 1;
+        log(url.pathname, cached.status, started, "cache hit");
+        res.writeHead(cached.status, { ...cached.headers, "x-cache": "hit" });
+        res.end(cached.body);
+        return;
+      }
+      const response = await sandbox.render("entry.js", props, { requestId });
+      if (!response.ok) return sendError(res, url.pathname, started, errorStatus(response), response.body);
+      if (response.redirect) {
+        log(url.pathname, 302, started, "redirect");
+        res.writeHead(302, { location: response.redirect });
+        res.end();
+        return;
+      }
+      const headers = { "content-type": "text/html; charset=utf-8", ...Object.fromEntries(response.headers) };
+      cache.set(key, { expires: Date.now() + CACHE_TTL_MS, status: response.status, headers, body: response.body });
+      log(url.pathname, response.status, started, "cache miss");
+      res.writeHead(response.status, { ...headers, "x-cache": "miss" });
+      res.end(response.body);
+    } catch (e) {
+      // The process died mid-request; it is being restarted
+      sendError(res, url.pathname, started, 503, e.message);
+    }
+  });
+}
+
+/** Self-test against a running host */
+async function check(base) {
+  const failures = [];
+  const expect = (what, condition) => {
+    console.error(`[node-host] ${condition ? "ok" : "FAILED"}: ${what}`);
+    if (!condition) failures.push(what);
+  };
+
+  const first = await fetch(`${base}/home?user=Alice`);
+  expect("renders a page", first.status === 200 && (await first.text()).includes("User: Alice"));
+  expect("first render is a cache miss", first.headers.get("x-cache") === "miss");
+  const second = await fetch(`${base}/home?user=Alice`);
+  expect("second render is a cache hit", second.headers.get("x-cache") === "hit");
+
+  const streamed = await fetch(`${base}/stream/about`);
+  expect("streams a page", streamed.status === 200 && (await streamed.text()).includes("About Us"));
+
+  // Bodies with multi-byte characters are framed by bytes, not characters
+  const unicode = await fetch(`${base}/home?user=${encodeURIComponent("Zoë 🚀")}`);
+  expect("keeps framing with multi-byte text", (await unicode.text()).includes("Zoë 🚀"));
+
+  const metrics = await (await fetch(`${base}/metrics`)).json();
+  expect("counts requests", metrics.requests === 4 && metrics.cacheHits === 1 && metrics.streams === 1);
+  return failures;
+}
+
+async function main() {
+  const sandbox = new SsrSandbox(BINARY, CHUNKS_DIR);
+  await sandbox.start();
+  const server = createHost(sandbox);
+  const selfTest = process.argv.includes("--check");
+  const port = selfTest ? 0 : Number(process.env.PORT || 3000);
+  await new Promise((resolve) => server.listen(port, "127.0.0.1", resolve));
+  const base = `http://127.0.0.1:${server.address().port}`;
+  console.error(`[node-host] Listening on ${base}`);
+
+  if (selfTest) {
+    const failures = await check(base);
+    server.close();
+    sandbox.stop();
+    process.exitCode = failures.length > 0 ? 1 : 0;
+    return;
+  }
+  const shutdown = () => {
+    server.close();
+    sandbox.stop();
+  };
+  process.on("SIGINT", shutdown);
+  process.on("SIGTERM", shutdown);
+}
+
+main().catch((e) => {
+  console.error(e);
+  process.exit(1);
+});
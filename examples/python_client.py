@@ -8,7 +8,7 @@ Keeps the process alive for fast subsequent renders.
 
 import subprocess
 import time
-from dataclasses import dataclass
+from dataclasses import dataclass, field
 from typing import Optional
 import json
 
@@ -17,6 +17,11 @@ import json
 class RenderResult:
     ok: bool
     body: str  # HTML if ok, error message if not
+    # Set when the render returns {html, head, statusCode, headers, redirect}
+    status_code: Optional[int] = None
+    redirect: Optional[str] = None
+    headers: list = field(default_factory=list)
+    head: Optional[str] = None
 
 
 class SSRSandbox:
@@ -38,7 +43,7 @@ class SSRSandbox:
             bufsize=1,  # Line buffered
         )
         # Wait for ready signal (optional, just for cleaner startup)
-        # The process writes to stderr when ready
+        # The process writes "[ssr-sandbox] State:ready" to stderr when ready
 
     def stop(self):
         """Stop the ssr-sandbox process."""
@@ -66,19 +71,33 @@ class SSRSandbox:
         self.process.stdin.write(f"{json.dumps(props)}\n")
         self.process.stdin.flush()
 
-        # Read response header (2 lines)
-        status_line = self.process.stdout.readline().strip()
-        length_line = self.process.stdout.readline().strip()
-        _empty_line = self.process.stdout.readline()  # Empty separator
-
-        # Parse headers
-        status = status_line.split(":", 1)[1] if ":" in status_line else "Error"
-        length = int(length_line.split(":", 1)[1]) if ":" in length_line else 0
+        # Read response header lines until the empty separator
+        status = "Error"
+        length = 0
+        result = RenderResult(ok=False, body="")
+        while True:
+            line = self.process.stdout.readline().rstrip("\n")
+            if not line:
+                break
+            name, _, value = line.partition(":")
+            if name == "Status":
+                status = value
+            elif name == "Length":
+                length = int(value)
+            elif name == "Http-Status":
+                result.status_code = int(value)
+            elif name == "Redirect":
+                result.redirect = value
+            elif name == "Header":
+                header_name, _, header_value = value.partition(": ")
+                result.headers.append((header_name, header_value))
+            elif name == "Head":
+                result.head = json.loads(value)
 
         # Read body
-        body = self.process.stdout.read(length)
-
-        return RenderResult(ok=(status == "Ok"), body=body)
+        result.body = self.process.stdout.read(length)
+        result.ok = status == "Ok"
+        return result
 
     def __enter__(self):
         self.start()
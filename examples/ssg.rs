@@ -0,0 +1,269 @@
+//! A static site generator: renders a list of routes to HTML files.
+//!
+//! Each page is streamed to a temporary file as it is rendered and only
+//! renamed into place once the render succeeded, so a failed page never
+//! leaves a half-written file behind. Pages whose props and bundle haven't
+//! changed since the last build are skipped, using a manifest of
+//! fingerprints (SHA-256 of the chunks dir's files and the page's props) kept
+//! in the output directory. Every page is logged with its size, chunk count
+//! and render time; failed pages are listed at the end and fail the build.
+//!
+//! ```bash
+//! cargo run --example ssg -- ./site
+//! ```
+
+use anyhow::{anyhow, Error};
+use deno_core::futures::StreamExt;
+use sha2::{Digest, Sha256};
+use ssr_sandbox_core::{create_runtime, execute_ssr_stream, props_hash, SandboxConfig};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Bundle the example renders (run `./build-ssr.sh` to render the bundled one)
+const CHUNKS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../example-src");
+const ENTRY: &str = "entry.js";
+/// Fingerprints of the pages of the last build, in the output directory
+const MANIFEST: &str = ".ssg-manifest.json";
+
+/// The pages of the site: output file and props
+fn routes() -> Vec<(String, serde_json::Value)> {
+    ["home", "about", "contact"]
+        .into_iter()
+        .map(|page| {
+            let file = match page {
+                "home" => "index.html".to_string(),
+                page => format!("{}/index.html", page),
+            };
+            let props = serde_json::json!({
+                "page": page,
+                "title": format!("SSR Sandbox - {}", page),
+            });
+            (file, props)
+        })
+        .collect()
+}
+
+/// Outcome of a build, by output file
+#[derive(Debug, Default)]
+struct Report {
+    rendered: Vec<String>,
+    /// Unchanged since the last build
+    cached: Vec<String>,
+    failed: Vec<(String, Error)>,
+}
+
+/// Render every route into `out_dir`
+async fn build(
+    config: &SandboxConfig,
+    routes: &[(String, serde_json::Value)],
+    out_dir: &Path,
+) -> Result<Report, Error> {
+    let entry = Path::new(&config.chunks_dir).join(ENTRY);
+    let bundle = bundle_fingerprint(Path::new(&config.chunks_dir))?;
+    let manifest_path = out_dir.join(MANIFEST);
+    let previous: BTreeMap<String, String> = match std::fs::read_to_string(&manifest_path) {
+        Ok(json) => serde_json::from_str(&json)?,
+        Err(_) => BTreeMap::new(),
+    };
+
+    let mut report = Report::default();
+    let mut manifest = BTreeMap::new();
+    for (file, props) in routes {
+        let fingerprint = format!("{}-{}", bundle, props_hash(props));
+        let path = out_dir.join(file);
+        if previous.get(file) == Some(&fingerprint) && path.exists() {
+            eprintln!("[ssg] {} unchanged", file);
+            manifest.insert(file.clone(), fingerprint);
+            report.cached.push(file.clone());
+            continue;
+        }
+
+        let started = Instant::now();
+        match render_page(config, &entry, props.clone(), &path).await {
+            Ok((bytes, chunks)) => {
+                eprintln!(
+                    "[ssg] {} {} bytes in {} chunk(s), {:.1}ms",
+                    file,
+                    bytes,
+                    chunks,
+                    started.elapsed().as_secs_f64() * 1000.0
+                );
+                manifest.insert(file.clone(), fingerprint);
+                report.rendered.push(file.clone());
+            }
+            Err(e) => {
+                eprintln!("[ssg] {} failed: {}", file, e);
+                report.failed.push((file.clone(), e));
+            }
+        }
+    }
+
+    std::fs::create_dir_all(out_dir)?;
+    std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(report)
+}
+
+/// Stream one page to `path`, returning its size and number of chunks
+async fn render_page(
+    config: &SandboxConfig,
+    entry: &Path,
+    props: serde_json::Value,
+    path: &Path,
+) -> Result<(usize, usize), Error> {
+    std::fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+    let partial = path.with_extension("html.partial");
+    let written = async {
+        // A runtime per page, so pages can't see each other's state
+        let mut runtime = create_runtime(config)?;
+        let mut rendered =
+            execute_ssr_stream(&mut runtime, entry, props, config.timeout_ms).await?;
+        let mut out = std::io::BufWriter::new(std::fs::File::create(&partial)?);
+        let (mut bytes, mut chunks) = (0, 0);
+        while let Some(chunk) = rendered.next().await {
+            let chunk = chunk?;
+            out.write_all(&chunk)?;
+            bytes += chunk.len();
+            chunks += 1;
+        }
+        out.flush()?;
+        Ok::<_, Error>((bytes, chunks))
+    }
+    .await;
+    match written {
+        Ok(written) => {
+            std::fs::rename(&partial, path)?;
+            Ok(written)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// SHA-256 (hex) of the paths and contents of every file below `dir`
+fn bundle_fingerprint(dir: &Path) -> Result<String, Error> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    let mut hash = Sha256::new();
+    for file in files {
+        hash.update(file.strip_prefix(dir)?.to_string_lossy().as_bytes());
+        hash.update(std::fs::read(&file)?);
+    }
+    Ok(hash
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let out_dir = PathBuf::from(
+        std::env::args()
+            .nth(1)
+            .unwrap_or_else(|| "site".to_string()),
+    );
+    let config = SandboxConfig {
+        chunks_dir: CHUNKS_DIR.to_string(),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let report = build(&config, &routes(), &out_dir).await?;
+    eprintln!(
+        "[ssg] {} rendered, {} unchanged, {} failed in {:.1}ms -> {}",
+        report.rendered.len(),
+        report.cached.len(),
+        report.failed.len(),
+        started.elapsed().as_secs_f64() * 1000.0,
+        out_dir.display()
+    );
+    if !report.failed.is_empty() {
+        return Err(anyhow!("{} page(s) failed to render", report.failed.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_builds_pages_and_skips_unchanged() {
+        let out = tempfile::tempdir().unwrap();
+        let config = SandboxConfig {
+            chunks_dir: CHUNKS_DIR.to_string(),
+            ..Default::default()
+        };
+        let report = build(&config, &routes(), out.path()).await.unwrap();
+        assert_eq!(report.rendered.len(), 3);
+        let about = std::fs::read_to_string(out.path().join("about/index.html")).unwrap();
+        assert!(about.contains("About Us"));
+
+        let report = build(&config, &routes(), out.path()).await.unwrap();
+        assert!(report.rendered.is_empty());
+        assert_eq!(report.cached.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_api_page_has_web_crypto() {
+        let out = tempfile::tempdir().unwrap();
+        let config = SandboxConfig {
+            chunks_dir: CHUNKS_DIR.to_string(),
+            ..Default::default()
+        };
+        let routes = vec![("apis.html".to_string(), serde_json::json!({"page": "apis"}))];
+        let report = build(&config, &routes, out.path()).await.unwrap();
+        assert!(report.failed.is_empty());
+        let html = std::fs::read_to_string(out.path().join("apis.html")).unwrap();
+        for hash in ["SHA-256", "SHA-384", "SHA-512"] {
+            assert!(html.contains(&format!("✓ crypto.subtle HMAC {} sign/verify", hash)));
+        }
+        assert!(!html.contains("✗ crypto"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_pages_leave_no_file() {
+        let chunks = tempfile::tempdir().unwrap();
+        std::fs::write(
+            chunks.path().join(ENTRY),
+            "export default function render(props) { if (props.page === 'broken') throw new Error('boom'); return `<p>${props.page}</p>`; }",
+        )
+        .unwrap();
+        let config = SandboxConfig {
+            chunks_dir: chunks.path().to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let routes = vec![
+            ("ok.html".to_string(), serde_json::json!({"page": "ok"})),
+            (
+                "broken.html".to_string(),
+                serde_json::json!({"page": "broken"}),
+            ),
+        ];
+        let out = tempfile::tempdir().unwrap();
+        let report = build(&config, &routes, out.path()).await.unwrap();
+        assert_eq!(report.rendered, vec!["ok.html".to_string()]);
+        assert_eq!(report.failed.len(), 1);
+        assert!(!out.path().join("broken.html").exists());
+        assert!(!out.path().join("broken.html.partial").exists());
+
+        // Failed pages are tried again on the next build
+        let report = build(&config, &routes, out.path()).await.unwrap();
+        assert_eq!(report.cached, vec!["ok.html".to_string()]);
+        assert_eq!(report.failed.len(), 1);
+    }
+}